@@ -0,0 +1,106 @@
+// Benchmarks for the paths init runs over and over or at every boot:
+// parsing the inittab, passing the state on at a re-exec and scanning
+// /proc. Run with `cargo bench`.
+
+use std::hint::black_box;
+use std::path::PathBuf;
+
+use criterion::{criterion_group, criterion_main, Criterion};
+
+use rye_init::inittab::{self, Attributes, InitAction};
+use rye_init::procscan;
+use rye_init::runlevel::Runlevel;
+use rye_init::state::{self, ChildFlags, Record, State};
+
+const ENTRIES: usize = 500;
+const PROCESSES: i32 = 400;
+
+// An inittab with ENTRIES lines of the kinds found in the wild
+fn big_inittab() -> String {
+    let mut tab = String::from("# generated\nid:3:initdefault:\nsi::sysinit:/etc/init.d/rcS\n");
+    for n in 0..ENTRIES {
+        let line = match n % 4 {
+            0 => format!("g{}:2345:respawn:/sbin/getty 38400 tty{}\n", n, n % 64),
+            1 => format!("d{}:345:respawn:[user:daemon group:daemon reload:HUP] /usr/sbin/daemon{} -f\n", n, n),
+            2 => format!("w{}:3:delayed:30:/usr/bin/job{} --once\n", n, n),
+            _ => format!("o{}:2:once:/bin/sh -c 'echo {} > /dev/null'\n", n, n),
+        };
+        tab.push_str(&line);
+    }
+    tab
+}
+
+// The state of an init running ENTRIES entries
+fn big_state() -> State {
+    let mut state = State { runlevel: Runlevel::Classic('3'), thislevel: Runlevel::Classic('3'), ..State::default() };
+    for n in 0..ENTRIES {
+        let mut attrs = Attributes::default();
+        if n % 3 == 0 {
+            attrs.user = Some("daemon".to_string());
+        }
+        state.records.push(Record {
+            id: format!("e{}", n),
+            rlevel: "2345".to_string(),
+            flags: ChildFlags::RUNNING | ChildFlags::XECUTED,
+            pid: 1000 + n as i32,
+            exstat: 0,
+            action: InitAction::Respawn,
+            process: format!("/usr/sbin/daemon{} --option value \"quoted\\nline\"", n),
+            attrs,
+            delay: 0,
+            due: 0,
+            tm: 1234,
+            count: 1,
+            respawns: n as u64,
+        });
+    }
+    state
+}
+
+// A copy of /proc with PROCESSES processes, made once per run
+fn proc_fixture() -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("rye-init-bench-proc-{}", std::process::id()));
+    for pid in 1..=PROCESSES {
+        let pdir = dir.join(pid.to_string());
+        std::fs::create_dir_all(&pdir).unwrap();
+        let comm = if pid % 10 == 0 { format!("kworker/{}:1", pid) } else { format!("proc ({})", pid) };
+        let stat = format!("{} ({}) S 1 {} {} 0 -1 4194560 100 0 0 0 1 2 0 0 20 0 1 0 100 1000000 200\n",
+            pid, comm, pid, pid);
+        std::fs::write(pdir.join("stat"), stat).unwrap();
+        let cmdline = if pid % 10 == 0 { Vec::new() } else { format!("/usr/bin/proc\0--pid\0{}\0", pid).into_bytes() };
+        std::fs::write(pdir.join("cmdline"), cmdline).unwrap();
+    }
+    dir
+}
+
+fn bench_inittab(c: &mut Criterion) {
+    let tab = big_inittab();
+    c.bench_function("inittab parse 500 entries", |b| b.iter(|| inittab::parse_str(black_box(&tab))));
+}
+
+fn bench_state(c: &mut Criterion) {
+    let state = big_state();
+    let mut stream = Vec::new();
+    state::serialize(&mut stream, &state).unwrap();
+
+    c.bench_function("state serialize 500 records", |b| {
+        b.iter(|| {
+            let mut out = Vec::with_capacity(stream.len());
+            state::serialize(&mut out, black_box(&state)).unwrap();
+            out
+        })
+    });
+    c.bench_function("state deserialize 500 records", |b| {
+        b.iter(|| state::deserialize(&mut black_box(&stream[..])).unwrap())
+    });
+}
+
+fn bench_procscan(c: &mut Criterion) {
+    let dir = proc_fixture();
+    let proc = dir.to_string_lossy().into_owned();
+    c.bench_function("procscan 400 processes", |b| b.iter(|| procscan::scan_in(black_box(&proc))));
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+criterion_group!(benches, bench_inittab, bench_state, bench_procscan);
+criterion_main!(benches);
@@ -0,0 +1,16 @@
+// Record when init was built. Init uses it as the lowest plausible time of
+// day when the clock comes up wrong at boot. SOURCE_DATE_EPOCH is honoured
+// for reproducible builds.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+fn main() {
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+
+    let secs = std::env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|s| s.parse::<u64>().ok())
+        .unwrap_or_else(|| SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0));
+
+    println!("cargo:rustc-env=RYE_INIT_BUILD_TIME={}", secs);
+}
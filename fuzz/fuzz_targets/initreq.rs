@@ -0,0 +1,33 @@
+// Requests as read from /run/initctl: a single request, and a stream cut
+// into pieces the way reads from the FIFO deliver it
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use rye_init::initreq::{InitRequest, RequestBuffer, INIT_REQUEST_SIZE};
+
+fuzz_target!(|data: &[u8]| {
+    if let Some(req) = InitRequest::from_bytes(data) {
+        let _ = req.cmd_name();
+        let _ = req.data_str();
+        assert_eq!(&req.to_bytes()[..], data);
+    }
+
+    // The first byte says how big the reads are
+    let Some((&chunk, rest)) = data.split_first() else {
+        return;
+    };
+    let chunk = chunk as usize + 1;
+
+    let mut buf = RequestBuffer::new();
+    let mut got = 0;
+    for piece in rest.chunks(chunk) {
+        buf.push(piece);
+        while let Some(req) = buf.next_request() {
+            assert!(req.is_valid());
+            got += 1;
+        }
+    }
+    assert!(got <= rest.len() / INIT_REQUEST_SIZE);
+    let _ = buf.take_dropped();
+});
@@ -0,0 +1,17 @@
+// The inittab parser, line by line and as a whole file
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use rye_init::inittab;
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(s) = std::str::from_utf8(data) else {
+        return;
+    };
+
+    let _ = inittab::parse_str(s);
+    for line in s.lines() {
+        let _ = inittab::id_range(line);
+    }
+});
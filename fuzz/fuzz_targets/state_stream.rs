@@ -0,0 +1,18 @@
+// The state stream an init reads from its predecessor
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use rye_init::state;
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(received) = state::deserialize(&mut &data[..]) else {
+        return;
+    };
+
+    // What we pass on in turn must be readable by the next init
+    let mut stream = Vec::new();
+    state::serialize(&mut stream, &received).unwrap();
+    let again = state::deserialize(&mut &stream[..]).expect("cannot read our own state stream");
+    assert_eq!(again.records.len(), received.records.len());
+});
@@ -0,0 +1,46 @@
+// Battery monitoring, for machines without a UPS daemon
+//
+// With battery-threshold in the config file init looks at the batteries
+// under POWER_SUPPLY every CHECK_INTERVAL. Once all of them together are
+// below the threshold while running on battery, the powerfailnow entries
+// run, as if a UPS daemon had reported a low battery. Without any the
+// power goes off. It happens once per discharge: the battery has to be
+// charging again, or above the threshold, before it can happen again.
+
+use std::path::Path;
+
+use rye_init::paths;
+
+pub const POWER_SUPPLY: &str = "/sys/class/power_supply";
+pub const CHECK_INTERVAL: u64 = 30_000;
+
+// One attribute of a power supply, trimmed
+fn attr(supply: &Path, name: &str) -> Option<String> {
+    std::fs::read_to_string(supply.join(name)).ok().map(|s| s.trim().to_string())
+}
+
+// The charge left in percent when we run on battery, None when a charger
+// is connected or there is no battery. Several batteries count as one.
+pub fn discharging() -> Option<u32> {
+    let mut capacities = Vec::new();
+    for entry in std::fs::read_dir(paths::path(POWER_SUPPLY)).ok()?.flatten() {
+        let supply = entry.path();
+        match attr(&supply, "type").as_deref() {
+            Some("Battery") => {}
+            Some("Mains") | Some("USB") if attr(&supply, "online").as_deref() == Some("1") => return None,
+            _ => continue,
+        }
+        if attr(&supply, "present").as_deref() == Some("0") {
+            continue;
+        }
+        if attr(&supply, "status").as_deref() != Some("Discharging") {
+            return None;
+        }
+        capacities.push(attr(&supply, "capacity")?.parse::<u32>().ok()?);
+    }
+
+    if capacities.is_empty() {
+        return None;
+    }
+    Some(capacities.iter().sum::<u32>() / capacities.len() as u32)
+}
@@ -0,0 +1,224 @@
+// bootlogd: store output from the console during bootup into a file.
+//
+// Usage: bootlogd [-c] [-d] [-e] [-s] [--tag-console | --split-per-console]
+//                 [--stop-after seconds] [--force] [--collapse-cr] [--append | --truncate]
+//                 [--quiet-console device]... [--buffer-size bytes]
+//                 [--console-queue bytes] [--console-drop oldest|newest]
+//                 [--max-console-failures count] [-l logfile] [-p pidfile]
+//
+// The log of each boot is appended to the logfile after a
+// "---- boot <time> ----" line, --truncate starts the file over instead.
+//
+// --collapse-cr keeps only the final version of a line that was redrawn
+// with carriage returns, as progress bars do.
+//
+// --split-per-console gives every console a logfile of its own instead of
+// interleaving them all in one: /var/log/boot.ttyS0, /var/log/boot.tty0,
+// or the -l logfile with the device appended. Each holds what reached its
+// console.
+//
+// --quiet-console logs what goes to a console without showing it there,
+// e.g. --quiet-console tty1 keeps a kiosk's screen clean while ttyS0 shows
+// the boot messages. It can be given once for every console.
+//
+// When the logfile's filesystem is full or read-only the log is kept in
+// memory and written once the logfile takes it again.
+//
+// Console output waits in a ring buffer of --buffer-size bytes (32 KiB by
+// default) until it is written to the log. Very chatty consoles or slow
+// storage may need more.
+//
+// Every console is written without blocking, so a serial console that
+// stopped taking output can't hold up the others. What it hasn't taken
+// waits for it, up to --console-queue bytes (16 KiB by default); then
+// --console-drop says whether the oldest output goes or the new output
+// isn't queued (the default). A console that takes nothing for 2 seconds,
+// or fails a write, has failed once. After --max-console-failures in a row
+// (3 by default, 0 for never) bootlogd stops writing to it, and says so.
+//
+// bootlogd stops capturing after --stop-after seconds, on SIGUSR2 or when
+// /run/rye-init/boot-done appears, whatever comes first.
+//
+// It won't fight over the console: when a splash daemon like plymouth is
+// running or the console is already redirected, it says so and exits.
+// --force takes the console anyway.
+
+use std::fs::File;
+use std::io::Write;
+
+use nix::pty::openpty;
+use nix::sys::signal::{self, SigHandler, Signal};
+use nix::unistd::{self, ForkResult};
+
+use rye_init::bootlogd::{self, Bootlogd, DropPolicy, Options};
+use rye_init::paths;
+use rye_init::sys::{Kernel, Sys};
+use rye_init::tty;
+
+const PROGNAME: &str = "bootlogd";
+
+extern "C" fn handle_signal(sig: libc::c_int) {
+    bootlogd::set_signal(sig);
+}
+
+fn usage() -> ! {
+    eprintln!("Usage: {} [-c] [-d] [-e] [-s] [--tag-console | --split-per-console] [--stop-after seconds] [--force] [--collapse-cr] [--append | --truncate] [--quiet-console device]... [--buffer-size bytes] [--console-queue bytes] [--console-drop oldest|newest] [--max-console-failures count] [-l logfile] [-p pidfile]",
+        PROGNAME);
+    std::process::exit(1);
+}
+
+fn main() {
+    let mut options = Options {
+        syncalot: false,
+        print_escape_characters: false,
+        create_log: false,
+        tag_console: false,
+        stop_after: None,
+        collapse_cr: false,
+        truncate: false,
+        quiet_consoles: Vec::new(),
+        buffer_size: bootlogd::RINGBUF_SIZE,
+        split_per_console: false,
+        console_queue: bootlogd::CONSOLE_QUEUE,
+        console_drop: DropPolicy::Newest,
+        max_console_failures: bootlogd::MAX_CONSOLE_FAILURES,
+    };
+    let mut dontfork = false;
+    let mut force = false;
+    let mut logfile = paths::path(bootlogd::LOGFILE);
+    let mut pidfile = None;
+
+    let mut args = std::env::args().skip(1);
+    while let Some(a) = args.next() {
+        match a.as_str() {
+            "-c" => options.create_log = true,
+            "-d" => dontfork = true,
+            "-e" => options.print_escape_characters = true,
+            "-s" => options.syncalot = true,
+            "--tag-console" => options.tag_console = true,
+            "--split-per-console" => options.split_per_console = true,
+            "--force" => force = true,
+            "--collapse-cr" => options.collapse_cr = true,
+            "--append" => options.truncate = false,
+            "--truncate" => options.truncate = true,
+            "--quiet-console" => {
+                let name = args.next().unwrap_or_else(|| usage());
+                options.quiet_consoles.push(bootlogd::console_device(&name));
+            }
+            "--buffer-size" => {
+                options.buffer_size = args.next().and_then(|s| s.parse().ok()).filter(|&n| n > 0)
+                    .unwrap_or_else(|| usage());
+            }
+            "--console-queue" => {
+                options.console_queue = args.next().and_then(|s| s.parse().ok()).filter(|&n| n > 0)
+                    .unwrap_or_else(|| usage());
+            }
+            "--console-drop" => {
+                options.console_drop = match args.next().as_deref() {
+                    Some("oldest") => DropPolicy::Oldest,
+                    Some("newest") => DropPolicy::Newest,
+                    _ => usage(),
+                };
+            }
+            "--max-console-failures" => {
+                options.max_console_failures = args.next().and_then(|s| s.parse().ok()).unwrap_or_else(|| usage());
+            }
+            "--stop-after" => {
+                let secs = args.next().and_then(|s| s.parse().ok()).unwrap_or_else(|| usage());
+                options.stop_after = Some(std::time::Duration::from_secs(secs));
+            }
+            "-l" => logfile = args.next().unwrap_or_else(|| usage()),
+            "-p" => pidfile = Some(args.next().unwrap_or_else(|| usage())),
+            _ => usage(),
+        }
+    }
+
+    if let Some((pid, name)) = bootlogd::splash_daemon() {
+        if !force {
+            eprintln!("{}: {} (pid {}) owns the console, not logging; use --force to log anyway",
+                PROGNAME, name, pid);
+            std::process::exit(1);
+        }
+        eprintln!("{}: {} (pid {}) owns the console, logging anyway", PROGNAME, name, pid);
+    }
+
+    // Open the real consoles before we redirect /dev/console
+    let cmdline = std::fs::read_to_string("/proc/cmdline").unwrap_or_default();
+    let mut names = bootlogd::consolenames(&cmdline);
+    if names.is_empty() {
+        names.extend(bootlogd::real_console());
+    }
+
+    let outputs: Vec<(String, File)> = names
+        .into_iter()
+        .filter_map(|name| {
+            let f = tty::open(&name).ok()?;
+            Some((name, f))
+        })
+        .collect();
+
+    if outputs.is_empty() {
+        eprintln!("{}: cannot find console device", PROGNAME);
+        std::process::exit(1);
+    }
+
+    let pty = match openpty(None, None) {
+        Ok(pty) => pty,
+        Err(e) => {
+            eprintln!("{}: cannot allocate pseudo tty: {}", PROGNAME, e);
+            std::process::exit(1);
+        }
+    };
+
+    let slave = File::from(pty.slave);
+    let mut redirected = bootlogd::set_console(&Kernel, &slave);
+    if let Err(e) = &redirected && e.raw_os_error() == Some(libc::EBUSY) {
+        if !force {
+            eprintln!("{}: another process already redirects the console; use --force to take it over",
+                PROGNAME);
+            std::process::exit(1);
+        }
+        redirected = bootlogd::release_console(&Kernel).and_then(|()| bootlogd::set_console(&Kernel, &slave));
+    }
+    if let Err(e) = redirected {
+        eprintln!("{}: ioctl(TIOCCONS): {}", PROGNAME, e);
+        std::process::exit(1);
+    }
+
+    if !dontfork {
+        match unsafe { unistd::fork() } {
+            Ok(ForkResult::Parent { .. }) => std::process::exit(0),
+            Ok(ForkResult::Child) => {
+                let _ = unistd::setsid();
+            }
+            Err(e) => {
+                eprintln!("{}: fork: {}", PROGNAME, e);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    if let Some(pidfile) = &pidfile && let Ok(mut f) = File::create(pidfile) {
+        let _ = writeln!(f, "{}", std::process::id());
+    }
+
+    // SIGUSR2 says boot is done, which ends the capture like the others
+    for sig in [Signal::SIGTERM, Signal::SIGQUIT, Signal::SIGINT, Signal::SIGUSR2] {
+        let _ = unsafe { signal::signal(sig, SigHandler::Handler(handle_signal)) };
+    }
+
+    let _ = Kernel.set_title(&format!("bootlogd [{}]", logfile));
+    let mut logger = Bootlogd::new(File::from(pty.master), outputs, &logfile, options);
+    let res = logger.run();
+
+    // Give the console back to the kernel
+    drop(slave);
+    if let Some(pidfile) = &pidfile {
+        let _ = std::fs::remove_file(pidfile);
+    }
+
+    if let Err(e) = res {
+        eprintln!("{}: {}", PROGNAME, e);
+        std::process::exit(1);
+    }
+}
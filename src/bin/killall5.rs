@@ -0,0 +1,130 @@
+// killall5: send a signal to all processes, as used when going down.
+//
+// Usage: killall5 [-v] [--dry-run] [-SIGNAL] [-o omitpid[,omitpid...]]
+//
+// Left alone are init, kernel threads, killall5's own session (the script
+// that runs it) and the pids given with -o. Everything is stopped while
+// the process table is read, so nothing forks its way out.
+//
+// -v         report each process that gets the signal and each that is
+//            left alone, and why
+// --dry-run  only report, send nothing
+//
+// The default signal is SIGTERM. Exit status is 2 if no process was
+// signalled.
+
+use nix::sys::signal::{self, Signal};
+use nix::unistd::{self, Pid};
+
+use rye_init::procscan::{self, ProcInfo};
+
+const PROGNAME: &str = "killall5";
+
+const EXIT_OK: i32 = 0;
+const EXIT_NONE: i32 = 2;               // Nothing was signalled
+
+struct Options {
+    signal: Signal,
+    omit: Vec<i32>,
+    verbose: bool,
+    dry_run: bool,
+}
+
+fn usage() -> ! {
+    eprintln!("Usage: {} [-v] [--dry-run] [-SIGNAL] [-o omitpid[,omitpid...]]", PROGNAME);
+    std::process::exit(1);
+}
+
+fn parse_omit(arg: &str, omit: &mut Vec<i32>) {
+    for pid in arg.split(',') {
+        match pid.parse() {
+            Ok(pid) => omit.push(pid),
+            Err(_) => usage(),
+        }
+    }
+}
+
+fn parse_signal(s: &str) -> Option<Signal> {
+    match s.parse::<i32>() {
+        Ok(n) => Signal::try_from(n).ok(),
+        Err(_) if s.starts_with("SIG") => s.parse().ok(),
+        Err(_) => format!("SIG{}", s).parse().ok(),
+    }
+}
+
+// Why a process is left alone, None if it gets the signal
+fn excluded(info: &ProcInfo, mysid: i32, omit: &[i32]) -> Option<&'static str> {
+    if info.pid == 1 {
+        Some("init")
+    } else if info.pid == std::process::id() as i32 {
+        Some("killall5 itself")
+    } else if info.kthread {
+        Some("kernel thread")
+    } else if info.session == mysid {
+        Some("our own session")
+    } else if omit.contains(&info.pid) {
+        Some("omitted with -o")
+    } else {
+        None
+    }
+}
+
+fn report(verb: &str, info: &ProcInfo, why: &str) {
+    let line = format!("{:<14} {:>7} {:<16} session {:<7} {}", verb, info.pid, info.comm, info.session, why);
+    println!("{}", line.trim_end());
+}
+
+fn main() {
+    let mut options = Options {
+        signal: Signal::SIGTERM,
+        omit: Vec::new(),
+        verbose: false,
+        dry_run: false,
+    };
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-v" => options.verbose = true,
+            "--dry-run" => options.dry_run = true,
+            "-o" => parse_omit(&args.next().unwrap_or_else(|| usage()), &mut options.omit),
+            _ if arg.starts_with("-o") => parse_omit(&arg[2..], &mut options.omit),
+            _ if arg.starts_with('-') => options.signal = parse_signal(&arg[1..]).unwrap_or_else(|| usage()),
+            _ => usage(),
+        }
+    }
+
+    let mysid = unistd::getsid(None).map(Pid::as_raw).unwrap_or(0);
+
+    // Freeze everybody while we look, unless we are only looking
+    if !options.dry_run {
+        let _ = signal::kill(Pid::from_raw(-1), Signal::SIGSTOP);
+    }
+
+    let verb = if options.dry_run { "would signal" } else { "signalled" };
+    let mut signalled = 0;
+    for info in procscan::scan() {
+        match excluded(&info, mysid, &options.omit) {
+            Some(why) => {
+                if options.verbose || options.dry_run {
+                    report("left alone", &info, why);
+                }
+            }
+            None => {
+                if !options.dry_run && signal::kill(Pid::from_raw(info.pid), options.signal).is_err() {
+                    continue;
+                }
+                if options.verbose || options.dry_run {
+                    report(verb, &info, "");
+                }
+                signalled += 1;
+            }
+        }
+    }
+
+    if !options.dry_run {
+        let _ = signal::kill(Pid::from_raw(-1), Signal::SIGCONT);
+    }
+
+    std::process::exit(if signalled > 0 { EXIT_OK } else { EXIT_NONE });
+}
@@ -0,0 +1,137 @@
+// pidof: find the process ids of running programs.
+//
+// Usage: pidof [-s] [-c] [-q] [-x] [-o omitpid[,omitpid...]] program...
+//
+// A program matches a process by its name, the base name of argv[0] or
+// the full path in argv[0]. -x also matches scripts, by the script name the
+// interpreter was started with.
+//
+// -s  print only one pid
+// -c  only processes with the same root directory as pidof itself
+// -q  print nothing, only set the exit status
+// -o  leave out these pids, %PPID is the parent of pidof
+//
+// Exit status is 0 if at least one process was found, 1 otherwise.
+
+use rye_init::procscan::{self, ProcInfo};
+
+const PROGNAME: &str = "pidof";
+
+// Interpreters whose first argument is the script being run
+const INTERPRETERS: &[&str] = &["sh", "bash", "dash", "ksh", "zsh", "perl", "python", "python3"];
+
+struct Options {
+    single: bool,
+    same_root: bool,
+    quiet: bool,
+    scripts: bool,
+    omit: Vec<i32>,
+}
+
+fn usage() -> ! {
+    eprintln!("Usage: {} [-s] [-c] [-q] [-x] [-o omitpid[,omitpid...]] program...", PROGNAME);
+    std::process::exit(1);
+}
+
+fn basename(s: &str) -> &str {
+    s.rsplit('/').next().unwrap_or(s)
+}
+
+// Whether a process is an instance of program
+fn matches(info: &ProcInfo, program: &str, scripts: bool) -> bool {
+    let mut args = info.cmdline.split(' ');
+    let argv0 = args.next().unwrap_or("");
+
+    if argv0.is_empty() {
+        // Kernel threads and zombies only have their name
+        return info.comm == program;
+    }
+    if argv0 == program || (!program.contains('/') && basename(argv0) == program) {
+        return true;
+    }
+    // The name is cut to 15 characters, which also matches longer names
+    if !program.contains('/') && info.comm == program {
+        return true;
+    }
+
+    if scripts && INTERPRETERS.contains(&basename(argv0)) && let Some(script) = args.next() {
+        return script == program || (!program.contains('/') && basename(script) == program);
+    }
+    false
+}
+
+fn parse_omit(arg: &str, omit: &mut Vec<i32>) {
+    for pid in arg.split(',') {
+        match pid {
+            "%PPID" => omit.push(nix::unistd::getppid().as_raw()),
+            _ => match pid.parse() {
+                Ok(pid) => omit.push(pid),
+                Err(_) => usage(),
+            },
+        }
+    }
+}
+
+fn main() {
+    let mut options = Options {
+        single: false,
+        same_root: false,
+        quiet: false,
+        scripts: false,
+        omit: vec![std::process::id() as i32],
+    };
+    let mut programs = Vec::new();
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-s" => options.single = true,
+            "-c" => options.same_root = true,
+            "-q" => options.quiet = true,
+            "-x" => options.scripts = true,
+            "-o" => parse_omit(&args.next().unwrap_or_else(|| usage()), &mut options.omit),
+            _ if arg.starts_with("-o") => parse_omit(&arg[2..], &mut options.omit),
+            _ if arg.starts_with('-') => usage(),
+            _ => programs.push(arg),
+        }
+    }
+    if programs.is_empty() {
+        usage();
+    }
+
+    // Only root can look at the root directory of every process, without
+    // it -c would hide everything
+    let my_root = if options.same_root && nix::unistd::geteuid().is_root() {
+        procscan::root_id(std::process::id() as i32)
+    } else {
+        None
+    };
+
+    let procs = procscan::scan();
+    let mut found = Vec::new();
+    for program in &programs {
+        for info in procs.iter().rev() {
+            if options.omit.contains(&info.pid) || found.contains(&info.pid) {
+                continue;
+            }
+            if !matches(info, program, options.scripts) {
+                continue;
+            }
+            if my_root.is_some() && procscan::root_id(info.pid) != my_root {
+                continue;
+            }
+            found.push(info.pid);
+            if options.single {
+                break;
+            }
+        }
+    }
+
+    if found.is_empty() {
+        std::process::exit(1);
+    }
+    if !options.quiet {
+        let pids: Vec<String> = found.iter().map(i32::to_string).collect();
+        println!("{}", pids.join(" "));
+    }
+}
@@ -0,0 +1,157 @@
+// readbootlog: show the boot log bootlogd wrote, without the terminal
+// escape sequences it may contain.
+//
+// Usage: readbootlog [--collapse-cr] [--list-boots | -b boot] [--index | --service name]
+//                    [--marker pattern]... [-f logfile]
+//
+// --collapse-cr keeps only the final version of lines that were redrawn
+// with carriage returns, for logs written without bootlogd --collapse-cr.
+//
+// A logfile bootlogd appends to holds several boots. --list-boots shows
+// them, -b picks one: 0 is the latest, -1 the one before and so on, a
+// positive number counts from the oldest, which is 1. Each boot is listed
+// with the time it started and the kernel's boot id, the one in
+// /run/rye-init/boot-id while that boot is running.
+//
+// --index writes an index of the logfile next to it, /var/log/boot.index
+// for /var/log/boot, so tools can jump to a part of a large log without
+// reading all of it. It has the byte offset of every boot, of the first
+// line logged in each second with its time, and of every line saying a
+// service is starting, see bootlogd::format_index. --service shows the
+// output of one service, from its line up to the next service starting,
+// in the latest boot or the one -b picks. It uses the index while that is
+// up to date.
+//
+// The lines saying a service starts are found with patterns, "Starting *"
+// and the like by default, where `*` is the name of the service.
+// --marker replaces them, it can be given once for every pattern.
+
+use std::io::Write;
+
+use rye_init::bootlogd::{self, IndexEntry};
+use rye_init::paths;
+
+const PROGNAME: &str = "readbootlog";
+
+fn usage() -> ! {
+    eprintln!("Usage: {} [--collapse-cr] [--list-boots | -b boot] [--index | --service name] [--marker pattern]... [-f logfile]",
+        PROGNAME);
+    std::process::exit(1);
+}
+
+fn main() {
+    let mut logfile = paths::path(bootlogd::LOGFILE);
+    let mut collapse = false;
+    let mut list = false;
+    let mut boot: Option<i64> = None;
+    let mut write_index = false;
+    let mut service = None;
+    let mut markers: Vec<String> = Vec::new();
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-f" => logfile = args.next().unwrap_or_else(|| usage()),
+            "--collapse-cr" => collapse = true,
+            "--list-boots" => list = true,
+            "-b" => boot = Some(args.next().and_then(|s| s.parse().ok()).unwrap_or_else(|| usage())),
+            "--index" => write_index = true,
+            "--service" => service = Some(args.next().unwrap_or_else(|| usage())),
+            "--marker" => markers.push(args.next().filter(|m| m.contains('*')).unwrap_or_else(|| usage())),
+            _ => usage(),
+        }
+    }
+
+    if write_index && service.is_some() {
+        usage();
+    }
+
+    let data = match std::fs::read(&logfile) {
+        Ok(data) => data,
+        Err(e) => {
+            eprintln!("{}: {}: {}", PROGNAME, logfile, e);
+            std::process::exit(1);
+        }
+    };
+
+    let own_markers = !markers.is_empty();
+    if !own_markers {
+        markers = bootlogd::MARKERS.iter().map(|m| m.to_string()).collect();
+    }
+    let index_file = format!("{}{}", logfile, bootlogd::INDEX_SUFFIX);
+
+    if write_index {
+        let index = bootlogd::index(&data, &markers);
+        if let Err(e) = std::fs::write(&index_file, bootlogd::format_index(data.len(), &index)) {
+            eprintln!("{}: {}: {}", PROGNAME, index_file, e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    let whole = &data[..];
+    let boots = bootlogd::boots(whole);
+    if list {
+        let count = boots.len() as i64;
+        for (n, (time, _)) in boots.iter().enumerate() {
+            let n = n as i64;
+            println!("{:>4} {:>4}  {}", n + 1 - count, n + 1, time.as_deref().unwrap_or("(no separator)"));
+        }
+        return;
+    }
+
+    let data = match boot.or(service.as_ref().map(|_| 0)) {
+        Some(b) => {
+            let index = if b > 0 { b - 1 } else { boots.len() as i64 - 1 + b };
+            match usize::try_from(index).ok().and_then(|i| boots.get(i)) {
+                Some((_, data)) => *data,
+                None => {
+                    eprintln!("{}: {}: no boot {}", PROGNAME, logfile, b);
+                    std::process::exit(1);
+                }
+            }
+        }
+        None => whole,
+    };
+
+    let data = match &service {
+        Some(name) => {
+            // An index written with other markers, or before the log grew, won't do
+            let saved = std::fs::read_to_string(&index_file).ok().and_then(|text| bootlogd::parse_index(&text));
+            let index = match saved {
+                Some((size, index)) if size == whole.len() && !own_markers => index,
+                _ => bootlogd::index(whole, &markers),
+            };
+            match service_output(whole, data, &index, name) {
+                Some(output) => output,
+                None => {
+                    eprintln!("{}: {}: no service {} in this boot", PROGNAME, logfile, name);
+                    std::process::exit(1);
+                }
+            }
+        }
+        None => data,
+    };
+
+    let mut data = bootlogd::strip_escapes(data);
+    if collapse {
+        data = bootlogd::collapse_cr(&data);
+    }
+
+    let _ = std::io::stdout().write_all(&data);
+}
+
+// The output of `service` in `boot`, a part of `whole`: from the last line
+// saying it starts up to the next service or boot
+fn service_output<'a>(whole: &'a [u8], boot: &[u8], index: &[IndexEntry], service: &str) -> Option<&'a [u8]> {
+    let start = boot.as_ptr() as usize - whole.as_ptr() as usize;
+    let end = start + boot.len();
+    let found = index.iter().rposition(|entry| {
+        matches!(entry, IndexEntry::Mark { offset, service: s, .. } if s == service && (start..end).contains(offset))
+    })?;
+    let next = index[found + 1..]
+        .iter()
+        .find(|entry| !matches!(entry, IndexEntry::Time { .. }))
+        .map_or(end, |entry| entry.offset().min(end));
+    whole.get(index[found].offset()..next)
+}
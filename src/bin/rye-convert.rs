@@ -0,0 +1,410 @@
+// rye-convert: turn simple systemd service units, or a BusyBox inittab,
+// into inittab entries.
+//
+// Only what maps cleanly onto inittab is converted: Type=simple and
+// Type=oneshot services with an ExecStart= line. Restart= decides between
+// respawn and once, User=, Group= and KillSignal= become attributes.
+// Everything else in the unit is reported on stderr and left out, so check
+// the warnings before using the result.
+//
+// With --from busybox the files are BusyBox inittabs, "tty::action:process"
+// lines. BusyBox has no runlevels: sysinit stays sysinit, wait and once
+// run at boot (bootwait and boot), respawn entries and an initdefault get
+// the runlevels, and shutdown entries run when entering 0 and 6. askfirst
+// is a respawn entry whose process waits for Enter first. The tty becomes
+// the tty attribute, a "-" in front of a shell makes it a login shell.
+// restart has no counterpart and is left out.
+//
+// Usage: rye-convert [--from systemd|busybox] [-r runlevels] file...
+//
+// -r overrides the runlevels otherwise derived from WantedBy=, or the
+// default 2345 of a BusyBox inittab.
+
+use std::collections::HashSet;
+use std::path::Path;
+
+use rye_init::inittab::{self, Attributes, ParseErrorKind, INITTAB_ID};
+
+const PROGNAME: &str = "rye-convert";
+
+// Runlevels used when the unit has no WantedBy= we know about
+const DEFAULT_RUNLEVELS: &str = "2345";
+
+// Shown by an askfirst entry before its process starts, like BusyBox does
+const ASKFIRST_PROMPT: &str = "Please press Enter to activate this console. ";
+
+// Directives that are harmless to drop
+const IGNORED: &[&str] = &["Documentation", "After", "Before", "Wants", "Requires", "Alias"];
+
+struct Service {
+    description: Option<String>,
+    service_type: String,
+    exec_start: Vec<String>,
+    restart: String,
+    wanted_by: Vec<String>,
+    attrs: Attributes,
+}
+
+fn warn(file: &str, lineno: usize, msg: &str) {
+    eprintln!("{}: {}:{}: {}", PROGNAME, file, lineno, msg);
+}
+
+// Read a unit file. Continuation lines are joined, comments are dropped.
+fn parse_unit(file: &str, content: &str) -> Service {
+    let mut service = Service {
+        description: None,
+        service_type: "simple".to_string(),
+        exec_start: Vec::new(),
+        restart: "no".to_string(),
+        wanted_by: Vec::new(),
+        attrs: Attributes::default(),
+    };
+
+    let mut section = String::new();
+    let mut pending = String::new();
+    let mut start = 0;
+
+    for (n, raw) in content.lines().enumerate() {
+        let line = raw.trim();
+        if pending.is_empty() {
+            start = n + 1;
+            if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+                continue;
+            }
+        }
+
+        if let Some(l) = line.strip_suffix('\\') {
+            pending.push_str(l);
+            pending.push(' ');
+            continue;
+        }
+        pending.push_str(line);
+        let line = std::mem::take(&mut pending);
+
+        if line.starts_with('[') && line.ends_with(']') {
+            section = line[1..line.len() - 1].to_string();
+            continue;
+        }
+
+        let Some((key, value)) = line.split_once('=') else {
+            warn(file, start, "not a directive, ignored");
+            continue;
+        };
+        let (key, value) = (key.trim(), value.trim());
+
+        match (section.as_str(), key) {
+            ("Unit", "Description") => service.description = Some(value.to_string()),
+            ("Service", "Type") => service.service_type = value.to_string(),
+            ("Service", "ExecStart") => {
+                // An empty assignment resets the list
+                if value.is_empty() {
+                    service.exec_start.clear();
+                } else {
+                    service.exec_start.push(value.to_string());
+                }
+            }
+            ("Service", "Restart") => service.restart = value.to_string(),
+            ("Service", "User") => service.attrs.user = Some(value.to_string()),
+            ("Service", "Group") => service.attrs.group = Some(value.to_string()),
+            ("Service", "KillSignal") if inittab::parse_signal(value).is_some() => {
+                service.attrs.stopsig = Some(value.to_string())
+            }
+            ("Install", "WantedBy") => service.wanted_by.extend(value.split_whitespace().map(|s| s.to_string())),
+            (_, key) if IGNORED.contains(&key) => {}
+            (section, key) => warn(file, start, &format!("{}={} in [{}] is not supported, ignored", key, value, section)),
+        }
+    }
+
+    service
+}
+
+// ExecStart= may start with prefixes that change how systemd runs the
+// command. We can't do any of that, but "-" (ignore failure) is harmless.
+fn strip_exec_prefix(file: &str, cmd: &str) -> String {
+    let prefixes = cmd.len() - cmd.trim_start_matches(['-', '@', ':', '+', '!']).len();
+    if cmd[..prefixes].chars().any(|c| c != '-') {
+        eprintln!("{}: {}: ExecStart= prefix \"{}\" is not supported, dropped", PROGNAME, file, &cmd[..prefixes]);
+    }
+    cmd[prefixes..].to_string()
+}
+
+fn runlevels(file: &str, wanted_by: &[String]) -> String {
+    let mut levels = String::new();
+    for target in wanted_by {
+        let add = match target.as_str() {
+            "multi-user.target" => "2345",
+            "graphical.target" => "5",
+            "rescue.target" => "1",
+            _ => {
+                eprintln!("{}: {}: WantedBy={} has no runlevel, ignored", PROGNAME, file, target);
+                ""
+            }
+        };
+        for c in add.chars() {
+            if !levels.contains(c) {
+                levels.push(c);
+            }
+        }
+    }
+
+    if levels.is_empty() {
+        DEFAULT_RUNLEVELS.to_string()
+    } else {
+        levels
+    }
+}
+
+// Inittab ids are short, so take the unit name and cut it down until it
+// doesn't clash with one we already used
+fn make_id(name: &str, used: &mut HashSet<String>) -> String {
+    let base: String = name
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .take(INITTAB_ID)
+        .collect();
+    let base = if base.is_empty() { "svc".to_string() } else { base };
+
+    let mut id = base.clone();
+    let mut n = 1;
+    while used.contains(&id) {
+        let suffix = n.to_string();
+        let keep = base.len().min(INITTAB_ID - suffix.len());
+        id = format!("{}{}", &base[..keep], suffix);
+        n += 1;
+    }
+
+    used.insert(id.clone());
+    id
+}
+
+// Why a line we made up is not a valid entry
+fn describe(kind: ParseErrorKind) -> String {
+    match kind {
+        ParseErrorKind::FieldCount => "malformed entry".to_string(),
+        ParseErrorKind::TooLong(field) => format!("{} field too long", field),
+        ParseErrorKind::UnknownAction(a) => format!("unknown action {}", a),
+        ParseErrorKind::BadAttribute(a) => format!("bad attribute {}", a),
+        ParseErrorKind::BadDelay(d) => format!("bad delay {}", d),
+        ParseErrorKind::BadIdRange(id) => format!("bad id range {}", id),
+        ParseErrorKind::DuplicateId { id, .. } => format!("duplicate id {}", id),
+        ParseErrorKind::BadInclude { path, .. } => format!("bad include {}", path),
+        ParseErrorKind::LegacyAction { name, .. } => format!("unsupported action {}", name),
+        ParseErrorKind::LongProcess { limit, .. } => format!("process field longer than {} bytes", limit),
+    }
+}
+
+fn convert(file: &str, levels: Option<&str>, used: &mut HashSet<String>) -> Option<String> {
+    let content = match std::fs::read_to_string(file) {
+        Ok(c) => c,
+        Err(e) => {
+            eprintln!("{}: {}: {}", PROGNAME, file, e);
+            return None;
+        }
+    };
+
+    let service = parse_unit(file, &content);
+
+    if service.exec_start.is_empty() {
+        eprintln!("{}: {}: no ExecStart=, skipped", PROGNAME, file);
+        return None;
+    }
+
+    let action = match (service.service_type.as_str(), service.restart.as_str()) {
+        ("simple", "no") | ("oneshot", _) => "once",
+        ("simple", _) => "respawn",
+        (t, _) => {
+            eprintln!("{}: {}: Type={} is not supported, skipped", PROGNAME, file, t);
+            return None;
+        }
+    };
+
+    if service.service_type == "oneshot" && service.restart != "no" {
+        eprintln!("{}: {}: Restart= is ignored for Type=oneshot", PROGNAME, file);
+    }
+
+    if service.service_type == "simple" && service.exec_start.len() > 1 {
+        eprintln!("{}: {}: Type=simple takes a single ExecStart=, skipped", PROGNAME, file);
+        return None;
+    }
+
+    // Several ExecStart= of a oneshot service run one after the other, a
+    // failing one stops the rest unless it has the "-" prefix
+    let mut process = String::new();
+    for (n, cmd) in service.exec_start.iter().enumerate() {
+        if n > 0 {
+            let prev = &service.exec_start[n - 1];
+            process.push_str(if prev.starts_with('-') { "; " } else { " && " });
+        }
+        process.push_str(&strip_exec_prefix(file, cmd));
+    }
+
+    let name = Path::new(file)
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let name = name.strip_suffix(".service").unwrap_or(&name);
+
+    let id = make_id(name, used);
+    let levels = match levels {
+        Some(l) => l.to_string(),
+        None => runlevels(file, &service.wanted_by),
+    };
+    let process = if service.attrs.is_empty() {
+        process
+    } else {
+        format!("{} {}", service.attrs, process)
+    };
+    let line = format!("{}:{}:{}:{}", id, levels, action, process);
+
+    // Whatever we print must be something init accepts
+    if let Err(e) = inittab::parse_line(&line, 1) {
+        eprintln!("{}: {}: can't be expressed in inittab ({}), skipped", PROGNAME, file, describe(e.kind));
+        used.remove(&id);
+        return None;
+    }
+
+    let mut out = String::new();
+    out.push_str(&format!("# {}", name));
+    if let Some(desc) = service.description {
+        out.push_str(&format!(": {}", desc));
+    }
+    out.push('\n');
+    out.push_str(&line);
+    out.push('\n');
+    Some(out)
+}
+
+// Quote a string for sh
+fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "'\\''"))
+}
+
+// The process field of a BusyBox entry. A leading "-" asks for a login
+// shell, which -l gives us for the shells we know.
+fn busybox_process(file: &str, lineno: usize, process: &str) -> String {
+    let Some(process) = process.strip_prefix('-') else {
+        return process.to_string();
+    };
+    let (prog, args) = process.split_once(char::is_whitespace).unwrap_or((process, ""));
+    if prog.ends_with("sh") {
+        format!("{} -l {}", prog, args).trim_end().to_string()
+    } else {
+        warn(file, lineno, &format!("\"-\" in front of {} has no counterpart, dropped", prog));
+        process.to_string()
+    }
+}
+
+// Convert a BusyBox inittab
+fn convert_busybox(file: &str, levels: Option<&str>, used: &mut HashSet<String>) -> Option<String> {
+    let content = match std::fs::read_to_string(file) {
+        Ok(c) => c,
+        Err(e) => {
+            eprintln!("{}: {}: {}", PROGNAME, file, e);
+            return None;
+        }
+    };
+    let levels = levels.unwrap_or(DEFAULT_RUNLEVELS);
+
+    let mut out = format!("# Converted from the BusyBox inittab {}\n", file);
+    let id = make_id("id", used);
+    out.push_str(&format!("{}:{}:initdefault:\n", id, &levels[..levels.len().min(1)]));
+
+    for (n, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fields: Vec<&str> = line.splitn(4, ':').collect();
+        let [tty, _, action, process] = fields[..] else {
+            warn(file, n + 1, "not a BusyBox inittab entry, ignored");
+            continue;
+        };
+        if process.trim().is_empty() {
+            warn(file, n + 1, "entry without a process, ignored");
+            continue;
+        }
+
+        let mut process = busybox_process(file, n + 1, process.trim());
+        let (new_action, runlevels) = match action {
+            "sysinit" => ("sysinit", ""),
+            "wait" => ("bootwait", ""),
+            "once" => ("boot", ""),
+            "respawn" => ("respawn", levels),
+            "askfirst" => {
+                process = format!("/bin/sh -c {}", shell_quote(&format!(
+                    "printf '\\n{}'; read reply; exec {}", ASKFIRST_PROMPT, process)));
+                ("respawn", levels)
+            }
+            "ctrlaltdel" => ("ctrlaltdel", ""),
+            "shutdown" => ("wait", "06"),
+            other => {
+                warn(file, n + 1, &format!("action {} has no counterpart, ignored", other));
+                continue;
+            }
+        };
+
+        let mut attrs = Attributes::default();
+        let tty = tty.trim().trim_start_matches("/dev/");
+        if !tty.is_empty() {
+            attrs.tty = Some(tty.to_string());
+        }
+        let id = make_id(if tty.is_empty() { action } else { tty }, used);
+        let process = if attrs.is_empty() { process } else { format!("{} {}", attrs, process) };
+        let entry = format!("{}:{}:{}:{}", id, runlevels, new_action, process);
+
+        if let Err(e) = inittab::parse_line(&entry, 1) {
+            warn(file, n + 1, &format!("can't be expressed in inittab ({}), skipped", describe(e.kind)));
+            used.remove(&id);
+            continue;
+        }
+        out.push_str(&entry);
+        out.push('\n');
+    }
+    Some(out)
+}
+
+fn usage() -> ! {
+    eprintln!("Usage: {} [--from systemd|busybox] [-r runlevels] file...", PROGNAME);
+    std::process::exit(1);
+}
+
+fn main() {
+    let mut files = Vec::new();
+    let mut args = std::env::args().skip(1);
+    let mut levels: Option<String> = None;
+    let mut busybox = false;
+
+    while let Some(a) = args.next() {
+        match a.as_str() {
+            "-r" => levels = Some(args.next().unwrap_or_else(|| usage())),
+            "--from" => match args.next().as_deref() {
+                Some("systemd") => busybox = false,
+                Some("busybox") => busybox = true,
+                _ => usage(),
+            },
+            _ if a.starts_with('-') => usage(),
+            _ => files.push(a),
+        }
+    }
+
+    if files.is_empty() {
+        usage();
+    }
+
+    let mut used = HashSet::new();
+    let mut failed = false;
+
+    for file in &files {
+        let converted = if busybox {
+            convert_busybox(file, levels.as_deref(), &mut used)
+        } else {
+            convert(file, levels.as_deref(), &mut used)
+        };
+        match converted {
+            Some(out) => print!("{}", out),
+            None => failed = true,
+        }
+    }
+
+    std::process::exit(if failed { 1 } else { 0 });
+}
@@ -0,0 +1,287 @@
+// rye-startpar: run rc scripts in parallel.
+//
+// Scripts are started as soon as every script they name in the
+// Required-Start field of their LSB header has finished. The output of each
+// script is buffered and printed in one piece when it is done, so the
+// console doesn't end up with the output of several scripts interleaved.
+//
+// Usage: rye-startpar [-a arg] [-p par] [-t timeout] [-d dir] [script...]
+
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::os::fd::OwnedFd;
+use std::path::Path;
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const PROGNAME: &str = "rye-startpar";
+
+// How often we look at the running scripts
+const POLL_INTERVAL: u64 = 50;
+
+// Seconds to wait for the output of a script that has exited
+const OUTPUT_WAIT: u64 = 1;
+
+struct Script {
+    path: String,
+    provides: Vec<String>,
+    requires: Vec<String>,
+}
+
+// Parse the LSB header of an init script. Scripts without one provide
+// their own name and require nothing.
+fn parse_lsb_header(path: &str) -> Script {
+    let name = Path::new(path)
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    // S20foo provides foo
+    let base = name.trim_start_matches(|c: char| c == 'S' || c == 'K' || c.is_ascii_digit());
+
+    let mut script = Script {
+        path: path.to_string(),
+        provides: Vec::new(),
+        requires: Vec::new(),
+    };
+
+    if let Ok(f) = File::open(path) {
+        let mut inside = false;
+        for line in BufReader::new(f).lines().map_while(Result::ok) {
+            let line = line.trim();
+            if line.starts_with("### BEGIN INIT INFO") {
+                inside = true;
+            } else if line.starts_with("### END INIT INFO") {
+                break;
+            } else if inside {
+                let line = line.trim_start_matches('#').trim();
+                if let Some((key, value)) = line.split_once(':') {
+                    let words = value.split_whitespace().map(|s| s.to_string());
+                    match key.trim() {
+                        "Provides" => script.provides.extend(words),
+                        "Required-Start" => script.requires.extend(words),
+                        _ => {}
+                    }
+                }
+            }
+        }
+    }
+
+    if script.provides.is_empty() {
+        script.provides.push(base.to_string());
+    }
+
+    script
+}
+
+// All start scripts (S??*) in an rc directory, in name order
+fn scripts_in_dir(dir: &str) -> Vec<String> {
+    let mut scripts: Vec<String> = match std::fs::read_dir(dir) {
+        Ok(entries) => entries
+            .flatten()
+            .filter(|e| e.file_name().to_string_lossy().starts_with('S'))
+            .map(|e| e.path().to_string_lossy().into_owned())
+            .collect(),
+        Err(e) => {
+            eprintln!("{}: {}: {}", PROGNAME, dir, e);
+            std::process::exit(1);
+        }
+    };
+    scripts.sort();
+    scripts
+}
+
+// Start a script with stdout and stderr going into a single pipe. A reader
+// thread collects the output and hands it over once the pipe is closed.
+fn start_script(script: &Script, arg: &str, idx: usize, tx: &mpsc::Sender<(usize, Vec<u8>)>) -> Option<Child> {
+    let (rd, wr): (OwnedFd, OwnedFd) = match nix::unistd::pipe() {
+        Ok(p) => p,
+        Err(e) => {
+            eprintln!("{}: pipe: {}", PROGNAME, e);
+            return None;
+        }
+    };
+
+    let wr2 = match wr.try_clone() {
+        Ok(fd) => fd,
+        Err(e) => {
+            eprintln!("{}: dup: {}", PROGNAME, e);
+            return None;
+        }
+    };
+
+    let child = Command::new(&script.path)
+        .arg(arg)
+        .stdin(Stdio::null())
+        .stdout(Stdio::from(wr))
+        .stderr(Stdio::from(wr2))
+        .spawn();
+
+    let child = match child {
+        Ok(child) => child,
+        Err(e) => {
+            eprintln!("{}: {}: {}", PROGNAME, script.path, e);
+            return None;
+        }
+    };
+
+    let tx = tx.clone();
+    thread::spawn(move || {
+        let mut output = Vec::new();
+        let _ = File::from(rd).read_to_end(&mut output);
+        let _ = tx.send((idx, output));
+    });
+
+    Some(child)
+}
+
+fn usage() -> ! {
+    eprintln!("Usage: {} [-a arg] [-p par] [-t timeout] [-d dir] [script...]", PROGNAME);
+    std::process::exit(1);
+}
+
+fn main() {
+    let mut arg = "start".to_string();
+    let mut par: usize = 0;
+    let mut timeout: u64 = 0;
+    let mut paths = Vec::new();
+
+    let mut args = std::env::args().skip(1);
+    while let Some(a) = args.next() {
+        match a.as_str() {
+            "-a" => arg = args.next().unwrap_or_else(|| usage()),
+            "-p" => par = args.next().and_then(|v| v.parse().ok()).unwrap_or_else(|| usage()),
+            "-t" => timeout = args.next().and_then(|v| v.parse().ok()).unwrap_or_else(|| usage()),
+            "-d" => paths.extend(scripts_in_dir(&args.next().unwrap_or_else(|| usage()))),
+            _ if a.starts_with('-') => usage(),
+            _ => paths.push(a),
+        }
+    }
+
+    let scripts: Vec<Script> = paths.iter().map(|p| parse_lsb_header(p)).collect();
+
+    // Which of our scripts provides a facility. Requirements nobody in this
+    // set provides ($local_fs and friends) are assumed to be satisfied.
+    let mut providers: HashMap<&str, Vec<usize>> = HashMap::new();
+    for (idx, script) in scripts.iter().enumerate() {
+        for p in &script.provides {
+            providers.entry(p.as_str()).or_default().push(idx);
+        }
+    }
+
+    let deps: Vec<HashSet<usize>> = scripts
+        .iter()
+        .enumerate()
+        .map(|(idx, script)| {
+            script.requires.iter()
+                .filter_map(|r| providers.get(r.as_str()))
+                .flatten()
+                .copied()
+                .filter(|&d| d != idx)
+                .collect()
+        })
+        .collect();
+
+    let deadline = (timeout > 0).then(|| Instant::now() + Duration::from_secs(timeout));
+    let (tx, rx) = mpsc::channel();
+
+    let mut started = vec![false; scripts.len()];
+    let mut finished = vec![false; scripts.len()];
+    let mut output: Vec<Option<Vec<u8>>> = vec![None; scripts.len()];
+    let mut running: Vec<(usize, Child)> = Vec::new();
+    let mut failed = false;
+    let mut timed_out = false;
+
+    loop {
+        // Start whatever is ready
+        for idx in 0..scripts.len() {
+            if par > 0 && running.len() >= par {
+                break;
+            }
+            if started[idx] || !deps[idx].iter().all(|&d| finished[d]) {
+                continue;
+            }
+
+            started[idx] = true;
+            match start_script(&scripts[idx], &arg, idx, &tx) {
+                Some(child) => running.push((idx, child)),
+                None => {
+                    finished[idx] = true;
+                    failed = true;
+                }
+            }
+        }
+
+        while let Ok((idx, out)) = rx.try_recv() {
+            output[idx] = Some(out);
+        }
+
+        // Collect the scripts that are done and print their output
+        let mut n = 0;
+        while n < running.len() {
+            let idx = running[n].0;
+            match running[n].1.try_wait() {
+                Ok(Some(status)) => {
+                    // The reader may still be draining the pipe. Don't wait
+                    // forever, a daemon started by the script may hold it open.
+                    while output[idx].is_none() {
+                        match rx.recv_timeout(Duration::from_secs(OUTPUT_WAIT)) {
+                            Ok((i, out)) => output[i] = Some(out),
+                            Err(_) => break,
+                        }
+                    }
+                    if let Some(out) = output[idx].take() {
+                        let _ = std::io::stdout().write_all(&out);
+                        let _ = std::io::stdout().flush();
+                    }
+                    if !status.success() {
+                        failed = true;
+                    }
+                    finished[idx] = true;
+                    running.remove(n);
+                }
+                Ok(None) => n += 1,
+                Err(_) => {
+                    finished[idx] = true;
+                    failed = true;
+                    running.remove(n);
+                }
+            }
+        }
+
+        if finished.iter().all(|&f| f) {
+            break;
+        }
+
+        // Scripts whose requirements can never finish (a dependency loop)
+        if running.is_empty() && !(0..scripts.len()).any(|i| !started[i] && deps[i].iter().all(|&d| finished[d])) {
+            for (idx, script) in scripts.iter().enumerate() {
+                if !started[idx] {
+                    eprintln!("{}: {}: unresolvable dependencies, not started", PROGNAME, script.path);
+                }
+            }
+            failed = true;
+            break;
+        }
+
+        if deadline.is_some_and(|d| Instant::now() >= d) {
+            for (idx, child) in running.iter_mut() {
+                eprintln!("{}: {}: timeout, killing", PROGNAME, scripts[*idx].path);
+                let _ = child.kill();
+                let _ = child.wait();
+            }
+            timed_out = true;
+            break;
+        }
+
+        thread::sleep(Duration::from_millis(POLL_INTERVAL));
+    }
+
+    if timed_out {
+        std::process::exit(2);
+    }
+    std::process::exit(if failed { 1 } else { 0 });
+}
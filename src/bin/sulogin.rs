@@ -0,0 +1,214 @@
+// sulogin: single user login. Asks for the root password before giving
+// out a maintenance shell, Control-D continues the boot instead.
+//
+// Usage: sulogin [-e] [-t timeout] [tty]
+//
+// -e, --force  when the root password can't be checked, because the
+//              password files can't be read or the root entry is missing,
+//              locked or broken, give out the shell anyway after a warning.
+//              Without it sulogin refuses and the boot continues. This is
+//              for exactly the case sulogin exists for: a damaged root
+//              filesystem.
+// -t timeout   continue the boot if nothing was typed for this many seconds
+//
+// The shell gets a clean environment: HOME=/root, a fixed PATH, TERM if we
+// got one and nothing else. It is not a login shell and ENV is unset, so no
+// profile or rc file is read, a broken one can't get in the way of the
+// repair. The tty becomes its controlling terminal with job control, and
+// the signals init or a boot script may have ignored are back to default.
+
+use std::ffi::{CStr, CString};
+use std::io::{self, Write};
+use std::os::fd::{AsFd, AsRawFd};
+use std::os::unix::process::CommandExt;
+use std::process::Command;
+use std::time::Duration;
+
+use nix::sys::signal::{self, SigHandler, SigSet, SigmaskHow, Signal};
+
+use rye_init::tty;
+
+const PROGNAME: &str = "sulogin";
+
+const SHADOW: &str = "/etc/shadow";
+const PASSWD: &str = "/etc/passwd";
+const SHELL: &str = "/bin/sh";
+const HOME: &str = "/root";
+const PATH: &str = "/sbin:/usr/sbin:/bin:/usr/bin";
+
+// Signals the shell needs at their defaults for job control and ^C
+const DEFAULT_SIGNALS: &[Signal] = &[
+    Signal::SIGINT, Signal::SIGQUIT, Signal::SIGTSTP, Signal::SIGTTIN, Signal::SIGTTOU, Signal::SIGHUP,
+    Signal::SIGCHLD,
+];
+
+#[link(name = "crypt")]
+unsafe extern "C" {
+    fn crypt(key: *const libc::c_char, salt: *const libc::c_char) -> *mut libc::c_char;
+}
+
+// Why the root password can't be checked
+enum NoHash {
+    Unreadable(String),
+    Missing,
+    Locked,
+    Invalid,
+}
+
+fn usage() -> ! {
+    eprintln!("Usage: {} [-e] [-t timeout] [tty]", PROGNAME);
+    std::process::exit(1);
+}
+
+// The password field of root in a passwd style file
+fn root_field(path: &str, field: usize) -> Result<Option<String>, String> {
+    let content = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+    Ok(content
+        .lines()
+        .find(|l| l.split(':').next() == Some("root"))
+        .and_then(|l| l.split(':').nth(field))
+        .map(str::to_string))
+}
+
+// Root's password hash. An empty hash means no password.
+fn root_hash() -> Result<String, NoHash> {
+    let hash = match root_field(PASSWD, 1).map_err(NoHash::Unreadable)? {
+        Some(h) if h == "x" => root_field(SHADOW, 1).map_err(NoHash::Unreadable)?.ok_or(NoHash::Missing)?,
+        Some(h) => h,
+        None => return Err(NoHash::Missing),
+    };
+
+    if hash.starts_with('!') {
+        Err(NoHash::Locked)
+    } else if hash.is_empty() || hash.starts_with('$') || hash.len() == 13 {
+        Ok(hash)
+    } else {
+        Err(NoHash::Invalid)
+    }
+}
+
+fn check_password(password: &str, hash: &str) -> bool {
+    let (Ok(key), Ok(salt)) = (CString::new(password), CString::new(hash)) else {
+        return false;
+    };
+    let out = unsafe { crypt(key.as_ptr(), salt.as_ptr()) };
+    if out.is_null() {
+        return false;
+    }
+    unsafe { CStr::from_ptr(out) }.to_bytes() == hash.as_bytes()
+}
+
+// Read a line without echo, see tty::read_line. None on Control-D or
+// timeout.
+fn read_password(timeout: Option<u16>) -> Option<String> {
+    let timeout = timeout.map(|secs| Duration::from_secs(secs as u64));
+    tty::read_line(io::stdin(), timeout, false)
+}
+
+// Make tty our stdin, stdout and stderr. Anybody else who has it open is
+// hung up first.
+fn use_tty(dev: &str) -> io::Result<()> {
+    let f = tty::open(dev)?;
+    if tty::make_controlling(&f).is_ok() {
+        let _ = tty::vhangup();
+    }
+    let f = tty::open(dev)?;
+    let _ = tty::make_controlling(&f);
+    for fd in 0..3 {
+        if unsafe { libc::dup2(f.as_raw_fd(), fd) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+fn reset_signals() {
+    for &sig in DEFAULT_SIGNALS {
+        let _ = unsafe { signal::signal(sig, SigHandler::SigDfl) };
+    }
+    let _ = signal::sigprocmask(SigmaskHow::SIG_SETMASK, Some(&SigSet::empty()), None);
+}
+
+fn shell() -> ! {
+    println!("Entering maintenance mode");
+    // The shell needs the tty as its controlling terminal for job control
+    let _ = tty::make_controlling(io::stdin().as_fd());
+    reset_signals();
+
+    let home = if std::path::Path::new(HOME).is_dir() { HOME } else { "/" };
+    let mut cmd = Command::new(SHELL);
+    cmd.arg0("sh")
+        .env_clear()
+        .env("HOME", home)
+        .env("PATH", PATH)
+        .env("SHELL", SHELL)
+        .env("USER", "root")
+        .env("LOGNAME", "root")
+        .current_dir(home);
+    if let Some(term) = std::env::var_os("TERM") {
+        cmd.env("TERM", term);
+    }
+    let err = cmd.exec();
+    eprintln!("{}: cannot run {}: {}", PROGNAME, SHELL, err);
+    std::process::exit(1);
+}
+
+fn main() {
+    let mut force = false;
+    let mut timeout = None;
+    let mut tty = None;
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-e" | "--force" => force = true,
+            "-t" => timeout = Some(args.next().and_then(|s| s.parse().ok()).unwrap_or_else(|| usage())),
+            _ if arg.starts_with('-') => usage(),
+            _ if tty.is_none() => tty = Some(arg),
+            _ => usage(),
+        }
+    }
+
+    if let Some(tty) = &tty && let Err(e) = use_tty(tty) {
+        eprintln!("{}: {}: {}", PROGNAME, tty, e);
+        std::process::exit(1);
+    }
+
+    let hash = match root_hash() {
+        Ok(hash) => hash,
+        Err(why) => {
+            let why = match why {
+                NoHash::Unreadable(e) => format!("cannot read the password database ({})", e),
+                NoHash::Missing => "there is no root account".to_string(),
+                NoHash::Locked => "the root account is locked".to_string(),
+                NoHash::Invalid => "the root password entry is broken".to_string(),
+            };
+            if !force {
+                eprintln!("{}: {}, continuing the boot", PROGNAME, why);
+                std::process::exit(1);
+            }
+            eprintln!();
+            eprintln!("*** WARNING: {} ***", why);
+            eprintln!("*** giving out a root shell WITHOUT a password (--force) ***");
+            eprintln!();
+            shell();
+        }
+    };
+
+    loop {
+        if hash.is_empty() {
+            print!("Press Enter for maintenance\n(or press Control-D to continue): ");
+        } else {
+            print!("Give root password for maintenance\n(or press Control-D to continue): ");
+        }
+        let _ = io::stdout().flush();
+
+        let Some(password) = read_password(timeout) else {
+            std::process::exit(0);
+        };
+        if hash.is_empty() || check_password(&password, &hash) {
+            shell();
+        }
+        println!("Login incorrect\n");
+    }
+}
@@ -0,0 +1,63 @@
+// The environment init was started with
+//
+// Whatever the bootloader or the initramfs left in our environment would
+// otherwise end up in every process init starts. At boot everything that
+// isn't on the allowlist is removed, the rest is handed to the children.
+//
+// The allowlist is read from ALLOW_FILE, one name per line; a trailing '*'
+// matches any name starting with what comes before it. Variables given on
+// the kernel command line as rye_init.NAME=value are picked up as well,
+// the kernel doesn't pass dotted parameters on to init.
+
+const ALLOW_FILE: &str = "/etc/rye-init/environment.allow";
+const DEFAULT_ALLOW: &[&str] = &["TERM", "CONSOLE", "rye_init.*"];
+const CMDLINE_PREFIX: &str = "rye_init.";
+
+fn allowed(name: &str, allow: &[String]) -> bool {
+    allow.iter().any(|pat| match pat.strip_suffix('*') {
+        Some(prefix) => name.starts_with(prefix),
+        None => name == pat,
+    })
+}
+
+pub fn read_allowlist() -> Vec<String> {
+    match std::fs::read_to_string(rye_init::paths::path(ALLOW_FILE)) {
+        Ok(s) => s
+            .lines()
+            .map(|l| l.trim())
+            .filter(|l| !l.is_empty() && !l.starts_with('#'))
+            .map(|l| l.to_string())
+            .collect(),
+        Err(_) => DEFAULT_ALLOW.iter().map(|s| s.to_string()).collect(),
+    }
+}
+
+// Remove everything not on the allowlist from our environment and return
+// what is left, plus the allowed rye_init.* variables from the kernel
+// command line, as NAME=value strings. Must run before any threads exist.
+pub fn scrub(allow: &[String], cmdline: &str) -> Vec<String> {
+    let mut kept = Vec::new();
+
+    for (name, value) in std::env::vars_os() {
+        let (Some(n), Some(v)) = (name.to_str(), value.to_str()) else {
+            unsafe { std::env::remove_var(&name) };
+            continue;
+        };
+
+        if allowed(n, allow) {
+            kept.push(format!("{}={}", n, v));
+        } else {
+            unsafe { std::env::remove_var(&name) };
+        }
+    }
+
+    for word in cmdline.split_whitespace() {
+        if let Some((name, _)) = word.split_once('=')
+            && name.starts_with(CMDLINE_PREFIX) && allowed(name, allow)
+            && !kept.iter().any(|k| k.split_once('=').map(|(n, _)| n) == Some(name)) {
+            kept.push(word.to_string());
+        }
+    }
+
+    kept
+}
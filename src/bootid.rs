@@ -0,0 +1,17 @@
+//! The ids of this boot and this machine.
+//!
+//! Init leaves them in [`BOOT_ID_FILE`] at boot and bootlogd marks each
+//! capture session in its log with the boot id, so logs collected off the
+//! box can be matched up. Both only read them here, which keeps init free
+//! of bootlogd in builds without it.
+
+pub const BOOT_ID_FILE: &str = "/run/rye-init/boot-id"; // Boot and machine id of this boot, written by init
+pub const KERNEL_BOOT_ID: &str = "/proc/sys/kernel/random/boot_id";
+pub const MACHINE_ID: &str = "/etc/machine-id";
+
+/// The kernel's id of this boot, the same for everything that runs until
+/// the next one.
+pub fn boot_id() -> Option<String> {
+    let id = std::fs::read_to_string(KERNEL_BOOT_ID).ok()?;
+    Some(id.trim().to_string()).filter(|id| !id.is_empty())
+}
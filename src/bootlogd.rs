@@ -25,21 +25,45 @@
  *
  */
 use libc;
-use std::io::Write;
-use std::fs::File;
+use std::collections::VecDeque;
+use std::io::{Read, Write};
+use std::fs::{File, OpenOptions};
 use std::io;
-use std::os::fd::AsRawFd;
+use std::os::fd::{AsFd, AsRawFd};
+use std::os::unix::fs::MetadataExt;
 use std::sync::atomic::{AtomicI32, Ordering};
-use std::time::{SystemTime, UNIX_EPOCH};
-use crate::GOT_SIGNALS;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use nix::fcntl::{fcntl, FcntlArg, OFlag};
+use nix::poll::{poll, PollFd, PollFlags, PollTimeout};
+use crate::bootid::boot_id;
+use crate::sys::{Kernel, Sys};
 
 const MAX_CONSOLES: i8 = 16;
-const KERNEL_COMMAND_LENGTH: i16 = 4096;
-const LOGFILE: &str = "/var/log/boot";
-const PATH_MAX: i16 = 2048;
-const RINGBUF_SIZE: usize = 32768;
+pub const LOGFILE: &str = "/var/log/boot";
+pub const BOOT_DONE: &str = "/run/rye-init/boot-done"; // Created when boot has finished
+pub const RINGBUF_SIZE: usize = 32768; // Default size of the ring buffer
+const READ_SIZE: usize = 1024;
+const POLL_TIMEOUT: u16 = 5000; // Retry opening the logfile this often (ms)
+const LOG_RETRY: Duration = Duration::from_secs(2); // Wait this long after the logfile failed us
+const MAX_PENDING: usize = 256 * 1024; // Log kept in memory while the logfile can't take it
+pub const CONSOLE_QUEUE: usize = 16384; // Default output a console may be behind by
+const WRITE_TIMEOUT: Duration = Duration::from_secs(2); // A console taking nothing this long failed a write
+const WRITE_POLL: u16 = 200; // Look at consoles that are behind this often (ms)
+pub const MAX_CONSOLE_FAILURES: u32 = 3; // Default failed writes in a row before a console is given up
+const INIT_ROOT: &str = "/proc/1/root"; // The root init runs in
+pub const SEPARATOR_START: &str = "---- boot "; // Starts the line that begins a capture session
+const SEPARATOR_END: &str = " ----";
+pub const INDEX_SUFFIX: &str = ".index"; // Appended to the logfile for readbootlog --index
+const STAMP_START: &str = "SystemTime { tv_sec: "; // How write_log starts a line
+const STAMP_END: &str = " }: ";
+
+// Lines that say a service is starting, `*` is its name
+pub const MARKERS: &[&str] = &["Starting *...", "Starting *:", "Starting *"];
+
+// Splash daemons that take over the console themselves
+const SPLASH_DAEMONS: &[&str] = &["plymouthd", "splashy", "usplash", "fbsplashd"];
 static GOT_SIGNAL: AtomicI32 = AtomicI32::new(0);
-fn set_signal(signal: i32) {
+pub fn set_signal(signal: i32) {
     GOT_SIGNAL.store(signal, Ordering::SeqCst);
 }
 
@@ -47,92 +71,95 @@ fn get_signal() -> bool {
     GOT_SIGNAL.load(Ordering::SeqCst) != 0
 }
 
+// The console output that hasn't been formatted for the log yet. It is
+// kept as where the oldest byte is and how many there are, so an empty
+// buffer and a full one can't be confused.
 struct RingBuf {
-    buf: [u8; RINGBUF_SIZE],
-    in_idx: usize,
-    out_idx: usize,
+    buf: Box<[u8]>,
+    start: usize,                       // Index of the oldest byte
+    len: usize,                         // Bytes held
 }
 
 impl RingBuf {
-    fn new() -> Self {
+    fn new(size: usize) -> Self {
         Self {
-            buf: *Box::new([0u8; RINGBUF_SIZE]),
-            in_idx: 0,
-            out_idx: 0,
+            buf: vec![0u8; size.max(1)].into_boxed_slice(),
+            start: 0,
+            len: 0,
         }
     }
 
-    // Write up to data.len bytes into the ring buffer starting at in_idx
-    // Returns the number of bytes written
-    fn push(&mut self, data: &[u8]) -> usize {
-        let written: usize;
-        let space: usize = if self.in_idx >= self.out_idx {
-            RINGBUF_SIZE - self.in_idx
-        } else {
-            self.out_idx - self.in_idx
-        };
+    fn capacity(&self) -> usize {
+        self.buf.len()
+    }
 
-        let to_write = std::cmp::min(data.len(), space);
+    fn is_full(&self) -> bool {
+        self.len == self.capacity()
+    }
 
-        if to_write == 0 {
-            return 0;
+    // Copy as much of data as fits, wrapping around the end of the buffer.
+    // Returns the number of bytes written. Unlike the original bootlogd.c
+    // nothing that hasn't been read is overwritten.
+    fn push(&mut self, data: &[u8]) -> usize {
+        let mut written = 0;
+        while written < data.len() && !self.is_full() {
+            let end = (self.start + self.len) % self.capacity();
+            let space = if end >= self.start { self.capacity() - end } else { self.start - end };
+            let n = space.min(data.len() - written);
+            self.buf[end..end + n].copy_from_slice(&data[written..written + n]);
+            self.len += n;
+            written += n;
         }
-
-        // This is not one-to-one with how the original bootlogd.c did it. In there,
-        // inptr could move outptr if it crossed it. Here we don't wrap, but there may
-        // be reason to implement exact behaviour in the future.
-
-        self.buf[self.in_idx..self.in_idx + to_write].copy_from_slice(&data[..to_write]);
-        self.in_idx = (self.in_idx + to_write) % RINGBUF_SIZE;
-        written = to_write;
-
         written
     }
 
-    // Get a continuous slice of available data starting at out_idx
+    // The oldest data that is contiguous in the buffer
     fn get_slice(&self) -> &[u8] {
-        if self.out_idx <= self.in_idx {
-            &self.buf[self.out_idx..self.in_idx]
-        } else {
-            &self.buf[self.out_idx..RINGBUF_SIZE]
-        }
+        let end = (self.start + self.len).min(self.capacity());
+        &self.buf[self.start..end]
     }
 
-    // Advance the outside pointer by length wrapping around at ring buffer size
+    // Drop the oldest length bytes, which have been read
     fn advance_out(&mut self, length: usize) {
-        self.out_idx = (self.out_idx + length) % RINGBUF_SIZE;
+        let length = length.min(self.len);
+        self.start = (self.start + length) % self.capacity();
+        self.len -= length;
+        if self.len == 0 {
+            self.start = 0;
+        }
     }
 
     fn available(&self) -> usize {
-        if self.in_idx >= self.out_idx {
-            self.in_idx - self.out_idx
-        } else {
-            RINGBUF_SIZE - self.in_idx + self.out_idx
-        }
+        self.len
     }
 }
 
+// Device and inode of a directory
+fn dir_id(path: &str) -> Option<(u64, u64)> {
+    let md = std::fs::metadata(path).ok()?;
+    Some((md.dev(), md.ino()))
+}
+
+// Format console output for the logfile into `fp`
 fn write_log(
-    fp: &mut File,
+    fp: &mut Vec<u8>,
     data: &[u8],
-    syncalot: bool,
     print_escape_characters: bool,
+    tag: &str,
     first_run: &mut bool,
+    inside_esc: &mut u8,
 ) -> io::Result<()> {
-    let mut inside_esc: u8 = 0;
-    let mut should_sync = false;
-
     let mut i = 0;
     while i < data.len() {
-        if (*first_run) {
+        if *first_run {
             let now = SystemTime::now();
-            if let Ok(n) = now.duration_since(UNIX_EPOCH) {
+            if now.duration_since(UNIX_EPOCH).is_ok() {
                 let ts = format!("{:?}", now);
                 write!(fp, "{}: ", ts)?;
             } else {
                 write!(fp, "?: ")?;
             }
-            should_sync = true;
+            write!(fp, "{}", tag)?;
             *first_run = false;
         }
 
@@ -140,28 +167,24 @@ fn write_log(
         let mut ignore = false;
 
         if !print_escape_characters {
-            if inside_esc == 1 {
+            if *inside_esc == 1 {
                 if byte == b'[' {
                     ignore = true;
-                    inside_esc = 2;
+                    *inside_esc = 2;
                 } else {
-                    if byte >= 64 && byte <= 95 {
+                    if (64..=95).contains(&byte) {
                         ignore = true;
                     }
-                    inside_esc = 0;
+                    *inside_esc = 0;
                 }
-            } else if inside_esc == 2 {
+            } else if *inside_esc == 2 {
                 match byte {
-                    b'0'..=b'9' | b';' | 32..=47 => {
-                        if inside_esc != 0 {
-                            ignore = true;
-                        }
+                    b'0'..=b'9' | b';' | 32..=47 if *inside_esc != 0 => {
+                        ignore = true;
                     }
-                    64..=126 => {
-                        if inside_esc != 0 {
-                            ignore = true;
-                            inside_esc = 0;
-                        }
+                    64..=126 if *inside_esc != 0 => {
+                        ignore = true;
+                        *inside_esc = 0;
                     }
                     _ => {}
                 }
@@ -173,7 +196,7 @@ fn write_log(
                     27 => {
                         // ESC
                         ignore = true;
-                        inside_esc = 1;
+                        *inside_esc = 1;
                     }
                     _ => {}
                 }
@@ -193,14 +216,973 @@ fn write_log(
         i += 1;
     }
 
-    if should_sync {
-        fp.flush()?;
-        if syncalot {
-            unsafe {
-                libc::fsync(fp.as_raw_fd());
+    Ok(())
+}
+
+// What goes when a console is too far behind to queue more output for it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DropPolicy {
+    Oldest,                             // What it has been waiting for longest
+    Newest,                             // The output that doesn't fit
+}
+
+pub struct Options {
+    pub syncalot: bool,                 // fsync() after every write
+    pub print_escape_characters: bool,  // Keep escape sequences in the log
+    pub create_log: bool,               // Create the logfile if it doesn't exist
+    pub tag_console: bool,              // Prefix lines with the consoles they went to
+    pub stop_after: Option<Duration>,   // Stop capturing this long after we started
+    pub collapse_cr: bool,              // Keep only the last version of a line redrawn with CR
+    pub truncate: bool,                 // Start the logfile over instead of appending
+    pub quiet_consoles: Vec<String>,    // Captured but not written to, as device paths
+    pub buffer_size: usize,             // Size of the ring buffer
+    pub split_per_console: bool,        // One logfile for every console
+    pub console_queue: usize,           // Output a console may be behind by
+    pub console_drop: DropPolicy,       // What goes when a console is that far behind
+    pub max_console_failures: u32,      // Failed writes in a row before a console is given up, 0 for never
+}
+
+// Progress bars redraw their line with a bare CR. Logged as is that is
+// either one unreadable run-on line or, with the CR stripped, nothing. The
+// collapser holds a line back until its newline and throws away what a
+// bare CR overwrote, so only the final version of the line remains. A CR
+// right before the newline is just a line end.
+#[derive(Debug, Default)]
+pub struct CrCollapser {
+    line: Vec<u8>,
+    cr: bool,                           // Last byte was a CR
+}
+
+impl CrCollapser {
+    pub fn new() -> Self {
+        CrCollapser::default()
+    }
+
+    // Add input, complete lines are appended to out
+    pub fn feed(&mut self, data: &[u8], out: &mut Vec<u8>) {
+        for &byte in data {
+            if self.cr && byte != b'\n' {
+                self.line.clear();
+            }
+            self.cr = byte == b'\r';
+            match byte {
+                b'\r' => {}
+                b'\n' => {
+                    out.append(&mut self.line);
+                    out.push(b'\n');
+                }
+                _ => self.line.push(byte),
             }
         }
     }
 
-    Ok(())
+    // What is left of an unfinished line
+    pub fn finish(&mut self, out: &mut Vec<u8>) {
+        out.append(&mut self.line);
+        self.cr = false;
+    }
+}
+
+// Collapse CR overwrites in a whole log at once
+pub fn collapse_cr(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len());
+    let mut collapser = CrCollapser::new();
+    collapser.feed(data, &mut out);
+    collapser.finish(&mut out);
+    out
+}
+
+// The line that starts a capture session, with the local time in ISO 8601
+// and the boot id, so the log can be matched up with others from the same
+// boot
+pub fn separator(now: SystemTime, boot_id: Option<&str>) -> String {
+    let t = now.duration_since(UNIX_EPOCH).map(|d| d.as_secs() as libc::time_t).unwrap_or(0);
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    unsafe { libc::localtime_r(&t, &mut tm) };
+
+    let offset = tm.tm_gmtoff / 60;
+    let sign = if offset < 0 { '-' } else { '+' };
+    let id = boot_id.map(|id| format!(" id {}", id)).unwrap_or_default();
+    format!("{}{:04}-{:02}-{:02}T{:02}:{:02}:{:02}{}{:02}:{:02}{}{}\n",
+        SEPARATOR_START, tm.tm_year + 1900, tm.tm_mon + 1, tm.tm_mday, tm.tm_hour, tm.tm_min, tm.tm_sec,
+        sign, offset.abs() / 60, offset.abs() % 60, id, SEPARATOR_END)
+}
+
+// Split a logfile into its capture sessions, as (time the session started,
+// its lines). Whatever comes before the first separator, as in a log from
+// an older bootlogd, is a session without a time.
+pub fn boots(data: &[u8]) -> Vec<(Option<String>, &[u8])> {
+    let mut boots = Vec::new();
+    let mut start = 0;
+    let mut time = None;
+
+    let mut pos = 0;
+    while pos < data.len() {
+        let end = data[pos..].iter().position(|&b| b == b'\n').map_or(data.len(), |n| pos + n + 1);
+        let line = String::from_utf8_lossy(&data[pos..end]);
+        let stamp = line.trim_end().strip_prefix(SEPARATOR_START).and_then(|l| l.strip_suffix(SEPARATOR_END));
+        if let Some(stamp) = stamp {
+            if pos > start || time.is_some() {
+                boots.push((time.take(), &data[start..pos]));
+            }
+            time = Some(stamp.to_string());
+            start = end;
+        }
+        pos = end;
+    }
+    if start < data.len() || time.is_some() {
+        boots.push((time, &data[start..]));
+    }
+    boots
+}
+
+// The time write_log stamped a line with, and the length of the stamp
+pub fn line_time(line: &[u8]) -> Option<(Duration, usize)> {
+    let text = String::from_utf8_lossy(&line[..line.len().min(80)]);
+    let rest = text.strip_prefix(STAMP_START)?;
+    let (secs, rest) = rest.split_once(", tv_nsec: ")?;
+    let (nanos, _) = rest.split_once(STAMP_END)?;
+    let len = STAMP_START.len() + secs.len() + ", tv_nsec: ".len() + nanos.len() + STAMP_END.len();
+    Some((Duration::new(secs.parse().ok()?, nanos.parse().ok()?), len))
+}
+
+// The service a line says is starting, by the first of `patterns` it
+// matches. A pattern is text with one `*` in it, which matches the name of
+// the service anywhere in the line: "Starting *..." finds "sshd" in
+// " * Starting sshd ... [ OK ]". A `*` at the end takes the rest of the
+// line. Trailing dots and spaces are no part of the name.
+pub fn marker(text: &str, patterns: &[String]) -> Option<String> {
+    patterns.iter().find_map(|pattern| {
+        let (before, after) = pattern.split_once('*')?;
+        let start = text.find(before)? + before.len();
+        let name = match after {
+            "" => &text[start..],
+            after => &text[start..start + text[start..].find(after)?],
+        };
+        let name = name.trim().trim_end_matches(['.', ' ']);
+        (!name.is_empty()).then(|| name.to_string())
+    })
+}
+
+// Where things are in a logfile, by byte offset
+#[derive(Debug, Clone, PartialEq)]
+pub enum IndexEntry {
+    Boot { offset: usize, time: String },           // A separator line, with the time in it
+    Time { offset: usize, time: Duration },         // The first line logged in a second
+    Mark { offset: usize, time: Option<Duration>, service: String }, // A service starting
+}
+
+impl IndexEntry {
+    pub fn offset(&self) -> usize {
+        match self {
+            IndexEntry::Boot { offset, .. } | IndexEntry::Time { offset, .. } | IndexEntry::Mark { offset, .. } => {
+                *offset
+            }
+        }
+    }
+}
+
+// Index a logfile: the boots in it, the time once a second and the lines
+// matching one of the marker `patterns`
+pub fn index(data: &[u8], patterns: &[String]) -> Vec<IndexEntry> {
+    let mut entries = Vec::new();
+    let mut second = None;
+
+    let mut pos = 0;
+    while pos < data.len() {
+        let end = data[pos..].iter().position(|&b| b == b'\n').map_or(data.len(), |n| pos + n + 1);
+        let line = &data[pos..end];
+        let text = String::from_utf8_lossy(line);
+        let separator = text.trim_end().strip_prefix(SEPARATOR_START).and_then(|l| l.strip_suffix(SEPARATOR_END));
+        if let Some(time) = separator {
+            entries.push(IndexEntry::Boot { offset: pos, time: time.to_string() });
+            second = None;
+            pos = end;
+            continue;
+        }
+
+        let (time, stamp) = line_time(line).map_or((None, 0), |(time, len)| (Some(time), len));
+        if let Some(time) = time.filter(|t| second != Some(t.as_secs())) {
+            entries.push(IndexEntry::Time { offset: pos, time });
+            second = Some(time.as_secs());
+        }
+        let text = collapse_cr(&strip_escapes(&line[stamp..]));
+        if let Some(service) = marker(&String::from_utf8_lossy(&text), patterns) {
+            entries.push(IndexEntry::Mark { offset: pos, time, service });
+        }
+        pos = end;
+    }
+    entries
+}
+
+fn format_time(time: Duration) -> String {
+    format!("{}.{:09}", time.as_secs(), time.subsec_nanos())
+}
+
+fn parse_time(s: &str) -> Option<Duration> {
+    let (secs, nanos) = s.split_once('.')?;
+    Some(Duration::new(secs.parse().ok()?, nanos.parse().ok()?))
+}
+
+// The index as a file, one entry a line with tab separated fields:
+//
+//   log     <size of the logfile it is for>
+//   boot    <offset> <time from the separator>
+//   time    <offset> <seconds.nanoseconds>
+//   mark    <offset> <seconds.nanoseconds or -> <service>
+pub fn format_index(size: usize, entries: &[IndexEntry]) -> String {
+    let mut out = format!("log\t{}\n", size);
+    for entry in entries {
+        let line = match entry {
+            IndexEntry::Boot { offset, time } => format!("boot\t{}\t{}\n", offset, time),
+            IndexEntry::Time { offset, time } => format!("time\t{}\t{}\n", offset, format_time(*time)),
+            IndexEntry::Mark { offset, time, service } => format!("mark\t{}\t{}\t{}\n", offset,
+                time.map_or("-".to_string(), format_time), service),
+        };
+        out.push_str(&line);
+    }
+    out
+}
+
+// Read an index file back, as the size of the logfile it is for and its
+// entries. None if it isn't one.
+pub fn parse_index(text: &str) -> Option<(usize, Vec<IndexEntry>)> {
+    let mut lines = text.lines();
+    let size = lines.next()?.strip_prefix("log\t")?.parse().ok()?;
+    let entries = lines
+        .map(|line| {
+            let fields: Vec<&str> = line.splitn(4, '\t').collect();
+            let offset = fields.get(1)?.parse().ok()?;
+            match fields.as_slice() {
+                ["boot", _, time] => Some(IndexEntry::Boot { offset, time: time.to_string() }),
+                ["time", _, time] => Some(IndexEntry::Time { offset, time: parse_time(time)? }),
+                ["mark", _, time, service] => {
+                    Some(IndexEntry::Mark { offset, time: parse_time(time), service: service.to_string() })
+                }
+                _ => None,
+            }
+        })
+        .collect::<Option<Vec<_>>>()?;
+    Some((size, entries))
+}
+
+// Remove terminal escape sequences (ESC [ ... final byte, and two byte
+// ESC sequences), as a log written with -e has them
+pub fn strip_escapes(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len());
+    let mut inside_esc = 0;
+    for &byte in data {
+        match (inside_esc, byte) {
+            (0, 27) => inside_esc = 1,
+            (0, _) => out.push(byte),
+            (1, b'[') => inside_esc = 2,
+            (1, _) => inside_esc = 0,
+            (_, 64..=126) => inside_esc = 0,
+            _ => {}
+        }
+    }
+    out
+}
+
+// The capture loop. Everything written to the console arrives on `console`
+// (the master side of the pty that has been made the console with
+// TIOCCONS), is copied to the real console devices in `outputs` and stored
+// in the logfile once it can be opened.
+//
+// Every capture session starts with a "---- boot <time> ----" line, so the
+// boots in a logfile that is appended to can be told apart. With truncate
+// the logfile only ever holds the current boot.
+//
+// The kernel hands us the output for all consoles as one stream, so with
+// tag_console a line is tagged with the devices it was actually written to,
+// e.g. "[tty1,ttyS0]". A console that stopped taking output drops out of
+// the tag.
+//
+// With split_per_console there is no shared logfile: every console gets
+// its own, the logfile name with the device appended (/var/log/boot.ttyS0,
+// /var/log/boot.tty0), and it holds what was actually written to that
+// console. Each has its own escape filter, timestamps and retry state, and
+// nothing is tagged.
+//
+// The consoles in quiet_consoles are logged like the others but get none
+// of the output, not even our own warnings: a kiosk can keep its splash on
+// the screen while the serial console shows everything. They never appear
+// in a tag.
+//
+// Started from the initramfs, bootlogd outlives the switch_root: it notices
+// that init's root is no longer its own, moves over to the real root and
+// puts what it logged so far at the start of the real logfile.
+//
+// A full or read-only filesystem doesn't end the capture: the log is kept
+// in memory, up to MAX_PENDING bytes and then what the ring buffer holds,
+// the logfile is opened again every LOG_RETRY and the consoles are warned
+// once.
+//
+// A console that stops taking output, a serial port with flow control
+// asserted or a wedged graphics driver, doesn't hold up the others: the
+// consoles are written without blocking and each has a queue of its own,
+// console_queue bytes long, with console_drop saying what goes when it is
+// full. A console that takes nothing for WRITE_TIMEOUT, or fails a write,
+// has failed once. After max_console_failures in a row it is given up,
+// and the log and the other consoles get a note saying so.
+//
+// Nothing in here knows about /dev/console, so the loop can be driven by
+// any pty pair.
+pub struct Bootlogd {
+    pub console: File,
+    pub outputs: Vec<Output>,
+    pub logfile: String,
+    pub options: Options,
+    pub sys: &'static dyn Sys,
+    logs: Vec<Log>,
+    tag: String,
+    pivoted: bool,                      // Followed init to the real root
+}
+
+// A console the output is mirrored to, and what it hasn't taken yet
+pub struct Output {
+    pub name: String,
+    file: File,
+    queue: VecDeque<u8>,                // Not written yet, oldest first
+    dropped: usize,                     // Bytes that didn't fit since the last notice
+    stalled: Option<Instant>,           // Since when the queue hasn't gone down
+    pub failures: u32,                  // Write timeouts and errors in a row
+}
+
+impl Output {
+    pub fn new(name: String, file: File) -> Self {
+        if let Ok(flags) = fcntl(&file, FcntlArg::F_GETFL) {
+            let _ = fcntl(&file, FcntlArg::F_SETFL(OFlag::from_bits_truncate(flags) | OFlag::O_NONBLOCK));
+        }
+        Output { name, file, queue: VecDeque::new(), dropped: 0, stalled: None, failures: 0 }
+    }
+
+    // Queue data for the console, making room by `options.console_drop`.
+    // Returns whether any of it will be shown.
+    fn push(&mut self, data: &[u8], options: &Options) -> bool {
+        let room = options.console_queue.saturating_sub(self.queue.len());
+        if data.len() > room {
+            match options.console_drop {
+                DropPolicy::Newest => {
+                    self.dropped += data.len();
+                    return false;
+                }
+                DropPolicy::Oldest => {
+                    let data = &data[data.len().saturating_sub(options.console_queue)..];
+                    let old = (data.len() - room).min(self.queue.len());
+                    self.queue.drain(..old);
+                    self.dropped += old;
+                    self.queue.extend(data);
+                    return true;
+                }
+            }
+        }
+        self.queue.extend(data);
+        true
+    }
+
+    // Write what the console takes without blocking, and the notice about
+    // what was dropped once everything else is out
+    fn write(&mut self) -> io::Result<()> {
+        loop {
+            if self.queue.is_empty() && self.dropped > 0 {
+                let notice = format!("\r\nbootlogd: {} bytes of output were too many for {}\r\n",
+                    std::mem::take(&mut self.dropped), self.name);
+                self.queue.extend(notice.as_bytes());
+            }
+            let (front, _) = self.queue.as_slices();
+            if front.is_empty() {
+                return Ok(());
+            }
+            match self.file.write(front) {
+                Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+                Ok(n) => {
+                    self.queue.drain(..n);
+                    self.stalled = None;
+                    self.failures = 0;
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    // Write what the console takes. Returns whether it failed: a write
+    // went wrong, or nothing could be written for WRITE_TIMEOUT.
+    fn flush(&mut self, now: Instant) -> bool {
+        let failed = match self.write() {
+            Err(_) => true,
+            Ok(()) if self.queue.is_empty() => false,
+            Ok(()) => {
+                let since = *self.stalled.get_or_insert(now);
+                now.duration_since(since) >= WRITE_TIMEOUT
+            }
+        };
+        if failed {
+            self.failures += 1;
+            self.stalled = None;
+        }
+        failed
+    }
+}
+
+// One logfile and the state of what goes into it
+struct Log {
+    path: String,
+    console: Option<String>,            // Only what reached this console, None for everything
+    file: Option<File>,
+    ringbuf: RingBuf,
+    first_run: bool,
+    inside_esc: u8,                     // Where the escape filter is in a sequence
+    pending: Vec<u8>,                   // Formatted log the logfile hasn't taken yet
+    collapser: CrCollapser,
+    separated: bool,                    // The session separator has been logged
+    truncated: bool,                    // The logfile of this root has been started over
+    retry_at: Option<Instant>,          // Don't try the logfile again before this
+    warned: bool,                       // The consoles know the logfile failed
+}
+
+// The logfile of one console with split_per_console: "/dev/ttyS0" logs to
+// "<logfile>.ttyS0"
+pub fn console_logfile(logfile: &str, console: &str) -> String {
+    let name = console.strip_prefix("/dev/").unwrap_or(console);
+    format!("{}.{}", logfile, name.replace('/', "-"))
+}
+
+impl Log {
+    fn new(path: String, console: Option<String>, buffer_size: usize) -> Self {
+        Log {
+            path,
+            console,
+            file: None,
+            ringbuf: RingBuf::new(buffer_size),
+            first_run: true,
+            inside_esc: 0,
+            pending: Vec::new(),
+            collapser: CrCollapser::new(),
+            separated: false,
+            truncated: false,
+            retry_at: None,
+            warned: false,
+        }
+    }
+
+    // Put console output into the ring buffer, as much as fits
+    fn store(&mut self, data: &[u8], collapse_cr: bool) {
+        if collapse_cr {
+            let mut lines = Vec::new();
+            self.collapser.feed(data, &mut lines);
+            self.ringbuf.push(&lines);
+        } else {
+            self.ringbuf.push(data);
+        }
+    }
+
+    // The end of the capture: what is left of an unfinished line
+    fn finish(&mut self) {
+        let mut rest = Vec::new();
+        self.collapser.finish(&mut rest);
+        self.ringbuf.push(&rest);
+        self.retry_at = None;
+    }
+
+    // Init switched to the real root. A logfile we had open on the
+    // initramfs is read back and goes to the new one first.
+    fn follow_root(&mut self) {
+        self.truncated = false;
+        self.retry_at = None;
+        if self.file.take().is_some() && let Ok(mut data) = std::fs::read(&self.path) {
+            data.append(&mut self.pending);
+            self.pending = data;
+        }
+    }
+
+    // The logfile usually lives on a filesystem that is mounted (or made
+    // writable) some time after we start, so keep trying. An error is one
+    // that keeps the log in memory.
+    fn open(&mut self, options: &Options) -> io::Result<()> {
+        if self.file.is_some() || self.retry_at.is_some_and(|t| Instant::now() < t) {
+            return Ok(());
+        }
+
+        let f = OpenOptions::new()
+            .append(true)
+            .create(options.create_log)
+            .open(&self.path);
+
+        // Once per root: after the switch_root the early log comes back
+        // through `pending`
+        match f {
+            Ok(f) => {
+                if options.truncate && !self.truncated {
+                    let _ = f.set_len(0);
+                    self.truncated = true;
+                }
+                self.file = Some(f);
+            }
+            Err(e) if matches!(e.raw_os_error(), Some(libc::ENOSPC) | Some(libc::EROFS)) => return Err(e),
+            Err(_) => self.retry_at = Some(Instant::now() + LOG_RETRY),
+        }
+        Ok(())
+    }
+
+    // Move whatever is in the ring buffer into the logfile, as far as it
+    // takes it
+    fn flush(&mut self, options: &Options, tag: &str, sys: &dyn Sys) -> io::Result<()> {
+        if !self.separated {
+            self.pending.extend_from_slice(separator(SystemTime::now(), boot_id().as_deref()).as_bytes());
+            self.separated = true;
+        }
+
+        while self.ringbuf.available() > 0 && self.pending.len() < MAX_PENDING {
+            let chunk = self.ringbuf.get_slice();
+            let len = chunk.len();
+            if len == 0 {
+                break;
+            }
+            let _ = write_log(&mut self.pending, chunk, options.print_escape_characters, tag,
+                &mut self.first_run, &mut self.inside_esc);
+            self.ringbuf.advance_out(len);
+        }
+
+        let Some(log) = self.file.as_mut() else {
+            return Ok(());
+        };
+        let mut written = 0;
+        let mut failed = Ok(());
+        while written < self.pending.len() {
+            match sys.write(log.as_fd(), &self.pending[written..]) {
+                Ok(0) => break,
+                Ok(n) => written += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => {
+                    failed = Err(e);
+                    break;
+                }
+            }
+        }
+        if written > 0 && options.syncalot {
+            let _ = sys.fsync(log.as_fd());
+        }
+        self.pending.drain(..written);
+        failed
+    }
+}
+
+impl Bootlogd {
+    pub fn new(console: File, outputs: Vec<(String, File)>, logfile: &str, options: Options) -> Self {
+        let logs = if options.split_per_console {
+            outputs
+                .iter()
+                .map(|(name, _)| Log::new(console_logfile(logfile, name), Some(name.clone()), options.buffer_size))
+                .collect()
+        } else {
+            vec![Log::new(logfile.to_string(), None, options.buffer_size)]
+        };
+        Bootlogd {
+            console,
+            outputs: outputs.into_iter().map(|(name, file)| Output::new(name, file)).collect(),
+            logfile: logfile.to_string(),
+            options,
+            sys: &Kernel,
+            logs,
+            tag: String::new(),
+            pivoted: false,
+        }
+    }
+
+    // Follow init once it has switched to the real root
+    fn follow_root(&mut self) {
+        if self.pivoted {
+            return;
+        }
+        let (Some(ours), Some(init)) = (dir_id("/"), dir_id(INIT_ROOT)) else {
+            return;
+        };
+        if ours == init {
+            return;
+        }
+        self.pivoted = true;
+        for log in &mut self.logs {
+            log.follow_root();
+        }
+
+        if nix::unistd::chroot(INIT_ROOT).is_ok() {
+            let _ = std::env::set_current_dir("/");
+        }
+    }
+
+    // Open the logfiles and write to them what they take
+    fn write_logs(&mut self) {
+        for i in 0..self.logs.len() {
+            let log = &mut self.logs[i];
+            let tag = if log.console.is_none() { &self.tag[..] } else { "" };
+            let res = log.open(&self.options).and_then(|()| log.flush(&self.options, tag, self.sys));
+            if let Err(e) = res {
+                self.log_failed(i, &e);
+            }
+        }
+    }
+
+    // A logfile can't be written for now. Keep its log in memory and tell
+    // the consoles, once.
+    fn log_failed(&mut self, i: usize, e: &io::Error) {
+        let log = &mut self.logs[i];
+        log.file = None;
+        log.retry_at = Some(Instant::now() + LOG_RETRY);
+        if std::mem::replace(&mut log.warned, true) {
+            return;
+        }
+        let msg = format!("\r\nbootlogd: cannot write {}: {}, keeping the log in memory\r\n", log.path, e);
+        let quiet = &self.options.quiet_consoles;
+        for out in self.outputs.iter_mut().filter(|out| !quiet.contains(&out.name)) {
+            out.push(msg.as_bytes(), &self.options);
+        }
+    }
+
+    // Handle one chunk of console output
+    fn handle_input(&mut self, data: &[u8]) {
+        let mut reached = Vec::new();
+        let quiet = &self.options.quiet_consoles;
+        for out in self.outputs.iter_mut() {
+            if quiet.contains(&out.name) || out.push(data, &self.options) {
+                reached.push(out.name.as_str());
+            }
+        }
+        if self.options.tag_console {
+            let tagged: Vec<&str> = reached
+                .iter()
+                .filter(|name| !quiet.iter().any(|q| q == *name))
+                .map(|name| name.strip_prefix("/dev/").unwrap_or(name))
+                .collect();
+            self.tag = format!("[{}] ", tagged.join(","));
+        }
+
+        for log in &mut self.logs {
+            if log.console.as_ref().is_none_or(|c| reached.contains(&c.as_str())) {
+                log.store(data, self.options.collapse_cr);
+            }
+        }
+    }
+
+    // Write to the consoles what they take, and give up the ones that
+    // failed max_console_failures times in a row
+    fn write_consoles(&mut self) {
+        let now = Instant::now();
+        let max = self.options.max_console_failures;
+        let mut given_up = Vec::new();
+        self.outputs.retain_mut(|out| {
+            let failed = out.flush(now) && max > 0 && out.failures >= max;
+            if failed {
+                given_up.push(out.name.clone());
+            }
+            !failed
+        });
+        for name in given_up {
+            let msg = format!("\r\nbootlogd: {} failed {} writes in a row, not writing to it any more\r\n", name, max);
+            self.handle_input(msg.as_bytes());
+        }
+    }
+
+    // Whether boot is over and we should stop capturing the console, which
+    // would otherwise include every password prompt on it until shutdown
+    fn boot_done(&self, started: Instant) -> bool {
+        if self.options.stop_after.is_some_and(|t| started.elapsed() >= t) {
+            return true;
+        }
+        std::path::Path::new(&crate::paths::path(BOOT_DONE)).exists()
+    }
+
+    // Run until a signal arrives, boot is done or the other side of the
+    // console goes away
+    pub fn run(&mut self) -> io::Result<()> {
+        let mut inbuf = [0u8; READ_SIZE];
+        let started = Instant::now();
+
+        while !get_signal() && !self.boot_done(started) {
+            let ready = {
+                let mut fds = vec![PollFd::new(self.console.as_fd(), PollFlags::POLLIN)];
+                let behind = self.outputs.iter().filter(|out| !out.queue.is_empty());
+                fds.extend(behind.map(|out| PollFd::new(out.file.as_fd(), PollFlags::POLLOUT)));
+                let timeout = if fds.len() > 1 { WRITE_POLL } else { POLL_TIMEOUT };
+                match poll(&mut fds, PollTimeout::from(timeout)) {
+                    Ok(_) => fds[0].revents().is_some_and(|events| !events.is_empty()),
+                    Err(nix::errno::Errno::EINTR) => false,
+                    Err(e) => return Err(e.into()),
+                }
+            };
+
+            if ready {
+                match self.console.read(&mut inbuf) {
+                    // EIO: the last user of the slave side closed it
+                    Ok(0) => break,
+                    Err(e) if e.raw_os_error() == Some(libc::EIO) => break,
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(e) => return Err(e),
+                    Ok(n) => self.handle_input(&inbuf[..n]),
+                }
+            }
+
+            self.write_consoles();
+            self.follow_root();
+            self.write_logs();
+        }
+
+        // Get the last bits to the consoles and into the logfiles before we go
+        self.write_consoles();
+        for log in &mut self.logs {
+            log.finish();
+        }
+        self.write_logs();
+        for log in &mut self.logs {
+            if let Some(f) = log.file.as_mut() {
+                f.flush()?;
+            }
+        }
+
+        let lost: Vec<String> = self.logs
+            .iter()
+            .filter(|log| log.warned && !log.pending.is_empty())
+            .map(|log| format!("{} bytes of log could not be written to {}", log.pending.len(), log.path))
+            .collect();
+        if !lost.is_empty() {
+            return Err(io::Error::other(lost.join(", ")));
+        }
+        Ok(())
+    }
+}
+
+// Make the slave side of our pty the console
+pub fn set_console(sys: &dyn Sys, slave: &File) -> io::Result<()> {
+    sys.set_console(slave.as_fd())
+}
+
+// Undo a console redirection somebody else made, so we can make our own.
+// TIOCCONS on the console itself resets it (needs CAP_SYS_ADMIN).
+pub fn release_console(sys: &dyn Sys) -> io::Result<()> {
+    let console = crate::tty::open("/dev/console")?;
+    sys.set_console(console.as_fd())
+}
+
+// A splash daemon that owns the console, as (pid, name). With one of those
+// running, what we capture is what it lets through, often nothing.
+pub fn splash_daemon() -> Option<(i32, String)> {
+    crate::procscan::scan()
+        .into_iter()
+        .find(|p| SPLASH_DAEMONS.contains(&p.comm.as_str()) && p.state != 'Z')
+        .map(|p| (p.pid, p.comm))
+}
+
+// Translate a console= argument into a device path: "ttyS0,115200n8"
+// becomes "/dev/ttyS0"
+pub fn console_device(arg: &str) -> String {
+    let name = arg.split(',').next().unwrap_or(arg);
+    if name.starts_with('/') {
+        name.to_string()
+    } else {
+        format!("/dev/{}", name)
+    }
+}
+
+// The consoles named on the kernel command line
+pub fn consolenames(cmdline: &str) -> Vec<String> {
+    let mut names: Vec<String> = cmdline
+        .split_whitespace()
+        .filter_map(|arg| arg.strip_prefix("console="))
+        .map(console_device)
+        .collect();
+
+    names.dedup();
+    names.truncate(MAX_CONSOLES as usize);
+    names
+}
+
+// Find the device behind /dev/console. Writing to /dev/console itself
+// would feed our own output back to us once TIOCCONS is in effect.
+pub fn real_console() -> Option<String> {
+    let f = File::open("/dev/console").ok()?;
+    let mut dev: libc::c_uint = 0;
+    if unsafe { libc::ioctl(f.as_raw_fd(), libc::TIOCGDEV as _, &mut dev) } < 0 {
+        return None;
+    }
+
+    let major = (dev >> 8) & 0xfff;
+    let minor = (dev & 0xff) | ((dev >> 12) & 0xfff00);
+
+    match major {
+        4 if minor < 64 => Some(format!("/dev/tty{}", minor)),
+        4 => Some(format!("/dev/ttyS{}", minor - 64)),
+        5 if minor == 1 => None,
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sys::Recorder;
+
+    // Everything the buffer holds, oldest first, the way the capture loop
+    // takes it out
+    fn drain(rb: &mut RingBuf) -> Vec<u8> {
+        let mut out = Vec::new();
+        while rb.available() > 0 {
+            let n = rb.get_slice().len();
+            out.extend_from_slice(rb.get_slice());
+            rb.advance_out(n);
+        }
+        out
+    }
+
+    // A capture with a logfile of its own and one console, whose system
+    // calls go to `sys`
+    fn logger(name: &str, sys: &'static Recorder) -> (Bootlogd, std::path::PathBuf) {
+        let path = std::env::temp_dir().join(format!("rye-init-bootlogd-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let options = Options {
+            syncalot: false,
+            print_escape_characters: false,
+            create_log: true,
+            tag_console: false,
+            stop_after: None,
+            collapse_cr: false,
+            truncate: false,
+            quiet_consoles: Vec::new(),
+            buffer_size: RINGBUF_SIZE,
+            split_per_console: false,
+            console_queue: CONSOLE_QUEUE,
+            console_drop: DropPolicy::Newest,
+            max_console_failures: MAX_CONSOLE_FAILURES,
+        };
+        let null = OpenOptions::new().write(true).open("/dev/null").unwrap();
+        let mut logger = Bootlogd::new(null.try_clone().unwrap(), vec![("/dev/null".to_string(), null)],
+            path.to_str().unwrap(), options);
+        logger.sys = sys;
+        (logger, path)
+    }
+
+    // How often the console was told the logfile failed
+    fn warnings(logger: &Bootlogd) -> usize {
+        let queued: Vec<u8> = logger.outputs[0].queue.iter().copied().collect();
+        String::from_utf8_lossy(&queued).matches("keeping the log in memory").count()
+    }
+
+    // The logfile fails every write with `errno` for a while: what the
+    // console said stays in memory, the console hears of it once, and it
+    // all goes to the logfile once that takes writes again
+    fn survives(errno: i32, name: &str) {
+        let sys: &'static Recorder = Box::leak(Box::default());
+        let (mut logger, path) = logger(name, sys);
+        sys.fail("write", Some(errno));
+
+        logger.handle_input(b"one\n");
+        logger.write_logs();
+        assert_eq!(warnings(&logger), 1);
+        assert!(logger.logs[0].file.is_none());
+        assert!(logger.logs[0].retry_at.is_some_and(|t| t > Instant::now()));
+        assert!(String::from_utf8_lossy(&logger.logs[0].pending).ends_with(": one\n"));
+
+        // Not tried again before LOG_RETRY is up, and when it is the
+        // console isn't told again
+        logger.handle_input(b"two\n");
+        logger.write_logs();
+        assert!(logger.logs[0].file.is_none());
+        logger.logs[0].retry_at = Some(Instant::now());
+        logger.write_logs();
+        assert_eq!(warnings(&logger), 1);
+        assert_eq!(std::fs::read(&path).unwrap(), b"");
+
+        sys.fail("write", None);
+        logger.logs[0].retry_at = Some(Instant::now());
+        logger.write_logs();
+        assert!(logger.logs[0].pending.is_empty());
+        let log = String::from_utf8(std::fs::read(&path).unwrap()).unwrap();
+        let one = log.find(": one\n").unwrap();
+        let two = log.find(": two\n").unwrap();
+        assert!(log.starts_with(SEPARATOR_START), "{}", log);
+        assert!(one < two, "{}", log);
+        assert_eq!(warnings(&logger), 1);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn full_disk_keeps_the_log() {
+        survives(libc::ENOSPC, "enospc");
+    }
+
+    #[test]
+    fn read_only_filesystem_keeps_the_log() {
+        survives(libc::EROFS, "erofs");
+    }
+
+    // What a logfile that never comes back costs is bounded
+    #[test]
+    fn log_in_memory_is_capped() {
+        let sys: &'static Recorder = Box::leak(Box::default());
+        let (mut logger, path) = logger("capped", sys);
+        sys.fail("write", Some(libc::ENOSPC));
+        let line = [b'x'; 1023].iter().chain(b"\n").copied().collect::<Vec<u8>>();
+        for _ in 0..2 * MAX_PENDING / line.len() {
+            logger.handle_input(&line);
+            logger.write_logs();
+        }
+        let log = &logger.logs[0];
+        assert!(log.pending.len() < MAX_PENDING + RINGBUF_SIZE, "{}", log.pending.len());
+        assert!(log.ringbuf.is_full());
+        assert_eq!(warnings(&logger), 1);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn ringbuf_empty() {
+        let mut rb = RingBuf::new(8);
+        assert_eq!(rb.capacity(), 8);
+        assert_eq!(rb.available(), 0);
+        assert!(!rb.is_full());
+        assert_eq!(rb.get_slice(), b"");
+        rb.advance_out(3);
+        assert_eq!(rb.available(), 0);
+        assert_eq!(rb.push(b""), 0);
+        assert_eq!(RingBuf::new(0).capacity(), 1);
+    }
+
+    #[test]
+    fn ringbuf_full() {
+        let mut rb = RingBuf::new(8);
+        assert_eq!(rb.push(b"0123456789"), 8);
+        assert!(rb.is_full());
+        assert_eq!(rb.available(), 8);
+        assert_eq!(rb.push(b"x"), 0);
+        assert_eq!(rb.get_slice(), b"01234567");
+
+        // What hasn't been read isn't overwritten, room is made by reading
+        rb.advance_out(2);
+        assert_eq!(rb.push(b"abc"), 2);
+        assert!(rb.is_full());
+        assert_eq!(drain(&mut rb), b"234567ab");
+        assert_eq!(rb.available(), 0);
+    }
+
+    #[test]
+    fn ringbuf_wraparound() {
+        let mut rb = RingBuf::new(8);
+        assert_eq!(rb.push(b"abcdef"), 6);
+        rb.advance_out(4);
+        assert_eq!(rb.push(b"ghijk"), 5);
+        assert_eq!(rb.available(), 7);
+
+        // The contiguous part ends at the end of the buffer, the rest
+        // follows from the start
+        assert_eq!(rb.get_slice(), b"efgh");
+        rb.advance_out(4);
+        assert_eq!(rb.get_slice(), b"ijk");
+        assert_eq!(rb.push(b"lmnopq"), 5);
+        assert!(rb.is_full());
+        assert_eq!(drain(&mut rb), b"ijklmnop");
+
+        // Emptied, it starts over at the beginning
+        assert_eq!(rb.push(b"rs"), 2);
+        assert_eq!(rb.get_slice(), b"rs");
+    }
 }
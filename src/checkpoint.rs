@@ -0,0 +1,70 @@
+// Checkpoints of init's state, for when a handover goes wrong
+//
+// When init executes itself again, after a panic too, it hands its state
+// to the new init through a pipe. Should that fail, the new init would
+// believe nothing is running and start everything a second time. So the
+// same state stream is also kept in CHECKPOINT_FILE, written every
+// CHECKPOINT_INTERVAL and whenever a runlevel switch begins or ends. An
+// init that got nothing through the pipe takes it from there, and checks
+// every pid in it as after any handover.
+//
+// The first line of the file is the kernel's boot id: a checkpoint left
+// by an earlier boot, on a /run that isn't a tmpfs yet, is ignored. The
+// file is only written when the state changed, through a temporary file
+// and a rename.
+
+use std::io;
+use std::path::Path;
+
+use rye_init::{bootid, paths};
+
+pub const CHECKPOINT_FILE: &str = "/run/rye-init/state";
+pub const CHECKPOINT_INTERVAL: u64 = 30_000;
+
+#[derive(Debug, Default)]
+pub struct Checkpoint {
+    written: Vec<u8>,                   // The state stream the file has now
+}
+
+impl Checkpoint {
+    pub fn new() -> Self {
+        Checkpoint::default()
+    }
+
+    // Keep the state stream `state`, unless the file has it already
+    pub fn write(&mut self, state: Vec<u8>) -> io::Result<()> {
+        if state == self.written {
+            return Ok(());
+        }
+
+        let file = paths::path(CHECKPOINT_FILE);
+        let path = Path::new(&file);
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let mut data = format!("{}\n", bootid::boot_id().unwrap_or_default()).into_bytes();
+        data.extend_from_slice(&state);
+        let tmp = format!("{}.new", file);
+        std::fs::write(&tmp, data)?;
+        std::fs::rename(&tmp, path)?;
+        self.written = state;
+        Ok(())
+    }
+}
+
+// The state stream of the last checkpoint of this boot, None if there is
+// none
+pub fn read() -> io::Result<Option<Vec<u8>>> {
+    let data = match std::fs::read(paths::path(CHECKPOINT_FILE)) {
+        Ok(data) => data,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let Some(end) = data.iter().position(|&b| b == b'\n') else {
+        return Ok(None);
+    };
+    match bootid::boot_id() {
+        Some(id) if id.as_bytes() == &data[..end] => Ok(Some(data[end + 1..].to_vec())),
+        _ => Ok(None),
+    }
+}
@@ -0,0 +1,53 @@
+// Clock sanity at early boot
+//
+// A machine with a dead RTC battery comes up in 1970. Respawn throttling
+// compares timestamps and wtmp gets useless records, so if the clock is
+// earlier than the time init was built, step it forward to that.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use nix::time::{clock_settime, ClockId};
+use nix::sys::time::TimeSpec;
+
+// Seconds since the epoch when this binary was built, set by build.rs
+pub fn build_time() -> u64 {
+    env!("RYE_INIT_BUILD_TIME").parse().unwrap_or(0)
+}
+
+pub fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+// Step the clock forward to the build time if it is behind. Returns the
+// old and the new time if the clock was changed.
+pub fn check_clock() -> nix::Result<Option<(u64, u64)>> {
+    let floor = build_time();
+    let old = now();
+
+    if old >= floor {
+        return Ok(None);
+    }
+
+    clock_settime(ClockId::CLOCK_REALTIME, TimeSpec::new(floor as i64, 0))?;
+    Ok(Some((old, floor)))
+}
+
+// Seconds of CLOCK_BOOTTIME: like CLOCK_MONOTONIC, but time spent
+// suspended counts. Respawn throttling uses it, so neither a clock step nor
+// a night in suspend makes an entry look like it respawns too fast (or
+// hides one that does).
+pub fn boottime() -> u64 {
+    match nix::time::clock_gettime(ClockId::CLOCK_BOOTTIME) {
+        Ok(ts) => ts.tv_sec() as u64,
+        Err(_) => 0,
+    }
+}
+
+// Milliseconds of CLOCK_MONOTONIC. It doesn't jump with the wall clock and
+// keeps counting across an exec, so it can be handed to our successor.
+pub fn monotonic_ms() -> u64 {
+    match nix::time::clock_gettime(ClockId::CLOCK_MONOTONIC) {
+        Ok(ts) => ts.tv_sec() as u64 * 1000 + ts.tv_nsec() as u64 / 1_000_000,
+        Err(_) => 0,
+    }
+}
@@ -0,0 +1,372 @@
+// Init's own settings
+//
+// CONFIG_FILE holds one setting per line, a keyword and its value:
+//
+//   # Told about every respawning entry that dies unexpectedly
+//   on-failure-exec /usr/local/sbin/notify-failure
+//   # Console messages per second and burst, per source, or "off"
+//   console-rate-limit 5 20
+//   # Tried in this order when init needs an emergency shell
+//   emergency-shell /sbin/sulogin
+//   emergency-shell /bin/busybox sh
+//   # Run on SIGPWR and Ctrl-Alt-Del when the inittab has no entries for them
+//   poweroff-command /sbin/poweroff
+//   reboot-command /sbin/reboot
+//   ctrlaltdel-command /sbin/shutdown -r now
+//   # Hang up the terminals of entries a runlevel switch stops
+//   hangup-ttys yes
+//   # Keep init's pages in memory
+//   lock-memory yes
+//   # Run init on these CPUs only, what it starts runs everywhere
+//   cpu-affinity 0
+//   # Run the powerfailnow entries when the battery drops below 5%, or "off"
+//   battery-threshold 5
+//   # Execute the new init program when a package upgrade replaced it
+//   on-upgrade re-exec
+//   # Runlevel switches and failures in the kernel log too, for pstore
+//   kmsg yes
+//   # Show the processes of entries in ps as "rye:<id> <command>"
+//   child-titles yes
+//   # inittab process fields over this many bytes are cut short, or
+//   # "reject" them like sysvinit, or "accept" them with a warning
+//   process-length 4096
+//   long-process truncate
+//   # As process 1 of a container: SIGTERM stops it, SIGINT restarts it,
+//   # both through runlevel 0 or 6 and within container-stop-timeout
+//   # seconds, below docker stop's 10. "auto" when a container is detected.
+//   container yes
+//   container-stop-timeout 8
+//   # Seconds a sysinit or bootwait entry may take, or "off". After that
+//   # init goes on without it: "continue" leaves it running, "kill" stops
+//   # it first, "emergency" stops it and starts an emergency shell.
+//   sysinit-timeout 300
+//   bootwait-timeout 600
+//   boot-timeout-action kill
+//   # Members may announce maintenance with shutdown --warn-only
+//   shutdown-warn-group operator
+//   # Keep what is sent to /dev/log until the syslog daemon is there
+//   early-syslog yes
+//   # An inittab with errors gets an emergency shell instead of a boot
+//   # without the lines that were wrong, rye_init.strict=1 does the same
+//   strict yes
+//   # BSD process accounting to this file once the first runlevel is
+//   # entered, off again before runlevel 0 or 6 unmounts it, or "off"
+//   process-accounting /var/log/account/pacct
+//   # Seconds the sync before a reboot or power off may take, or "off" to
+//   # wait for it however long it takes. A dying disk can't hang the
+//   # reboot forever.
+//   sync-timeout 30
+//   # Seconds after init starts by which the runlevel of the initdefault
+//   # entry, or without one the first runlevel after S, must be entered,
+//   # or "off". After that init logs the entries it still waits for and
+//   # tells on-failure-exec (with ID=boot-deadline) and the hardware
+//   # watchdog, see watchdog.rs, if they are named. The action is one of
+//   # boot-timeout-action's, or "report" to keep waiting.
+//   boot-deadline 900
+//   boot-deadline-action continue
+//   boot-deadline-notify on-failure watchdog
+//
+// Empty lines and lines starting with '#' are ignored. The file is read
+// at boot and again when init re-executes itself, shutdown reads it for
+// shutdown-warn-group.
+
+use rye_init::inittab::{self, InitAction, LongProcess, ProcessLimit};
+
+use crate::initlog::RateLimit;
+
+pub const CONFIG_FILE: &str = "/etc/rye-init/init.conf";
+
+// The emergency shells tried when the config file names none
+pub const EMERGENCY_SHELLS: &[&str] = &["/sbin/sulogin", "/usr/sbin/sulogin", "/bin/sh", "/bin/busybox sh"];
+
+pub const POWEROFF_COMMAND: &str = "/sbin/shutdown -hP now";
+pub const REBOOT_COMMAND: &str = "/sbin/shutdown -r now";
+
+// Seconds a container has to stop in, docker stop kills it after 10
+pub const CONTAINER_STOP_TIMEOUT: u64 = 8;
+
+// Seconds the sync before a reboot may take
+pub const SYNC_TIMEOUT: u64 = 30;
+
+// What init does about a boot entry that is still running when its
+// timeout is over
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BootTimeout {
+    Continue,                           // Stop waiting for it, leave it running
+    Kill,                               // Stop it and go on
+    Emergency,                          // Stop it and wait for an emergency shell
+}
+
+impl BootTimeout {
+    // "continue", "kill" or "emergency"
+    pub fn parse(s: &str) -> Option<BootTimeout> {
+        match s {
+            "continue" => Some(BootTimeout::Continue),
+            "kill" => Some(BootTimeout::Kill),
+            "emergency" => Some(BootTimeout::Emergency),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Config {
+    pub on_failure_exec: Option<String>, // Run when a respawning entry exits abnormally
+    pub console_limit: RateLimit,       // How much init may write to the console
+    pub emergency_shells: Vec<String>,  // Commands for an emergency shell, the first that runs wins
+    pub poweroff_command: String,       // SIGPWR without powerfail entries
+    pub reboot_command: String,         // Reboot without entries that do it
+    pub ctrlaltdel_command: Option<String>, // Ctrl-Alt-Del without ctrlaltdel entries, else reboot_command
+    pub hangup_ttys: bool,              // Hang up the terminals of stopped entries
+    pub lock_memory: bool,              // mlockall() init
+    pub cpu_affinity: Vec<usize>,       // CPUs init runs on, empty for all
+    pub battery_threshold: Option<u32>, // Battery percentage that means powerfailnow
+    pub reexec_on_upgrade: bool,        // Execute an upgraded init program by ourselves
+    pub kmsg: bool,                     // Mirror important events to /dev/kmsg
+    pub child_titles: bool,             // Name children after their entry in argv[0]
+    pub process_limit: ProcessLimit,    // What to do with long inittab process fields
+    pub container: Option<bool>,        // SIGTERM and SIGINT stop the container, None to detect
+    pub container_stop_timeout: u64,    // Seconds the container has to stop
+    pub sysinit_timeout: Option<u64>,   // Seconds a sysinit entry is waited for
+    pub bootwait_timeout: Option<u64>,  // Seconds a bootwait entry is waited for
+    pub boot_timeout_action: BootTimeout, // What happens to an entry that takes longer
+    pub shutdown_warn_group: Option<String>, // Group whose members may run shutdown --warn-only
+    pub early_syslog: bool,             // Bind /dev/log before the syslog daemon does
+    pub strict: bool,                   // Refuse an inittab with errors
+    pub process_accounting: Option<String>, // File acct(2) writes to while the system is up
+    pub sync_timeout: Option<u64>,      // Seconds the sync before a reboot may take
+    pub boot_deadline: Option<u64>,     // Seconds the boot may take
+    pub boot_deadline_action: Option<BootTimeout>, // What happens to the entries holding it up, None for nothing
+    pub boot_deadline_hook: bool,       // Run on-failure-exec when the boot takes longer
+    pub boot_deadline_watchdog: bool,   // Let the hardware watchdog reset a boot that takes longer
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            on_failure_exec: None,
+            console_limit: RateLimit::default(),
+            emergency_shells: EMERGENCY_SHELLS.iter().map(|s| s.to_string()).collect(),
+            poweroff_command: POWEROFF_COMMAND.to_string(),
+            reboot_command: REBOOT_COMMAND.to_string(),
+            ctrlaltdel_command: None,
+            hangup_ttys: true,
+            lock_memory: true,
+            cpu_affinity: Vec::new(),
+            battery_threshold: None,
+            reexec_on_upgrade: false,
+            kmsg: false,
+            child_titles: false,
+            process_limit: ProcessLimit::default(),
+            container: Some(false),
+            container_stop_timeout: CONTAINER_STOP_TIMEOUT,
+            sysinit_timeout: None,
+            bootwait_timeout: None,
+            boot_timeout_action: BootTimeout::Kill,
+            shutdown_warn_group: None,
+            early_syslog: false,
+            strict: false,
+            process_accounting: None,
+            sync_timeout: Some(SYNC_TIMEOUT),
+            boot_deadline: None,
+            boot_deadline_action: None,
+            boot_deadline_hook: false,
+            boot_deadline_watchdog: false,
+        }
+    }
+}
+
+impl Config {
+    // Parse a config file. Returns the settings and complaints about the
+    // lines that were not understood.
+    pub fn parse(s: &str) -> (Config, Vec<String>) {
+        let mut config = Config::default();
+        let mut errors = Vec::new();
+        let mut shells = Vec::new();
+
+        for (n, line) in s.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (key, value) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+            let value = value.trim();
+
+            match key {
+                "on-failure-exec" if !value.is_empty() => config.on_failure_exec = Some(value.to_string()),
+                "on-failure-exec" => config.on_failure_exec = None,
+                "console-rate-limit" => match parse_rate_limit(value) {
+                    Some(limit) => config.console_limit = limit,
+                    None => errors.push(format!("{}:{}: bad console-rate-limit \"{}\"", CONFIG_FILE, n + 1, value)),
+                },
+                "emergency-shell" if !value.is_empty() => shells.push(value.to_string()),
+                "emergency-shell" => errors.push(format!("{}:{}: emergency-shell without a command", CONFIG_FILE, n + 1)),
+                "poweroff-command" if !value.is_empty() => config.poweroff_command = value.to_string(),
+                "reboot-command" if !value.is_empty() => config.reboot_command = value.to_string(),
+                "ctrlaltdel-command" if !value.is_empty() => config.ctrlaltdel_command = Some(value.to_string()),
+                "hangup-ttys" => match value {
+                    "yes" => config.hangup_ttys = true,
+                    "no" => config.hangup_ttys = false,
+                    _ => errors.push(format!("{}:{}: hangup-ttys is yes or no, not \"{}\"", CONFIG_FILE, n + 1, value)),
+                },
+                "lock-memory" => match value {
+                    "yes" => config.lock_memory = true,
+                    "no" => config.lock_memory = false,
+                    _ => errors.push(format!("{}:{}: lock-memory is yes or no, not \"{}\"", CONFIG_FILE, n + 1, value)),
+                },
+                "cpu-affinity" => match value.split_whitespace().map(str::parse).collect::<Result<Vec<usize>, _>>() {
+                    Ok(cpus) => config.cpu_affinity = cpus,
+                    Err(_) => errors.push(format!("{}:{}: bad cpu-affinity \"{}\"", CONFIG_FILE, n + 1, value)),
+                },
+                "battery-threshold" if value == "off" => config.battery_threshold = None,
+                "battery-threshold" => match value.parse() {
+                    Ok(percent @ 1..=100) => config.battery_threshold = Some(percent),
+                    _ => errors.push(format!("{}:{}: bad battery-threshold \"{}\"", CONFIG_FILE, n + 1, value)),
+                },
+                "kmsg" => match value {
+                    "yes" => config.kmsg = true,
+                    "no" => config.kmsg = false,
+                    _ => errors.push(format!("{}:{}: kmsg is yes or no, not \"{}\"", CONFIG_FILE, n + 1, value)),
+                },
+                "child-titles" => match value {
+                    "yes" => config.child_titles = true,
+                    "no" => config.child_titles = false,
+                    _ => errors.push(format!("{}:{}: child-titles is yes or no, not \"{}\"", CONFIG_FILE, n + 1, value)),
+                },
+                "process-length" => match value.parse() {
+                    Ok(length @ 1..=inittab::MAX_PROCESS_LENGTH) => config.process_limit.length = length,
+                    _ => errors.push(format!("{}:{}: bad process-length \"{}\"", CONFIG_FILE, n + 1, value)),
+                },
+                "long-process" => match value.parse::<LongProcess>() {
+                    Ok(policy) => config.process_limit.policy = policy,
+                    Err(_) => errors.push(format!("{}:{}: long-process is reject, truncate or accept, not \"{}\"",
+                        CONFIG_FILE, n + 1, value)),
+                },
+                "container" => match value {
+                    "yes" => config.container = Some(true),
+                    "no" => config.container = Some(false),
+                    "auto" => config.container = None,
+                    _ => errors.push(format!("{}:{}: container is yes, no or auto, not \"{}\"", CONFIG_FILE, n + 1, value)),
+                },
+                "container-stop-timeout" => match value.parse() {
+                    Ok(secs @ 1..) => config.container_stop_timeout = secs,
+                    _ => errors.push(format!("{}:{}: bad container-stop-timeout \"{}\"", CONFIG_FILE, n + 1, value)),
+                },
+                "sysinit-timeout" | "bootwait-timeout" => match parse_timeout(value) {
+                    Some(secs) if key == "sysinit-timeout" => config.sysinit_timeout = secs,
+                    Some(secs) => config.bootwait_timeout = secs,
+                    None => errors.push(format!("{}:{}: bad {} \"{}\"", CONFIG_FILE, n + 1, key, value)),
+                },
+                "sync-timeout" => match parse_timeout(value) {
+                    Some(secs) => config.sync_timeout = secs,
+                    None => errors.push(format!("{}:{}: bad sync-timeout \"{}\"", CONFIG_FILE, n + 1, value)),
+                },
+                "boot-timeout-action" => match BootTimeout::parse(value) {
+                    Some(action) => config.boot_timeout_action = action,
+                    None => errors.push(format!("{}:{}: boot-timeout-action is continue, kill or emergency, not \"{}\"",
+                        CONFIG_FILE, n + 1, value)),
+                },
+                "boot-deadline" => match parse_timeout(value) {
+                    Some(secs) => config.boot_deadline = secs,
+                    None => errors.push(format!("{}:{}: bad boot-deadline \"{}\"", CONFIG_FILE, n + 1, value)),
+                },
+                "boot-deadline-action" => match value {
+                    "report" => config.boot_deadline_action = None,
+                    _ => match BootTimeout::parse(value) {
+                        Some(action) => config.boot_deadline_action = Some(action),
+                        None => errors.push(format!(
+                            "{}:{}: boot-deadline-action is report, continue, kill or emergency, not \"{}\"",
+                            CONFIG_FILE, n + 1, value)),
+                    },
+                },
+                "boot-deadline-notify" => {
+                    config.boot_deadline_hook = false;
+                    config.boot_deadline_watchdog = false;
+                    for word in value.split_whitespace() {
+                        match word {
+                            "on-failure" => config.boot_deadline_hook = true,
+                            "watchdog" => config.boot_deadline_watchdog = true,
+                            "none" => {}
+                            _ => errors.push(format!(
+                                "{}:{}: boot-deadline-notify takes on-failure, watchdog or none, not \"{}\"",
+                                CONFIG_FILE, n + 1, word)),
+                        }
+                    }
+                }
+                "shutdown-warn-group" if !value.is_empty() => config.shutdown_warn_group = Some(value.to_string()),
+                "shutdown-warn-group" => config.shutdown_warn_group = None,
+                "early-syslog" => match value {
+                    "yes" => config.early_syslog = true,
+                    "no" => config.early_syslog = false,
+                    _ => errors.push(format!("{}:{}: early-syslog is yes or no, not \"{}\"", CONFIG_FILE, n + 1, value)),
+                },
+                "process-accounting" => match value {
+                    "off" => config.process_accounting = None,
+                    _ if value.starts_with('/') => config.process_accounting = Some(value.to_string()),
+                    _ => errors.push(format!("{}:{}: process-accounting is a file or off, not \"{}\"",
+                        CONFIG_FILE, n + 1, value)),
+                },
+                "strict" => match value {
+                    "yes" => config.strict = true,
+                    "no" => config.strict = false,
+                    _ => errors.push(format!("{}:{}: strict is yes or no, not \"{}\"", CONFIG_FILE, n + 1, value)),
+                },
+                "on-upgrade" => match value {
+                    "manual" => config.reexec_on_upgrade = false,
+                    "re-exec" => config.reexec_on_upgrade = true,
+                    _ => errors.push(format!("{}:{}: on-upgrade is manual or re-exec, not \"{}\"", CONFIG_FILE, n + 1, value)),
+                },
+                "poweroff-command" | "reboot-command" | "ctrlaltdel-command" => {
+                    errors.push(format!("{}:{}: {} without a command", CONFIG_FILE, n + 1, key))
+                }
+                _ => errors.push(format!("{}:{}: unknown setting \"{}\"", CONFIG_FILE, n + 1, key)),
+            }
+        }
+
+        if !shells.is_empty() {
+            config.emergency_shells = shells;
+        }
+        (config, errors)
+    }
+
+    // How long init waits for a boot entry with this action, None for as
+    // long as it takes
+    pub fn boot_timeout(&self, action: InitAction) -> Option<u64> {
+        match action {
+            InitAction::SysInit => self.sysinit_timeout,
+            InitAction::BootWait => self.bootwait_timeout,
+            _ => None,
+        }
+    }
+
+    // Read CONFIG_FILE. A missing file means the defaults.
+    pub fn load() -> (Config, Vec<String>) {
+        match std::fs::read_to_string(rye_init::paths::path(CONFIG_FILE)) {
+            Ok(s) => Config::parse(&s),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => (Config::default(), Vec::new()),
+            Err(e) => (Config::default(), vec![format!("{}: {}", CONFIG_FILE, e)]),
+        }
+    }
+}
+
+// "off" or a number of seconds
+fn parse_timeout(value: &str) -> Option<Option<u64>> {
+    if value == "off" {
+        return Some(None);
+    }
+    match value.parse() {
+        Ok(secs @ 1..) => Some(Some(secs)),
+        _ => None,
+    }
+}
+
+// "off", "<rate>" or "<rate> <burst>"
+fn parse_rate_limit(value: &str) -> Option<RateLimit> {
+    let words: Vec<&str> = value.split_whitespace().collect();
+    match words.as_slice() {
+        ["off"] => Some(RateLimit { rate: 0, ..RateLimit::default() }),
+        [rate] => Some(RateLimit { rate: rate.parse().ok()?, ..RateLimit::default() }),
+        [rate, burst] => Some(RateLimit { rate: rate.parse().ok()?, burst: burst.parse().ok()? }),
+        _ => None,
+    }
+}
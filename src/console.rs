@@ -0,0 +1,24 @@
+// Console handling for init
+
+use std::io::Write;
+use std::time::Duration;
+
+use rye_init::tty;
+
+pub const CONSOLE: &str = "/dev/console";
+
+// Ask a question on the console and wait for a line of input, see
+// tty::read_line. None when nothing was typed for timeout milliseconds or
+// if the console can't be used, a console that doesn't take the prompt
+// right away included.
+pub fn console_ask(dev: &str, prompt: &str, timeout: u16) -> Option<String> {
+    let mut f = tty::open_nonblocking(dev).ok()?;
+    f.write_all(prompt.as_bytes()).ok()?;
+    read_line(&f, timeout)
+}
+
+// A line typed on the console, with backspace and ^U, as UTF-8 or else
+// Latin-1
+pub fn read_line(f: &std::fs::File, timeout: u16) -> Option<String> {
+    tty::read_line(f, Some(Duration::from_millis(timeout as u64)), true).map(|line| line.trim().to_string())
+}
@@ -0,0 +1,183 @@
+// The control socket
+//
+// Requests on the FIFO can come from anybody who can open it, and init has
+// no way to tell who that was. The control socket takes the same requests,
+// one init_request per SOCK_SEQPACKET message, and the kernel tells us the
+// uid and pid of the process on the other end.
+
+use std::os::fd::{AsRawFd, BorrowedFd, IntoRawFd, OwnedFd};
+
+use nix::sys::socket::{self, sockopt, AddressFamily, Backlog, MsgFlags, SockFlag, SockType, UnixAddr};
+use nix::sys::stat::{self, Mode};
+use nix::sys::time::{TimeVal, TimeValLike};
+use rye_init::initreq::{self, InitRequest, INIT_REQUEST_SIZE};
+use rye_init::paths;
+
+// Who sent a request
+#[derive(Debug, Clone, Copy)]
+pub struct Peer {
+    pub uid: u32,
+    pub pid: i32,
+}
+
+// How long a client waits for the answer to INIT_CMD_FEATURES. An init
+// from before that request never answers it.
+const FEATURES_TIMEOUT: i64 = 2;
+
+// How long a client waits for init to take or refuse a request
+const ACK_TIMEOUT: i64 = 5;
+
+// What the init on the other end of the control socket understands
+#[derive(Debug, Clone, Default)]
+pub struct Features {
+    pub version: i32,                   // Its CONTROL_VERSION, 0 if it didn't say
+    pub commands: Vec<String>,          // Names of the extensions it answers
+}
+
+impl Features {
+    pub fn supports(&self, cmd: i32) -> bool {
+        let name = InitRequest::new(cmd).cmd_name();
+        self.commands.iter().any(|c| c == name)
+    }
+}
+
+pub enum ControlRead {
+    Request(Box<InitRequest>),          // Boxed, a request dwarfs the other variants
+    Garbage(usize),                     // A message that is no request
+    Nothing,                            // Would block
+    Closed,
+}
+
+// Create the listening socket. With root_only the socket is not even
+// accessible to other users.
+pub fn control_listen(path: &str, root_only: bool) -> nix::Result<i32> {
+    if let Some(dir) = std::path::Path::new(path).parent() {
+        let _ = std::fs::create_dir_all(dir);
+    }
+    let _ = std::fs::remove_file(path);
+
+    let fd = socket::socket(
+        AddressFamily::Unix,
+        SockType::SeqPacket,
+        SockFlag::SOCK_CLOEXEC | SockFlag::SOCK_NONBLOCK,
+        None,
+    )?;
+
+    socket::bind(fd.as_raw_fd(), &UnixAddr::new(path)?)?;
+    let mode = if root_only { 0o600 } else { 0o666 };
+    stat::fchmodat(nix::fcntl::AT_FDCWD, path, Mode::from_bits_truncate(mode), stat::FchmodatFlags::FollowSymlink)?;
+    socket::listen(&fd, Backlog::new(8)?)?;
+
+    Ok(fd.into_raw_fd())
+}
+
+// Accept a connection and find out who is on the other side
+pub fn control_accept(listen_fd: i32) -> Option<(i32, Peer)> {
+    let fd = socket::accept4(listen_fd, SockFlag::SOCK_CLOEXEC | SockFlag::SOCK_NONBLOCK).ok()?;
+
+    let borrowed = unsafe { BorrowedFd::borrow_raw(fd) };
+    match socket::getsockopt(&borrowed, sockopt::PeerCredentials) {
+        Ok(cred) => Some((fd, Peer { uid: cred.uid(), pid: cred.pid() })),
+        Err(_) => {
+            let _ = nix::unistd::close(fd);
+            None
+        }
+    }
+}
+
+pub fn control_recv(fd: i32) -> ControlRead {
+    let mut buf = [0u8; INIT_REQUEST_SIZE + 1];
+    match socket::recv(fd, &mut buf, MsgFlags::empty()) {
+        Ok(0) => ControlRead::Closed,
+        Ok(n) => match InitRequest::from_bytes(&buf[..n]) {
+            Some(req) if req.is_valid() => ControlRead::Request(Box::new(req)),
+            _ => ControlRead::Garbage(n),
+        },
+        Err(nix::errno::Errno::EAGAIN) | Err(nix::errno::Errno::EINTR) => ControlRead::Nothing,
+        Err(_) => ControlRead::Closed,
+    }
+}
+
+// Tell a client how its request is getting on
+pub fn control_ack(fd: i32, status: i32) {
+    let mut ack = InitRequest::new(initreq::INIT_CMD_ACK);
+    ack.runlevel = status;
+    let _ = control_reply(fd, &ack);
+}
+
+// Tell a client its request was refused, and why: one of the
+// INIT_REFUSED_ reasons
+pub fn control_refuse(fd: i32, reason: i32) {
+    let mut ack = InitRequest::new(initreq::INIT_CMD_ACK);
+    ack.runlevel = initreq::INIT_ACK_REFUSED;
+    ack.sleeptime = reason;
+    let _ = control_reply(fd, &ack);
+}
+
+// Send a client something other than an ack
+pub fn control_reply(fd: i32, req: &InitRequest) -> nix::Result<()> {
+    socket::send(fd, &req.to_bytes(), MsgFlags::MSG_NOSIGNAL)?;
+    Ok(())
+}
+
+// Client side: connect to init, to send requests and read the answers
+pub fn control_connect(path: &str) -> nix::Result<OwnedFd> {
+    let fd = socket::socket(AddressFamily::Unix, SockType::SeqPacket, SockFlag::SOCK_CLOEXEC, None)?;
+    socket::connect(fd.as_raw_fd(), &UnixAddr::new(path)?)?;
+    Ok(fd)
+}
+
+// Client side: ask init which extensions it has. An init that doesn't
+// answer has none.
+pub fn control_features(path: &str) -> nix::Result<Features> {
+    let fd = control_connect(path)?;
+    let _ = socket::setsockopt(&fd, sockopt::ReceiveTimeout, &TimeVal::seconds(FEATURES_TIMEOUT));
+    socket::send(fd.as_raw_fd(), &InitRequest::new(initreq::INIT_CMD_FEATURES).to_bytes(), MsgFlags::empty())?;
+
+    loop {
+        match control_recv(fd.as_raw_fd()) {
+            ControlRead::Request(r) if r.cmd == initreq::INIT_CMD_FEATURES => {
+                return Ok(Features {
+                    version: r.runlevel,
+                    commands: r.data_str().split_whitespace().map(str::to_string).collect(),
+                });
+            }
+            ControlRead::Request(_) | ControlRead::Garbage(_) => {}
+            ControlRead::Nothing | ControlRead::Closed => return Ok(Features::default()),
+        }
+    }
+}
+
+// Client side: send a request over the control socket
+pub fn control_send(path: &str, req: &InitRequest) -> nix::Result<()> {
+    let fd = control_connect(path)?;
+    socket::send(fd.as_raw_fd(), &req.to_bytes(), MsgFlags::empty())?;
+    Ok(())
+}
+
+// Client side: send a request over the control socket and wait for the
+// ack. None if init didn't answer in time.
+pub fn control_ask(path: &str, req: &InitRequest) -> nix::Result<Option<InitRequest>> {
+    let fd = control_connect(path)?;
+    let _ = socket::setsockopt(&fd, sockopt::ReceiveTimeout, &TimeVal::seconds(ACK_TIMEOUT));
+    socket::send(fd.as_raw_fd(), &req.to_bytes(), MsgFlags::empty())?;
+
+    loop {
+        match control_recv(fd.as_raw_fd()) {
+            ControlRead::Request(ack) if ack.cmd == initreq::INIT_CMD_ACK => return Ok(Some(*ack)),
+            ControlRead::Request(_) | ControlRead::Garbage(_) => {}
+            ControlRead::Nothing | ControlRead::Closed => return Ok(None),
+        }
+    }
+}
+
+// Hand a request to init: over the control socket if init has one, so it
+// knows who we are, otherwise through the FIFO
+pub fn send_request(req: &InitRequest) -> std::io::Result<()> {
+    if control_send(&paths::path(initreq::INIT_SOCKET), req).is_ok() {
+        return Ok(());
+    }
+
+    let mut fifo = std::fs::OpenOptions::new().write(true).open(paths::path(initreq::INIT_FIFO))?;
+    std::io::Write::write_all(&mut fifo, &req.to_bytes())
+}
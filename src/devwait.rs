@@ -0,0 +1,64 @@
+// Waiting for terminals that aren't there yet
+//
+// On hardware that is slow to probe, a serial console or the terminal of a
+// getty may only show up in /dev some time after its runlevel has come. A
+// getty started before that dies right away and counts against the respawn
+// limit. Instead a respawning entry whose terminal is missing waits for it,
+// up to DEVICE_WAIT. Init watches /dev with inotify and tries again
+// whenever something is created there; once DEVICE_WAIT is over the entry
+// is started anyway.
+
+use std::os::fd::{AsFd, AsRawFd};
+
+use nix::sys::inotify::{AddWatchFlags, InitFlags, Inotify};
+
+pub const DEV_DIR: &str = "/dev";
+pub const DEVICE_WAIT: u64 = 30_000;    // Milliseconds an entry waits for its terminal
+
+#[derive(Debug, Default)]
+pub struct DevWatch {
+    inotify: Option<Inotify>,
+}
+
+impl DevWatch {
+    pub fn new() -> Self {
+        DevWatch::default()
+    }
+
+    // Start watching DEV_DIR, the first time something waits. Returns the
+    // fd for the event loop to watch, None if it already is.
+    pub fn open(&mut self) -> nix::Result<Option<i32>> {
+        if self.inotify.is_some() {
+            return Ok(None);
+        }
+
+        let inotify = Inotify::init(InitFlags::IN_NONBLOCK | InitFlags::IN_CLOEXEC)?;
+        inotify.add_watch(DEV_DIR, AddWatchFlags::IN_CREATE | AddWatchFlags::IN_ATTRIB | AddWatchFlags::IN_MOVED_TO)?;
+        let fd = inotify.as_fd().as_raw_fd();
+        self.inotify = Some(inotify);
+        Ok(Some(fd))
+    }
+
+    // Throw away the events, we only look at whether the devices exist
+    pub fn drain(&self) {
+        if let Some(inotify) = &self.inotify {
+            while let Ok(events) = inotify.read_events() {
+                if events.is_empty() {
+                    break;
+                }
+            }
+        }
+    }
+}
+
+// The devices of a process field that look like terminals, like the
+// "ttyS0" of a getty command line
+pub fn terminal_args(process: &str) -> Vec<String> {
+    process
+        .split_whitespace()
+        .filter_map(|w| {
+            let name = w.strip_prefix("/dev/").unwrap_or(w);
+            (name.starts_with("tty") && name.len() > 3).then(|| format!("{}/{}", DEV_DIR, name))
+        })
+        .collect()
+}
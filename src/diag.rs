@@ -0,0 +1,139 @@
+// The diagnostic dump: everything init knows, for a bug report
+//
+// When init seems stuck, SIGQUIT or `rye-initctl diag` has it write what it
+// is doing right now: the runlevel, every entry with its flags, pid and
+// respawn counters, the timers, the debug messages of the ring (see
+// initlog.rs) and its open fds. The dump goes to DIAG_FILE, replacing the
+// one before, and to the console. A console that doesn't take it within
+// CONSOLE_TIMEOUT gets the rest cut off, init doesn't hang on a serial line
+// somebody pressed Ctrl-S on. rye-initctl diag gets it too.
+//
+// The values of the variables set with rye-initctl set-env are left out,
+// only their names are in the dump.
+
+use std::fmt::Write as _;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::os::fd::AsFd;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+
+use nix::poll::{poll, PollFd, PollFlags, PollTimeout};
+
+use rye_init::{bootid, paths, tty};
+
+use crate::{clock, initlog, InitState};
+
+pub const DIAG_FILE: &str = "/run/rye-init/diag.txt";
+const CONSOLE_TIMEOUT: u64 = 2000;      // Milliseconds the console gets for the dump
+
+// The dump, as text
+pub fn report(state: &InitState) -> String {
+    let mut out = String::new();
+    let now = clock::monotonic_ms();
+
+    let _ = writeln!(out, "rye-init {} diagnostic dump, pid {}", crate::VERSION, std::process::id());
+    let _ = writeln!(out, "time {} monotonic {} boot id {}", clock::now(), now,
+        bootid::boot_id().unwrap_or_else(|| "unknown".to_string()));
+    let _ = writeln!(out, "runlevel {} previous {} default {}", state.curlevel, state.prevlevel, state.dfl_level);
+    let entering = state.entering.map(|t| format!("{} ms", t.elapsed().as_millis()));
+    let _ = writeln!(out, "switching for {} did_boot {} reload {} emergency shell {} debug {}",
+        entering.as_deref().unwrap_or("-"), state.did_boot, state.reload, state.emerg_shell, initlog::debug());
+    let vars: Vec<&str> = state.extra_env.vars.iter().flatten()
+        .map(|v| v.split('=').next().unwrap_or(v))
+        .collect();
+    let _ = writeln!(out, "set-env {}", if vars.is_empty() { "-".to_string() } else { vars.join(" ") });
+
+    let _ = writeln!(out, "\nentries:");
+    for child in state.children() {
+        let _ = writeln!(out, "  {} {} {} pid {} pgid {} flags {} exstat {} count {} respawns {} tm {}",
+            child.id, child.rlevel, child.action.as_str(), child.pid, child.pgid, child.flags.names(),
+            child.exstat, child.count, child.respawns, child.tm);
+        if child.due != 0 || child.dev_wait != 0 {
+            let _ = writeln!(out, "    due {} dev_wait {}", child.due, child.dev_wait);
+        }
+        if !child.process.is_empty() {
+            let _ = writeln!(out, "    {}", child.process);
+        }
+    }
+
+    let _ = writeln!(out, "\ntimers:");
+    for (due, what) in state.timers.pending() {
+        let _ = writeln!(out, "  {} (in {} ms) {:?}", due, due.saturating_sub(now), what);
+    }
+
+    let _ = writeln!(out, "\nfds:");
+    for (fd, target) in open_fds() {
+        let _ = writeln!(out, "  {} {}", fd, target);
+    }
+    let conns: Vec<String> = state.control_conns.iter()
+        .map(|(fd, peer)| format!("{} (uid {} pid {})", fd, peer.uid, peer.pid))
+        .collect();
+    let _ = writeln!(out, "  fifo {} control {} timer {} connections {}", state.pipe_fd, state.control_fd,
+        state.timer_fd, if conns.is_empty() { "-".to_string() } else { conns.join(", ") });
+
+    let _ = writeln!(out, "\ndebug messages:");
+    for msg in initlog::debug_messages() {
+        let _ = writeln!(out, "  {}", msg);
+    }
+    out
+}
+
+// Our open fds and what they are
+fn open_fds() -> Vec<(i32, String)> {
+    let Ok(dir) = std::fs::read_dir("/proc/self/fd") else {
+        return Vec::new();
+    };
+    let mut fds: Vec<(i32, String)> = dir
+        .flatten()
+        .filter_map(|entry| {
+            let fd = entry.file_name().to_str()?.parse().ok()?;
+            let target = std::fs::read_link(entry.path()).ok()?;
+            Some((fd, target.to_string_lossy().into_owned()))
+        })
+        .collect();
+    fds.sort();
+    fds
+}
+
+// Replace DIAG_FILE with the dump
+pub fn write_file(text: &str) -> io::Result<()> {
+    let file = paths::path(DIAG_FILE);
+    let path = Path::new(&file);
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)?
+        .write_all(text.as_bytes())
+}
+
+// Write the dump to the console, as much as it takes in CONSOLE_TIMEOUT
+pub fn write_console(console: &str, text: &str) -> io::Result<()> {
+    let mut f = tty::open_nonblocking(console)?;
+    let data = text.replace('\n', "\r\n");
+    let mut data = data.as_bytes();
+    let deadline = clock::monotonic_ms() + CONSOLE_TIMEOUT;
+
+    while !data.is_empty() {
+        match f.write(data) {
+            Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+            Ok(n) => data = &data[n..],
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                let left = deadline.saturating_sub(clock::monotonic_ms());
+                if left == 0 {
+                    return Err(io::Error::new(io::ErrorKind::TimedOut, "console too slow, dump cut short"));
+                }
+                let mut fds = [PollFd::new(f.as_fd(), PollFlags::POLLOUT)];
+                let _ = poll(&mut fds, PollTimeout::try_from(left).unwrap_or(PollTimeout::MAX));
+            }
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,143 @@
+// Keeping early syslog messages until the syslog daemon is there
+//
+// On a sysv-style boot the syslog daemon comes up somewhere in the boot
+// scripts, and what the daemons started before it send to /dev/log is
+// lost: there is nobody on the socket. With early-syslog in the config
+// file init binds /dev/log itself at boot, when nobody has, and keeps the
+// messages that arrive there, up to MAX_BUFFERED bytes; after that the
+// oldest go.
+//
+// A syslog daemon removes /dev/log and binds its own. Init checks every
+// CHECK_INTERVAL whether the socket at DEV_LOG is still its own. Once it
+// isn't, init closes its socket and sends the daemon the messages it kept,
+// as they came, so they still carry the time they were written. Messages
+// the daemon can't take right now are sent at the next check.
+//
+// A /dev/log that nobody listens on, like the one an init before an
+// execution of itself left behind, is taken over; the messages that init
+// had are lost.
+
+use std::collections::VecDeque;
+use std::io;
+use std::os::fd::AsRawFd;
+use std::os::unix::fs::{FileTypeExt, MetadataExt, PermissionsExt};
+use std::os::unix::net::UnixDatagram;
+
+use rye_init::paths;
+
+pub const DEV_LOG: &str = "/dev/log";
+pub const CHECK_INTERVAL: u64 = 1000;
+const MAX_BUFFERED: usize = 256 * 1024;
+const MAX_MESSAGE: usize = 8192;        // Longer messages are cut short
+
+#[derive(Debug, Default)]
+pub struct EarlyLog {
+    socket: Option<UnixDatagram>,
+    inode: (u64, u64),                  // Device and inode of our /dev/log
+    messages: VecDeque<Vec<u8>>,        // Kept for the syslog daemon, oldest first
+    size: usize,                        // Bytes in messages
+    pub dropped: usize,                 // Messages that didn't fit
+}
+
+impl EarlyLog {
+    pub fn new() -> Self {
+        EarlyLog::default()
+    }
+
+    // Whether there is something left to do
+    pub fn active(&self) -> bool {
+        self.socket.is_some() || !self.messages.is_empty()
+    }
+
+    // Bind DEV_LOG, unless a syslog daemon has it already. Returns the fd
+    // for the event loop to watch, None if there is nothing to watch.
+    pub fn open(&mut self) -> io::Result<Option<i32>> {
+        if self.socket.is_some() {
+            return Ok(None);
+        }
+
+        let path = paths::path(DEV_LOG);
+        match UnixDatagram::unbound()?.connect(&path) {
+            Ok(()) => return Ok(None),
+            Err(e) if e.raw_os_error() == Some(libc::ECONNREFUSED) => std::fs::remove_file(&path)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+
+        let socket = UnixDatagram::bind(&path)?;
+        socket.set_nonblocking(true)?;
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o666))?;
+        let meta = std::fs::metadata(&path)?;
+        self.inode = (meta.dev(), meta.ino());
+        let fd = socket.as_raw_fd();
+        self.socket = Some(socket);
+        Ok(Some(fd))
+    }
+
+    // Keep what arrived on our socket
+    pub fn drain(&mut self) {
+        let Some(socket) = &self.socket else {
+            return;
+        };
+        let mut buf = [0u8; MAX_MESSAGE];
+        while let Ok(n) = socket.recv(&mut buf) {
+            self.messages.push_back(buf[..n].to_vec());
+            self.size += n;
+            while self.size > MAX_BUFFERED {
+                let Some(old) = self.messages.pop_front() else {
+                    break;
+                };
+                self.size -= old.len();
+                self.dropped += 1;
+            }
+        }
+    }
+
+    // Whether a syslog daemon has put its own socket at DEV_LOG. A missing
+    // one may be on its way, the daemon removes ours first.
+    pub fn taken_over(&self) -> bool {
+        if self.socket.is_none() {
+            return false;
+        }
+        match std::fs::metadata(paths::path(DEV_LOG)) {
+            Ok(meta) => meta.file_type().is_socket() && (meta.dev(), meta.ino()) != self.inode,
+            Err(_) => false,
+        }
+    }
+
+    // Our socket, for the event loop
+    pub fn fd(&self) -> Option<i32> {
+        self.socket.as_ref().map(|socket| socket.as_raw_fd())
+    }
+
+    // Give up our socket, keeping what is still in it. The event loop must
+    // have forgotten it.
+    pub fn close(&mut self) {
+        self.drain();
+        self.socket = None;
+    }
+
+    // Send the syslog daemon what we kept, as much as it takes now.
+    // Returns the number of messages sent.
+    pub fn replay(&mut self) -> io::Result<usize> {
+        if self.socket.is_some() || self.messages.is_empty() {
+            return Ok(0);
+        }
+
+        let socket = UnixDatagram::unbound()?;
+        socket.set_nonblocking(true)?;
+        socket.connect(paths::path(DEV_LOG))?;
+        let mut sent = 0;
+        while let Some(msg) = self.messages.front() {
+            match socket.send(msg) {
+                Ok(_) => {}
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) => return Err(e),
+            }
+            self.size -= msg.len();
+            self.messages.pop_front();
+            sent += 1;
+        }
+        Ok(sent)
+    }
+}
@@ -0,0 +1,95 @@
+// The init main loop waits on a single epoll instance. Every watched fd is
+// registered together with the kind of event it delivers, so the loop can
+// dispatch without having to look the fd up first.
+//
+// Child exits are picked up through pidfds: a pidfd becomes readable when its
+// process terminates. Unlike SIGCHLD these notifications can't be coalesced,
+// so a burst of simultaneous exits is never lost, and they keep working when
+// something else (a test harness, a debugger) is meddling with SIGCHLD.
+
+use std::os::fd::BorrowedFd;
+
+use nix::sys::epoll::{Epoll, EpollCreateFlags, EpollEvent, EpollFlags, EpollTimeout};
+
+// Maximum number of events fetched per wait
+const MAX_EVENTS: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EventSource {
+    ChildExit = 1,          // pidfd of a child became readable
+    InitFifo = 2,           // a request arrived on /run/initctl
+    ControlListen = 3,      // a connection to the control socket
+    ControlConn = 4,        // a request on a control connection
+    Timer = 5,              // the timerfd of the timer wheel went off
+    DevCreated = 6,         // something appeared in /dev
+    EarlyLog = 7,           // a message on init's /dev/log
+}
+
+impl EventSource {
+    fn from_u32(n: u32) -> Option<Self> {
+        match n {
+            1 => Some(EventSource::ChildExit),
+            2 => Some(EventSource::InitFifo),
+            3 => Some(EventSource::ControlListen),
+            4 => Some(EventSource::ControlConn),
+            5 => Some(EventSource::Timer),
+            6 => Some(EventSource::DevCreated),
+            7 => Some(EventSource::EarlyLog),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct EventLoop {
+    epoll: Epoll,
+}
+
+impl EventLoop {
+    pub fn new() -> nix::Result<Self> {
+        Ok(EventLoop {
+            epoll: Epoll::new(EpollCreateFlags::EPOLL_CLOEXEC)?,
+        })
+    }
+
+    pub fn watch(&self, source: EventSource, fd: i32) -> nix::Result<()> {
+        if fd < 0 {
+            return Ok(());
+        }
+
+        let data = ((source as u64) << 32) | fd as u32 as u64;
+        let fd = unsafe { BorrowedFd::borrow_raw(fd) };
+        self.epoll.add(fd, EpollEvent::new(EpollFlags::EPOLLIN, data))
+    }
+
+    // The fd must be unwatched before it is closed
+    pub fn unwatch(&self, fd: i32) {
+        if fd < 0 {
+            return;
+        }
+
+        let fd = unsafe { BorrowedFd::borrow_raw(fd) };
+        let _ = self.epoll.delete(fd);
+    }
+
+    // Wait up to timeout milliseconds (-1 waits forever) and return the
+    // sources that are ready. An interrupted wait returns nothing.
+    pub fn wait(&self, timeout: i32) -> Vec<(EventSource, i32)> {
+        let mut events = [EpollEvent::empty(); MAX_EVENTS];
+        let timeout = EpollTimeout::try_from(timeout).unwrap_or(EpollTimeout::NONE);
+
+        let n = match self.epoll.wait(&mut events, timeout) {
+            Ok(n) => n,
+            Err(_) => return Vec::new(),
+        };
+
+        events[..n]
+            .iter()
+            .filter_map(|ev| {
+                let data = ev.data();
+                let source = EventSource::from_u32((data >> 32) as u32)?;
+                Some((source, data as u32 as i32))
+            })
+            .collect()
+    }
+}
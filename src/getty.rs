@@ -0,0 +1,113 @@
+//! Getty entries for the console.
+//!
+//! After moving a machine from a VGA console to a serial one the inittab
+//! often still only has gettys on the virtual terminals. This finds the
+//! consoles the kernel is using and builds getty entries for them.
+
+use crate::inittab::{Attributes, Entry, InitAction, INITTAB_ID};
+
+/// The consoles the kernel writes to, the last one is `/dev/console`.
+pub const ACTIVE_CONSOLES: &str = "/sys/class/tty/console/active";
+
+/// The kernel command line, for the `console=` arguments.
+pub const KERNEL_CMDLINE: &str = "/proc/cmdline";
+
+/// The speed of a serial console that doesn't name one.
+pub const DEFAULT_BAUD: u32 = 115200;
+
+/// Where a getty given without a path is looked for.
+pub const GETTY_PATH: &[&str] = &["/sbin", "/usr/sbin", "/bin", "/usr/bin"];
+
+/// The names (`tty0`, `ttyS0`, ...) of the active consoles.
+pub fn active_consoles() -> Vec<String> {
+    std::fs::read_to_string(ACTIVE_CONSOLES)
+        .map(|s| s.split_whitespace().map(|n| n.to_string()).collect())
+        .unwrap_or_default()
+}
+
+/// The serial consoles, as name and speed: the active consoles that
+/// aren't virtual terminals and the `console=` arguments of the kernel
+/// command line, `console=ttyS0,9600n8` gives the speed.
+pub fn serial_consoles() -> Vec<(String, u32)> {
+    let cmdline = std::fs::read_to_string(KERNEL_CMDLINE).unwrap_or_default();
+    let mut consoles: Vec<(String, u32)> = Vec::new();
+    let named = cmdline.split_whitespace().filter_map(|arg| arg.strip_prefix("console=")).map(|arg| {
+        let (name, options) = arg.split_once(',').unwrap_or((arg, ""));
+        let baud = options.split(|c: char| !c.is_ascii_digit()).next().and_then(|b| b.parse().ok());
+        (name.trim_start_matches("/dev/").to_string(), baud)
+    });
+
+    for (name, baud) in active_consoles().into_iter().map(|name| (name, None)).chain(named) {
+        if name.is_empty() || name == "tty" || is_vt(&name) {
+            continue;
+        }
+        match consoles.iter_mut().find(|(n, _)| *n == name) {
+            Some((_, speed)) => *speed = baud.unwrap_or(*speed),
+            None => consoles.push((name, baud.unwrap_or(DEFAULT_BAUD))),
+        }
+    }
+    consoles
+}
+
+/// Whether a console is a virtual terminal rather than a serial line.
+pub fn is_vt(name: &str) -> bool {
+    name.strip_prefix("tty").is_some_and(|n| !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()))
+}
+
+/// Whether some entry runs on the console `name`. `tty0` is whichever
+/// virtual terminal is in front, so a getty on any of them will do.
+pub fn has_getty(entries: &[Entry], name: &str) -> bool {
+    let mentions = |e: &Entry, tty: &str| {
+        e.attrs.tty.as_deref().is_some_and(|t| t.trim_start_matches("/dev/") == tty)
+            || e.process.replace("%i", &e.id).split_whitespace().any(|w| w.trim_start_matches("/dev/") == tty)
+    };
+
+    entries.iter().any(|e| {
+        if name == "tty0" {
+            (1..64).any(|n| mentions(e, &format!("tty{}", n)))
+        } else {
+            mentions(e, name)
+        }
+    })
+}
+
+/// Find a program on [`GETTY_PATH`] unless it is given with a path.
+pub fn find_program(prog: &str) -> Option<String> {
+    if prog.contains('/') {
+        return Some(prog.to_string());
+    }
+    GETTY_PATH
+        .iter()
+        .map(|dir| format!("{}/{}", dir, prog))
+        .find(|path| std::path::Path::new(path).exists())
+}
+
+/// A respawning getty entry for the console `name`, with an id not yet in
+/// `entries`.
+pub fn getty_entry(getty: &str, name: &str, entries: &[Entry]) -> Entry {
+    let base: String = name.chars().filter(|c| c.is_ascii_alphanumeric()).collect();
+    let mut id: String = base.chars().rev().take(INITTAB_ID).collect::<Vec<_>>().into_iter().rev().collect();
+    let mut n = 0;
+    while entries.iter().any(|e| e.id == id) {
+        n += 1;
+        id = format!("gt{}", n);
+    }
+
+    // tty0 itself is no place for a getty
+    let process = if name == "tty0" {
+        format!("{} tty1 linux", getty)
+    } else if is_vt(name) {
+        format!("{} {} linux", getty, name)
+    } else {
+        format!("{} -L {} 115200 vt100", getty, name)
+    };
+
+    Entry {
+        id,
+        runlevels: "2345".to_string(),
+        action: InitAction::Respawn,
+        process,
+        attrs: Attributes::default(),
+        delay: 0,
+    }
+}
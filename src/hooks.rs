@@ -0,0 +1,196 @@
+// Runlevel transition hooks
+//
+// Executables in /etc/rye-init/hooks.d/pre-level-change run before init
+// stops the processes of the old runlevel, the ones in post-level-change
+// once the new runlevel has been entered. They run one at a time in name
+// order with RUNLEVEL and PREVLEVEL set, and init waits for each, but never
+// longer than HOOK_TIMEOUT. What they print goes to the init log.
+//
+// The on-failure-exec command from init.conf runs whenever a respawning
+// entry exits with a non-zero status or is killed by a signal, with ID,
+// EXIT_STATUS and SIGNAL set. Init doesn't wait for it, it may well be
+// talking to the network. With boot-deadline-notify on-failure it also
+// runs when the boot takes longer than boot-deadline, with ID set to
+// BOOT_DEADLINE_ID and WAITING to the ids of the entries init still waits
+// for.
+
+use std::io::Read;
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+use nix::fcntl::{self, FcntlArg, OFlag};
+use nix::sys::signal::Signal;
+use rye_init::paths;
+use rye_init::runlevel::Runlevel;
+
+use crate::{spawn, InitLogger, LogLevel};
+
+pub const HOOKS_DIR: &str = "/etc/rye-init/hooks.d";
+pub const PRE_LEVEL_CHANGE: &str = "pre-level-change";
+pub const POST_LEVEL_CHANGE: &str = "post-level-change";
+
+pub const BOOT_DEADLINE_ID: &str = "boot-deadline";
+
+const HOOK_TIMEOUT: Duration = Duration::from_secs(10);
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+const MAX_OUTPUT: usize = 4096;         // Bytes of output per hook that get logged
+
+// The hooks of a stage, in the order they run. Editor backups and hidden
+// files are left alone, as is anything not executable.
+fn hooks(stage: &str) -> Vec<PathBuf> {
+    let Ok(dir) = std::fs::read_dir(Path::new(&paths::path(HOOKS_DIR)).join(stage)) else {
+        return Vec::new();
+    };
+
+    let mut hooks: Vec<PathBuf> = dir
+        .flatten()
+        .filter(|e| {
+            let name = e.file_name();
+            let name = name.to_string_lossy();
+            !name.starts_with('.') && !name.ends_with('~')
+        })
+        .filter(|e| {
+            std::fs::metadata(e.path())
+                .is_ok_and(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+        })
+        .map(|e| e.path())
+        .collect();
+    hooks.sort();
+    hooks
+}
+
+// Run one hook. Returns its output, and what went wrong if it didn't exit
+// with status 0.
+fn run_hook(path: &Path, runlevel: &Runlevel, prevlevel: &Runlevel) -> Result<(Vec<u8>, Option<String>), String> {
+    let (rd, wr) = nix::unistd::pipe2(OFlag::O_CLOEXEC).map_err(|e| e.to_string())?;
+    let _ = fcntl::fcntl(&rd, FcntlArg::F_SETFL(OFlag::O_NONBLOCK));
+    let wr2 = wr.try_clone().map_err(|e| e.to_string())?;
+
+    let mut command = Command::new(path);
+    command
+        .env_clear()
+        .env("INIT_VERSION", format!("rye-init-{}", crate::VERSION))
+        .env("PATH", crate::PATH_DEFAULT)
+        .env("RUNLEVEL", runlevel.to_string())
+        .env("PREVLEVEL", prevlevel.to_string())
+        .stdin(Stdio::null())
+        .stdout(Stdio::from(wr))
+        .stderr(Stdio::from(wr2));
+    // Nothing else of init's goes to the hook
+    unsafe {
+        command.pre_exec(|| {
+            spawn::cloexec_from(3);
+            Ok(())
+        })
+    };
+    let mut child = command.spawn().map_err(|e| e.to_string())?;
+
+    let mut pipe = std::fs::File::from(rd);
+    let mut output = Vec::new();
+    let deadline = Instant::now() + HOOK_TIMEOUT;
+    let failed = loop {
+        drain(&mut pipe, &mut output);
+        match child.try_wait() {
+            Ok(Some(status)) if status.success() => break None,
+            Ok(Some(status)) => break Some(status.to_string()),
+            Ok(None) if Instant::now() >= deadline => {
+                let _ = child.kill();
+                let _ = child.wait();
+                break Some(format!("killed after {} seconds", HOOK_TIMEOUT.as_secs()));
+            }
+            Ok(None) => std::thread::sleep(POLL_INTERVAL),
+            Err(e) => break Some(e.to_string()),
+        }
+    };
+    drain(&mut pipe, &mut output);
+
+    Ok((output, failed))
+}
+
+// Read what is in the pipe without blocking, keeping at most MAX_OUTPUT
+fn drain(pipe: &mut std::fs::File, output: &mut Vec<u8>) {
+    let mut buf = [0u8; 1024];
+    while let Ok(n) = pipe.read(&mut buf) {
+        if n == 0 {
+            break;
+        }
+        let take = n.min(MAX_OUTPUT.saturating_sub(output.len()));
+        output.extend_from_slice(&buf[..take]);
+    }
+}
+
+// Run the hooks of a stage for a switch from prevlevel to runlevel
+pub fn run_hooks(stage: &str, runlevel: &Runlevel, prevlevel: &Runlevel, log: &dyn InitLogger) {
+    for path in hooks(stage) {
+        let name = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+        match run_hook(&path, runlevel, prevlevel) {
+            Ok((output, failed)) => {
+                for line in String::from_utf8_lossy(&output).lines().filter(|l| !l.trim().is_empty()) {
+                    log.initlog(LogLevel::Verbose, &format!("{} {}: {}", stage, name, line));
+                }
+                if let Some(why) = failed {
+                    log.initlog(LogLevel::Verbose, &format!("{} hook {}: {}", stage, name, why));
+                }
+            }
+            Err(e) => log.initlog(LogLevel::Verbose, &format!("cannot run {} hook {}: {}", stage, name, e)),
+        }
+    }
+}
+
+// Whether a wait status means the process died unexpectedly
+pub fn abnormal_exit(status: i32) -> bool {
+    libc::WIFSIGNALED(status) || (libc::WIFEXITED(status) && libc::WEXITSTATUS(status) != 0)
+}
+
+// Start the on-failure-exec command for entry `id`. EXIT_STATUS is empty
+// for a process that was killed, SIGNAL for one that exited.
+pub fn run_failure_hook(command: &str, id: &str, status: i32, log: &dyn InitLogger) {
+    let (exit_status, signal) = if libc::WIFSIGNALED(status) {
+        let sig = libc::WTERMSIG(status);
+        let name = Signal::try_from(sig).map(|s| s.as_str().to_string()).unwrap_or_else(|_| sig.to_string());
+        (String::new(), name)
+    } else {
+        (libc::WEXITSTATUS(status).to_string(), String::new())
+    };
+
+    let spawned = spawn_failure_hook(command, &[("ID", id), ("EXIT_STATUS", &exit_status), ("SIGNAL", &signal)]);
+    if let Err(e) = spawned {
+        log.initlog(LogLevel::Verbose, &format!("cannot run on-failure-exec for \"{}\": {}", id, e));
+    }
+}
+
+// Start the on-failure-exec command for a boot that isn't finished after
+// boot-deadline, `waiting` are the entries holding it up
+pub fn run_deadline_hook(command: &str, waiting: &[String], log: &dyn InitLogger) {
+    let waiting = waiting.join(" ");
+    let vars = [("ID", BOOT_DEADLINE_ID), ("EXIT_STATUS", ""), ("SIGNAL", ""), ("WAITING", &waiting)];
+    if let Err(e) = spawn_failure_hook(command, &vars) {
+        log.initlog(LogLevel::Verbose, &format!("cannot run on-failure-exec for the boot deadline: {}", e));
+    }
+}
+
+// Start the on-failure-exec command with these variables, without waiting
+// for it. It is reaped along with everything else init collects.
+fn spawn_failure_hook(command: &str, vars: &[(&str, &str)]) -> std::io::Result<()> {
+    let mut shell = Command::new("/bin/sh");
+    shell
+        .arg("-c")
+        .arg(command)
+        .env_clear()
+        .env("INIT_VERSION", format!("rye-init-{}", crate::VERSION))
+        .env("PATH", crate::PATH_DEFAULT)
+        .envs(vars.iter().copied())
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+    unsafe {
+        shell.pre_exec(|| {
+            spawn::cloexec_from(3);
+            Ok(())
+        })
+    };
+    shell.spawn().map(drop)
+}
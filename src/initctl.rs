@@ -0,0 +1,595 @@
+// rye-initctl: the control operations that have no sysvinit equivalent.
+// Init behaves like this when it is started under the name rye-initctl.
+//
+// Usage: rye-initctl [--json] COMMAND [ARGS...]
+//
+//   status [ID...]          the entries, what they are doing and how they last exited
+//   list [--level LEVEL]    the entries that run at LEVEL, the current runlevel by default,
+//                           and whether init would start them there
+//   plan LEVEL              what telinit LEVEL would stop and start, in order
+//   boot-report             how the boot entries went, and the rescue report if one failed
+//   dump-state [PATH]       the state stream, as init would hand it to its successor
+//   restart ID              stop an entry and start it again
+//   enable ID, disable ID   let an entry start, or stop it and keep it stopped
+//   reload ID               send an entry its reload signal
+//   set-env NAME=VALUE...   add to the environment of what init starts from now on
+//   unset-env NAME...
+//   power fail|fail-now|ok  tell init about the power supply, as a UPS daemon would
+//   debug on|off            switch init's debug output on or off
+//   debug dump              init's recent debug messages, logged or not
+//   diag                    init's diagnostic dump, also written to /run/rye-init/diag.txt
+//                           and the console, like on SIGQUIT
+//   features                the control protocol version and extensions of init
+//   sessions                who is logged in, from utmp, for systems without who(1)
+//   reread                  read the inittab again, like telinit q, and show what changed
+//   generate TEMPLATE...    inittab lines for getty, serial-getty or sshd on this machine,
+//                           see templates.rs in the library
+//
+// The extensions go over the control socket and wait for init's answer.
+// set-env, unset-env and power are sysvinit requests that init doesn't
+// answer, they are only handed over. telinit stays the sysvinit interface.
+//
+// With --json the output is one JSON object, errors included, for scripts.
+//
+// generate needs no init: it reads the inittab and its drop-ins to leave out
+// what they have already and prints lines to paste.
+
+use std::io::Write;
+use std::os::fd::AsRawFd;
+
+use nix::sys::socket::{self, sockopt, MsgFlags};
+use nix::sys::time::{TimeVal, TimeValLike};
+
+use rye_init::initreq::{self, InitRequest};
+use rye_init::inittab::{self, InitAction};
+use rye_init::paths;
+use rye_init::policy::{self, Planned, Start, Step};
+use rye_init::runlevel::{Runlevel, RunlevelSet};
+use rye_init::state::{self, ChildFlags, Record, State};
+use rye_init::templates;
+
+use crate::config;
+use crate::control::{self, ControlRead};
+use crate::rescue;
+#[cfg(feature = "utmp")]
+use crate::{last, utmp};
+use crate::version::json_str;
+
+const EXIT_OK: i32 = 0;
+const EXIT_FAILED: i32 = 1;
+
+// How long to wait for an answer
+const ANSWER_TIMEOUT: i64 = 10;
+
+fn usage() -> i32 {
+    eprintln!("Usage: rye-initctl [--json] status [ID...]");
+    eprintln!("       rye-initctl [--json] list [--level LEVEL]");
+    eprintln!("       rye-initctl [--json] plan LEVEL");
+    eprintln!("       rye-initctl [--json] boot-report");
+    eprintln!("       rye-initctl dump-state [PATH]");
+    eprintln!("       rye-initctl [--json] restart|enable|disable|reload ID");
+    eprintln!("       rye-initctl [--json] set-env NAME=VALUE...");
+    eprintln!("       rye-initctl [--json] unset-env NAME...");
+    eprintln!("       rye-initctl [--json] power fail|fail-now|ok");
+    eprintln!("       rye-initctl [--json] debug on|off");
+    eprintln!("       rye-initctl debug dump");
+    eprintln!("       rye-initctl diag");
+    eprintln!("       rye-initctl [--json] features");
+    eprintln!("       rye-initctl [--json] sessions");
+    eprintln!("       rye-initctl [--json] reread");
+    eprintln!("       rye-initctl [--json] generate getty|serial-getty|sshd...");
+    EXIT_FAILED
+}
+
+// Send a request and wait for init to acknowledge it. Replies that come
+// before the ack are handed to `reply`. An init that doesn't know the
+// request would never answer, so it isn't sent there at all.
+fn ask(req: &InitRequest, reply: &mut dyn FnMut(&InitRequest)) -> Result<(), String> {
+    let features = control::control_features(&paths::path(initreq::INIT_SOCKET))
+        .map_err(|e| format!("cannot connect to {}: {}", paths::path(initreq::INIT_SOCKET), e))?;
+    if !features.supports(req.cmd) {
+        return Err(format!("the running init does not support {}", req.cmd_name()));
+    }
+
+    let fd = control::control_connect(&paths::path(initreq::INIT_SOCKET))
+        .map_err(|e| format!("cannot connect to {}: {}", paths::path(initreq::INIT_SOCKET), e))?;
+    let _ = socket::setsockopt(&fd, sockopt::ReceiveTimeout, &TimeVal::seconds(ANSWER_TIMEOUT));
+
+    socket::send(fd.as_raw_fd(), &req.to_bytes(), MsgFlags::empty())
+        .map_err(|e| format!("cannot send request: {}", e))?;
+
+    loop {
+        match control::control_recv(fd.as_raw_fd()) {
+            ControlRead::Request(ack) if ack.cmd == initreq::INIT_CMD_ACK => {
+                return match ack.runlevel {
+                    initreq::INIT_ACK_ACCEPTED | initreq::INIT_ACK_DONE => Ok(()),
+                    _ => Err(format!("rejected: {}", initreq::refusal_reason(ack.sleeptime))),
+                };
+            }
+            ControlRead::Request(r) if r.cmd == req.cmd => reply(&r),
+            ControlRead::Request(_) | ControlRead::Garbage(_) => {}
+            ControlRead::Nothing => return Err("no answer from init".to_string()),
+            ControlRead::Closed => return Err("init closed the connection".to_string()),
+        }
+    }
+}
+
+// Report how a command went: nothing or an error on stderr, or with
+// --json an object on stdout either way
+fn finish(json: bool, what: &str, result: Result<(), String>) -> i32 {
+    match (result, json) {
+        (Ok(()), false) => EXIT_OK,
+        (Ok(()), true) => {
+            println!("{{\"ok\":true}}");
+            EXIT_OK
+        }
+        (Err(e), false) => {
+            eprintln!("rye-initctl: {}: {}", what, e);
+            EXIT_FAILED
+        }
+        (Err(e), true) => {
+            println!("{{\"ok\":false,\"error\":{}}}", json_str(&format!("{}: {}", what, e)));
+            EXIT_FAILED
+        }
+    }
+}
+
+// Ask init to do something to one entry
+fn entry_request(json: bool, cmd: i32, id: &str) -> i32 {
+    let mut req = InitRequest::new(cmd);
+    req.set_data_str(id);
+    let result = ask(&req, &mut |_| {});
+    finish(json, &format!("{} {}", req.cmd_name(), id), result)
+}
+
+// Hand init a request it doesn't answer
+fn hand_over(json: bool, what: &str, req: &InitRequest) -> i32 {
+    let result = control::send_request(req).map_err(|e| format!("cannot reach init: {}", e));
+    finish(json, what, result)
+}
+
+// set-env and unset-env: the strings go into data one after the other,
+// each ended by a NUL
+fn set_env(json: bool, cmd: i32, vars: &[&str]) -> i32 {
+    let what = if cmd == initreq::INIT_CMD_SETENV { "set-env" } else { "unset-env" };
+    for var in vars {
+        let name = var.split('=').next().unwrap_or_default();
+        if name.is_empty() || name.starts_with("INIT_") || (cmd == initreq::INIT_CMD_SETENV) != var.contains('=') {
+            return finish(json, what, Err(format!("bad variable \"{}\"", var)));
+        }
+    }
+    let mut req = InitRequest::new(cmd);
+    if !req.set_data_strings(vars) {
+        return finish(json, what, Err("too much to send at once".to_string()));
+    }
+    hand_over(json, what, &req)
+}
+
+fn power(json: bool, event: &str) -> i32 {
+    let cmd = match event {
+        "fail" => initreq::INIT_CMD_POWERFAIL,
+        "fail-now" => initreq::INIT_CMD_POWERFAILNOW,
+        "ok" => initreq::INIT_CMD_POWEROK,
+        _ => return usage(),
+    };
+    hand_over(json, &format!("power {}", event), &InitRequest::new(cmd))
+}
+
+// Switch init's debug output, or print the debug messages it kept or its
+// diagnostic dump
+fn debug(json: bool, what: &str) -> i32 {
+    let mut req = InitRequest::new(initreq::INIT_CMD_DEBUG);
+    req.set_data_str(what);
+    if !matches!(what, "dump" | "diag") {
+        return finish(json, &format!("debug {}", what), ask(&req, &mut |_| {}));
+    }
+
+    let mut messages = Vec::new();
+    let result = ask(&req, &mut |r| {
+        let len = (r.runlevel.max(0) as usize).min(initreq::INIT_DATA_SIZE);
+        messages.extend_from_slice(&r.data[..len]);
+    });
+    let written = result.and_then(|()| std::io::stdout().write_all(&messages).map_err(|e| e.to_string()));
+    finish(false, if what == "diag" { "diag" } else { "debug dump" }, written)
+}
+
+// Have init read the inittab again and show what changed, see reread.rs
+// in init
+fn reread(json: bool) -> i32 {
+    let mut changes = Vec::new();
+    let result = ask(&InitRequest::new(initreq::INIT_CMD_REREAD), &mut |r| {
+        let len = (r.runlevel.max(0) as usize).min(initreq::INIT_DATA_SIZE);
+        changes.extend_from_slice(&r.data[..len]);
+    });
+    if result.is_err() {
+        return finish(json, "reread", result);
+    }
+    if !json {
+        let written = std::io::stdout().write_all(&changes).map_err(|e| e.to_string());
+        return finish(false, "reread", written);
+    }
+
+    let lines: Vec<String> = String::from_utf8_lossy(&changes).lines().map(json_str).collect();
+    println!("{{\"ok\":true,\"changes\":[{}]}}", lines.join(","));
+    EXIT_OK
+}
+
+// Get the state stream from init, in INIT_DATA_SIZE pieces
+fn fetch_state() -> Result<Vec<u8>, String> {
+    let mut state = Vec::new();
+    ask(&InitRequest::new(initreq::INIT_CMD_DUMPSTATE), &mut |r| {
+        let len = (r.runlevel.max(0) as usize).min(initreq::INIT_DATA_SIZE);
+        state.extend_from_slice(&r.data[..len]);
+    })?;
+    Ok(state)
+}
+
+fn load_state() -> Result<State, String> {
+    let data = fetch_state()?;
+    state::deserialize(&mut &data[..]).map_err(|e| format!("bad state from init: {}", e))
+}
+
+// Without a path init sends the state back and it goes to stdout. With one,
+// init writes the file itself, which only root may ask for.
+fn dump_state(path: Option<&str>) -> i32 {
+    let Some(path) = path else {
+        let written = fetch_state().and_then(|state| std::io::stdout().write_all(&state).map_err(|e| e.to_string()));
+        return finish(false, "dump-state", written);
+    };
+
+    let path = match std::path::absolute(path) {
+        Ok(p) => p,
+        Err(e) => {
+            eprintln!("rye-initctl: dump-state {}: {}", path, e);
+            return EXIT_FAILED;
+        }
+    };
+    let mut req = InitRequest::new(initreq::INIT_CMD_DUMPSTATE);
+    req.set_data_str(&path.to_string_lossy());
+    finish(false, "dump-state", ask(&req, &mut |_| {}))
+}
+
+// What an entry is doing, in a word
+fn entry_state(rec: &Record) -> &'static str {
+    if rec.flags.contains(ChildFlags::DISABLED) {
+        "disabled"
+    } else if rec.flags.contains(ChildFlags::FAILING) {
+        "failing"
+    } else if rec.flags.contains(ChildFlags::RUNNING) {
+        "running"
+    } else if rec.due != 0 {
+        "pending"
+    } else if rec.flags.contains(ChildFlags::XECUTED) {
+        "exited"
+    } else {
+        "inactive"
+    }
+}
+
+// How the last process of an entry ended, if one did
+fn last_exit(rec: &Record) -> Option<i32> {
+    let ended = rec.flags.contains(ChildFlags::XECUTED) && !rec.flags.contains(ChildFlags::RUNNING);
+    ended.then_some(rec.exstat)
+}
+
+fn entry_json(rec: &Record) -> String {
+    entry_json_with(rec, "")
+}
+
+// An entry as JSON, with `extra` (",\"key\":value...") added to the end
+fn entry_json_with(rec: &Record, extra: &str) -> String {
+    let exit = last_exit(rec).map_or("null".to_string(), |status| status.to_string());
+    let flags: Vec<String> = rec.flags.decode().map(|(name, set)| format!("{}:{}", json_str(name), set)).collect();
+    format!("{{\"id\":{},\"runlevels\":{},\"action\":{},\"state\":{},\"flags\":{{{}}},\"pid\":{},\
+             \"exit_status\":{},\"respawns\":{},\"process\":{}{}}}",
+        json_str(&rec.id), json_str(&rec.rlevel), json_str(rec.action.as_str()), json_str(entry_state(rec)),
+        flags.join(","), rec.pid, exit, rec.respawns, json_str(&rec.process), extra)
+}
+
+// How long init waits for an entry before it goes on without it, from its
+// timeout attribute or the config file, None for as long as it takes
+fn wait_timeout(rec: &Record, config: &config::Config) -> Option<u64> {
+    if !policy::waits(rec.action) {
+        return None;
+    }
+    rec.attrs.timeout.as_deref().and_then(inittab::parse_timeout).or_else(|| config.boot_timeout(rec.action))
+}
+
+fn print_entries(records: &[&Record]) {
+    println!("{:<8} {:<12} {:<9} {:>7} {:>8}  LAST EXIT", "ID", "ACTION", "STATE", "PID", "RESPAWNS");
+    for rec in records {
+        let pid = if rec.pid > 0 { rec.pid.to_string() } else { "-".to_string() };
+        let mut exit = last_exit(rec).map_or("-".to_string(), rescue::describe_status);
+        if rec.flags.contains(ChildFlags::TIMEDOUT) {
+            exit = format!("timed out, {}", exit);
+        }
+        println!("{:<8} {:<12} {:<9} {:>7} {:>8}  {}", rec.id, rec.action.as_str(), entry_state(rec), pid,
+            rec.respawns, exit);
+    }
+}
+
+fn status(json: bool, ids: &[&str]) -> i32 {
+    let state = match load_state() {
+        Ok(state) => state,
+        Err(e) => return finish(json, "status", Err(e)),
+    };
+    for id in ids {
+        if !state.records.iter().any(|r| r.id == *id) {
+            return finish(json, "status", Err(format!("no entry \"{}\"", id)));
+        }
+    }
+    let records: Vec<&Record> = state.records.iter().filter(|r| ids.is_empty() || ids.contains(&r.id.as_str())).collect();
+
+    if json {
+        let entries: Vec<String> = records.iter().map(|r| entry_json(r)).collect();
+        println!("{{\"runlevel\":{},\"previous\":{},\"entries\":[{}]}}",
+            json_str(&state.runlevel.to_string()), json_str(&state.prevlevel.to_string()), entries.join(","));
+    } else {
+        println!("runlevel {} (previous {})", state.runlevel, state.prevlevel);
+        print_entries(&records);
+    }
+    EXIT_OK
+}
+
+// What init would do about an entry at `level`, by the rules the main loop
+// goes by
+fn change_at(rec: &Record, level: &Runlevel) -> &'static str {
+    let runlevels = RunlevelSet::parse(&rec.rlevel);
+    if policy::start(rec.action, rec.flags, &runlevels, level) != Start::Skip {
+        "start"
+    } else if rec.flags.contains(ChildFlags::RUNNING) {
+        "keep"
+    } else {
+        "none"
+    }
+}
+
+// The entries that run at a runlevel and whether they would be started
+// there
+fn list(json: bool, level: Option<&str>) -> i32 {
+    let state = match load_state() {
+        Ok(state) => state,
+        Err(e) => return finish(json, "list", Err(e)),
+    };
+    let level = match level {
+        None => state.runlevel.clone(),
+        Some(name) => match Runlevel::parse(name) {
+            Some(level) => level,
+            None => return finish(json, "list", Err(format!("bad runlevel \"{}\"", name))),
+        },
+    };
+    let records: Vec<&Record> = state.records.iter()
+        .filter(|r| policy::runs_at(r.action, r.flags, &RunlevelSet::parse(&r.rlevel), &level))
+        .collect();
+
+    if json {
+        let entries: Vec<String> = records.iter()
+            .map(|r| entry_json_with(r, &format!(",\"change\":{}", json_str(change_at(r, &level)))))
+            .collect();
+        println!("{{\"level\":{},\"runlevel\":{},\"entries\":[{}]}}",
+            json_str(&level.to_string()), json_str(&state.runlevel.to_string()), entries.join(","));
+        return EXIT_OK;
+    }
+
+    println!("runlevel {}, entries of runlevel {}", state.runlevel, level);
+    println!("{:<8} {:<12} {:<9} {:>7}  AT {}", "ID", "ACTION", "STATE", "PID", level);
+    for rec in records {
+        let pid = if rec.pid > 0 { rec.pid.to_string() } else { "-".to_string() };
+        println!("{:<8} {:<12} {:<9} {:>7}  {}", rec.id, rec.action.as_str(), entry_state(rec), pid,
+            change_at(rec, &level));
+    }
+    EXIT_OK
+}
+
+// What a switch to `target` would do, by the planner init itself goes by
+fn plan(json: bool, target: &str) -> i32 {
+    let state = match load_state() {
+        Ok(state) => state,
+        Err(e) => return finish(json, "plan", Err(e)),
+    };
+    let Some(level) = Runlevel::parse(target) else {
+        return finish(json, "plan", Err(format!("bad runlevel \"{}\"", target)));
+    };
+    let entries = state.records.iter()
+        .map(|r| Planned { action: r.action, flags: r.flags, runlevels: RunlevelSet::parse(&r.rlevel) })
+        .collect();
+    let steps = policy::plan(entries, &state.runlevel, &level);
+    let (config, _) = config::Config::load();
+
+    let describe = |step: &Step| match *step {
+        Step::Stop(n) => ("stop", &state.records[n].id, "now", None),
+        Step::Start(n, Start::SpawnAndWait) => {
+            ("start", &state.records[n].id, "wait", wait_timeout(&state.records[n], &config))
+        }
+        Step::Start(n, Start::SpawnLater) => ("start", &state.records[n].id, "delayed", None),
+        Step::Start(n, _) => ("start", &state.records[n].id, "now", None),
+    };
+
+    if json {
+        let steps: Vec<String> = steps.iter().map(describe)
+            .map(|(what, id, how, timeout)| format!("{{\"step\":{},\"id\":{},\"how\":{},\"timeout\":{}}}",
+                json_str(what), json_str(id), json_str(how), timeout.map_or("null".to_string(), |t| t.to_string())))
+            .collect();
+        println!("{{\"from\":{},\"to\":{},\"steps\":[{}]}}", json_str(&state.runlevel.to_string()),
+            json_str(&level.to_string()), steps.join(","));
+        return EXIT_OK;
+    }
+
+    println!("runlevel {} to {}", state.runlevel, level);
+    if steps.is_empty() {
+        println!("  nothing to do");
+    }
+    for (what, id, how, timeout) in steps.iter().map(describe) {
+        match (how, timeout) {
+            ("wait", Some(secs)) => println!("  {} {} and wait for it, {} seconds at most", what, id, secs),
+            ("wait", None) => println!("  {} {} and wait for it", what, id),
+            ("delayed", _) => println!("  {} {} after its delay", what, id),
+            _ => println!("  {} {}", what, id),
+        }
+    }
+    EXIT_OK
+}
+
+// The boot entries, and the report of the rescue console if one of them
+// failed
+fn boot_report(json: bool) -> i32 {
+    let state = match load_state() {
+        Ok(state) => state,
+        Err(e) => return finish(json, "boot-report", Err(e)),
+    };
+    let records: Vec<&Record> = state.records.iter()
+        .filter(|r| matches!(r.action, InitAction::SysInit | InitAction::Boot | InitAction::BootWait))
+        .collect();
+    let failed = records.iter().filter(|r| last_exit(r).is_some_and(|status| status != 0)).count();
+    let report = std::fs::read_to_string(paths::path(rescue::RESCUE_REPORT)).ok();
+    let (config, _) = config::Config::load();
+
+    if json {
+        let entries: Vec<String> = records.iter()
+            .map(|r| entry_json_with(r, &format!(",\"timeout\":{}",
+                wait_timeout(r, &config).map_or("null".to_string(), |t| t.to_string()))))
+            .collect();
+        println!("{{\"booted\":{},\"failed\":{},\"entries\":[{}],\"rescue_report\":{}}}",
+            state.did_boot, failed, entries.join(","), report.as_deref().map_or("null".to_string(), json_str));
+        return EXIT_OK;
+    }
+
+    let done = if state.did_boot { "done" } else { "still booting" };
+    println!("boot {}, {} of {} boot entries failed", done, failed, records.len());
+    print_entries(&records);
+    let timeouts: Vec<String> = records.iter()
+        .filter_map(|r| {
+            let secs = wait_timeout(r, &config)?;
+            let over = if r.flags.contains(ChildFlags::TIMEDOUT) { ", ran out" } else { "" };
+            Some(format!("  {} {} seconds{}", r.id, secs, over))
+        })
+        .collect();
+    if !timeouts.is_empty() {
+        println!("\ntimeouts:");
+        for line in timeouts {
+            println!("{}", line);
+        }
+    }
+    if let Some(report) = report {
+        println!("\nReport of the rescue console ({}):", rescue::RESCUE_REPORT);
+        print!("{}", report);
+    }
+    EXIT_OK
+}
+
+// Show the control protocol version and the extensions of the running init
+fn features(json: bool) -> i32 {
+    match control::control_features(&paths::path(initreq::INIT_SOCKET)) {
+        Ok(features) if json => {
+            let names: Vec<String> = features.commands.iter().map(|c| json_str(c)).collect();
+            println!("{{\"version\":{},\"commands\":[{}]}}", features.version, names.join(","));
+            EXIT_OK
+        }
+        Ok(features) => {
+            println!("control version {}", features.version);
+            for name in &features.commands {
+                println!("{}", name);
+            }
+            EXIT_OK
+        }
+        Err(e) => finish(json, "features", Err(format!("cannot connect to {}: {}",
+            paths::path(initreq::INIT_SOCKET), e))),
+    }
+}
+
+// Who is logged in: the USER_PROCESS records in utmp whose process is
+// still there
+#[cfg(feature = "utmp")]
+fn sessions(json: bool) -> i32 {
+    let records = match utmp::read_records(&paths::path(utmp::UTMP_FILE)) {
+        Ok(records) => records,
+        Err(e) => return finish(json, "sessions", Err(format!("{}: {}", paths::path(utmp::UTMP_FILE), e))),
+    };
+    let sessions: Vec<_> = records.iter()
+        .filter(|r| r.ut_type == utmp::USER_PROCESS)
+        .filter(|r| r.pid <= 0 || nix::sys::signal::kill(nix::unistd::Pid::from_raw(r.pid), None) != Err(nix::errno::Errno::ESRCH))
+        .collect();
+
+    if json {
+        let entries: Vec<String> = sessions.iter().map(|r| format!(
+            "{{\"user\":{},\"tty\":{},\"pid\":{},\"login\":{},\"host\":{}}}",
+            json_str(&utmp::field_str(&r.user)), json_str(&utmp::field_str(&r.line)), r.pid, r.tv_sec,
+            json_str(&utmp::field_str(&r.host)))).collect();
+        println!("{{\"sessions\":[{}]}}", entries.join(","));
+        return EXIT_OK;
+    }
+
+    println!("{:<12} {:<12} {:>7}  {:<16}  FROM", "USER", "TTY", "PID", "LOGIN");
+    for r in sessions {
+        println!("{:<12} {:<12} {:>7}  {:<16}  {}", utmp::field_str(&r.user), utmp::field_str(&r.line), r.pid,
+            last::strftime(r.tv_sec, "%Y-%m-%d %H:%M"), utmp::field_str(&r.host));
+    }
+    EXIT_OK
+}
+
+#[cfg(not(feature = "utmp"))]
+fn sessions(json: bool) -> i32 {
+    finish(json, "sessions", Err("init is built without utmp".to_string()))
+}
+
+// Inittab lines from templates, for the entries there aren't yet
+fn generate(json: bool, names: &[&str]) -> i32 {
+    let mut parser = inittab::Parser::new(inittab::DuplicatePolicy::FirstWins);
+    let files = std::iter::once(paths::path(inittab::INITTAB)).chain(inittab::drop_ins(&paths::path(inittab::INITTAB_DIR)));
+    for file in files {
+        // A new system may have no inittab yet
+        let _ = parser.add_file(&file);
+    }
+    let entries = parser.finish().entries;
+
+    let mut lines = Vec::new();
+    for &name in names {
+        let Some(template) = templates::template(name) else {
+            return finish(json, "generate", Err(format!("no template \"{}\"", name)));
+        };
+        match templates::generate(template, &entries) {
+            Ok(generated) => lines.extend(generated),
+            Err(e) => return finish(json, "generate", Err(format!("{}: {}", name, e))),
+        }
+    }
+
+    if json {
+        let lines: Vec<String> = lines.iter().map(String::as_str).map(json_str).collect();
+        println!("{{\"ok\":true,\"lines\":[{}]}}", lines.join(","));
+    } else {
+        for line in lines {
+            println!("{}", line);
+        }
+    }
+    EXIT_OK
+}
+
+pub fn initctl(args: &[String]) -> i32 {
+    let json = args.iter().any(|a| a == "--json");
+    let args: Vec<&str> = args.iter().map(String::as_str).filter(|&a| a != "--json").collect();
+
+    match args.as_slice() {
+        ["status", ids @ ..] => status(json, ids),
+        ["list"] => list(json, None),
+        ["list", "--level", level] => list(json, Some(level)),
+        ["plan", level] => plan(json, level),
+        ["boot-report"] => boot_report(json),
+        ["dump-state"] if !json => dump_state(None),
+        ["dump-state", path] if !json => dump_state(Some(path)),
+        ["restart", id] => entry_request(json, initreq::INIT_CMD_RESTART, id),
+        ["enable", id] => entry_request(json, initreq::INIT_CMD_ENABLE, id),
+        ["disable", id] => entry_request(json, initreq::INIT_CMD_DISABLE, id),
+        ["reload", id] => entry_request(json, initreq::INIT_CMD_RELOAD, id),
+        ["set-env", vars @ ..] if !vars.is_empty() => set_env(json, initreq::INIT_CMD_SETENV, vars),
+        ["unset-env", vars @ ..] if !vars.is_empty() => set_env(json, initreq::INIT_CMD_UNSETENV, vars),
+        ["power", event] => power(json, event),
+        ["debug", what @ ("on" | "off")] => debug(json, what),
+        ["debug", "dump"] if !json => debug(false, "dump"),
+        ["diag"] if !json => debug(false, "diag"),
+        ["features"] => features(json),
+        ["sessions"] => sessions(json),
+        ["reread"] => reread(json),
+        ["generate", names @ ..] if !names.is_empty() => generate(json, names),
+        _ => usage(),
+    }
+}
@@ -0,0 +1,703 @@
+// Logging for init: to the console, to syslog, or both.
+//
+// Everything init reports about runlevels and inittab entries goes through
+// LogEvent so the wording stays the same everywhere and log based alerting
+// can rely on it. Optionally the message is preceded by RFC 5424 structured
+// data carrying the same information in key="value" form.
+//
+// The console is written without blocking. A serial console with flow
+// control asserted or its cable pulled takes nothing, then up to
+// CONSOLE_QUEUE bytes wait for it and are written whenever init comes
+// around again. What doesn't fit is dropped, and counted in a notice once
+// the console takes output again. Init never waits for the console.
+//
+// When many entries fail at once the console messages alone can slow down
+// a boot on a 9600 baud serial console. Each source, an inittab id or init
+// itself, gets a token bucket: `burst` messages at once, refilled at `rate`
+// per second. What doesn't fit only goes to syslog, and the console is told
+// how many messages were suppressed once the source may write again.
+//
+// With "kmsg yes" in the config file the events worth having after a
+// crash, runlevel switches, respawning entries disabled and boot entries
+// that failed, are also written to KMSG, starting with KMSG_PREFIX. The
+// kernel log survives in pstore or ramoops where /var never was writable.
+//
+// Syslog messages are sent to /dev/log without blocking, the way syslog(3)
+// would. When the syslog daemon is restarted its socket goes away and a new
+// one takes its place: the first message that isn't taken closes our
+// connection, and init connects again, right away and then after
+// SYSLOG_RETRY_MIN milliseconds, doubling up to SYSLOG_RETRY_MAX while
+// nobody is there. Until then up to SYSLOG_QUEUE bytes of messages wait,
+// with the time they were logged, the oldest go first when there are more.
+// The daemon is told how many were lost.
+//
+// Debug messages (initdbg!) are kept in a ring of the last DEBUG_RING_SIZE
+// whether debugging is on or not, `rye-initctl debug dump` shows them after
+// the fact. Debugging is switched on with rye_init.debug=1 on the kernel
+// command line, `rye-initctl debug on` or DEBUG_SIGNAL, which toggles it.
+// Only then are they logged as well.
+
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, Write};
+use std::os::unix::net::UnixDatagram;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+use rye_init::runlevel::Runlevel;
+use rye_init::{localtime, paths};
+use rye_init::tty;
+
+use crate::{clock, console, earlylog, LogLevel};
+
+// SD-ID of our structured data element (32473 is the example PEN)
+const SD_ID: &str = "rye-init@32473";
+
+// Source of the messages that aren't about an entry
+const INIT_SOURCE: &str = "init";
+
+// Source of debug messages, they have a rate limit of their own
+const DEBUG_SOURCE: &str = "debug";
+
+// Debug messages kept for `rye-initctl debug dump`
+pub const DEBUG_RING_SIZE: usize = 256;
+
+// Console output that waits for the console to take it
+pub const CONSOLE_QUEUE: usize = 4096;
+
+// Syslog messages that wait for the syslog daemon
+pub const SYSLOG_QUEUE: usize = 16384;
+
+// Milliseconds between attempts to reach the syslog daemon
+const SYSLOG_RETRY_MIN: u64 = 250;
+const SYSLOG_RETRY_MAX: u64 = 60000;
+
+// daemon.info, what syslog(3) would send for LOG_DAEMON and LOG_INFO
+const SYSLOG_PRIORITY: u8 = 30;
+
+static DEBUG: AtomicBool = AtomicBool::new(false);
+static DEBUG_RING: Mutex<VecDeque<String>> = Mutex::new(VecDeque::new());
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimit {
+    pub rate: u32,                      // Console messages per second, 0 for no limit
+    pub burst: u32,                     // Messages a source may write at once
+}
+
+pub const DEFAULT_RATE_LIMIT: RateLimit = RateLimit { rate: 5, burst: 20 };
+
+impl Default for RateLimit {
+    fn default() -> Self {
+        DEFAULT_RATE_LIMIT
+    }
+}
+
+#[derive(Debug)]
+struct Bucket {
+    source: String,
+    tokens: u64,                        // Thousandths of a message
+    last: u64,                          // Monotonic ms of the last refill
+    suppressed: u64,                    // Messages dropped since the last summary
+}
+
+#[derive(Debug)]
+struct Limiter {
+    limit: RateLimit,
+    buckets: Vec<Bucket>,
+}
+
+#[derive(Debug)]
+struct ConsoleQueue {
+    pending: VecDeque<u8>,              // Not written yet, at most CONSOLE_QUEUE bytes
+    dropped: u64,                       // Messages that didn't fit since the last notice
+}
+
+static QUEUE: Mutex<ConsoleQueue> = Mutex::new(ConsoleQueue { pending: VecDeque::new(), dropped: 0 });
+
+impl ConsoleQueue {
+    // Append a line, or count it as dropped when it doesn't fit
+    fn push(&mut self, line: &[u8]) {
+        self.note_dropped(line.len());
+        if self.pending.len() + line.len() > CONSOLE_QUEUE {
+            self.dropped += 1;
+            return;
+        }
+        self.pending.extend(line);
+    }
+
+    // Say how many messages were dropped, once there is room for it and
+    // `reserve` more bytes
+    fn note_dropped(&mut self, reserve: usize) {
+        if self.dropped == 0 {
+            return;
+        }
+        let notice = format!("\rINIT: {} console messages dropped\r\n", self.dropped);
+        if self.pending.len() + notice.len() + reserve <= CONSOLE_QUEUE {
+            self.pending.extend(notice.as_bytes());
+            self.dropped = 0;
+        }
+    }
+
+    // Write what the console takes without blocking
+    fn write_to(&mut self, f: &mut File) {
+        while !self.pending.is_empty() {
+            let (front, _) = self.pending.as_slices();
+            match f.write(front) {
+                Ok(n) if n > 0 => {
+                    self.pending.drain(..n);
+                }
+                _ => break,
+            }
+        }
+        self.note_dropped(0);
+    }
+
+    fn flush(&mut self, console: &str) {
+        if self.pending.is_empty() && self.dropped == 0 {
+            return;
+        }
+        if let Ok(mut f) = tty::open_nonblocking(console) {
+            self.write_to(&mut f);
+            // The notice, if there was no room for it before
+            self.write_to(&mut f);
+        }
+    }
+}
+
+#[derive(Debug)]
+struct SyslogConn {
+    socket: Option<UnixDatagram>,       // Connected to /dev/log, None until we are
+    pending: VecDeque<Vec<u8>>,         // Not sent yet, oldest first
+    size: usize,                        // Bytes in pending, at most SYSLOG_QUEUE
+    dropped: u64,                       // Messages that didn't fit since the last notice
+    retry: u64,                         // Monotonic ms of the next attempt to connect
+    backoff: u64,                       // Ms before the attempt after that
+}
+
+static SYSLOG: Mutex<SyslogConn> = Mutex::new(SyslogConn {
+    socket: None,
+    pending: VecDeque::new(),
+    size: 0,
+    dropped: 0,
+    retry: 0,
+    backoff: SYSLOG_RETRY_MIN,
+});
+
+impl SyslogConn {
+    // Queue a message, dropping the oldest ones it has no room for
+    fn push(&mut self, msg: Vec<u8>) {
+        if msg.len() > SYSLOG_QUEUE {
+            self.dropped += 1;
+            return;
+        }
+        while self.size + msg.len() > SYSLOG_QUEUE {
+            let Some(old) = self.pending.pop_front() else { break };
+            self.size -= old.len();
+            self.dropped += 1;
+        }
+        self.size += msg.len();
+        self.pending.push_back(msg);
+    }
+
+    // Connect to the syslog daemon's socket at `dev_log`, unless the last
+    // attempt was too recent
+    fn connect(&mut self, dev_log: &str, now: u64) -> bool {
+        if self.socket.is_some() {
+            return true;
+        }
+        if now < self.retry {
+            return false;
+        }
+        let socket = UnixDatagram::unbound().and_then(|socket| {
+            socket.connect(dev_log)?;
+            socket.set_nonblocking(true)?;
+            Ok(socket)
+        });
+        match socket {
+            Ok(socket) => {
+                self.socket = Some(socket);
+                true
+            }
+            Err(_) => {
+                self.disconnect(now);
+                false
+            }
+        }
+    }
+
+    // Nobody takes our messages, try again after the backoff
+    fn disconnect(&mut self, now: u64) {
+        self.socket = None;
+        self.retry = now + self.backoff;
+        self.backoff = (self.backoff * 2).min(SYSLOG_RETRY_MAX);
+    }
+
+    // Send what is queued, as far as the syslog daemon takes it. Returns
+    // when to try again if something is left.
+    fn flush(&mut self, dev_log: &str, now: u64) -> Option<u64> {
+        while self.connect(dev_log, now) {
+            let notice;
+            let msg = match self.pending.front() {
+                Some(msg) => msg,
+                None if self.dropped > 0 => {
+                    notice = syslog_line(&format!("{} syslog messages lost, the syslog daemon was away", self.dropped));
+                    &notice
+                }
+                None => break,
+            };
+            let Some(socket) = &self.socket else { break };
+            match socket.send(msg) {
+                Ok(_) => {
+                    self.backoff = SYSLOG_RETRY_MIN;
+                    match self.pending.pop_front() {
+                        Some(msg) => self.size -= msg.len(),
+                        None => self.dropped = 0,
+                    }
+                }
+                // The daemon is behind, its socket is full
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock || e.raw_os_error() == Some(libc::ENOBUFS) => {
+                    return Some(now + SYSLOG_RETRY_MIN);
+                }
+                // The daemon is gone, or was restarted with a new socket
+                Err(e) if matches!(e.kind(), io::ErrorKind::ConnectionRefused | io::ErrorKind::ConnectionReset
+                    | io::ErrorKind::NotConnected | io::ErrorKind::BrokenPipe | io::ErrorKind::NotFound) => {
+                    self.disconnect(now);
+                }
+                // Not a message it will ever take
+                Err(_) => match self.pending.pop_front() {
+                    Some(msg) => {
+                        self.size -= msg.len();
+                        self.dropped += 1;
+                    }
+                    None => self.dropped = 0,
+                },
+            }
+        }
+        (!self.pending.is_empty() || self.dropped > 0).then_some(self.retry)
+    }
+}
+
+// A message as syslog(3) sends it, stamped with the local time
+fn syslog_line(msg: &str) -> Vec<u8> {
+    let stamp = localtime::format(clock::now() as i64, "%b %e %H:%M:%S");
+    format!("<{}>{} init: {}", SYSLOG_PRIORITY, stamp, msg).into_bytes()
+}
+
+static LIMITER: Mutex<Limiter> = Mutex::new(Limiter { limit: DEFAULT_RATE_LIMIT, buckets: Vec::new() });
+
+impl Limiter {
+    // Whether `source` may write to the console now, and how many of its
+    // messages were suppressed before this one
+    fn admit(&mut self, source: &str, now: u64) -> (bool, u64) {
+        if self.limit.rate == 0 {
+            return (true, 0);
+        }
+        let full = self.limit.burst.max(1) as u64 * 1000;
+        let rate = self.limit.rate as u64;
+
+        let n = match self.buckets.iter().position(|b| b.source == source) {
+            Some(n) => n,
+            None => {
+                self.buckets.push(Bucket { source: source.to_string(), tokens: full, last: now, suppressed: 0 });
+                self.buckets.len() - 1
+            }
+        };
+        let bucket = &mut self.buckets[n];
+        bucket.tokens = (bucket.tokens + now.saturating_sub(bucket.last) * rate).min(full);
+        bucket.last = now;
+
+        if bucket.tokens < 1000 {
+            bucket.suppressed += 1;
+            return (false, 0);
+        }
+        bucket.tokens -= 1000;
+        (true, std::mem::take(&mut bucket.suppressed))
+    }
+
+    // The suppression summaries that are due, forgetting the sources that
+    // have been quiet long enough to be back at a full bucket
+    fn due(&mut self, now: u64) -> Vec<(String, u64)> {
+        let full = self.limit.burst.max(1) as u64 * 1000;
+        let rate = self.limit.rate as u64;
+        let mut due = Vec::new();
+
+        for bucket in self.buckets.iter_mut() {
+            bucket.tokens = (bucket.tokens + now.saturating_sub(bucket.last) * rate).min(full);
+            bucket.last = now;
+            if bucket.suppressed > 0 && bucket.tokens >= 1000 {
+                bucket.tokens -= 1000;
+                due.push((bucket.source.clone(), std::mem::take(&mut bucket.suppressed)));
+            }
+        }
+        self.buckets.retain(|b| b.tokens < full || b.suppressed > 0);
+        due
+    }
+}
+
+// Change the console rate limit
+pub fn set_rate_limit(limit: RateLimit) {
+    if let Ok(mut limiter) = LIMITER.lock() {
+        limiter.limit = limit;
+        limiter.buckets.clear();
+    }
+}
+
+fn suppressed_message(source: &str, count: u64) -> String {
+    format!("{} messages from {} suppressed", count, source)
+}
+
+// Report the suppressed messages of sources that have gone quiet since
+pub fn flush_suppressed(console: &str) {
+    let due = match LIMITER.lock() {
+        Ok(mut limiter) => limiter.due(clock::monotonic_ms()),
+        Err(_) => return,
+    };
+    for (source, count) in due {
+        log_console(console, &suppressed_message(&source, count));
+    }
+}
+
+pub const KMSG: &str = "/dev/kmsg";
+pub const KMSG_PREFIX: &str = "rye-init";
+
+#[derive(Debug, Clone, Copy)]
+pub enum LogEvent<'a> {
+    RunlevelSwitch { level: &'a Runlevel },
+    RunlevelEntered { level: &'a Runlevel, millis: u128 },
+    RespawnTooFast { id: &'a str },
+    EntryStarted { id: &'a str, pid: i32 },
+    EntryExited { id: &'a str, pid: i32, status: i32 },
+    BootEntryFailed { id: &'a str, status: i32 },
+    BootEntryTimedOut { id: &'a str, secs: u64 },
+    EntryTimedOut { id: &'a str, secs: u64 },
+    BootDeadlineMissed { secs: u64, level: &'a Runlevel, waiting: &'a [String] },
+}
+
+impl LogEvent<'_> {
+    // Whose console rate limit the message counts against
+    pub fn source(&self) -> &str {
+        match self {
+            LogEvent::RunlevelSwitch { .. } | LogEvent::RunlevelEntered { .. }
+                | LogEvent::BootDeadlineMissed { .. } => INIT_SOURCE,
+            LogEvent::RespawnTooFast { id } | LogEvent::EntryStarted { id, .. } | LogEvent::EntryExited { id, .. }
+                | LogEvent::BootEntryFailed { id, .. } | LogEvent::BootEntryTimedOut { id, .. }
+                | LogEvent::EntryTimedOut { id, .. } => id,
+        }
+    }
+
+    pub fn level(&self) -> LogLevel {
+        match self {
+            LogEvent::RunlevelSwitch { .. } | LogEvent::RespawnTooFast { .. } | LogEvent::BootEntryTimedOut { .. }
+                | LogEvent::EntryTimedOut { .. } | LogEvent::BootDeadlineMissed { .. } => LogLevel::Verbose,
+            _ => LogLevel::Syslog,
+        }
+    }
+
+    // The syslog priority the event goes to the kernel log with, None if
+    // it doesn't
+    pub fn kmsg_priority(&self) -> Option<u8> {
+        match self {
+            LogEvent::BootEntryFailed { .. } | LogEvent::BootEntryTimedOut { .. } | LogEvent::EntryTimedOut { .. }
+                | LogEvent::BootDeadlineMissed { .. } => Some(3),
+            LogEvent::RespawnTooFast { .. } => Some(4),
+            LogEvent::RunlevelSwitch { .. } | LogEvent::RunlevelEntered { .. } => Some(5),
+            LogEvent::EntryStarted { .. } | LogEvent::EntryExited { .. } => None,
+        }
+    }
+
+    pub fn message(&self) -> String {
+        match self {
+            LogEvent::RunlevelSwitch { level } => format!("Switching to runlevel: {}", level),
+            LogEvent::RunlevelEntered { level, millis } => {
+                format!("Entering runlevel: {} took {} ms", level, millis)
+            }
+            LogEvent::RespawnTooFast { id } => {
+                format!("Id \"{}\" respawning too fast: disabled for 5 minutes", id)
+            }
+            LogEvent::EntryStarted { id, pid } => format!("Id \"{}\" started as pid {}", id, pid),
+            LogEvent::EntryExited { id, pid, status } => {
+                format!("Id \"{}\" (pid {}) exited with status {}", id, pid, status)
+            }
+            LogEvent::BootEntryFailed { id, status } => format!("Boot entry \"{}\" failed with status {}", id, status),
+            LogEvent::BootEntryTimedOut { id, secs } => {
+                format!("Boot entry \"{}\" still running after {} seconds", id, secs)
+            }
+            LogEvent::EntryTimedOut { id, secs } => {
+                format!("Id \"{}\" stopped after its timeout of {} seconds", id, secs)
+            }
+            LogEvent::BootDeadlineMissed { secs, level, waiting: [] } => {
+                format!("Boot not finished after {} seconds, at runlevel {} waiting for nothing", secs, level)
+            }
+            LogEvent::BootDeadlineMissed { secs, level, waiting } => {
+                format!("Boot not finished after {} seconds, at runlevel {} waiting for \"{}\"", secs, level,
+                    waiting.join("\", \""))
+            }
+        }
+    }
+
+    fn params(&self) -> Vec<(&'static str, String)> {
+        match self {
+            LogEvent::RunlevelSwitch { level } => {
+                vec![("event", "runlevel-switch".to_string()), ("level", level.to_string())]
+            }
+            LogEvent::RunlevelEntered { level, millis } => vec![
+                ("event", "runlevel-entered".to_string()),
+                ("level", level.to_string()),
+                ("ms", millis.to_string()),
+            ],
+            LogEvent::RespawnTooFast { id } => {
+                vec![("event", "respawn-too-fast".to_string()), ("id", id.to_string())]
+            }
+            LogEvent::EntryStarted { id, pid } => vec![
+                ("event", "entry-started".to_string()),
+                ("id", id.to_string()),
+                ("pid", pid.to_string()),
+            ],
+            LogEvent::EntryExited { id, pid, status } => vec![
+                ("event", "entry-exited".to_string()),
+                ("id", id.to_string()),
+                ("pid", pid.to_string()),
+                ("status", status.to_string()),
+            ],
+            LogEvent::BootEntryFailed { id, status } => vec![
+                ("event", "boot-entry-failed".to_string()),
+                ("id", id.to_string()),
+                ("status", status.to_string()),
+            ],
+            LogEvent::BootEntryTimedOut { id, secs } => vec![
+                ("event", "boot-entry-timeout".to_string()),
+                ("id", id.to_string()),
+                ("seconds", secs.to_string()),
+            ],
+            LogEvent::EntryTimedOut { id, secs } => vec![
+                ("event", "entry-timeout".to_string()),
+                ("id", id.to_string()),
+                ("seconds", secs.to_string()),
+            ],
+            LogEvent::BootDeadlineMissed { secs, level, waiting } => vec![
+                ("event", "boot-deadline".to_string()),
+                ("seconds", secs.to_string()),
+                ("level", level.to_string()),
+                ("waiting", waiting.join(" ")),
+            ],
+        }
+    }
+
+    // The RFC 5424 STRUCTURED-DATA element for this event
+    pub fn structured_data(&self) -> String {
+        let mut sd = format!("[{}", SD_ID);
+        for (name, value) in self.params() {
+            let value = value.replace('\\', "\\\\").replace('"', "\\\"").replace(']', "\\]");
+            sd.push_str(&format!(" {}=\"{}\"", name, value));
+        }
+        sd.push(']');
+        sd
+    }
+}
+
+// Write a line to the kernel log
+pub fn log_kmsg(priority: u8, msg: &str) {
+    if let Ok(mut f) = std::fs::OpenOptions::new().write(true).open(KMSG) {
+        let _ = writeln!(f, "<{}>{}: {}", priority, KMSG_PREFIX, msg);
+    }
+}
+
+pub fn log_syslog(msg: &str) {
+    if let Ok(mut conn) = SYSLOG.try_lock() {
+        conn.push(syslog_line(msg));
+        conn.flush(&paths::path(earlylog::DEV_LOG), clock::monotonic_ms());
+    }
+}
+
+// Send the syslog messages that are waiting, if the syslog daemon is
+// there. Returns the monotonic ms to try again at if some are left.
+pub fn flush_syslog() -> Option<u64> {
+    let dev_log = paths::path(earlylog::DEV_LOG);
+    SYSLOG.try_lock().ok().and_then(|mut conn| conn.flush(&dev_log, clock::monotonic_ms()))
+}
+
+pub fn log_console(console: &str, msg: &str) {
+    let line = format!("\rINIT: {}\r\n", msg);
+    match QUEUE.try_lock() {
+        Ok(mut queue) => {
+            queue.push(line.as_bytes());
+            queue.flush(console);
+        }
+        // Init panicked while it had the queue, this is the panic message
+        Err(_) => {
+            if let Ok(mut f) = tty::open_nonblocking(console) {
+                let _ = f.write(line.as_bytes());
+            }
+        }
+    }
+}
+
+// Write what is still queued for the console, as far as it takes it
+pub fn flush_console(console: &str) {
+    if let Ok(mut queue) = QUEUE.try_lock() {
+        queue.flush(console);
+    }
+}
+
+// Write to the console within the rate limit of `source`
+fn log_console_limited(source: &str, console: &str, msg: &str) {
+    let (admitted, suppressed) = match LIMITER.lock() {
+        Ok(mut limiter) => limiter.admit(source, clock::monotonic_ms()),
+        Err(_) => (true, 0),
+    };
+    if suppressed > 0 {
+        log_console(console, &suppressed_message(source, suppressed));
+    }
+    if admitted {
+        log_console(console, msg);
+    }
+}
+
+// Switch debug logging on or off
+pub fn set_debug(on: bool) {
+    DEBUG.store(on, Ordering::Relaxed);
+}
+
+pub fn debug() -> bool {
+    DEBUG.load(Ordering::Relaxed)
+}
+
+// Keep a debug message in the ring and log it if debugging is on
+pub fn debuglog(level: LogLevel, msg: &str) {
+    if let Ok(mut ring) = DEBUG_RING.lock() {
+        if ring.len() == DEBUG_RING_SIZE {
+            ring.pop_front();
+        }
+        ring.push_back(format!("{} {}", clock::monotonic_ms(), msg));
+    }
+    if debug() {
+        initlog_from(DEBUG_SOURCE, console::CONSOLE, level, msg);
+    }
+}
+
+// The debug messages in the ring, oldest first, each preceded by the
+// monotonic milliseconds it was logged at
+pub fn debug_messages() -> Vec<String> {
+    DEBUG_RING.lock().map(|ring| ring.iter().cloned().collect()).unwrap_or_default()
+}
+
+pub fn initlog(console: &str, level: LogLevel, msg: &str) {
+    initlog_from(INIT_SOURCE, console, level, msg);
+}
+
+pub fn initlog_from(source: &str, console: &str, level: LogLevel, msg: &str) {
+    match level {
+        LogLevel::Console => log_console_limited(source, console, msg),
+        LogLevel::Syslog => log_syslog(msg),
+        LogLevel::Verbose => {
+            log_console_limited(source, console, msg);
+            log_syslog(msg);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A syslog daemon of our own: a datagram socket bound at `path`
+    fn syslogd(path: &str) -> UnixDatagram {
+        let _ = std::fs::remove_file(path);
+        let socket = UnixDatagram::bind(path).unwrap();
+        socket.set_nonblocking(true).unwrap();
+        socket
+    }
+
+    // The messages it got so far, without the priority and time stamp
+    fn received(syslogd: &UnixDatagram) -> Vec<String> {
+        let mut buf = [0u8; 4096];
+        let mut messages = Vec::new();
+        while let Ok(n) = syslogd.recv(&mut buf) {
+            let msg = String::from_utf8_lossy(&buf[..n]).into_owned();
+            let (_, text) = msg.split_once(" init: ").unwrap_or_else(|| panic!("{:?}", msg));
+            messages.push(text.to_string());
+        }
+        messages
+    }
+
+    fn conn() -> SyslogConn {
+        SyslogConn {
+            socket: None,
+            pending: VecDeque::new(),
+            size: 0,
+            dropped: 0,
+            retry: 0,
+            backoff: SYSLOG_RETRY_MIN,
+        }
+    }
+
+    // The daemon is stopped and started again, the way a restart or an
+    // upgrade of the syslog package does it: the messages logged while it
+    // was away wait, init tries again with growing pauses, and the daemon
+    // gets them all once it is back
+    #[test]
+    fn syslogd_restart_loses_nothing() {
+        let dev_log = std::env::temp_dir().join(format!("rye-init-dev-log-{}", std::process::id()));
+        let dev_log = dev_log.to_str().unwrap();
+        let mut conn = conn();
+
+        let daemon = syslogd(dev_log);
+        conn.push(syslog_line("one"));
+        assert_eq!(conn.flush(dev_log, 1000), None);
+        assert_eq!(received(&daemon), ["one"]);
+
+        drop(daemon);
+        std::fs::remove_file(dev_log).unwrap();
+        conn.push(syslog_line("two"));
+        assert_eq!(conn.flush(dev_log, 2000), Some(2000 + SYSLOG_RETRY_MIN));
+        conn.push(syslog_line("three"));
+        assert_eq!(conn.flush(dev_log, 2000 + SYSLOG_RETRY_MIN), Some(2000 + 3 * SYSLOG_RETRY_MIN));
+
+        // Back, but not tried again before the pause is up
+        let daemon = syslogd(dev_log);
+        assert_eq!(conn.flush(dev_log, 2000 + 2 * SYSLOG_RETRY_MIN), Some(2000 + 3 * SYSLOG_RETRY_MIN));
+        assert!(received(&daemon).is_empty());
+        assert_eq!(conn.flush(dev_log, 2000 + 3 * SYSLOG_RETRY_MIN), None);
+        assert_eq!(received(&daemon), ["two", "three"]);
+        assert_eq!(conn.backoff, SYSLOG_RETRY_MIN);
+        let _ = std::fs::remove_file(dev_log);
+    }
+
+    // A daemon that stays away: the pauses stop growing at
+    // SYSLOG_RETRY_MAX, the oldest messages make room for new ones, and
+    // the daemon hears how many are gone once it is there
+    #[test]
+    fn syslogd_away_for_long() {
+        let dev_log = std::env::temp_dir().join(format!("rye-init-dev-log-away-{}", std::process::id()));
+        let dev_log = dev_log.to_str().unwrap();
+        let _ = std::fs::remove_file(dev_log);
+        let mut conn = conn();
+
+        let mut now = 0;
+        let lines = 2 * SYSLOG_QUEUE / syslog_line("message 0000").len();
+        for n in 0..lines {
+            conn.push(syslog_line(&format!("message {:04}", n)));
+            if let Some(retry) = conn.flush(dev_log, now) {
+                now = retry;
+            }
+        }
+        assert_eq!(conn.backoff, SYSLOG_RETRY_MAX);
+        assert!(conn.size <= SYSLOG_QUEUE);
+        let kept = conn.pending.len();
+
+        // It takes a few at a time, the rest wait until it has read them
+        let daemon = syslogd(dev_log);
+        let mut got = Vec::new();
+        while let Some(retry) = conn.flush(dev_log, now) {
+            assert_eq!(retry, now + SYSLOG_RETRY_MIN);
+            got.extend(received(&daemon));
+            now = retry;
+        }
+        got.extend(received(&daemon));
+        assert_eq!(got.len(), kept + 1);
+        assert_eq!(got[0], format!("message {:04}", lines - kept));
+        assert_eq!(got[kept - 1], format!("message {:04}", lines - 1));
+        assert_eq!(got[kept], format!("{} syslog messages lost, the syslog daemon was away", lines - kept));
+        let _ = std::fs::remove_file(dev_log);
+    }
+}
@@ -0,0 +1,562 @@
+//! Requests to init, as sent over the `/run/initctl` FIFO.
+//!
+//! A request is the `struct init_request` of sysvinit's initreq.h: four
+//! ints and a 368 byte union, [`INIT_REQUEST_SIZE`] bytes in native byte
+//! order. [`InitRequest`] reads and writes that layout, so a UPS daemon or
+//! an admin tool can talk to rye-init, or to sysvinit, without a hand
+//! rolled struct and without unsafe code:
+//!
+//! ```text
+//! let req = Command::PowerFail.request().unwrap();
+//! std::fs::OpenOptions::new().write(true).open(INIT_FIFO)?.write_all(&req.to_bytes())?;
+//! ```
+//!
+//! [`InitRequest::command`] is the other direction, what a request asks
+//! for, for tools that log or inspect what goes over the FIFO. Commands
+//! below 100 are the ones sysvinit knows. The extensions from
+//! [`INIT_CMD_WAITLVL`] on are rye-init's own. They are sent over the
+//! control socket at [`INIT_SOCKET`], where init answers them with
+//! requests of its own, [`INIT_CMD_ACK`] for most of them. [`INIT_CMD_RUNLVL`]
+//! is answered there too, so telinit can say why a switch was refused,
+//! see [`refusal_reason`]. Which of them an init answers it says in its
+//! reply to [`INIT_CMD_FEATURES`]. A
+//! request with a command init doesn't know is ignored, so a tool can
+//! send one to sysvinit and fall back when nothing happens.
+//!
+//! [`RequestBuffer`] cuts what is read from the FIFO into requests.
+
+use crate::runlevel::Runlevel;
+
+/// The FIFO init reads requests from.
+pub const INIT_FIFO: &str = "/run/initctl";
+/// The control socket for requests init answers, see control.rs in init.
+pub const INIT_SOCKET: &str = "/run/rye-init/initctl.sock";
+/// What every request starts with.
+pub const INIT_MAGIC: i32 = 0x03091969;
+
+pub const INIT_CMD_START: i32 = 0;
+/// Switch runlevels. A runlevel of 0 means the name of the runlevel is in
+/// data.
+pub const INIT_CMD_RUNLVL: i32 = 1;
+pub const INIT_CMD_POWERFAIL: i32 = 2;
+pub const INIT_CMD_POWERFAILNOW: i32 = 3;
+pub const INIT_CMD_POWEROK: i32 = 4;
+pub const INIT_CMD_BSD: i32 = 5;
+/// Set variables for what init starts, see [`Command::SetEnv`].
+pub const INIT_CMD_SETENV: i32 = 6;
+pub const INIT_CMD_UNSETENV: i32 = 7;
+/// Use the terminal named in data as the console.
+pub const INIT_CMD_CHANGECONS: i32 = 12345;
+
+// Extensions, only meaningful on the control socket where init can answer
+
+/// Like [`INIT_CMD_RUNLVL`], but report when the runlevel has been entered.
+pub const INIT_CMD_WAITLVL: i32 = 100;
+/// Init's answer, the status is in runlevel.
+pub const INIT_CMD_ACK: i32 = 101;
+/// Send the reload signal to the entry named in data.
+pub const INIT_CMD_RELOAD: i32 = 102;
+/// Write the state stream to the path in data, or send it back.
+pub const INIT_CMD_DUMPSTATE: i32 = 103;
+/// Answered with CONTROL_VERSION in runlevel and the names of the
+/// extensions in data.
+pub const INIT_CMD_FEATURES: i32 = 104;
+/// Stop the entry named in data and start it again.
+pub const INIT_CMD_RESTART: i32 = 105;
+/// Let the entry named in data start again.
+pub const INIT_CMD_ENABLE: i32 = 106;
+/// Stop the entry named in data and keep it from starting.
+pub const INIT_CMD_DISABLE: i32 = 107;
+/// Data "on" or "off" switches debug output, "dump" sends the ring back.
+pub const INIT_CMD_DEBUG: i32 = 108;
+/// Read the inittab again, what changed is sent back.
+pub const INIT_CMD_REREAD: i32 = 109;
+
+/// Bumped whenever a control socket extension is added.
+pub const CONTROL_VERSION: i32 = 8;
+
+/// The extensions this init answers, by the [`InitRequest::cmd_name`] they
+/// are announced as.
+pub const EXTENSIONS: &[i32] = &[INIT_CMD_RUNLVL, INIT_CMD_WAITLVL, INIT_CMD_RELOAD, INIT_CMD_DUMPSTATE, INIT_CMD_FEATURES,
+                                 INIT_CMD_RESTART, INIT_CMD_ENABLE, INIT_CMD_DISABLE, INIT_CMD_DEBUG,
+                                 INIT_CMD_REREAD];
+
+/// The switch has started.
+pub const INIT_ACK_ACCEPTED: i32 = 1;
+/// Kills done, all wait entries have exited.
+pub const INIT_ACK_DONE: i32 = 2;
+/// The request was refused, `sleeptime` says why: one of the
+/// `INIT_REFUSED_` reasons.
+pub const INIT_ACK_REFUSED: i32 = 3;
+
+/// No reason given, as from an init before [`CONTROL_VERSION`] 8.
+pub const INIT_REFUSED_OTHER: i32 = 0;
+/// Not a runlevel init knows.
+pub const INIT_REFUSED_RUNLEVEL: i32 = 1;
+/// The system is going down, init takes no other runlevel until it is.
+pub const INIT_REFUSED_BUSY: i32 = 2;
+/// The sender may not make the request.
+pub const INIT_REFUSED_PERMISSION: i32 = 3;
+
+/// Why a request was refused, from the `sleeptime` of the ack.
+pub fn refusal_reason(reason: i32) -> &'static str {
+    match reason {
+        INIT_REFUSED_RUNLEVEL => "invalid runlevel",
+        INIT_REFUSED_BUSY => "transition in progress",
+        INIT_REFUSED_PERMISSION => "permission",
+        _ => "no reason given, see the init log",
+    }
+}
+
+/// Size of the request on the wire: four ints and a 368 byte union.
+pub const INIT_REQUEST_SIZE: usize = 384;
+pub const INIT_DATA_SIZE: usize = 368;
+
+/// A request as it goes over the wire.
+#[derive(Debug, Clone)]
+pub struct InitRequest {
+    pub magic: i32,                     // Magic number
+    pub cmd: i32,                       // What kind of request
+    pub runlevel: i32,                  // Runlevel to change to
+    pub sleeptime: i32,                 // Time between TERM and KILL
+    pub data: [u8; INIT_DATA_SIZE],     // Command specific payload
+}
+
+impl InitRequest {
+    /// A request for `cmd` with everything else zero.
+    pub fn new(cmd: i32) -> Self {
+        InitRequest {
+            magic: INIT_MAGIC,
+            cmd,
+            runlevel: 0,
+            sleeptime: 0,
+            data: [0u8; INIT_DATA_SIZE],
+        }
+    }
+
+    /// Decode a request as read from the FIFO, in native byte order. None
+    /// if `buf` isn't exactly [`INIT_REQUEST_SIZE`] long. The magic number
+    /// is not checked, see [`is_valid`](Self::is_valid).
+    pub fn from_bytes(buf: &[u8]) -> Option<Self> {
+        if buf.len() != INIT_REQUEST_SIZE {
+            return None;
+        }
+
+        let int_at = |off: usize| i32::from_ne_bytes([buf[off], buf[off + 1], buf[off + 2], buf[off + 3]]);
+
+        let mut data = [0u8; INIT_DATA_SIZE];
+        data.copy_from_slice(&buf[16..]);
+
+        Some(InitRequest {
+            magic: int_at(0),
+            cmd: int_at(4),
+            runlevel: int_at(8),
+            sleeptime: int_at(12),
+            data,
+        })
+    }
+
+    /// The request as it is written to the FIFO.
+    pub fn to_bytes(&self) -> [u8; INIT_REQUEST_SIZE] {
+        let mut buf = [0u8; INIT_REQUEST_SIZE];
+        buf[0..4].copy_from_slice(&self.magic.to_ne_bytes());
+        buf[4..8].copy_from_slice(&self.cmd.to_ne_bytes());
+        buf[8..12].copy_from_slice(&self.runlevel.to_ne_bytes());
+        buf[12..16].copy_from_slice(&self.sleeptime.to_ne_bytes());
+        buf[16..].copy_from_slice(&self.data);
+        buf
+    }
+
+    /// Name of the command for log messages.
+    pub fn cmd_name(&self) -> &'static str {
+        match self.cmd {
+            INIT_CMD_START => "start",
+            INIT_CMD_RUNLVL => "runlevel",
+            INIT_CMD_POWERFAIL => "powerfail",
+            INIT_CMD_POWERFAILNOW => "powerfailnow",
+            INIT_CMD_POWEROK => "powerok",
+            INIT_CMD_BSD => "bsd",
+            INIT_CMD_SETENV => "setenv",
+            INIT_CMD_UNSETENV => "unsetenv",
+            INIT_CMD_CHANGECONS => "changecons",
+            INIT_CMD_WAITLVL => "waitlevel",
+            INIT_CMD_ACK => "ack",
+            INIT_CMD_RELOAD => "reload",
+            INIT_CMD_DUMPSTATE => "dumpstate",
+            INIT_CMD_FEATURES => "features",
+            INIT_CMD_RESTART => "restart",
+            INIT_CMD_ENABLE => "enable",
+            INIT_CMD_DISABLE => "disable",
+            INIT_CMD_DEBUG => "debug",
+            INIT_CMD_REREAD => "reread",
+            _ => "unknown",
+        }
+    }
+
+    /// Whether the request starts with [`INIT_MAGIC`].
+    pub fn is_valid(&self) -> bool {
+        self.magic == INIT_MAGIC
+    }
+
+    /// The payload interpreted as a NUL terminated string.
+    pub fn data_str(&self) -> String {
+        let end = self.data.iter().position(|&b| b == 0).unwrap_or(INIT_DATA_SIZE);
+        String::from_utf8_lossy(&self.data[..end]).into_owned()
+    }
+
+    /// Put `s` into the payload, cut short to leave room for the NUL.
+    pub fn set_data_str(&mut self, s: &str) {
+        self.data = [0u8; INIT_DATA_SIZE];
+        let len = std::cmp::min(s.len(), INIT_DATA_SIZE - 1);
+        self.data[..len].copy_from_slice(&s.as_bytes()[..len]);
+    }
+
+    /// The runlevel of a [`INIT_CMD_RUNLVL`] or [`INIT_CMD_WAITLVL`]
+    /// request. Named runlevels don't fit in an int, they come in data.
+    pub fn level(&self) -> Option<Runlevel> {
+        if self.runlevel == 0 {
+            Runlevel::parse(&self.data_str())
+        } else {
+            char::from_u32(self.runlevel as u32).map(Runlevel::from)
+        }
+    }
+
+    /// The NUL separated strings in the payload, up to the empty one that
+    /// ends them.
+    pub fn data_strings(&self) -> Vec<String> {
+        self.data
+            .split(|&b| b == 0)
+            .take_while(|item| !item.is_empty())
+            .map(|item| String::from_utf8_lossy(item).into_owned())
+            .collect()
+    }
+
+    /// Put `strings` into the payload, each ended by a NUL, followed by an
+    /// empty one. False if they don't fit, the payload is left empty then.
+    pub fn set_data_strings(&mut self, strings: &[&str]) -> bool {
+        self.data = [0u8; INIT_DATA_SIZE];
+        let size = strings.iter().map(|s| s.len() + 1).sum::<usize>() + 1;
+        if size > INIT_DATA_SIZE || strings.iter().any(|s| s.is_empty()) {
+            return false;
+        }
+        let mut len = 0;
+        for s in strings {
+            self.data[len..len + s.len()].copy_from_slice(s.as_bytes());
+            len += s.len() + 1;
+        }
+        true
+    }
+
+    /// What the request asks for. None if it doesn't start with
+    /// [`INIT_MAGIC`] or names a runlevel that can't be one.
+    pub fn command(&self) -> Option<Command> {
+        if !self.is_valid() {
+            return None;
+        }
+        let command = match self.cmd {
+            INIT_CMD_RUNLVL => Command::Runlevel(self.level()?),
+            INIT_CMD_WAITLVL => Command::WaitLevel(self.level()?),
+            INIT_CMD_POWERFAIL => Command::PowerFail,
+            INIT_CMD_POWERFAILNOW => Command::PowerFailNow,
+            INIT_CMD_POWEROK => Command::PowerOk,
+            INIT_CMD_SETENV => Command::SetEnv(self.data_strings()),
+            INIT_CMD_UNSETENV => Command::UnsetEnv(self.data_strings()),
+            INIT_CMD_CHANGECONS => Command::ChangeConsole(self.data_str()),
+            INIT_CMD_ACK => Command::Ack(self.runlevel),
+            INIT_CMD_RELOAD => Command::Reload(self.data_str()),
+            INIT_CMD_DUMPSTATE => Command::DumpState(self.data_str()),
+            INIT_CMD_FEATURES => Command::Features,
+            INIT_CMD_RESTART => Command::Restart(self.data_str()),
+            INIT_CMD_ENABLE => Command::Enable(self.data_str()),
+            INIT_CMD_DISABLE => Command::Disable(self.data_str()),
+            INIT_CMD_DEBUG => Command::Debug(self.data_str()),
+            INIT_CMD_REREAD => Command::Reread,
+            cmd => Command::Other(cmd),
+        };
+        Some(command)
+    }
+}
+
+/// What a request asks for, decoded. Requests init sends back with data
+/// of their own, the pieces of a state dump for instance, are only told
+/// apart by the request they answer and aren't covered.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    /// Switch to the runlevel, `Q` reads the inittab again.
+    Runlevel(Runlevel),
+    /// Switch to the runlevel and report when it has been entered.
+    WaitLevel(Runlevel),
+    /// The power is failing, from a UPS daemon.
+    PowerFail,
+    /// The power is failing and the battery is low.
+    PowerFailNow,
+    /// The power is back.
+    PowerOk,
+    /// `NAME=value` strings for the environment of what init starts.
+    SetEnv(Vec<String>),
+    /// Names of variables to remove from it.
+    UnsetEnv(Vec<String>),
+    /// The terminal to use as the console.
+    ChangeConsole(String),
+    /// Init's answer, [`INIT_ACK_DONE`] for instance.
+    Ack(i32),
+    /// Send the reload signal to the entry with this id.
+    Reload(String),
+    /// Write the state stream to this path, or send it back if empty.
+    DumpState(String),
+    /// Ask init which extensions it answers.
+    Features,
+    /// Stop the entry with this id and start it again.
+    Restart(String),
+    /// Let the entry with this id start again.
+    Enable(String),
+    /// Stop the entry with this id and keep it from starting.
+    Disable(String),
+    /// "on", "off" or "dump".
+    Debug(String),
+    /// Read the inittab again.
+    Reread,
+    /// A command this module doesn't know, or [`INIT_CMD_START`] and
+    /// [`INIT_CMD_BSD`] that no init acts on.
+    Other(i32),
+}
+
+impl Command {
+    /// The command number it is sent as.
+    pub fn cmd(&self) -> i32 {
+        match self {
+            Command::Runlevel(_) => INIT_CMD_RUNLVL,
+            Command::WaitLevel(_) => INIT_CMD_WAITLVL,
+            Command::PowerFail => INIT_CMD_POWERFAIL,
+            Command::PowerFailNow => INIT_CMD_POWERFAILNOW,
+            Command::PowerOk => INIT_CMD_POWEROK,
+            Command::SetEnv(_) => INIT_CMD_SETENV,
+            Command::UnsetEnv(_) => INIT_CMD_UNSETENV,
+            Command::ChangeConsole(_) => INIT_CMD_CHANGECONS,
+            Command::Ack(_) => INIT_CMD_ACK,
+            Command::Reload(_) => INIT_CMD_RELOAD,
+            Command::DumpState(_) => INIT_CMD_DUMPSTATE,
+            Command::Features => INIT_CMD_FEATURES,
+            Command::Restart(_) => INIT_CMD_RESTART,
+            Command::Enable(_) => INIT_CMD_ENABLE,
+            Command::Disable(_) => INIT_CMD_DISABLE,
+            Command::Debug(_) => INIT_CMD_DEBUG,
+            Command::Reread => INIT_CMD_REREAD,
+            Command::Other(cmd) => *cmd,
+        }
+    }
+
+    /// The request that asks for this. None if what it carries doesn't fit
+    /// into [`INIT_DATA_SIZE`], or for an environment list with an empty
+    /// string in it.
+    pub fn request(&self) -> Option<InitRequest> {
+        let mut req = InitRequest::new(self.cmd());
+        match self {
+            Command::Runlevel(Runlevel::Classic(c)) | Command::WaitLevel(Runlevel::Classic(c)) => {
+                req.runlevel = *c as i32;
+            }
+            Command::Runlevel(Runlevel::Named(s))
+            | Command::WaitLevel(Runlevel::Named(s))
+            | Command::ChangeConsole(s)
+            | Command::Reload(s)
+            | Command::DumpState(s)
+            | Command::Restart(s)
+            | Command::Enable(s)
+            | Command::Disable(s)
+            | Command::Debug(s) => {
+                if s.len() >= INIT_DATA_SIZE {
+                    return None;
+                }
+                req.set_data_str(s);
+            }
+            Command::SetEnv(vars) | Command::UnsetEnv(vars) => {
+                let vars: Vec<&str> = vars.iter().map(String::as_str).collect();
+                if !req.set_data_strings(&vars) {
+                    return None;
+                }
+            }
+            Command::Ack(status) => req.runlevel = *status,
+            Command::PowerFail | Command::PowerFailNow | Command::PowerOk | Command::Features | Command::Reread
+                | Command::Other(_) => {}
+        }
+        Some(req)
+    }
+}
+
+/// Requests we keep around at most before we start throwing input away.
+const MAX_BUFFERED: usize = 16 * INIT_REQUEST_SIZE;
+
+/// Seconds between warnings about garbage on the FIFO.
+pub const GARBAGE_WARN_INTERVAL: u64 = 60;
+
+/// Collects what is read from the FIFO and cuts it into requests. Nothing in
+/// here blocks: a partial request stays in the buffer until the rest comes
+/// in, and anything that doesn't start with the magic number is skipped up
+/// to the next place where it appears.
+#[derive(Debug, Default)]
+pub struct RequestBuffer {
+    buf: Vec<u8>,
+    dropped: usize,                     // Bytes thrown away since the last warning
+    warned: Option<u64>,                // When we last warned about them
+}
+
+impl RequestBuffer {
+    pub fn new() -> Self {
+        RequestBuffer::default()
+    }
+
+    /// Add input. When someone floods the FIFO the excess is dropped.
+    pub fn push(&mut self, data: &[u8]) {
+        let room = MAX_BUFFERED.saturating_sub(self.buf.len());
+        let take = data.len().min(room);
+        self.buf.extend_from_slice(&data[..take]);
+        self.dropped += data.len() - take;
+    }
+
+    /// How many more bytes push will accept.
+    pub fn room(&self) -> usize {
+        MAX_BUFFERED.saturating_sub(self.buf.len())
+    }
+
+    /// The next complete request, if there is one.
+    pub fn next_request(&mut self) -> Option<InitRequest> {
+        let magic = INIT_MAGIC.to_ne_bytes();
+
+        // Resynchronize on the magic number
+        let start = self.buf.windows(magic.len()).position(|w| w == magic);
+        let skip = match start {
+            Some(n) => n,
+            // Keep a tail that may be the start of the magic number
+            None => self.buf.len().saturating_sub(magic.len() - 1),
+        };
+        if skip > 0 {
+            self.buf.drain(..skip);
+            self.dropped += skip;
+        }
+
+        if start.is_none() || self.buf.len() < INIT_REQUEST_SIZE {
+            return None;
+        }
+
+        let req = InitRequest::from_bytes(&self.buf[..INIT_REQUEST_SIZE]);
+        self.buf.drain(..INIT_REQUEST_SIZE);
+        req
+    }
+
+    /// Bytes of garbage to warn about at `now`, in seconds of a clock that
+    /// doesn't step back like the wall clock can: all that was thrown away
+    /// since the last warning. None if there is none, or while
+    /// the last warning is less than [`GARBAGE_WARN_INTERVAL`] ago, so a
+    /// flood of garbage doesn't become a flood of warnings.
+    pub fn garbage_warning(&mut self, now: u64) -> Option<usize> {
+        if self.dropped == 0 || self.warned.is_some_and(|warned| now < warned + GARBAGE_WARN_INTERVAL) {
+            return None;
+        }
+        self.warned = Some(now);
+        Some(std::mem::take(&mut self.dropped))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(runlevel: char) -> [u8; INIT_REQUEST_SIZE] {
+        Command::Runlevel(Runlevel::from(runlevel)).request().unwrap().to_bytes()
+    }
+
+    fn command(req: Option<InitRequest>) -> Option<Command> {
+        req.and_then(|req| req.command())
+    }
+
+    #[test]
+    fn partial_reads_wait_for_the_rest() {
+        let req = request('3');
+        for chunk in [1, 3, 100, INIT_REQUEST_SIZE - 1] {
+            let mut buf = RequestBuffer::new();
+            let (pieces, last) = req.split_at(INIT_REQUEST_SIZE - 1);
+            for piece in pieces.chunks(chunk) {
+                buf.push(piece);
+                assert!(buf.next_request().is_none(), "in chunks of {}", chunk);
+            }
+            buf.push(last);
+            assert_eq!(command(buf.next_request()), Some(Command::Runlevel(Runlevel::from('3'))));
+            assert!(buf.next_request().is_none());
+            assert_eq!(buf.garbage_warning(0), None, "in chunks of {}", chunk);
+        }
+    }
+
+    #[test]
+    fn requests_read_at_once_come_out_one_by_one() {
+        let mut buf = RequestBuffer::new();
+        let mut input = [request('2'), request('5')].concat();
+        input.extend_from_slice(&request('1')[..10]);
+        buf.push(&input);
+
+        assert_eq!(command(buf.next_request()), Some(Command::Runlevel(Runlevel::from('2'))));
+        assert_eq!(command(buf.next_request()), Some(Command::Runlevel(Runlevel::from('5'))));
+        assert!(buf.next_request().is_none());
+        buf.push(&request('1')[10..]);
+        assert_eq!(command(buf.next_request()), Some(Command::Runlevel(Runlevel::from('1'))));
+    }
+
+    #[test]
+    fn garbage_before_a_request_is_skipped() {
+        let magic = INIT_MAGIC.to_ne_bytes();
+        let mut garbage = b"not a request at all\n".to_vec();
+        // Looks like the start of the magic number, but isn't
+        garbage.extend_from_slice(&magic[..3]);
+        garbage.push(!magic[3]);
+
+        let mut buf = RequestBuffer::new();
+        buf.push(&garbage);
+        assert!(buf.next_request().is_none());
+        buf.push(&magic[..2]);
+        assert!(buf.next_request().is_none());
+        buf.push(&request('4')[2..]);
+        assert_eq!(command(buf.next_request()), Some(Command::Runlevel(Runlevel::from('4'))));
+        assert_eq!(buf.garbage_warning(0), Some(garbage.len()));
+    }
+
+    #[test]
+    fn flood_is_cut_off() {
+        let mut buf = RequestBuffer::new();
+        let flood = vec![request('2'); MAX_BUFFERED / INIT_REQUEST_SIZE + 2].concat();
+        buf.push(&flood);
+        assert_eq!(buf.room(), 0);
+        assert_eq!(buf.garbage_warning(0), Some(2 * INIT_REQUEST_SIZE));
+
+        let mut n = 0;
+        while buf.next_request().is_some() {
+            n += 1;
+        }
+        assert_eq!(n, MAX_BUFFERED / INIT_REQUEST_SIZE);
+        assert_eq!(buf.room(), MAX_BUFFERED);
+    }
+
+    #[test]
+    fn garbage_warnings_are_throttled() {
+        let mut buf = RequestBuffer::new();
+        let garbage = |buf: &mut RequestBuffer, n: usize| {
+            buf.push(&vec![b'x'; n]);
+            assert!(buf.next_request().is_none());
+        };
+        let tail = INIT_MAGIC.to_ne_bytes().len() - 1;
+
+        assert_eq!(buf.garbage_warning(1000), None);
+        garbage(&mut buf, 100);
+        assert_eq!(buf.garbage_warning(1000), Some(100 - tail));
+
+        // Counted but not warned about until the interval is over
+        garbage(&mut buf, 50);
+        assert_eq!(buf.garbage_warning(1010), None);
+        garbage(&mut buf, 50);
+        assert_eq!(buf.garbage_warning(1000 + GARBAGE_WARN_INTERVAL - 1), None);
+        assert_eq!(buf.garbage_warning(1000 + GARBAGE_WARN_INTERVAL), Some(100));
+
+        // Nothing new, nothing to say
+        assert_eq!(buf.garbage_warning(5000), None);
+        garbage(&mut buf, 10);
+        assert_eq!(buf.garbage_warning(5000), Some(10));
+    }
+}
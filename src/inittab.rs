@@ -0,0 +1,965 @@
+//! Parsing of `/etc/inittab`.
+//!
+//! Every non-empty line that does not start with `#` has the form
+//! `id:runlevels:action:process`. Lines that can't be parsed are reported
+//! in [`Inittab::errors`] and otherwise ignored, just like init does. An
+//! action of another init or a misspelling listed in
+//! [`crate::policy::LEGACY_ACTIONS`] is replaced by the closest action
+//! rye-init has, noted in [`Inittab::warnings`].
+//!
+//! In the extended syntax the process field starts with a list of
+//! attributes in square brackets:
+//!
+//! ```text
+//! ng:2345:respawn:[user:www group:www reload:HUP] /usr/sbin/nginx -g 'daemon off;'
+//! ```
+//!
+//! An attribute's value may contain colons itself, the scheduling
+//! attributes take an optional priority that way:
+//!
+//! ```text
+//! wd:2345:respawn:[oomscore:-1000 sched:fifo:10 ioclass:rt] /usr/sbin/watchdog -F
+//! ```
+//!
+//! `stopsig` names the signal that asks the process to stop when its
+//! runlevel is left, `TERM` by default, before it is killed:
+//!
+//! ```text
+//! pg:2345:respawn:[user:postgres stopsig:INT] /usr/bin/postgres -D /var/lib/pgsql
+//! ```
+//!
+//! `timeout` is for the entries init waits for, `sysinit`, `bootwait`,
+//! `wait` and the like: after that many seconds the process is stopped,
+//! the entry counts as failed and init goes on with the entries after it.
+//!
+//! ```text
+//! nf:2:wait:[timeout:120] /etc/init.d/nfs-mount start
+//! ```
+//!
+//! The process field may contain colons, only the first three separate
+//! fields. The exception is the `delayed` action, whose process field
+//! starts with the number of seconds to wait after the runlevel has been
+//! entered:
+//!
+//! ```text
+//! nt:2345:delayed:30:/usr/sbin/ntpd -q
+//! ```
+//!
+//! When init starts the process it replaces `%i` with the id of the entry,
+//! `%r` with the current runlevel, `%c` with the console device and `%%`
+//! with a single `%`, so one line can serve as a template:
+//!
+//! ```text
+//! 3:2345:respawn:/sbin/getty 38400 tty%i
+//! ```
+//!
+//! An id with a numeric range in square brackets stands for one entry per
+//! number. The process field has to use `%i` then, or all of them would run
+//! the same command. This line gives six gettys with the ids `tty1` to
+//! `tty6`, the limit on the id length applies to those:
+//!
+//! ```text
+//! tty[1-6]:2345:respawn:/sbin/getty 38400 %i
+//! ```
+//!
+//! Files ending in `.tab` in [`INITTAB_DIR`] are read after the inittab,
+//! in name order. Ids must be unique across all of them, what happens to
+//! an id that is used again is decided by a [`DuplicatePolicy`].
+//!
+//! As some distributions patch into sysvinit, a file can pull in others
+//! where it stands: `#include FILE` reads one file, `#include_dir DIR` the
+//! `.tab` files of a directory like [`INITTAB_DIR`]. Relative names are
+//! relative to the including file. sysvinit reads both as comments. What
+//! is included counts as part of the including file, for
+//! [`DuplicatePolicy::RejectFile`] too. Includes nest at most
+//! [`MAX_INCLUDE_DEPTH`] deep and a file can't include itself, not even
+//! through others.
+//!
+//! ```text
+//! #include /etc/inittab.local
+//! #include_dir gettys.d
+//! ```
+//!
+//! A process field longer than [`ProcessLimit::length`] bytes is kept with
+//! a warning by default. [`LongProcess`] can have it cut to the limit or
+//! the line rejected instead, as sysvinit does. No line may be longer than
+//! [`MAX_PROCESS_LENGTH`].
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+
+use nix::sys::signal::Signal;
+
+use crate::sys::{IoClass, Sched};
+
+/// Default location of the inittab.
+pub const INITTAB: &str = "/etc/inittab";
+/// Default location of the drop-in directory.
+pub const INITTAB_DIR: &str = "/etc/inittab.d";
+
+/// Maximum length of the id field.
+pub const INITTAB_ID: usize = 8;
+/// Maximum length of the runlevels field.
+pub const RUNLEVEL_LENGTH: usize = 12;
+/// Maximum length of the action field.
+pub const ACTION_LENGTH: usize = 33;
+/// Default limit on the length of the process field, see [`ProcessLimit`].
+pub const PROCESS_LENGTH: usize = 4096;
+/// Maximum length of the process field, whatever the limit.
+pub const MAX_PROCESS_LENGTH: usize = 65536;
+/// Maximum number of entries an id range may stand for.
+pub const MAX_ID_RANGE: u32 = 64;
+/// How deep `#include` may nest.
+pub const MAX_INCLUDE_DEPTH: usize = 8;
+
+/// Actions to be taken by init.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum InitAction {
+    Respawn = 1,
+    Wait = 2,
+    Once = 3,
+    Boot = 4,
+    BootWait = 5,
+    PowerFail = 6,
+    PowerWait = 7,
+    PowerOkWait = 8,
+    CtrlAltDel = 9,
+    Off = 10,
+    OnDemand = 11,
+    InitDefault = 12,
+    SysInit = 13,
+    PowerFailNow = 14,
+    KbRequest = 15,
+    Delayed = 16,
+}
+
+/// Every action, for looking one up by its name.
+pub const ACTIONS: &[InitAction] = &[
+    InitAction::Respawn,
+    InitAction::Wait,
+    InitAction::Once,
+    InitAction::Boot,
+    InitAction::BootWait,
+    InitAction::PowerFail,
+    InitAction::PowerWait,
+    InitAction::PowerOkWait,
+    InitAction::CtrlAltDel,
+    InitAction::Off,
+    InitAction::OnDemand,
+    InitAction::InitDefault,
+    InitAction::SysInit,
+    InitAction::PowerFailNow,
+    InitAction::KbRequest,
+    InitAction::Delayed,
+];
+
+impl std::str::FromStr for InitAction {
+    type Err = String;
+
+    /// Look up an action by its inittab name, ignoring ASCII case, the way
+    /// the inittab and the state handed to the next init are read.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        ACTIONS
+            .iter()
+            .copied()
+            .find(|action| action.as_str().eq_ignore_ascii_case(s))
+            .ok_or_else(|| format!("unknown action \"{}\"", s))
+    }
+}
+
+impl InitAction {
+    /// The name of the action as written in the inittab, and in the state
+    /// handed to the next init.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            InitAction::Respawn => "respawn",
+            InitAction::Wait => "wait",
+            InitAction::Once => "once",
+            InitAction::Boot => "boot",
+            InitAction::BootWait => "bootwait",
+            InitAction::PowerFail => "powerfail",
+            InitAction::PowerWait => "powerwait",
+            InitAction::PowerOkWait => "powerokwait",
+            InitAction::CtrlAltDel => "ctrlaltdel",
+            InitAction::Off => "off",
+            InitAction::OnDemand => "ondemand",
+            InitAction::InitDefault => "initdefault",
+            InitAction::SysInit => "sysinit",
+            InitAction::PowerFailNow => "powerfailnow",
+            InitAction::KbRequest => "kbrequest",
+            InitAction::Delayed => "delayed",
+        }
+    }
+}
+
+/// Per-entry settings from the extended syntax.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Attributes {
+    /// Run the process as this user (name or uid).
+    pub user: Option<String>,
+    /// Run the process with this group (name or gid). Defaults to the
+    /// primary group of `user`.
+    pub group: Option<String>,
+    /// Run the process on this terminal as its controlling tty: a device
+    /// path, a name under `/dev` or `console`.
+    pub tty: Option<String>,
+    /// The signal that makes the process reload its configuration, see
+    /// [`parse_signal`].
+    pub reload: Option<String>,
+    /// The signal that asks the process to stop, instead of `SIGTERM`.
+    pub stopsig: Option<String>,
+    /// The SELinux context or AppArmor profile the process is executed in,
+    /// depending on what init was built with.
+    pub secontext: Option<String>,
+    /// The process's OOM score adjustment, -1000 (never killed) to 1000.
+    pub oomscore: Option<String>,
+    /// The CPU scheduling policy, see [`parse_sched`].
+    pub sched: Option<String>,
+    /// The I/O scheduling class, see [`parse_ioclass`].
+    pub ioclass: Option<String>,
+    /// Seconds init waits for the process of an entry it waits for, see
+    /// [`parse_timeout`].
+    pub timeout: Option<String>,
+}
+
+/// An OOM score adjustment, -1000 to 1000.
+pub fn parse_oomscore(s: &str) -> Option<i32> {
+    s.parse().ok().filter(|n| (-1000..=1000).contains(n))
+}
+
+/// `other`, `idle`, or `fifo` or `rr` with a real-time priority from 1 to
+/// 99 after a colon, 1 if left out: `fifo:10`.
+pub fn parse_sched(s: &str) -> Option<(Sched, i32)> {
+    let (name, priority) = match s.split_once(':') {
+        Some((name, priority)) => (name, Some(priority.parse().ok().filter(|n| (1..=99).contains(n))?)),
+        None => (s, None),
+    };
+    match name {
+        "other" if priority.is_none() => Some((Sched::Other, 0)),
+        "idle" if priority.is_none() => Some((Sched::Idle, 0)),
+        "fifo" => Some((Sched::Fifo, priority.unwrap_or(1))),
+        "rr" => Some((Sched::RoundRobin, priority.unwrap_or(1))),
+        _ => None,
+    }
+}
+
+/// `idle`, or `rt` or `be` with a level from 0 (highest) to 7 after a
+/// colon, 4 if left out: `be:2`.
+pub fn parse_ioclass(s: &str) -> Option<(IoClass, i32)> {
+    let (name, level) = match s.split_once(':') {
+        Some((name, level)) => (name, Some(level.parse().ok().filter(|n| (0..=7).contains(n))?)),
+        None => (s, None),
+    };
+    match name {
+        "idle" if level.is_none() => Some((IoClass::Idle, 0)),
+        "rt" => Some((IoClass::RealTime, level.unwrap_or(4))),
+        "be" => Some((IoClass::BestEffort, level.unwrap_or(4))),
+        _ => None,
+    }
+}
+
+/// A timeout in seconds, at least 1.
+pub fn parse_timeout(s: &str) -> Option<u64> {
+    s.parse().ok().filter(|&n| n > 0)
+}
+
+/// A signal given by name, with or without `SIG`, or by number.
+pub fn parse_signal(s: &str) -> Option<Signal> {
+    if let Ok(n) = s.parse::<i32>() {
+        return Signal::try_from(n).ok();
+    }
+    let name = s.to_uppercase();
+    let name = if name.starts_with("SIG") { name } else { format!("SIG{}", name) };
+    Signal::from_str(&name).ok()
+}
+
+impl Attributes {
+    /// Set the attribute `key`.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), ParseErrorKind> {
+        if value.is_empty() {
+            return Err(ParseErrorKind::BadAttribute(key.to_string()));
+        }
+
+        match key {
+            "user" => self.user = Some(value.to_string()),
+            "group" => self.group = Some(value.to_string()),
+            "tty" => self.tty = Some(value.to_string()),
+            "reload" if parse_signal(value).is_some() => self.reload = Some(value.to_string()),
+            "stopsig" if parse_signal(value).is_some() => self.stopsig = Some(value.to_string()),
+            "secontext" => self.secontext = Some(value.to_string()),
+            "oomscore" if parse_oomscore(value).is_some() => self.oomscore = Some(value.to_string()),
+            "sched" if parse_sched(value).is_some() => self.sched = Some(value.to_string()),
+            "ioclass" if parse_ioclass(value).is_some() => self.ioclass = Some(value.to_string()),
+            "timeout" if parse_timeout(value).is_some() => self.timeout = Some(value.to_string()),
+            _ => return Err(ParseErrorKind::BadAttribute(key.to_string())),
+        }
+        Ok(())
+    }
+
+    /// The attributes that are set, as key/value pairs.
+    pub fn pairs(&self) -> Vec<(&'static str, &str)> {
+        let mut pairs = Vec::new();
+        if let Some(user) = &self.user {
+            pairs.push(("user", user.as_str()));
+        }
+        if let Some(group) = &self.group {
+            pairs.push(("group", group.as_str()));
+        }
+        if let Some(tty) = &self.tty {
+            pairs.push(("tty", tty.as_str()));
+        }
+        if let Some(reload) = &self.reload {
+            pairs.push(("reload", reload.as_str()));
+        }
+        if let Some(stopsig) = &self.stopsig {
+            pairs.push(("stopsig", stopsig.as_str()));
+        }
+        if let Some(secontext) = &self.secontext {
+            pairs.push(("secontext", secontext.as_str()));
+        }
+        if let Some(oomscore) = &self.oomscore {
+            pairs.push(("oomscore", oomscore.as_str()));
+        }
+        if let Some(sched) = &self.sched {
+            pairs.push(("sched", sched.as_str()));
+        }
+        if let Some(ioclass) = &self.ioclass {
+            pairs.push(("ioclass", ioclass.as_str()));
+        }
+        if let Some(timeout) = &self.timeout {
+            pairs.push(("timeout", timeout.as_str()));
+        }
+        pairs
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pairs().is_empty()
+    }
+}
+
+impl fmt::Display for Attributes {
+    // The bracketed form used in the process field, empty if nothing is set
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_empty() {
+            return Ok(());
+        }
+
+        let pairs: Vec<String> = self.pairs().iter().map(|(k, v)| format!("{}:{}", k, v)).collect();
+        write!(f, "[{}]", pairs.join(" "))
+    }
+}
+
+// Split the attribute list off the process field. "[ -x foo ] && foo" is a
+// command, attributes start right after the bracket.
+fn split_attributes(process: &str) -> Result<(Attributes, &str), ParseErrorKind> {
+    let mut attrs = Attributes::default();
+
+    let Some(rest) = process.strip_prefix('[') else {
+        return Ok((attrs, process));
+    };
+    if !rest.starts_with(|c: char| c.is_ascii_alphabetic()) {
+        return Ok((attrs, process));
+    }
+
+    let Some((list, command)) = rest.split_once(']') else {
+        return Err(ParseErrorKind::BadAttribute(rest.to_string()));
+    };
+
+    for attr in list.split_whitespace() {
+        let (key, value) = attr.split_once(':').unwrap_or((attr, ""));
+        attrs.set(key, value)?;
+    }
+
+    Ok((attrs, command.trim_start()))
+}
+
+/// The id of an entry in init's process table: not empty and at most
+/// [`INITTAB_ID`] bytes. Anything longer is refused rather than cut, two
+/// ids that only differ after the limit must not become one.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct EntryId(String);
+
+impl EntryId {
+    /// `id` if it fits.
+    pub fn new(id: &str) -> Option<Self> {
+        (!id.is_empty() && id.len() <= INITTAB_ID).then(|| EntryId(id.to_string()))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl std::ops::Deref for EntryId {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl PartialEq<str> for EntryId {
+    fn eq(&self, other: &str) -> bool {
+        self.0 == other
+    }
+}
+
+impl PartialEq<&str> for EntryId {
+    fn eq(&self, other: &&str) -> bool {
+        self.0 == *other
+    }
+}
+
+impl fmt::Display for EntryId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// One inittab line.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    /// Unique id of the entry, at most [`INITTAB_ID`] characters.
+    pub id: String,
+    /// The runlevels this entry is active in, see [`crate::runlevel`].
+    pub runlevels: String,
+    /// What init does with the entry.
+    pub action: InitAction,
+    /// The command line to run, without the attributes.
+    pub process: String,
+    /// Attributes from the extended syntax.
+    pub attrs: Attributes,
+    /// Seconds a `delayed` entry waits after its runlevel was entered.
+    pub delay: u32,
+}
+
+/// Why a line was rejected.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseErrorKind {
+    /// The line has fewer than four fields.
+    FieldCount,
+    /// A field exceeds its maximum length.
+    TooLong(&'static str),
+    /// The action field names no known action.
+    UnknownAction(String),
+    /// An attribute is unknown, has no value or the list isn't closed.
+    BadAttribute(String),
+    /// A `delayed` entry without a valid number of seconds.
+    BadDelay(String),
+    /// An id range that is malformed, too large, or whose process field
+    /// doesn't use `%i`.
+    BadIdRange(String),
+    /// The id was already used, `first` says where (`file:line`).
+    DuplicateId { id: String, first: String },
+    /// An `#include` or `#include_dir` that can't be followed, and why.
+    BadInclude { path: String, reason: String },
+    /// Not an error: an action from [`crate::policy::LEGACY_ACTIONS`] that
+    /// was replaced by `action`.
+    LegacyAction { name: String, action: InitAction },
+    /// Not an error: a process field over the limit of a [`ProcessLimit`]
+    /// that was kept, cut to the limit if `truncated`.
+    LongProcess { limit: usize, truncated: bool },
+}
+
+/// A line of the inittab that could not be parsed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    /// The file the line is in, if it was read from a file.
+    pub file: Option<String>,
+    /// Line number, starting at 1.
+    pub line: usize,
+    pub kind: ParseErrorKind,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(file) = &self.file {
+            write!(f, "{}: ", file)?;
+        }
+        match &self.kind {
+            ParseErrorKind::FieldCount => write!(f, "line {}: expected id:runlevels:action:process", self.line),
+            ParseErrorKind::TooLong(field) => write!(f, "line {}: {} field too long", self.line, field),
+            ParseErrorKind::UnknownAction(a) => write!(f, "line {}: unknown action \"{}\"", self.line, a),
+            ParseErrorKind::BadAttribute(a) => write!(f, "line {}: bad attribute \"{}\"", self.line, a),
+            ParseErrorKind::BadDelay(d) => write!(f, "line {}: bad delay \"{}\"", self.line, d),
+            ParseErrorKind::BadIdRange(id) => write!(f, "line {}: bad id range \"{}\"", self.line, id),
+            ParseErrorKind::DuplicateId { id, first } => {
+                write!(f, "line {}: duplicate id \"{}\", first used at {}", self.line, id, first)
+            }
+            ParseErrorKind::BadInclude { path, reason } => {
+                write!(f, "line {}: cannot include {}: {}", self.line, path, reason)
+            }
+            ParseErrorKind::LegacyAction { name, action } => {
+                write!(f, "line {}: action \"{}\" is not supported, using \"{}\"", self.line, name, action.as_str())
+            }
+            ParseErrorKind::LongProcess { limit, truncated: true } => {
+                write!(f, "line {}: process field longer than {} bytes, cut short", self.line, limit)
+            }
+            ParseErrorKind::LongProcess { limit, truncated: false } => {
+                write!(f, "line {}: process field longer than {} bytes", self.line, limit)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// What to do with an entry whose id is already used.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum DuplicatePolicy {
+    /// Keep the entry seen first, like sysvinit.
+    #[default]
+    FirstWins,
+    /// The later entry replaces the earlier one in its place.
+    LastWins,
+    /// Ignore every entry of a file that reuses an id.
+    RejectFile,
+}
+
+impl std::str::FromStr for DuplicatePolicy {
+    type Err = String;
+
+    /// Look up a policy by the name --duplicates takes.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "first-wins" => Ok(DuplicatePolicy::FirstWins),
+            "last-wins" => Ok(DuplicatePolicy::LastWins),
+            "reject-file" => Ok(DuplicatePolicy::RejectFile),
+            _ => Err(format!("unknown policy \"{}\"", s)),
+        }
+    }
+}
+
+/// What to do with a process field that is longer than the limit.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum LongProcess {
+    /// Ignore the line, like sysvinit.
+    Reject,
+    /// Keep the field up to the limit.
+    Truncate,
+    /// Keep the whole field.
+    #[default]
+    Accept,
+}
+
+impl std::str::FromStr for LongProcess {
+    type Err = String;
+
+    /// Look up a policy by the name long-process takes in the config file.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "reject" => Ok(LongProcess::Reject),
+            "truncate" => Ok(LongProcess::Truncate),
+            "accept" => Ok(LongProcess::Accept),
+            _ => Err(format!("unknown long-process policy \"{}\"", s)),
+        }
+    }
+}
+
+/// How long a process field may be and what happens to longer ones. Every
+/// choice but rejecting the line leaves a warning.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ProcessLimit {
+    pub length: usize,
+    pub policy: LongProcess,
+}
+
+impl Default for ProcessLimit {
+    fn default() -> Self {
+        ProcessLimit { length: PROCESS_LENGTH, policy: LongProcess::default() }
+    }
+}
+
+impl ProcessLimit {
+    /// Hold an entry to the limit. Returns the warning for an entry that
+    /// was kept although it is too long.
+    pub fn apply(&self, entry: &mut Entry, lineno: usize) -> Result<Option<ParseError>, ParseError> {
+        if entry.process.len() <= self.length {
+            return Ok(None);
+        }
+        let err = |kind| ParseError { file: None, line: lineno, kind };
+        match self.policy {
+            LongProcess::Reject => Err(err(ParseErrorKind::TooLong("process"))),
+            LongProcess::Truncate => {
+                let mut end = self.length;
+                while !entry.process.is_char_boundary(end) {
+                    end -= 1;
+                }
+                entry.process.truncate(end);
+                Ok(Some(err(ParseErrorKind::LongProcess { limit: self.length, truncated: true })))
+            }
+            LongProcess::Accept => Ok(Some(err(ParseErrorKind::LongProcess { limit: self.length, truncated: false }))),
+        }
+    }
+}
+
+/// The result of parsing a whole inittab.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Inittab {
+    /// The valid entries, in file order.
+    pub entries: Vec<Entry>,
+    /// The lines that were rejected, including duplicate ids.
+    pub errors: Vec<ParseError>,
+    /// Lines that were taken with a different action than they asked for.
+    pub warnings: Vec<ParseError>,
+    /// Files left out entirely by [`DuplicatePolicy::RejectFile`].
+    pub rejected: Vec<String>,
+    /// The files that were pulled in with `#include` and `#include_dir`,
+    /// whose changes matter as much as those of the inittab.
+    pub included: Vec<String>,
+}
+
+/// Parse a single line. Returns `Ok(None)` for comments and empty lines.
+pub fn parse_line(line: &str, lineno: usize) -> Result<Option<Entry>, ParseError> {
+    let line = line.trim_end();
+    if line.is_empty() || line.trim_start().starts_with('#') {
+        return Ok(None);
+    }
+
+    let err = |kind| ParseError { file: None, line: lineno, kind };
+
+    let parts: Vec<&str> = line.splitn(4, ':').collect();
+    if parts.len() != 4 {
+        return Err(err(ParseErrorKind::FieldCount));
+    }
+
+    let (id, runlevels, action, process) = (parts[0], parts[1], parts[2], parts[3]);
+
+    // The ids of a range are checked once it is expanded
+    if id.len() > INITTAB_ID && !id.contains('[') {
+        return Err(err(ParseErrorKind::TooLong("id")));
+    }
+    if runlevels.len() > RUNLEVEL_LENGTH {
+        return Err(err(ParseErrorKind::TooLong("runlevels")));
+    }
+    if action.len() > ACTION_LENGTH {
+        return Err(err(ParseErrorKind::TooLong("action")));
+    }
+    if process.len() > MAX_PROCESS_LENGTH {
+        return Err(err(ParseErrorKind::TooLong("process")));
+    }
+
+    let action: InitAction = action.parse().map_err(|_| err(ParseErrorKind::UnknownAction(action.to_string())))?;
+
+    let (delay, process) = match action {
+        InitAction::Delayed => {
+            let (secs, rest) = process.split_once(':').unwrap_or((process, ""));
+            let delay = secs.trim().parse().map_err(|_| err(ParseErrorKind::BadDelay(secs.to_string())))?;
+            (delay, rest)
+        }
+        _ => (0, process),
+    };
+
+    let (attrs, process) = split_attributes(process).map_err(err)?;
+    // Nothing waits for the other entries
+    if attrs.timeout.is_some() && !crate::policy::waits(action) {
+        return Err(err(ParseErrorKind::BadAttribute("timeout".to_string())));
+    }
+
+    Ok(Some(Entry {
+        id: id.to_string(),
+        runlevels: runlevels.to_string(),
+        action,
+        process: process.to_string(),
+        attrs,
+        delay,
+    }))
+}
+
+/// The ids an id like `tty[1-6]` stands for. `None` if the id has no
+/// range. A lower bound with leading zeros gives ids of that width, so
+/// `s[01-12]` is `s01` to `s12`.
+pub fn id_range(id: &str) -> Option<Result<Vec<String>, ParseErrorKind>> {
+    let (prefix, rest) = id.split_once('[')?;
+    let bad = || ParseErrorKind::BadIdRange(id.to_string());
+
+    let Some((range, suffix)) = rest.split_once(']') else {
+        return Some(Err(bad()));
+    };
+    let Some((lo, hi)) = range.split_once('-') else {
+        return Some(Err(bad()));
+    };
+    let (Ok(first), Ok(last)) = (lo.parse::<u32>(), hi.parse::<u32>()) else {
+        return Some(Err(bad()));
+    };
+    if first > last || last - first >= MAX_ID_RANGE || suffix.contains(['[', ']']) {
+        return Some(Err(bad()));
+    }
+
+    let width = if lo.starts_with('0') { lo.len() } else { 0 };
+    let ids: Vec<String> = (first..=last).map(|n| format!("{}{:0w$}{}", prefix, n, suffix, w = width)).collect();
+    if ids.iter().any(|id| id.len() > INITTAB_ID) {
+        return Some(Err(ParseErrorKind::TooLong("id")));
+    }
+    Some(Ok(ids))
+}
+
+/// Parse a line that may use an id range into the entries it stands for.
+/// Returns an empty list for comments and empty lines.
+pub fn parse_entries(line: &str, lineno: usize) -> Result<Vec<Entry>, ParseError> {
+    let Some(entry) = parse_line(line, lineno)? else {
+        return Ok(Vec::new());
+    };
+
+    let err = |kind| ParseError { file: None, line: lineno, kind };
+    match id_range(&entry.id) {
+        None => Ok(vec![entry]),
+        Some(Err(kind)) => Err(err(kind)),
+        Some(Ok(_)) if !entry.process.contains("%i") => Err(err(ParseErrorKind::BadIdRange(entry.id))),
+        Some(Ok(ids)) => Ok(ids.into_iter().map(|id| Entry { id, ..entry.clone() }).collect()),
+    }
+}
+
+// A line rejected for its action parsed again with the action that
+// replaces it, if it is a legacy one, and the warning about it
+fn legacy_entries(line: &str, lineno: usize, e: &ParseError) -> Option<(Vec<Entry>, ParseError)> {
+    let ParseErrorKind::UnknownAction(name) = &e.kind else {
+        return None;
+    };
+    let action = crate::policy::legacy_action(name)?;
+    let mut parts: Vec<&str> = line.splitn(4, ':').collect();
+    parts[2] = action.as_str();
+    let entries = parse_entries(&parts.join(":"), lineno).ok()?;
+    let warning = ParseError { file: None, line: lineno, kind: ParseErrorKind::LegacyAction { name: name.clone(), action } };
+    Some((entries, warning))
+}
+
+/// Parses files one after the other into a single [`Inittab`], keeping
+/// track of where each id was used.
+#[derive(Debug, Default)]
+pub struct Parser {
+    policy: DuplicatePolicy,
+    limit: ProcessLimit,
+    tab: Inittab,
+    seen: HashMap<String, String>,
+}
+
+impl Parser {
+    pub fn new(policy: DuplicatePolicy) -> Self {
+        Parser {
+            policy,
+            ..Parser::default()
+        }
+    }
+
+    /// Hold process fields to `limit` instead of the default.
+    pub fn set_process_limit(&mut self, limit: ProcessLimit) {
+        self.limit = limit;
+    }
+
+    /// Add the contents of a file. `file` is used in error messages.
+    pub fn add_str(&mut self, file: Option<&str>, s: &str) {
+        let mut staged: Vec<Entry> = Vec::new();
+        let mut added = Vec::new();
+        let mut reject = false;
+
+        let mut source = Vec::new();
+        let mut stack: Vec<String> = file.map(|f| vec![canonical(f)]).unwrap_or_default();
+        self.expand(file, s, &mut stack, &mut source);
+
+        let mut warnings = Vec::new();
+        let limit = self.limit;
+        let lines: Vec<_> = source.into_iter().flat_map(|(file, n, line)| {
+            let parsed = parse_entries(&line, n).or_else(|e| match legacy_entries(&line, n, &e) {
+                Some((entries, mut warning)) => {
+                    warning.file = file.clone();
+                    warnings.push(warning);
+                    Ok(entries)
+                }
+                None => Err(e),
+            });
+            // The entries of an id range share the process field, one
+            // warning is enough
+            let parsed = parsed.and_then(|mut entries| {
+                let mut long = None;
+                for entry in &mut entries {
+                    long = limit.apply(entry, n)?.or(long);
+                }
+                if let Some(mut warning) = long {
+                    warning.file = file.clone();
+                    warnings.push(warning);
+                }
+                Ok(entries)
+            });
+            match parsed {
+                Ok(entries) => entries.into_iter().map(|e| (file.clone(), n, Ok(e))).collect(),
+                Err(e) => vec![(file, n, Err(e))],
+            }
+        }).collect();
+        self.tab.warnings.extend(warnings);
+
+        for (file, n, entry) in lines {
+            let file = file.as_deref();
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(mut e) => {
+                    e.file = file.map(str::to_string);
+                    self.tab.errors.push(e);
+                    continue;
+                }
+            };
+
+            let here = match file {
+                Some(file) => format!("{}:{}", file, n),
+                None => format!("line {}", n),
+            };
+            let Some(first) = self.seen.get(&entry.id).cloned() else {
+                self.seen.insert(entry.id.clone(), here);
+                added.push(entry.id.clone());
+                staged.push(entry);
+                continue;
+            };
+
+            self.tab.errors.push(ParseError {
+                file: file.map(str::to_string),
+                line: n,
+                kind: ParseErrorKind::DuplicateId { id: entry.id.clone(), first },
+            });
+
+            match self.policy {
+                DuplicatePolicy::FirstWins => {}
+                DuplicatePolicy::LastWins => {
+                    self.seen.insert(entry.id.clone(), here);
+                    if let Some(old) = self.tab.entries.iter_mut().chain(staged.iter_mut()).find(|e| e.id == entry.id) {
+                        *old = entry;
+                    }
+                }
+                DuplicatePolicy::RejectFile => reject = true,
+            }
+        }
+
+        if reject {
+            for id in added {
+                self.seen.remove(&id);
+            }
+            self.tab.rejected.push(file.unwrap_or("inittab").to_string());
+            return;
+        }
+
+        self.tab.entries.extend(staged);
+    }
+
+    // The lines of `s` with the files it includes in their place, each
+    // with the file and line number it came from. `stack` holds the files
+    // being read, to catch a file that includes itself.
+    fn expand(&mut self, file: Option<&str>, s: &str, stack: &mut Vec<String>,
+              out: &mut Vec<(Option<String>, usize, String)>) {
+        for (n, line) in s.lines().enumerate() {
+            let Some((dir, name)) = include_directive(line) else {
+                out.push((file.map(str::to_string), n + 1, line.to_string()));
+                continue;
+            };
+
+            let err = |path: &str, reason: String| ParseError {
+                file: file.map(str::to_string),
+                line: n + 1,
+                kind: ParseErrorKind::BadInclude { path: path.to_string(), reason },
+            };
+            let path = include_path(file, name);
+            let files = if dir { drop_ins(&path) } else { vec![path] };
+
+            for path in files {
+                if stack.len() >= MAX_INCLUDE_DEPTH {
+                    self.tab.errors.push(err(&path, format!("nested more than {} deep", MAX_INCLUDE_DEPTH)));
+                    continue;
+                }
+                let id = canonical(&path);
+                if stack.contains(&id) {
+                    self.tab.errors.push(err(&path, "it includes itself".to_string()));
+                    continue;
+                }
+                let s = match std::fs::read_to_string(&path) {
+                    Ok(s) => s,
+                    Err(e) => {
+                        self.tab.errors.push(err(&path, e.to_string()));
+                        continue;
+                    }
+                };
+                if !self.tab.included.contains(&path) {
+                    self.tab.included.push(path.clone());
+                }
+                stack.push(id);
+                self.expand(Some(&path), &s, stack, out);
+                stack.pop();
+            }
+        }
+    }
+
+    /// Read and add a file.
+    pub fn add_file(&mut self, path: &str) -> io::Result<()> {
+        let s = std::fs::read_to_string(path)?;
+        self.add_str(Some(path), &s);
+        Ok(())
+    }
+
+    pub fn finish(self) -> Inittab {
+        self.tab
+    }
+}
+
+// An `#include FILE` or `#include_dir DIR` line, as whether it is a
+// directory and the name
+fn include_directive(line: &str) -> Option<(bool, &str)> {
+    let line = line.trim();
+    let (dir, rest) = match line.strip_prefix("#include_dir") {
+        Some(rest) => (true, rest),
+        None => (false, line.strip_prefix("#include")?),
+    };
+    if !rest.starts_with(char::is_whitespace) {
+        return None;
+    }
+    let name = rest.trim();
+    (!name.is_empty()).then_some((dir, name))
+}
+
+// Where an included name is: absolute names under the alternate root,
+// relative ones next to the including file
+fn include_path(file: Option<&str>, name: &str) -> String {
+    if name.starts_with('/') {
+        return crate::paths::path(name);
+    }
+    match file.and_then(|f| Path::new(f).parent()) {
+        Some(dir) if !dir.as_os_str().is_empty() => format!("{}/{}", dir.display(), name),
+        _ => name.to_string(),
+    }
+}
+
+// The name a file is known by for cycle detection, whatever way it was
+// reached
+fn canonical(path: &str) -> String {
+    std::fs::canonicalize(path).map(|p| p.display().to_string()).unwrap_or_else(|_| path.to_string())
+}
+
+/// The `.tab` files in a drop-in directory, sorted by name. A missing
+/// directory has none.
+pub fn drop_ins(dir: &str) -> Vec<String> {
+    let mut files: Vec<String> = match std::fs::read_dir(dir) {
+        Ok(entries) => entries
+            .flatten()
+            .filter(|e| e.file_type().is_ok_and(|t| !t.is_dir()))
+            .filter_map(|e| e.file_name().to_str().map(str::to_string))
+            .filter(|name| name.ends_with(".tab") && !name.starts_with('.'))
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+    files.sort();
+    files.into_iter().map(|name| format!("{}/{}", dir, name)).collect()
+}
+
+/// Parse the contents of an inittab. Duplicate ids are reported, the
+/// first entry is kept.
+pub fn parse_str(s: &str) -> Inittab {
+    let mut parser = Parser::new(DuplicatePolicy::FirstWins);
+    parser.add_str(None, s);
+    parser.finish()
+}
+
+/// Read and parse an inittab file.
+pub fn parse_file(path: &str) -> io::Result<Inittab> {
+    let mut parser = Parser::new(DuplicatePolicy::FirstWins);
+    parser.add_file(path)?;
+    Ok(parser.finish())
+}
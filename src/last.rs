@@ -0,0 +1,416 @@
+// last: show the logins and reboots recorded in wtmp, newest first. Init
+// behaves like this when it is started under the name last.
+//
+// Usage: last [-f file] [-n num] [-i] [-d] [-R] [-w] [--since TIME]
+//             [--until TIME] [name|tty...]
+//        last [-f file] --repair newfile
+//
+// -i shows the address the login came from instead of the host name, -d
+// looks that address up instead, -R leaves the host column out. -w
+// prints user and host names in full instead of cutting them to the
+// column. --since and --until only show logins within that time, given as
+// "now", "today", "yesterday", "YYYY-MM-DD [hh:mm]" or "-N" followed by
+// m, h or d for minutes, hours or days ago.
+//
+// A wtmp that a crash left with a half written record, or with garbage in
+// the middle, is read as far as it makes sense: records that can't be
+// real are skipped, said where on standard error, and the rest is shown.
+// --repair copies the records that are fine into a new file, which can
+// then replace the damaged one.
+
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+use rye_init::paths;
+
+use crate::utmp::{self, UtmpRecord};
+
+const EXIT_OK: i32 = 0;
+const EXIT_FAILED: i32 = 1;
+
+// Column widths unless -w
+const USER_WIDTH: usize = 8;
+const LINE_WIDTH: usize = 12;
+const HOST_WIDTH: usize = 16;
+
+// -d: addresses remembered, and how long all lookups together may take
+// before the rest is shown as addresses
+const DNS_CACHE_SIZE: usize = 256;
+const DNS_TIMEOUT: Duration = Duration::from_secs(5);
+
+struct Options {
+    file: String,
+    max: Option<usize>,
+    ip: bool,
+    dns: bool,
+    nohost: bool,
+    fullnames: bool,
+    since: Option<i64>,
+    until: Option<i64>,
+    names: Vec<String>,
+    repair: Option<String>,
+}
+
+fn usage() -> i32 {
+    eprintln!("Usage: last [-f file] [-n num] [-i] [-d] [-R] [-w] [--since TIME] [--until TIME] [name|tty...]");
+    eprintln!("       last [-f file] --repair newfile");
+    EXIT_FAILED
+}
+
+fn localtime(t: i64) -> libc::tm {
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    let t = t as libc::time_t;
+    unsafe { libc::localtime_r(&t, &mut tm) };
+    tm
+}
+
+// Seconds since the epoch of a local date and time
+fn mktime(year: i32, mon: i32, mday: i32, hour: i32, min: i32) -> Option<i64> {
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    tm.tm_year = year - 1900;
+    tm.tm_mon = mon - 1;
+    tm.tm_mday = mday;
+    tm.tm_hour = hour;
+    tm.tm_min = min;
+    tm.tm_isdst = -1;
+    match unsafe { libc::mktime(&mut tm) } {
+        -1 => None,
+        t => Some(t),
+    }
+}
+
+pub fn strftime(t: i64, fmt: &str) -> String {
+    let tm = localtime(t);
+    let Ok(fmt) = std::ffi::CString::new(fmt) else {
+        return String::new();
+    };
+    let mut buf = [0u8; 64];
+    let n = unsafe { libc::strftime(buf.as_mut_ptr() as *mut libc::c_char, buf.len(), fmt.as_ptr(), &tm) };
+    String::from_utf8_lossy(&buf[..n]).into_owned()
+}
+
+// The time argument of --since and --until
+fn parse_time(s: &str, now: i64) -> Option<i64> {
+    let today = localtime(now);
+    let midnight = mktime(today.tm_year + 1900, today.tm_mon + 1, today.tm_mday, 0, 0);
+
+    match s {
+        "now" => return Some(now),
+        "today" => return midnight,
+        "yesterday" => return mktime(today.tm_year + 1900, today.tm_mon + 1, today.tm_mday - 1, 0, 0),
+        _ => {}
+    }
+
+    if let Some(ago) = s.strip_prefix('-') {
+        let unit = match ago.chars().last()? {
+            'm' => 60,
+            'h' => 3600,
+            'd' => 86400,
+            _ => return None,
+        };
+        let n: i64 = ago[..ago.len() - 1].parse().ok()?;
+        return Some(now - n * unit);
+    }
+
+    let (date, clock) = s.split_once(' ').unwrap_or((s, "00:00"));
+    let date: Vec<i32> = date.split('-').map(|p| p.parse().ok()).collect::<Option<_>>()?;
+    let (hour, min) = clock.trim().split_once(':')?;
+    let (hour, min): (i32, i32) = (hour.parse().ok()?, min.parse().ok()?);
+    match date.as_slice() {
+        &[y, mo, d] if (1..=12).contains(&mo) && (1..=31).contains(&d) && hour < 24 && min < 60 => {
+            mktime(y, mo, d, hour, min)
+        }
+        _ => None,
+    }
+}
+
+// The address a login came from, if one was recorded
+pub fn address(rec: &UtmpRecord) -> Option<IpAddr> {
+    let mut bytes = [0u8; 16];
+    for (n, v) in rec.addr_v6.iter().enumerate() {
+        bytes[n * 4..n * 4 + 4].copy_from_slice(&v.to_ne_bytes());
+    }
+
+    if bytes.iter().all(|&b| b == 0) {
+        None
+    } else if bytes[4..].iter().all(|&b| b == 0) {
+        Some(IpAddr::V4(Ipv4Addr::new(bytes[0], bytes[1], bytes[2], bytes[3])))
+    } else {
+        Some(IpAddr::V6(Ipv6Addr::from(bytes)))
+    }
+}
+
+// The name of an address, from the resolver
+fn reverse_lookup(ip: IpAddr) -> Option<String> {
+    let mut host = [0 as libc::c_char; libc::NI_MAXHOST as usize];
+
+    let rc = match ip {
+        IpAddr::V4(ip) => {
+            let mut sin: libc::sockaddr_in = unsafe { std::mem::zeroed() };
+            sin.sin_family = libc::AF_INET as libc::sa_family_t;
+            sin.sin_addr.s_addr = u32::from_ne_bytes(ip.octets());
+            unsafe {
+                libc::getnameinfo(&sin as *const _ as *const libc::sockaddr,
+                    std::mem::size_of::<libc::sockaddr_in>() as libc::socklen_t,
+                    host.as_mut_ptr(), host.len() as libc::socklen_t,
+                    std::ptr::null_mut(), 0, libc::NI_NAMEREQD)
+            }
+        }
+        IpAddr::V6(ip) => {
+            let mut sin6: libc::sockaddr_in6 = unsafe { std::mem::zeroed() };
+            sin6.sin6_family = libc::AF_INET6 as libc::sa_family_t;
+            sin6.sin6_addr.s6_addr = ip.octets();
+            unsafe {
+                libc::getnameinfo(&sin6 as *const _ as *const libc::sockaddr,
+                    std::mem::size_of::<libc::sockaddr_in6>() as libc::socklen_t,
+                    host.as_mut_ptr(), host.len() as libc::socklen_t,
+                    std::ptr::null_mut(), 0, libc::NI_NAMEREQD)
+            }
+        }
+    };
+    if rc != 0 {
+        return None;
+    }
+    let name = unsafe { std::ffi::CStr::from_ptr(host.as_ptr()) };
+    Some(name.to_string_lossy().into_owned())
+}
+
+// Looks addresses up for -d. A resolver that doesn't answer must not make
+// last hang: each lookup runs in a thread we stop waiting for once the
+// time for all of them has run out, from then on addresses stay addresses.
+struct Resolver {
+    cache: HashMap<IpAddr, Option<String>>,
+    deadline: Instant,
+}
+
+impl Resolver {
+    fn new() -> Self {
+        Resolver {
+            cache: HashMap::new(),
+            deadline: Instant::now() + DNS_TIMEOUT,
+        }
+    }
+
+    fn lookup(&mut self, ip: IpAddr) -> String {
+        if let Some(name) = self.cache.get(&ip) {
+            return name.clone().unwrap_or_else(|| ip.to_string());
+        }
+
+        let left = self.deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return ip.to_string();
+        }
+
+        let (tx, rx) = mpsc::channel();
+        std::thread::spawn(move || {
+            let _ = tx.send(reverse_lookup(ip));
+        });
+        let name = match rx.recv_timeout(left) {
+            Ok(name) => name,
+            Err(_) => return ip.to_string(),
+        };
+
+        if self.cache.len() < DNS_CACHE_SIZE {
+            self.cache.insert(ip, name.clone());
+        }
+        name.unwrap_or_else(|| ip.to_string())
+    }
+}
+
+// "(hh:mm)", or "(d+hh:mm)" for a day or more
+fn duration(secs: i64) -> String {
+    let mins = secs.max(0) / 60;
+    let (days, hours, mins) = (mins / 1440, mins / 60 % 24, mins % 60);
+    if days > 0 {
+        format!("({}+{:02}:{:02})", days, hours, mins)
+    } else {
+        format!("({:02}:{:02})", hours, mins)
+    }
+}
+
+fn column(s: &str, width: usize, full: bool) -> String {
+    if full {
+        format!("{:<width$}", s, width = width)
+    } else {
+        format!("{:<width$.width$}", s, width = width)
+    }
+}
+
+// How a session or boot ended: still going, at a time, or cut short
+#[derive(Clone, Copy)]
+enum End {
+    Running,
+    At(i64),
+    Cut(i64, &'static str),
+}
+
+fn print_entry(options: &Options, user: &str, line: &str, host: &str, start: i64, end: End, running: &str) {
+    let ended = match end {
+        End::Running => format!("  {}", running),
+        End::At(t) => format!("- {}  {}", strftime(t, "%H:%M"), duration(t - start)),
+        End::Cut(t, why) => format!("- {}  {}", why, duration(t - start)),
+    };
+    let host = if options.nohost { String::new() } else { column(host, HOST_WIDTH, options.fullnames) + " " };
+    println!("{} {} {}{} {}",
+        column(user, USER_WIDTH, options.fullnames),
+        column(line, LINE_WIDTH, options.fullnames),
+        host,
+        strftime(start, "%a %b %e %H:%M"),
+        ended);
+}
+
+fn shown(options: &Options, user: &str, line: &str, start: i64) -> bool {
+    if options.since.is_some_and(|t| start < t) || options.until.is_some_and(|t| start > t) {
+        return false;
+    }
+    options.names.is_empty() || options.names.iter().any(|n| n == user || n == line)
+}
+
+fn list(options: &Options, records: &[UtmpRecord]) {
+    let mut logouts: HashMap<String, i64> = HashMap::new();
+    let mut resolver = Resolver::new();
+    let mut end = End::Running;
+    let mut printed = 0;
+
+    for rec in records.iter().rev() {
+        if options.max.is_some_and(|max| printed >= max) {
+            break;
+        }
+        let t = rec.tv_sec;
+        let line = utmp::field_str(&rec.line);
+
+        match rec.ut_type {
+            utmp::BOOT_TIME => {
+                if shown(options, "reboot", "system boot", t) {
+                    let kernel = utmp::field_str(&rec.host);
+                    let boot_end = match end {
+                        End::Running => End::Running,
+                        End::At(e) | End::Cut(e, _) => End::At(e),
+                    };
+                    print_entry(options, "reboot", "system boot", &kernel, t, boot_end, "still running");
+                    printed += 1;
+                }
+                // Whoever was logged in before didn't log out
+                logouts.clear();
+                end = End::Cut(t, "crash");
+            }
+            utmp::RUN_LVL if matches!(char::from_u32((rec.pid % 256) as u32), Some('0' | '6')) => {
+                logouts.clear();
+                end = End::Cut(t, "down");
+            }
+            utmp::DEAD_PROCESS if !line.is_empty() => {
+                logouts.insert(line, t);
+            }
+            utmp::USER_PROCESS => {
+                let user = utmp::field_str(&rec.user);
+                let session_end = match logouts.remove(&line) {
+                    Some(out) => End::At(out),
+                    None => end,
+                };
+                if shown(options, &user, &line, t) {
+                    let host = match address(rec) {
+                        Some(ip) if options.ip => ip.to_string(),
+                        Some(ip) if options.dns && !options.nohost => resolver.lookup(ip),
+                        _ => utmp::field_str(&rec.host),
+                    };
+                    print_entry(options, &user, &line, &host, t, session_end, "still logged in");
+                    printed += 1;
+                }
+                // An earlier login on this line ended here at the latest
+                logouts.insert(line, t);
+            }
+            _ => {}
+        }
+    }
+}
+
+// --repair: the records that are fine go to a new file
+fn repair(file: &str, repaired: &str, scan: &utmp::Scan) -> i32 {
+    if let Err(e) = utmp::write_records(repaired, scan.layout, &scan.records) {
+        eprintln!("last: {}: {}", repaired, e);
+        return EXIT_FAILED;
+    }
+    let damaged: usize = scan.damaged.iter().map(|(_, len)| len).sum();
+    println!("{} records of {} copied to {}, {} damaged bytes left out", scan.records.len(), file, repaired,
+        damaged);
+    EXIT_OK
+}
+
+pub fn last(args: &[String]) -> i32 {
+    let now = crate::clock::now() as i64;
+    let mut options = Options {
+        file: paths::path(utmp::WTMP_FILE),
+        max: None,
+        ip: false,
+        dns: false,
+        nohost: false,
+        fullnames: false,
+        since: None,
+        until: None,
+        names: Vec::new(),
+        repair: None,
+    };
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-f" => match args.next() {
+                Some(f) => options.file = f.clone(),
+                None => return usage(),
+            },
+            "-n" => match args.next().and_then(|n| n.parse().ok()) {
+                Some(n) => options.max = Some(n),
+                None => return usage(),
+            },
+            "-i" | "--ip" => options.ip = true,
+            "-d" | "--dns" => options.dns = true,
+            "-R" | "--nohostname" => options.nohost = true,
+            "-w" | "--fullnames" => options.fullnames = true,
+            "--repair" => match args.next() {
+                Some(f) => options.repair = Some(f.clone()),
+                None => return usage(),
+            },
+            "-s" | "--since" | "-t" | "--until" => {
+                let Some(t) = args.next().and_then(|s| parse_time(s, now)) else {
+                    eprintln!("last: bad time for {}", arg);
+                    return EXIT_FAILED;
+                };
+                if matches!(arg.as_str(), "-s" | "--since") {
+                    options.since = Some(t);
+                } else {
+                    options.until = Some(t);
+                }
+            }
+            _ if arg.starts_with('-') && arg[1..].parse::<usize>().is_ok() => {
+                options.max = arg[1..].parse().ok();
+            }
+            _ if arg.starts_with('-') => return usage(),
+            _ => options.names.push(arg.clone()),
+        }
+    }
+
+    let scan = match utmp::read_scan(&options.file) {
+        Ok(scan) => scan,
+        Err(e) => {
+            eprintln!("last: {}: {}", options.file, e);
+            return EXIT_FAILED;
+        }
+    };
+    for (offset, len) in &scan.damaged {
+        eprintln!("last: {}: skipped {} damaged bytes at offset {}", options.file, len, offset);
+    }
+
+    if let Some(repaired) = &options.repair {
+        return repair(&options.file, repaired, &scan);
+    }
+    let records = scan.records;
+
+    list(&options, &records);
+
+    let begins = records.first().map(|r| r.tv_sec).unwrap_or(now);
+    let name = options.file.rsplit('/').next().unwrap_or(&options.file);
+    println!("\n{} begins {}", name, strftime(begins, "%a %b %e %H:%M:%S %Y"));
+    EXIT_OK
+}
@@ -0,0 +1,67 @@
+// Why the last init went away
+//
+// Right before init executes itself again or has the kernel end its
+// container, it writes one JSON line to LAST_EXIT, and logs the same line:
+//
+//   {"reason":"panic","detail":"panicked at src/main.rs:812:9: ...","time":1760600000,"pid":1,"runlevel":"3"}
+//
+// reason is one of
+//
+//   panic               an internal error, init re-executed itself to recover
+//   upgrade             the init program was upgraded and init executed the
+//                       new one, see upgrade.rs
+//   container-shutdown  init powered off or restarted the container it is
+//                       process 1 of, the kernel ends it with a signal
+//
+// Those are the only ways init leaves: it has no request to re-execute
+// itself, and process 1 takes no signal it doesn't handle.
+//
+// An orchestrator supervising a containerized rye-init can tell from the
+// record why it has to start one again. The file is overwritten every
+// time, it only ever describes the last exit.
+
+use std::path::Path;
+
+use rye_init::runlevel::Runlevel;
+
+use crate::clock;
+use crate::version::json_str;
+
+pub const LAST_EXIT: &str = "/run/rye-init/last-exit";
+
+// Files container managers leave for the processes they run
+const CONTAINER_FILES: &[(&str, &str)] = &[("/.dockerenv", "docker"), ("/run/.containerenv", "podman")];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Reason {
+    Panic,
+    Upgrade,
+    ContainerShutdown,
+}
+
+impl Reason {
+    pub fn name(self) -> &'static str {
+        match self {
+            Reason::Panic => "panic",
+            Reason::Upgrade => "upgrade",
+            Reason::ContainerShutdown => "container-shutdown",
+        }
+    }
+}
+
+// The record for LAST_EXIT, without the newline
+pub fn record(reason: Reason, detail: &str, runlevel: &Runlevel) -> String {
+    format!("{{\"reason\":{},\"detail\":{},\"time\":{},\"pid\":{},\"runlevel\":{}}}",
+        json_str(reason.name()), json_str(detail), clock::now(), std::process::id(),
+        json_str(&runlevel.to_string()))
+}
+
+// The container manager we run under, from the "container" variable
+// systemd-nspawn, LXC and friends set, or the files Docker and Podman
+// leave. None on a machine of our own.
+pub fn container() -> Option<String> {
+    if let Ok(name) = std::env::var("container") && !name.is_empty() {
+        return Some(name);
+    }
+    CONTAINER_FILES.iter().find(|(file, _)| Path::new(file).exists()).map(|(_, name)| name.to_string())
+}
@@ -0,0 +1,18 @@
+pub mod bootid;
+#[cfg(feature = "bootlogd")]
+pub mod bootlogd;
+pub mod getty;
+pub mod initreq;
+pub mod inittab;
+pub mod localtime;
+pub mod paths;
+pub mod procscan;
+pub mod policy;
+pub mod profile;
+pub mod runlevel;
+#[cfg(feature = "shutdown")]
+pub mod shutdown;
+pub mod state;
+pub mod sys;
+pub mod templates;
+pub mod tty;
@@ -0,0 +1,489 @@
+//! Local time without the C library's locale.
+//!
+//! The messages wall and shutdown send to every terminal carry times that
+//! people read at a glance. They are formatted here, with English names and
+//! no dependence on the locale of whoever runs the command, in the timezone
+//! of the machine:
+//!
+//! * `TZ`, when it is set: a file (`:/path`, `:Europe/Berlin` or the same
+//!   without the colon, looked up in [`ZONEINFO`]) or a POSIX rule like
+//!   `CET-1CEST,M3.5.0,M10.5.0/3`. An empty `TZ` is UTC.
+//! * Otherwise [`LOCALTIME`], a TZif file.
+//!
+//! Either is read once per process, see [`local`]. What can't be read or
+//! understood is UTC.
+
+use std::fmt::Write;
+use std::sync::OnceLock;
+
+use crate::paths;
+
+/// The timezone of the machine.
+pub const LOCALTIME: &str = "/etc/localtime";
+
+/// Where zone names from `TZ` are looked up.
+pub const ZONEINFO: &str = "/usr/share/zoneinfo";
+
+const DAY_NAMES: [&str; 7] = ["Sunday", "Monday", "Tuesday", "Wednesday", "Thursday", "Friday", "Saturday"];
+const MONTH_NAMES: [&str; 12] = ["January", "February", "March", "April", "May", "June", "July", "August",
+                                 "September", "October", "November", "December"];
+
+/// Offset from UTC and name of the time in a zone at some instant.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LocalType {
+    pub utoff: i32,                     // Seconds east of UTC
+    pub isdst: bool,
+    pub abbr: String,                   // "CEST", or "+03" for zones without a name
+}
+
+// A day in a POSIX rule
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum RuleDay {
+    Julian(u32),                        // Jn: 1..=365, February 29 never counts
+    Zero(u32),                          // n: 0..=365, February 29 counts
+    Month(u32, u32, u32),               // Mm.w.d: weekday d of week w (5 is the last) of month m
+}
+
+// Daylight saving time of a POSIX rule, from start to end of every year.
+// The times are seconds after midnight local time, may be negative or
+// past 24 hours.
+#[derive(Debug, Clone, PartialEq)]
+struct Dst {
+    local: LocalType,
+    start: (RuleDay, i32),
+    end: (RuleDay, i32),
+}
+
+// A POSIX TZ rule
+#[derive(Debug, Clone, PartialEq)]
+struct Rule {
+    std: LocalType,
+    dst: Option<Dst>,
+}
+
+/// A timezone: the transitions of a TZif file and the rule for the times
+/// after them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Zone {
+    transitions: Vec<(i64, usize)>,     // When, and the index into types from then on
+    types: Vec<LocalType>,
+    rule: Option<Rule>,
+}
+
+/// A broken down local time.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LocalTime {
+    pub year: i64,
+    pub month: u32,                     // 1..=12
+    pub day: u32,                       // 1..=31
+    pub hour: u32,
+    pub minute: u32,
+    pub second: u32,
+    pub weekday: u32,                   // 0 is Sunday
+    pub yday: u32,                      // 0 is January 1
+    pub local: LocalType,
+}
+
+/// Days since the epoch of a date in the proleptic Gregorian calendar.
+pub fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let mp = (month as i64 + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+/// The date of a day since the epoch, as (year, month, day).
+pub fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    (yoe + era * 400 + (month <= 2) as i64, month, day)
+}
+
+fn is_leap(year: i64) -> bool {
+    year % 4 == 0 && (year % 100 != 0 || year % 400 == 0)
+}
+
+fn month_days(year: i64, month: u32) -> u32 {
+    match month {
+        2 if is_leap(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+fn weekday(days: i64) -> u32 {
+    (days + 4).rem_euclid(7) as u32
+}
+
+impl RuleDay {
+    // Days since the epoch of this day in year
+    fn days(self, year: i64) -> i64 {
+        let jan1 = days_from_civil(year, 1, 1);
+        match self {
+            RuleDay::Julian(n) => jan1 + n as i64 - 1 + (is_leap(year) && n >= 60) as i64,
+            RuleDay::Zero(n) => jan1 + n as i64,
+            RuleDay::Month(m, w, d) => {
+                let first = days_from_civil(year, m, 1);
+                let mut day = (d + 7 - weekday(first)) % 7 + (w - 1) * 7;
+                while day >= month_days(year, m) {
+                    day -= 7;
+                }
+                first + day as i64
+            }
+        }
+    }
+}
+
+impl Rule {
+    fn local_type(&self, t: i64) -> &LocalType {
+        let Some(dst) = &self.dst else {
+            return &self.std;
+        };
+
+        let year = civil_from_days((t + self.std.utoff as i64).div_euclid(86400)).0;
+        // Start is given in standard time, end in daylight saving time
+        let start = dst.start.0.days(year) * 86400 + dst.start.1 as i64 - self.std.utoff as i64;
+        let end = dst.end.0.days(year) * 86400 + dst.end.1 as i64 - dst.local.utoff as i64;
+        let in_dst = if start <= end {
+            start <= t && t < end
+        } else {
+            !(end <= t && t < start)
+        };
+        if in_dst { &dst.local } else { &self.std }
+    }
+}
+
+// A POSIX rule piece by piece
+struct Parser<'a> {
+    s: &'a str,
+}
+
+impl Parser<'_> {
+    fn eat(&mut self, c: char) -> bool {
+        match self.s.strip_prefix(c) {
+            Some(rest) => {
+                self.s = rest;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn number(&mut self) -> Option<u32> {
+        let end = self.s.find(|c: char| !c.is_ascii_digit()).unwrap_or(self.s.len());
+        let n = self.s[..end].parse().ok()?;
+        self.s = &self.s[end..];
+        Some(n)
+    }
+
+    // "CET", or "<+03>"
+    fn name(&mut self) -> Option<String> {
+        let name = if self.eat('<') {
+            let end = self.s.find('>')?;
+            let name = &self.s[..end];
+            self.s = &self.s[end + 1..];
+            name
+        } else {
+            let end = self.s.find(|c: char| !c.is_ascii_alphabetic()).unwrap_or(self.s.len());
+            let name = &self.s[..end];
+            self.s = &self.s[end..];
+            name
+        };
+        (name.len() >= 3).then(|| name.to_string())
+    }
+
+    // [+-]hh[:mm[:ss]] in seconds
+    fn time(&mut self) -> Option<i32> {
+        let sign = if self.eat('-') { -1 } else { self.eat('+'); 1 };
+        let mut secs = self.number()? as i32 * 3600;
+        if self.eat(':') {
+            secs += self.number()? as i32 * 60;
+            if self.eat(':') {
+                secs += self.number()? as i32;
+            }
+        }
+        Some(sign * secs)
+    }
+
+    // Jn, n or Mm.w.d, with an optional /time
+    fn rule_day(&mut self) -> Option<(RuleDay, i32)> {
+        let day = if self.eat('J') {
+            RuleDay::Julian(self.number().filter(|n| (1..=365).contains(n))?)
+        } else if self.eat('M') {
+            let m = self.number().filter(|n| (1..=12).contains(n))?;
+            self.eat('.').then_some(())?;
+            let w = self.number().filter(|n| (1..=5).contains(n))?;
+            self.eat('.').then_some(())?;
+            let d = self.number().filter(|n| *n <= 6)?;
+            RuleDay::Month(m, w, d)
+        } else {
+            RuleDay::Zero(self.number().filter(|n| *n <= 365)?)
+        };
+        let time = if self.eat('/') { self.time()? } else { 2 * 3600 };
+        Some((day, time))
+    }
+}
+
+impl Rule {
+    fn parse(spec: &str) -> Option<Rule> {
+        let mut p = Parser { s: spec };
+        let name = p.name()?;
+        // POSIX offsets are west of UTC
+        let utoff = -p.time()?;
+        let std = LocalType { utoff, isdst: false, abbr: name };
+        if p.s.is_empty() {
+            return Some(Rule { std, dst: None });
+        }
+
+        let name = p.name()?;
+        let dst_utoff = if p.s.starts_with(',') { utoff + 3600 } else { -p.time()? };
+        let local = LocalType { utoff: dst_utoff, isdst: true, abbr: name };
+        // A DST name without rules: the US rules, like the C library
+        let (start, end) = if p.eat(',') {
+            let start = p.rule_day()?;
+            p.eat(',').then_some(())?;
+            (start, p.rule_day()?)
+        } else {
+            ((RuleDay::Month(3, 2, 0), 7200), (RuleDay::Month(11, 1, 0), 7200))
+        };
+        p.s.is_empty().then_some(Rule { std, dst: Some(Dst { local, start, end }) })
+    }
+}
+
+fn be32(data: &[u8], at: usize) -> Option<i64> {
+    Some(i32::from_be_bytes(data.get(at..at + 4)?.try_into().ok()?) as i64)
+}
+
+fn be64(data: &[u8], at: usize) -> Option<i64> {
+    Some(i64::from_be_bytes(data.get(at..at + 8)?.try_into().ok()?))
+}
+
+impl Zone {
+    /// Coordinated Universal Time.
+    pub fn utc() -> Zone {
+        Zone { transitions: Vec::new(), types: Vec::new(), rule: Rule::parse("UTC0") }
+    }
+
+    /// A zone from a POSIX TZ rule, like `EST5EDT,M3.2.0,M11.1.0`.
+    pub fn from_posix(spec: &str) -> Option<Zone> {
+        Some(Zone { transitions: Vec::new(), types: Vec::new(), rule: Some(Rule::parse(spec)?) })
+    }
+
+    /// A zone from the contents of a TZif file, version 1 to 4.
+    pub fn from_tzif(data: &[u8]) -> Option<Zone> {
+        if !data.starts_with(b"TZif") {
+            return None;
+        }
+
+        // Header: magic, version, 15 bytes reserved, six counts
+        let counts = |at: usize| -> Option<[usize; 6]> {
+            let mut n = [0; 6];
+            for (i, count) in n.iter_mut().enumerate() {
+                *count = be32(data, at + 20 + i * 4)? as u32 as usize;
+            }
+            Some(n)
+        };
+        let block = |n: [usize; 6], time_size: usize| {
+            let [isutcnt, isstdcnt, leapcnt, timecnt, typecnt, charcnt] = n;
+            timecnt * time_size + timecnt + typecnt * 6 + charcnt + leapcnt * (time_size + 4) + isstdcnt + isutcnt
+        };
+
+        let mut at = 0;
+        let mut n = counts(at)?;
+        let mut time_size = 4;
+        if data[4] >= b'2' {
+            // Skip the 32 bit data, the 64 bit version follows it
+            at += 44 + block(n, 4);
+            if data.get(at..at + 4) != Some(b"TZif") {
+                return None;
+            }
+            n = counts(at)?;
+            time_size = 8;
+        }
+        let [_, _, _, timecnt, typecnt, charcnt] = n;
+        if typecnt == 0 {
+            return None;
+        }
+
+        let times = at + 44;
+        let indices = times + timecnt * time_size;
+        let infos = indices + timecnt;
+        let chars = infos + typecnt * 6;
+        let names = data.get(chars..chars + charcnt)?;
+
+        let mut types = Vec::with_capacity(typecnt);
+        for i in 0..typecnt {
+            let info = infos + i * 6;
+            let utoff = be32(data, info)? as i32;
+            let isdst = *data.get(info + 4)? != 0;
+            let idx = *data.get(info + 5)? as usize;
+            let name = names.get(idx..)?;
+            let end = name.iter().position(|&b| b == 0).unwrap_or(name.len());
+            types.push(LocalType { utoff, isdst, abbr: String::from_utf8_lossy(&name[..end]).into_owned() });
+        }
+
+        let mut transitions = Vec::with_capacity(timecnt);
+        for i in 0..timecnt {
+            let t = if time_size == 8 { be64(data, times + i * 8)? } else { be32(data, times + i * 4)? };
+            let idx = *data.get(indices + i)? as usize;
+            if idx >= typecnt {
+                return None;
+            }
+            transitions.push((t, idx));
+        }
+
+        // The footer of version 2 and later: the rule after the last transition
+        let mut rule = None;
+        if time_size == 8 {
+            let footer = data.get(at + 44 + block(n, 8)..).unwrap_or_default();
+            if let Some(spec) = std::str::from_utf8(footer).ok().and_then(|f| f.strip_prefix('\n')) {
+                rule = spec.split('\n').next().filter(|s| !s.is_empty()).and_then(Rule::parse);
+            }
+        }
+
+        Some(Zone { transitions, types, rule })
+    }
+
+    /// The zone named by `TZ`, or [`LOCALTIME`] without it.
+    pub fn load() -> Zone {
+        let tz = match std::env::var("TZ") {
+            Ok(tz) => tz,
+            Err(_) => return Zone::file(&paths::path(LOCALTIME)).unwrap_or_else(Zone::utc),
+        };
+        if tz.is_empty() {
+            return Zone::utc();
+        }
+
+        let name = tz.strip_prefix(':').unwrap_or(&tz);
+        let file = if name.starts_with('/') { name.to_string() } else { format!("{}/{}", ZONEINFO, name) };
+        Some(file)
+            .filter(|_| !name.contains(".."))
+            .and_then(|file| Zone::file(&file))
+            .or_else(|| Zone::from_posix(name))
+            .unwrap_or_else(Zone::utc)
+    }
+
+    fn file(path: &str) -> Option<Zone> {
+        Zone::from_tzif(&std::fs::read(path).ok()?)
+    }
+
+    /// Offset and name of the local time at `t`, seconds since the epoch.
+    pub fn local_type(&self, t: i64) -> LocalType {
+        let after = self.transitions.partition_point(|&(at, _)| at <= t);
+        match &self.rule {
+            Some(rule) if after == self.transitions.len() => return rule.local_type(t).clone(),
+            _ => {}
+        }
+        match after.checked_sub(1) {
+            Some(n) => self.types[self.transitions[n].1].clone(),
+            // Before the first transition, or a file without any
+            None => self.types.first().cloned().unwrap_or_else(|| LocalType {
+                utoff: 0, isdst: false, abbr: "UTC".to_string(),
+            }),
+        }
+    }
+
+    /// The local time at `t`, seconds since the epoch.
+    pub fn local_time(&self, t: i64) -> LocalTime {
+        let local = self.local_type(t);
+        let secs = t + local.utoff as i64;
+        let days = secs.div_euclid(86400);
+        let rest = secs.rem_euclid(86400) as u32;
+        let (year, month, day) = civil_from_days(days);
+        LocalTime {
+            year,
+            month,
+            day,
+            hour: rest / 3600,
+            minute: rest / 60 % 60,
+            second: rest % 60,
+            weekday: weekday(days),
+            yday: (days - days_from_civil(year, 1, 1)) as u32,
+            local,
+        }
+    }
+}
+
+impl LocalTime {
+    /// Format like strftime() in the C locale. Understood are `%a %A %b %B
+    /// %c %d %D %e %F %h %H %I %j %k %l %m %M %n %p %R %s %S %t %T %u %w %y
+    /// %Y %z %Z %%`, anything else is copied as it is.
+    pub fn format(&self, fmt: &str) -> String {
+        let mut out = String::new();
+        let mut chars = fmt.chars();
+        while let Some(c) = chars.next() {
+            if c != '%' {
+                out.push(c);
+                continue;
+            }
+            let hour12 = match self.hour % 12 { 0 => 12, h => h };
+            let _ = match chars.next() {
+                Some('a') => write!(out, "{}", &DAY_NAMES[self.weekday as usize][..3]),
+                Some('A') => write!(out, "{}", DAY_NAMES[self.weekday as usize]),
+                Some('b') | Some('h') => write!(out, "{}", &MONTH_NAMES[self.month as usize - 1][..3]),
+                Some('B') => write!(out, "{}", MONTH_NAMES[self.month as usize - 1]),
+                Some('c') => write!(out, "{}", self.format("%a %b %e %H:%M:%S %Y")),
+                Some('d') => write!(out, "{:02}", self.day),
+                Some('D') => write!(out, "{}", self.format("%m/%d/%y")),
+                Some('e') => write!(out, "{:2}", self.day),
+                Some('F') => write!(out, "{}", self.format("%Y-%m-%d")),
+                Some('H') => write!(out, "{:02}", self.hour),
+                Some('I') => write!(out, "{:02}", hour12),
+                Some('j') => write!(out, "{:03}", self.yday + 1),
+                Some('k') => write!(out, "{:2}", self.hour),
+                Some('l') => write!(out, "{:2}", hour12),
+                Some('m') => write!(out, "{:02}", self.month),
+                Some('M') => write!(out, "{:02}", self.minute),
+                Some('n') => writeln!(out),
+                Some('p') => write!(out, "{}", if self.hour < 12 { "AM" } else { "PM" }),
+                Some('R') => write!(out, "{}", self.format("%H:%M")),
+                Some('s') => write!(out, "{}", self.timestamp()),
+                Some('S') => write!(out, "{:02}", self.second),
+                Some('t') => write!(out, "\t"),
+                Some('T') => write!(out, "{}", self.format("%H:%M:%S")),
+                Some('u') => write!(out, "{}", if self.weekday == 0 { 7 } else { self.weekday }),
+                Some('w') => write!(out, "{}", self.weekday),
+                Some('y') => write!(out, "{:02}", self.year.rem_euclid(100)),
+                Some('Y') => write!(out, "{}", self.year),
+                Some('z') => {
+                    let off = self.local.utoff.unsigned_abs() / 60;
+                    write!(out, "{}{:02}{:02}", if self.local.utoff < 0 { '-' } else { '+' }, off / 60, off % 60)
+                }
+                Some('Z') => write!(out, "{}", self.local.abbr),
+                Some('%') => write!(out, "%"),
+                Some(c) => write!(out, "%{}", c),
+                None => write!(out, "%"),
+            };
+        }
+        out
+    }
+
+    /// Seconds since the epoch.
+    pub fn timestamp(&self) -> i64 {
+        days_from_civil(self.year, self.month, self.day) * 86400
+            + (self.hour * 3600 + self.minute * 60 + self.second) as i64
+            - self.local.utoff as i64
+    }
+}
+
+/// The timezone of the machine, read the first time it is needed.
+pub fn local() -> &'static Zone {
+    static LOCAL: OnceLock<Zone> = OnceLock::new();
+    LOCAL.get_or_init(Zone::load)
+}
+
+/// Format `t`, seconds since the epoch, in the local timezone, see
+/// [`LocalTime::format`].
+pub fn format(t: i64, fmt: &str) -> String {
+    local().local_time(t).format(fmt)
+}
@@ -1,15 +1,74 @@
-use std::time::{SystemTime, UNIX_EPOCH};
 use std::sync::atomic::{AtomicBool, Ordering};
+use nix::sys::signal::{self, SaFlags, SigAction, SigHandler, SigSet, Signal};
+
+mod battery;
+mod checkpoint;
+mod clock;
+mod config;
+mod bootenv;
+mod console;
+mod control;
+mod devwait;
+mod diag;
+mod earlylog;
+mod event;
+mod hooks;
+mod initlog;
+mod initctl;
+#[cfg(feature = "utmp")]
+mod last;
+mod last_exit;
+#[cfg(feature = "metrics")]
+mod metrics;
+mod panic;
+mod pidfd;
+mod reread;
+mod rescue;
+mod runlevel_cmd;
+mod sandbox;
+mod security;
+mod status;
+#[cfg(feature = "shutdown")]
+mod shutdown_cmd;
+mod spawn;
+mod splash;
+mod telinit;
+mod timer;
+mod trace;
+mod transitions;
+mod upgrade;
+#[cfg(feature = "utmp")]
+mod utmp;
+mod version;
+#[cfg(feature = "wall")]
+mod wall;
+mod watchdog;
+
+use event::{EventLoop, EventSource};
+use initlog::LogEvent;
+use initreq::InitRequest;
+use nix::fcntl::OFlag;
+use nix::sys::stat::Mode;
+use std::os::fd::{BorrowedFd, FromRawFd, IntoRawFd};
+use std::os::unix::fs::OpenOptionsExt;
+use rye_init::bootid;
+use rye_init::getty;
+use rye_init::initreq;
+use rye_init::inittab::{self, EntryId, InitAction};
+use rye_init::paths;
+use rye_init::policy;
+use rye_init::procscan::{self, ProcCache};
+use rye_init::profile;
+use rye_init::runlevel::{self, Runlevel, RunlevelSet};
+use rye_init::state::{self, ChildFlags};
+use rye_init::sys::{Kernel, Reboot, Sys};
+use rye_init::tty;
 
 // Standard configuration
 const CHANGE_WAIT: bool = false; // Change runlevel while waiting for a process to exit?
 const INIT_PROGRAM: &str = "/sbin/init";
 const VERSION: &str = "0.1.0";
 
-// Debug and test modes
-const DEBUG: bool = false;       // Debug code off
-const INITDEBUG: bool = false;   // Fork at startup to debug init
-
 // Constants
 const INITPID: i32 = 1;          // pid of first process
 const PIPE_FD: i32 = 10;         // File number of initfifo
@@ -25,6 +84,17 @@ const LONG_SLEEP: u64 = 30000;
 const MAXSPAWN: u32 = 10;        // Max times respawned in...
 const TESTTIME: u64 = 120;       // ...this many seconds
 const SLEEPTIME: u64 = 300;      // Disable time
+const MAX_BACKOFF: u64 = 8;      // Longest wait in seconds between two respawns
+const REAP_BATCH: usize = 64;    // Most children collected per trip through the loop
+
+// Seconds between /proc scans for naming orphans we reap
+const PROC_SCAN_INTERVAL: u64 = 2;
+
+// Milliseconds between two checks of the process table against reality
+const RECONCILE_INTERVAL: u64 = 60_000;
+
+// How long to wait for an answer in interactive boot (ms)
+const CONFIRM_TIMEOUT: u16 = 10000;
 
 // State parser command constants and structures
 const NR_EXTRA_ENV: usize = 16;
@@ -32,6 +102,23 @@ const NR_EXTRA_ENV: usize = 16;
 // Global atomic signals
 static GOT_CONT: AtomicBool = AtomicBool::new(false);
 static GOT_SIGNALS: AtomicBool = AtomicBool::new(false);
+static GOT_PWR: AtomicBool = AtomicBool::new(false);
+static GOT_CAD: AtomicBool = AtomicBool::new(false);
+static GOT_TERM: AtomicBool = AtomicBool::new(false);
+static GOT_DEBUG: AtomicBool = AtomicBool::new(false);
+static GOT_DIAG: AtomicBool = AtomicBool::new(false);
+
+// SIGRTMIN plus this toggles debug output
+const DEBUG_SIGNAL: i32 = 3;
+
+// Id of the entries init makes up itself, no inittab id can look like it
+const SPECIAL_ID: &str = "~~";
+
+// Where a UPS daemon says what SIGPWR is about: F(ail), L(ow) or O(k)
+const POWER_STATUS: &str = "/run/powerstatus";
+
+// Init's own OOM score adjustment: never the OOM killer's choice
+const INIT_OOM_SCORE_ADJ: i32 = -1000;
 
 // Default path inherited by every child
 const PATH_DEFAULT: &str = "/sbin:/usr/sbin:/bin:/usr/bin";
@@ -39,7 +126,14 @@ const PATH_DEFAULT: &str = "/sbin:/usr/sbin:/bin:/usr/bin";
 // Signature for re-exec fd
 const SIGNATURE: &str = "12567362";
 
+macro_rules! initdbg {
+    ($level:expr, $fmt:expr $(, $args:expr)*) => {
+        initlog::debuglog($level, &format!($fmt $(, $args)*))
+    };
+}
+
 // Extra environment variables
+#[derive(Debug)]
 pub struct ExtraEnv {
     pub vars: [Option<String>; NR_EXTRA_ENV],
 }
@@ -54,69 +148,6 @@ impl ExtraEnv {
 }
 
 
-// Actions to be taken by init
-#[derive(Debug, Clone, Copy, PartialEq)]
-pub enum InitAction {
-    Respawn = 1,
-    Wait = 2,
-    Once = 3,
-    Boot = 4,
-    BootWait = 5,
-    PowerFail = 6,
-    PowerWait = 7,
-    PowerOkWait = 8,
-    CtrlAltDel = 9,
-    Off = 10,
-    OnDemand = 11,
-    InitDefault = 12,
-    SysInit = 13,
-    PowerFailNow = 14,
-    KbRequest = 15,
-}
-
-impl InitAction {
-    pub fn from_str(s: &str) -> Option<Self> {
-        match s.to_lowercase().as_str() {
-            "respawn" => Some(InitAction::Respawn),
-            "wait" => Some(InitAction::Wait),
-            "once" => Some(InitAction::Once),
-            "boot" => Some(InitAction::Boot),
-            "bootwait" => Some(InitAction::BootWait),
-            "powerfail" => Some(InitAction::PowerFail),
-            "powerwait" => Some(InitAction::PowerWait),
-            "powerokwait" => Some(InitAction::PowerOkWait),
-            "ctrlaltdel" => Some(InitAction::CtrlAltDel),
-            "off" => Some(InitAction::Off),
-            "ondemand" => Some(InitAction::OnDemand),
-            "initdefault" => Some(InitAction::InitDefault),
-            "sysinit" => Some(InitAction::SysInit),
-            "powerfailnow" => Some(InitAction::PowerFailNow),
-            "kbrequest" => Some(InitAction::KbRequest),
-            _ => None,
-        }
-    }
-}
-
-// String length constants
-const INITTAB_ID: usize = 8;
-const RUNLEVEL_LENGTH: usize = 12;
-const ACTION_LENGTH: usize = 33;
-const PROCESS_LENGTH: usize = 512;
-
-// Values for the 'flags' field (using bitflags)
-bitflags::bitflags! {
-    #[derive(Debug, Clone, Copy)]
-    pub struct ChildFlags: u32 {
-        const RUNNING = 2;      // Process is still running
-        const KILLME = 4;       // Kill this process
-        const DEMAND = 8;       // "runlevels" a b c
-        const FAILING = 16;     // process respawns rapidly
-        const WAITING = 32;     // We're waiting for this process
-        const ZOMBIE = 64;      // This process is already dead
-        const XECUTED = 128;    // Set if spawned once or more times
-    }
-}
-
 // Log levels
 #[derive(Debug, Clone, Copy)]
 pub enum LogLevel {
@@ -127,76 +158,85 @@ pub enum LogLevel {
 
 const NO_PROCESS: i32 = 0;
 
+// Work scheduled on the timer wheel
+#[derive(Debug, Clone)]
+pub enum Timeout {
+    DelayedStart(EntryId),              // A delayed or backed off entry may start now
+    DeviceWait(EntryId),                // An entry has waited long enough for its terminal
+    Reconcile,                          // Time to check the process table
+    Battery,                            // Time to look at the battery
+    Upgrade,                            // Time to look at the init program
+    ContainerStop,                      // The container took too long to stop
+    Checkpoint,                         // Time to save the state, see checkpoint.rs
+    BootTimeout(EntryId, i32),          // An entry init waits for (and its pid) took too long
+    EarlyLog,                           // Time to look for the syslog daemon, see earlylog.rs
+    Syslog,                             // Time to send the syslog messages that wait, see initlog.rs
+    BootDeadline,                       // The boot should have finished by now, see boot-deadline in config.rs
+}
+
 // Information about a process in the in-core inittab
 #[derive(Debug, Clone)]
 pub struct Child {
     pub flags: ChildFlags,              // Status of this entry
     pub exstat: i32,                    // Exit status of process
     pub pid: i32,                       // Pid of this process
-    pub tm: u64,                        // When respawned last (Unix timestamp)
+    pub pidfd: i32,                     // pidfd of this process, -1 if none
+    pub pgid: i32,                      // Its process group, if it leads one
+    pub tm: u64,                        // When respawned last (CLOCK_BOOTTIME seconds)
     pub count: u32,                     // Times respawned in the last 2 minutes
-    pub id: String,                     // Inittab id (must be unique, max 8 chars)
-    pub rlevel: String,                 // run levels (max 12 chars)
+    pub respawns: u64,                  // Times started again since boot
+    pub id: EntryId,                    // Inittab id (must be unique)
+    pub rlevel: RunlevelSet,            // run levels
     pub action: InitAction,             // what to do
-    pub process: String,                // The command line (max 512 chars)
+    pub process: String,                // The command line
+    pub fallback: Vec<String>,          // Tried in turn when process can't be executed
+    pub attrs: inittab::Attributes,     // user, group, ... from the extended syntax
+    pub delay: u32,                     // Seconds a delayed entry waits
+    pub due: u64,                       // When it starts (monotonic ms), 0 if not pending
+    pub timer: timer::TimerId,          // Timer for due, 0 if none
+    pub dev_wait: u64,                  // Since when (monotonic ms) it waits for its terminal, 0 if not
     pub new: Option<Box<Child>>,        // New entry (after inittab re-read)
     pub next: Option<Box<Child>>,       // For the linked list
 }
 
 impl Child {
-    pub fn new() -> Self {
+    pub fn new(id: EntryId) -> Self {
         Child {
             flags: ChildFlags::empty(),
             exstat: 0,
             pid: NO_PROCESS,
-            tm: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs(),
+            pidfd: -1,
+            pgid: NO_PROCESS,
+            tm: clock::boottime(),
             count: 0,
-            id: String::new(),
-            rlevel: String::new(),
+            respawns: 0,
+            id,
+            rlevel: RunlevelSet::default(),
             action: InitAction::Once,
             process: String::new(),
+            fallback: Vec::new(),
+            attrs: inittab::Attributes::default(),
+            delay: 0,
+            due: 0,
+            timer: 0,
+            dev_wait: 0,
             new: None,
             next: None,
         }
     }
 
-    pub fn from_inittab_line(line: &str) -> Option<Self> {
-        let parts: Vec<&str> = line.split(':').collect();
-        if parts.len() != 4 {
-            return None;
-        }
-
-        let id = parts[0];
-        let runlevels = parts[1];
-        let action_str = parts[2];
-        let process = parts[3];
+    pub fn builder(id: EntryId) -> ChildBuilder {
+        ChildBuilder { child: Child::new(id) }
+    }
 
-        // Validate lengths
-        if id.len() > INITTAB_ID ||
-            runlevels.len() > RUNLEVEL_LENGTH ||
-            action_str.len() > ACTION_LENGTH ||
-            process.len() > PROCESS_LENGTH {
-            return None;
+    pub fn from_inittab_line(line: &str) -> Option<Self> {
+        match inittab::parse_line(line, 0) {
+            Ok(Some(entry)) => Child::try_from(entry).ok(),
+            _ => None,
         }
-
-        let action = InitAction::from_str(action_str)?;
-
-        Some(Child {
-            flags: ChildFlags::empty(),
-            exstat: 0,
-            pid: NO_PROCESS,
-            tm: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs(),
-            count: 0,
-            id: id.to_string(),
-            rlevel: runlevels.to_string(),
-            action,
-            process: process.to_string(),
-            new: None,
-            next: None,
-        })
     }
 
-    pub fn should_run_at_level(&self, level: char) -> bool {
+    pub fn should_run_at_level(&self, level: &Runlevel) -> bool {
         self.rlevel.contains(level)
     }
 
@@ -208,7 +248,25 @@ impl Child {
         self.flags.contains(ChildFlags::FAILING)
     }
 
+    // Count a start at `now`, in seconds of CLOCK_BOOTTIME, against the
+    // TESTTIME window. Returns whether the entry came back MAXSPAWN times
+    // within it.
+    pub fn count_start(&mut self, now: u64) -> bool {
+        if self.tm + TESTTIME > now {
+            self.count += 1;
+        } else {
+            self.count = 0;
+            self.tm = now;
+        }
+        self.count >= MAXSPAWN
+    }
+
+    // An entry is RUNNING from the fork until its process has been reaped.
+    // From then on it is a ZOMBIE, which only says that exstat holds how
+    // the last process ended, and its pid is released. Starting it again
+    // makes it RUNNING once more.
     pub fn mark_running(&mut self) {
+        self.flags.remove(ChildFlags::ZOMBIE | ChildFlags::TIMEDOUT);
         self.flags.insert(ChildFlags::RUNNING);
     }
 
@@ -220,32 +278,142 @@ impl Child {
     pub fn mark_executed(&mut self) {
         self.flags.insert(ChildFlags::XECUTED);
     }
+
+    // Open a pidfd for the current pid, right after it was spawned
+    pub fn attach_pidfd(&mut self) {
+        pidfd::pidfd_close(self.pidfd);
+        self.pidfd = pidfd::pidfd_open(self.pid);
+    }
+
+    pub fn close_pidfd(&mut self) {
+        pidfd::pidfd_close(self.pidfd);
+        self.pidfd = -1;
+    }
+
+    // Remember the process group the process leads. Every process we start
+    // is a session leader, so that is its own pid unless it moved.
+    pub fn attach_group(&mut self) {
+        let pid = nix::unistd::Pid::from_raw(self.pid);
+        self.pgid = match nix::unistd::getpgid(Some(pid)) {
+            Ok(pgid) if pgid == pid => self.pid,
+            _ => NO_PROCESS,
+        };
+    }
+
+    // Forget the pid once it has been reaped, it may be reused from now on.
+    // So may the process group, which is only ours while its leader is.
+    pub fn release_pid(&mut self) {
+        self.close_pidfd();
+        self.pid = NO_PROCESS;
+        self.pgid = NO_PROCESS;
+    }
+
+    pub fn send_signal(&self, sig: Signal) -> nix::Result<()> {
+        pidfd::send_signal(self.pidfd, self.pid, sig)
+    }
+
+    // What asks the process to stop: its stopsig attribute or SIGTERM
+    pub fn stop_signal(&self) -> Signal {
+        self.attrs.stopsig.as_deref().and_then(inittab::parse_signal).unwrap_or(Signal::SIGTERM)
+    }
+
+    // Signal the process and the helpers it started: shells and daemons
+    // leave children behind that would outlive a signal to the process
+    // alone. Those that put themselves into a group of their own are out of
+    // reach.
+    pub fn send_stop_signal(&self, sig: Signal) -> nix::Result<()> {
+        if self.pgid > 0 && self.pgid == self.pid {
+            match signal::killpg(nix::unistd::Pid::from_raw(self.pgid), sig) {
+                Err(nix::errno::Errno::ESRCH) => {}
+                res => return res,
+            }
+        }
+        self.send_signal(sig)
+    }
+
+    // Entries whose process starts with '+' do their own utmp bookkeeping
+    pub fn uses_utmp(&self) -> bool {
+        !self.process.starts_with('+')
+    }
+}
+
+// The entries of a process table in order, see InitState::children
+pub struct Children<'a> {
+    next: Option<&'a Child>,
+}
+
+impl<'a> Iterator for Children<'a> {
+    type Item = &'a Child;
+
+    fn next(&mut self) -> Option<&'a Child> {
+        let child = self.next?;
+        self.next = child.next.as_deref();
+        Some(child)
+    }
+}
+
+// Entries that don't come from the inittab, the emergency shell for
+// instance, are put together with this
+pub struct ChildBuilder {
+    child: Child,
+}
+
+impl ChildBuilder {
+    pub fn rlevel(mut self, rlevel: RunlevelSet) -> Self {
+        self.child.rlevel = rlevel;
+        self
+    }
+
+    pub fn action(mut self, action: InitAction) -> Self {
+        self.child.action = action;
+        self
+    }
+
+    pub fn process(mut self, process: &str) -> Self {
+        self.child.process = process.to_string();
+        self
+    }
+
+    pub fn fallback(mut self, fallback: &[String]) -> Self {
+        self.child.fallback = fallback.to_vec();
+        self
+    }
+
+    pub fn attrs(mut self, attrs: inittab::Attributes) -> Self {
+        self.child.attrs = attrs;
+        self
+    }
+
+    pub fn delay(mut self, delay: u32) -> Self {
+        self.child.delay = delay;
+        self
+    }
+
+    pub fn flags(mut self, flags: ChildFlags) -> Self {
+        self.child.flags = flags;
+        self
+    }
+
+    pub fn build(self) -> Child {
+        self.child
+    }
 }
 
-// Tokens in state parser
-#[derive(Debug, Clone, Copy, PartialEq)]
-pub enum StateToken {
-    Ver = 1,
-    End = 2,
-    Rec = 3,
-    Eor = 4,
-    Lev = 5,
-    Flag = 6,
-    Action = 7,
-    Process = 8,
-    Pid = 9,
-    Exs = 10,
-    Eof = -1,
-    Runlevel = -2,
-    ThisLevel = -3,
-    PrevLevel = -4,
-    GotSign = -5,
-    WroteWtmpReboot = -6,
-    WroteUtmpReboot = -7,
-    SlTime = -8,
-    DidBoot = -9,
-    WroteWtmpRlevel = -16,
-    WroteUtmpRlevel = -17,
+// An entry from the parser. Only fails for an id that is too long, which
+// the parser doesn't let through once id ranges are expanded.
+impl TryFrom<inittab::Entry> for Child {
+    type Error = String;
+
+    fn try_from(entry: inittab::Entry) -> Result<Self, Self::Error> {
+        let id = EntryId::new(&entry.id).ok_or_else(|| format!("bad id \"{}\"", entry.id))?;
+        Ok(Child::builder(id)
+            .rlevel(RunlevelSet::parse(&entry.runlevels))
+            .action(entry.action)
+            .process(&entry.process)
+            .attrs(entry.attrs)
+            .delay(entry.delay)
+            .build())
+    }
 }
 
 // Global state struct
@@ -257,9 +425,9 @@ pub struct InitState {
     pub wrote_utmp_reboot: bool,
     pub wrote_wtmp_rlevel: bool,
     pub wrote_utmp_rlevel: bool,
-    pub curlevel: char,                 // Current runlevel
-    pub prevlevel: char,                // Previous runlevel
-    pub dfl_level: char,                // Default runlevel
+    pub curlevel: Runlevel,             // Current runlevel
+    pub prevlevel: Runlevel,            // Previous runlevel
+    pub dfl_level: Runlevel,            // Default runlevel
     pub emerg_shell: bool,              // Start emergency shell?
     pub sleep_time: u64,                // Sleep time between TERM and KILL
     pub console_dev: Option<String>,    // Console device
@@ -267,7 +435,51 @@ pub struct InitState {
     pub did_boot: bool,                 // Is BOOT* done?
     pub reload: bool,                   // Should we do initialization stuff?
     pub myname: String,                 // What should we exec
+    pub args: Vec<String>,              // What we were started with, passed on when we exec
+    pub sandbox: bool,                  // Not process 1 but --sandbox, see sandbox.rs
     pub oops_error: i32,                // Used be re-exec. May be refactored out later
+    #[cfg(feature = "utmp")]
+    pub utmp: utmp::Utmp,               // Where utmp/wtmp records go
+    pub structured_log: bool,           // Add RFC 5424 structured data to log messages
+    pub extra_env: ExtraEnv,            // Set with INIT_CMD_SETENV
+    pub deferred: Vec<InitRequest>,     // Requests read while we were busy
+    pub reap_more: bool,                // Children were left for the next trip
+    pub confirm: bool,                  // Ask before starting boot entries
+    pub procs: ProcCache,               // Recently seen processes, to name orphans
+    pub fifo_buf: initreq::RequestBuffer, // Input from /run/initctl not yet handled
+    pub control_fd: i32,                // Listening control socket
+    pub control_conns: Vec<(i32, control::Peer)>, // Open control connections
+    pub control_root_only: bool,        // Only take requests from root there
+    pub boot_env: Vec<String>,          // Allowed part of the environment we got
+    pub auto_getty: Option<String>,     // Getty to start on a console without one
+    pub emergency_shells: Option<Vec<String>>, // From rye_init.emergency=, over the config file
+    pub strict: Option<bool>,           // From rye_init.strict=, over the config file
+    pub duplicates: inittab::DuplicatePolicy, // What to do with a reused inittab id
+    pub entering: Option<std::time::Instant>, // When the runlevel switch in progress began
+    pub container_stop: Option<Reboot>, // The container is being stopped, see container_signal
+    pub level_waiters: Vec<i32>,        // Control connections waiting for the switch to finish
+    pub timers: timer::TimerWheel<Timeout>, // Scheduled work
+    pub timer_fd: i32,                  // timerfd of the wheel
+    pub rescue_vt: Option<String>,      // Where a failed boot entry gets a shell
+    pub rescue_pid: i32,                // That shell, NO_PROCESS if none
+    #[cfg(feature = "metrics")]
+    pub metrics: Option<metrics::Metrics>, // Kept up to date with --metrics
+    pub status: status::StatusDir,      // Per entry status files
+    pub checkpoint: checkpoint::Checkpoint, // The state as last saved to /run
+    pub splash: splash::Splash,         // What a boot splash was told
+    pub dev_watch: devwait::DevWatch,   // Notices new devices while entries wait for one
+    pub early_log: earlylog::EarlyLog,  // /dev/log until the syslog daemon takes it
+    pub syslog_retry: u64,              // When Timeout::Syslog is due, 0 if it isn't scheduled
+    pub accounting: bool,               // We turned process accounting on
+    pub booting: bool,                  // The default runlevel wasn't entered yet and boot-deadline is set
+    pub watchdog: Option<std::fs::File>, // The hardware watchdog, started when the boot took too long
+    pub trace: Option<trace::Recorder>, // Where --trace records events
+    pub config: config::Config,         // Settings from init.conf
+    pub sys: &'static dyn Sys,          // Where reboot and friends go
+    pub run_read_only: bool,            // /run takes no FIFO or files, see run_unwritable
+    pub battery_low: bool,              // The battery went below the threshold, see battery.rs
+    pub binary: Option<upgrade::Binary>, // The init program as we know it
+    pub upgraded: Option<upgrade::Binary>, // A new one seen at the last check, see upgrade.rs
 }
 
 impl InitState {
@@ -279,9 +491,9 @@ impl InitState {
             wrote_utmp_reboot: true,
             wrote_wtmp_rlevel: true,
             wrote_utmp_rlevel: true,
-            curlevel: 'S',   // single-user mode
-            prevlevel: 'N',  // no previous runlevel
-            dfl_level: '0',  // Default runlevel
+            curlevel: Runlevel::Classic('S'),   // single-user mode
+            prevlevel: Runlevel::Classic('N'),  // no previous runlevel
+            dfl_level: Runlevel::Classic('0'),  // Default runlevel
             emerg_shell: false,
             sleep_time: WAIT_BETWEEN_SIGNALS,
             console_dev: None,
@@ -289,494 +501,3107 @@ impl InitState {
             did_boot: false,
             reload: false,
             myname: INIT_PROGRAM.to_string(),
+            args: Vec::new(),
+            sandbox: false,
             oops_error: 0,
+            #[cfg(feature = "utmp")]
+            utmp: utmp::Utmp::new(),
+            structured_log: false,
+            extra_env: ExtraEnv::new(),
+            deferred: Vec::new(),
+            reap_more: false,
+            confirm: false,
+            procs: ProcCache::new(),
+            fifo_buf: initreq::RequestBuffer::new(),
+            control_fd: -1,
+            control_conns: Vec::new(),
+            control_root_only: true,
+            boot_env: Vec::new(),
+            auto_getty: None,
+            emergency_shells: None,
+            strict: None,
+            duplicates: inittab::DuplicatePolicy::FirstWins,
+            entering: None,
+            container_stop: None,
+            level_waiters: Vec::new(),
+            timers: timer::TimerWheel::new(),
+            timer_fd: -1,
+            rescue_vt: None,
+            rescue_pid: NO_PROCESS,
+            #[cfg(feature = "metrics")]
+            metrics: None,
+            status: status::StatusDir::new(),
+            checkpoint: checkpoint::Checkpoint::new(),
+            splash: splash::Splash::new(),
+            dev_watch: devwait::DevWatch::new(),
+            early_log: earlylog::EarlyLog::new(),
+            syslog_retry: 0,
+            accounting: false,
+            booting: false,
+            watchdog: None,
+            trace: None,
+            config: config::Config::default(),
+            sys: &Kernel,
+            run_read_only: false,
+            battery_low: false,
+            binary: None,
+            upgraded: None,
         }
     }
 
-    // New children are added to the start of the list
+    // New children are added to the start of the list, ahead of the
+    // inittab: the emergency shell is waited for before anything else
     pub fn add_child(&mut self, mut child: Child) {
         child.next = self.family.take();
         self.family = Some(Box::new(child));
     }
 
-    pub fn find_child_by_id(&self, id: &str) -> Option<&Child> {
-        let mut current = self.family.as_ref();
-        while let Some(child) = current {
-            if child.id == id {
-                return Some(child);
-            }
-            current = child.next.as_ref();
+    // Make children the process table, in their order. That is the order
+    // start_if_needed goes through them in, so entries of a runlevel start
+    // in inittab order however the list is kept.
+    pub fn set_family(&mut self, children: Vec<Child>) {
+        self.family = None;
+        for child in children.into_iter().rev() {
+            self.add_child(child);
         }
-        None
     }
 
-    pub fn find_child_by_pid(&self, pid: i32) -> Option<&Child> {
-        let mut current = self.family.as_ref();
-        while let Some(child) = current {
-            if child.pid == pid {
-                return Some(child);
-            }
-            current = child.next.as_ref();
-        }
-        None
+    // The process table from the first entry to the last
+    pub fn children(&self) -> Children<'_> {
+        Children { next: self.family.as_deref() }
     }
 
-    pub fn remove_child_by_pid(&mut self, pid: i32) -> Option<Child> {
-        let mut current = &mut self.family;
-        while let Some(child) = current {
-            if child.pid == pid {
-                let mut removed = current.take().unwrap();
-                *current = removed.next.take();
-                return Some(*removed);
-            }
-            current = &mut current.as_mut().unwrap().next;
+    // Execute ourselves again, handing our state over through STATE_PIPE.
+    // A child writes the state so a full pipe can't block us before the
+    // exec. The new init gets our arguments, --root and the rest, from
+    // self.args: set_title has written over the ones in our memory. Only
+    // returns if something went wrong.
+    pub fn re_exec(&mut self) -> nix::Result<()> {
+        let mut argv = vec![c"init".to_owned()];
+        for arg in &self.args {
+            argv.push(std::ffi::CString::new(arg.as_str()).map_err(|_| nix::errno::Errno::EINVAL)?);
         }
-        None
-    }
-}
+        let (rd, wr) = nix::unistd::pipe2(OFlag::O_CLOEXEC)?;
 
-// Command lookup table for state parser
-struct StateCommand {
-    name: &'static str,
-    cmd: StateToken,
-}
-
-const STATE_COMMANDS: &[StateCommand] = &[
-    StateCommand { name: "VER", cmd: StateToken::Ver },
-    StateCommand { name: "END", cmd: StateToken::End },
-    StateCommand { name: "REC", cmd: StateToken::Rec },
-    StateCommand { name: "EOR", cmd: StateToken::Eor },
-    StateCommand { name: "LEV", cmd: StateToken::Lev },
-    StateCommand { name: "FL ", cmd: StateToken::Flag },
-    StateCommand { name: "AC ", cmd: StateToken::Action },
-    StateCommand { name: "CMD", cmd: StateToken::Process },
-    StateCommand { name: "PID", cmd: StateToken::Pid },
-    StateCommand { name: "EXS", cmd: StateToken::Exs },
-    StateCommand { name: "-RL", cmd: StateToken::Runlevel },
-    StateCommand { name: "-TL", cmd: StateToken::ThisLevel },
-    StateCommand { name: "-PL", cmd: StateToken::PrevLevel },
-    StateCommand { name: "-SI", cmd: StateToken::GotSign },
-    StateCommand { name: "-WR", cmd: StateToken::WroteWtmpReboot },
-    StateCommand { name: "-WU", cmd: StateToken::WroteUtmpReboot },
-    StateCommand { name: "-ST", cmd: StateToken::SlTime },
-    StateCommand { name: "-DB", cmd: StateToken::DidBoot },
-    StateCommand { name: "-LW", cmd: StateToken::WroteWtmpRlevel },
-    StateCommand { name: "-LU", cmd: StateToken::WroteUtmpRlevel },
-];
-
-// Flag lookup table
-struct FlagMapping {
-    name: &'static str,
-    mask: ChildFlags,
-}
-
-const FLAG_MAPPINGS: &[FlagMapping] = &[
-    FlagMapping { name: "RU", mask: ChildFlags::RUNNING },
-    FlagMapping { name: "DE", mask: ChildFlags::DEMAND },
-    FlagMapping { name: "XD", mask: ChildFlags::XECUTED },
-    FlagMapping { name: "WT", mask: ChildFlags::WAITING },
-];
+        match unsafe { nix::unistd::fork() }? {
+            nix::unistd::ForkResult::Child => {
+                drop(rd);
+                let code = if send_state(std::fs::File::from(wr), self).is_ok() { 0 } else { 1 };
+                unsafe { libc::_exit(code) }
+            }
+            nix::unistd::ForkResult::Parent { .. } => {}
+        }
+        drop(wr);
+
+        // STATE_PIPE is the one fd the new init inherits on purpose, dup2()
+        // leaves close-on-exec off. Whatever we have open there, the event
+        // loop or a pidfd, is kept in a spare fd that goes away with the
+        // exec, and comes back if the exec fails.
+        let rd = rd.into_raw_fd();
+        let mut occupant = None;
+        unsafe {
+            if rd != STATE_PIPE {
+                let flags = libc::fcntl(STATE_PIPE, libc::F_GETFD);
+                if flags >= 0 {
+                    let spare = libc::fcntl(STATE_PIPE, libc::F_DUPFD_CLOEXEC, 0);
+                    if spare < 0 {
+                        libc::close(rd);
+                        return Err(nix::errno::Errno::last());
+                    }
+                    occupant = Some((spare, flags));
+                }
+                libc::dup2(rd, STATE_PIPE);
+                libc::close(rd);
+            } else {
+                libc::fcntl(STATE_PIPE, libc::F_SETFD, 0);
+            }
+        }
 
-// FreeBSD specific code
-#[cfg(target_os = "freebsd")]
-mod freebsd_compat {
-    const UTMP_FILE: &str = "/var/run/utmp";
-    const RUN_LVL: i32 = 1;
+        let err = match std::ffi::CString::new(self.myname.as_str()) {
+            Ok(prog) => nix::unistd::execv(&prog, &argv).unwrap_err(),
+            Err(_) => nix::errno::Errno::EINVAL,
+        };
 
-    #[repr(C)]
-    pub struct Utmp {
-        pub ut_id: [u8; 4],
+        unsafe {
+            match occupant {
+                Some((spare, flags)) => {
+                    libc::dup2(spare, STATE_PIPE);
+                    libc::fcntl(STATE_PIPE, libc::F_SETFD, flags);
+                    libc::close(spare);
+                }
+                None => {
+                    libc::close(STATE_PIPE);
+                }
+            }
+        }
+        Err(err)
     }
-}
-
-// TODO: Implement prototypes
-pub trait InitLogger {
-    fn initlog(&self, level: LogLevel, msg: &str);
-}
-
-pub trait UtmpWriter {
-    fn write_utmp_wtmp(&self, user: &str, id: &str, pid: i32, entry_type: i32, line: &str);
-    fn write_wtmp(&self, user: &str, id: &str, pid: i32, entry_type: i32, line: &str);
-}
-
-pub trait TerminalController {
-    fn set_term(&self, how: i32);
-    fn print(&self, msg: &str);
-}
 
-pub trait WallMessenger {
-    fn wall(&self, text: &str, remote: bool);
-}
+    // Without a state from the pipe: pick up the last checkpoint of this
+    // boot, if there is one, see checkpoint.rs
+    pub fn receive_checkpoint(&mut self) -> bool {
+        self.oops_error = 0;
+        let data = match checkpoint::read() {
+            Ok(Some(data)) => data,
+            Ok(None) => return false,
+            Err(e) => {
+                self.initlog(LogLevel::Verbose, &format!("cannot read {}: {}", paths::path(checkpoint::CHECKPOINT_FILE), e));
+                return false;
+            }
+        };
 
-macro_rules! initdbg {
-    ($level:expr, $fmt:expr $(, $args:expr)*) => {
-        if DEBUG {
-            // TODO: Call initlog
-            eprintln!($fmt $(, $args)*);
+        receive_state(&mut &data[..], self);
+        if self.oops_error != 0 {
+            self.initlog(LogLevel::Verbose, "cannot read the state checkpoint, starting from the inittab");
+            return false;
         }
-    };
-}
+        self.initlog(LogLevel::Verbose, "no state from the previous init, continuing from its last checkpoint");
+        true
+    }
 
-// Signal handler helpers
-pub fn set_got_cont() {
-    GOT_CONT.store(true, Ordering::Relaxed);
-}
+    // Save the state for an init that doesn't get it through the pipe
+    fn save_checkpoint(&mut self) {
+        if self.run_read_only {
+            return;
+        }
+        let mut data = Vec::new();
+        if send_state(&mut data, self).is_err() {
+            return;
+        }
+        if let Err(e) = self.checkpoint.write(data) {
+            initdbg!(LogLevel::Verbose, "cannot write {}: {}", paths::path(checkpoint::CHECKPOINT_FILE), e);
+        }
+    }
 
-pub fn got_cont() -> bool {
-    GOT_CONT.load(Ordering::Relaxed)
-}
+    // Pick up the state of the init that exec'd us, if there is one
+    pub fn receive_from_pipe(&mut self) -> bool {
+        if nix::fcntl::fcntl(unsafe { BorrowedFd::borrow_raw(STATE_PIPE) }, nix::fcntl::FcntlArg::F_GETFD).is_err() {
+            return false;
+        }
 
-pub fn clear_got_cont() {
-    GOT_CONT.store(false, Ordering::Relaxed);
-}
+        let f = unsafe { std::fs::File::from_raw_fd(STATE_PIPE) };
+        receive_state(&mut std::io::BufReader::new(f), self);
 
-pub fn set_got_signals() {
-    GOT_SIGNALS.store(true, Ordering::Relaxed);
-}
+        if self.oops_error != 0 {
+            self.initlog(LogLevel::Verbose, "cannot read the state of the previous init");
+            return false;
+        }
+        true
+    }
 
-pub fn got_signals() -> bool {
-    GOT_SIGNALS.load(Ordering::Relaxed)
-}
+    // After a panic: get a shell onto the console and start over with a
+    // fresh copy of ourselves, keeping the process table
+    pub fn recover(&mut self, events: &EventLoop) {
+        self.initlog(LogLevel::Verbose, "internal error, trying to recover");
+        let detail = panic::last_panic().unwrap_or_else(|| "unknown panic".to_string());
+        self.record_exit(last_exit::Reason::Panic, &detail);
+
+        if self.find_child_by_id(SPECIAL_ID).is_none() {
+            let mut shell = self.emergency_shell();
+            self.spawn_child(&mut shell, events);
+            self.add_child(shell);
+        }
 
-pub fn clear_got_signals() {
-    GOT_SIGNALS.store(false, Ordering::Relaxed);
-}
+        if let Err(e) = self.re_exec() {
+            self.initlog(LogLevel::Verbose, &format!("cannot re-exec {}: {}", self.myname, e));
+        }
+    }
 
-pub fn is_valid_runlevel(c: char) -> bool {
-    matches!(c, '0'..='6' | 'S' | 's' | 'A'..='C' | 'a'..='c')
-}
+    // Say why this init is going away, in LAST_EXIT and the log
+    fn record_exit(&mut self, reason: last_exit::Reason, detail: &str) {
+        let record = last_exit::record(reason, detail, &self.curlevel);
+        self.initlog(LogLevel::Verbose, &format!("exiting: {}", record));
+        if self.run_read_only {
+            return;
+        }
 
-pub fn normalize_runlevel(c: char) -> char {
-    match c {
-        's' => 'S',
-        'a' => 'A',
-        'b' => 'B',
-        'c' => 'C',
-        _ => c,
+        let file = paths::path(last_exit::LAST_EXIT);
+        if let Some(dir) = std::path::Path::new(&file).parent() {
+            let _ = std::fs::create_dir_all(dir);
+        }
+        match std::fs::write(&file, format!("{}\n", record)) {
+            Err(e) if self.run_unwritable(&file, &e) => {}
+            Err(e) => self.initlog(LogLevel::Verbose, &format!("cannot write {}: {}", file, e)),
+            Ok(()) => {}
+        }
     }
-}
 
-pub fn create_emergency_shell() -> Child {
-    Child {
-        flags: ChildFlags::WAITING,
-        exstat: 0,
-        pid: NO_PROCESS,
-        tm: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs(),
-        count: 0,
-        id: "~~".to_string(),
-        rlevel: "S".to_string(),
-        action: InitAction::Once,
-        process: "/sbin/sulogin".to_string(),
-        new: None,
-        next: None,
+    // Options init takes from the kernel command line
+    pub fn parse_cmdline(&mut self, cmdline: &str) {
+        for word in cmdline_words(cmdline) {
+            if word == "confirm" {
+                self.confirm = true;
+            } else if let Some(on) = word.strip_prefix("rye_init.debug=") {
+                initlog::set_debug(on != "0");
+            } else if let Some(on) = word.strip_prefix("rye_init.strict=") {
+                self.strict = Some(on != "0");
+            } else if let Some(shells) = word.strip_prefix("rye_init.emergency=") {
+                self.emergency_shells = Some(shells.split(',').filter(|s| !s.is_empty()).map(str::to_string).collect());
+            }
+        }
     }
-}
 
-
-// Poweroff child definition
-pub fn create_poweroff_child() -> Child {
-    Child {
-        flags: ChildFlags::empty(),
-        exstat: 0,
-        pid: NO_PROCESS,
-        tm: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs(),
-        count: 0,
-        id: "~~".to_string(),
-        rlevel: "S".to_string(),
-        action: InitAction::Once,
-        process: "/sbin/shutdown -hP now".to_string(),
-        new: None,
-        next: None,
+    // The emergency shell entry, with the commands from the kernel command
+    // line or else the config file
+    fn emergency_shell(&self) -> Child {
+        let shells = self.emergency_shells.as_ref().filter(|s| !s.is_empty()).unwrap_or(&self.config.emergency_shells);
+        create_emergency_shell(shells)
     }
-}
 
-pub fn is_power_action(action: InitAction) -> bool {
-    matches!(action,
-        InitAction::PowerWait |
-        InitAction::PowerFail |
-        InitAction::PowerOkWait |
-        InitAction::PowerFailNow |
-        InitAction::CtrlAltDel
-    )
-}
+    // Warn when nobody can log in on the console, and with --auto-getty
+    // add a getty for it
+    fn check_console_getty(&self, entries: &mut Vec<inittab::Entry>) {
+        let Some(console) = getty::active_consoles().pop() else {
+            return;
+        };
+        if getty::has_getty(entries, &console) {
+            return;
+        }
 
-// Sleep function - sleeps for specified milliseconds
-pub fn do_msleep(msec: u64) {
-    std::thread::sleep(std::time::Duration::from_millis(msec));
-}
+        self.initlog(LogLevel::Verbose, &format!("no inittab entry runs a getty on the console ({})", console));
 
-// Non-failing memory allocation
-pub fn imalloc(size: usize) -> Vec<u8> {
-    loop {
-        match std::panic::catch_unwind(|| vec![0u8; size]) {
-            Ok(vec) => return vec,
-            Err(_) => {
-                eprintln!("out of memory");
-                do_msleep(SHORT_SLEEP);
+        let Some(prog) = &self.auto_getty else {
+            return;
+        };
+        match getty::find_program(prog) {
+            Some(path) => {
+                let entry = getty::getty_entry(&path, &console, entries);
+                self.initlog(LogLevel::Verbose, &format!("adding \"{}\" as id {}", entry.process, entry.id));
+                entries.push(entry);
             }
+            None => self.initlog(LogLevel::Verbose, &format!("{}: not found", prog)),
         }
     }
-}
 
-// String duplication
-pub fn istrdup(s: &str) -> String {
-    loop {
-        match std::panic::catch_unwind(|| s.to_string()) {
-            Ok(string) => return string,
-            Err(_) => {
-                eprintln!("out of memory");
-                do_msleep(SHORT_SLEEP);
+    // Options init takes from its argument list. Unknown options are
+    // warned about and skipped, a boot must not fail over an option from
+    // another init or version. Words that aren't options come from the
+    // kernel command line and are left alone. "-z xxx" only widens argv for
+    // programs that write into it, like sysvinit it is ignored.
+    pub fn parse_args(&mut self, args: &[String]) {
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            if arg == "--auto-getty" {
+                self.auto_getty = args.next().cloned();
+            } else if arg == "--metrics" {
+                self.enable_metrics();
+            } else if arg == "--trace" {
+                let path = args.next().map(String::as_str).unwrap_or("");
+                match trace::Recorder::create(path) {
+                    Ok(recorder) => self.trace = Some(recorder),
+                    Err(e) => self.initlog(LogLevel::Verbose, &format!("--trace: {}: {}", path, e)),
+                }
+            } else if arg == "--root" {
+                // Taken care of before anything looked at a path
+                args.next();
+            } else if arg == sandbox::SANDBOX_OPTION {
+                // Taken care of before we went for process 1
+            } else if arg == "--rescue-vt" {
+                self.rescue_vt = args.next().cloned();
+            } else if arg == "--duplicates" {
+                let policy = args.next().map(String::as_str).unwrap_or("");
+                match policy.parse() {
+                    Ok(policy) => self.duplicates = policy,
+                    Err(e) => self.initlog(LogLevel::Verbose, &format!("--duplicates: {}", e)),
+                }
+            } else if arg == "-z" {
+                args.next();
+            } else if arg.starts_with("-z") {
+                // The padding stuck to the option
+            } else if arg.starts_with('-') && arg.len() > 1 {
+                self.initlog(LogLevel::Verbose, &format!("ignoring unknown option \"{}\"", arg));
             }
         }
     }
-}
 
-// Send state information to a file descriptor
-pub fn send_state<W: std::io::Write>(mut writer: W, state: &InitState) -> std::io::Result<()> {
-    use std::io::Write;
-
-    writeln!(writer, "VER{}", VERSION)?;
-    writeln!(writer, "-RL{}", state.curlevel)?;
-    writeln!(writer, "-TL{}", state.curlevel)?; // thislevel same as curlevel in our implementation
-    writeln!(writer, "-PL{}", state.prevlevel)?;
-    writeln!(writer, "-SI{}", if got_signals() { 1 } else { 0 })?;
-    writeln!(writer, "-WR{}", if state.wrote_wtmp_reboot { 1 } else { 0 })?;
-    writeln!(writer, "-WU{}", if state.wrote_utmp_reboot { 1 } else { 0 })?;
-    writeln!(writer, "-ST{}", state.sleep_time)?;
-    writeln!(writer, "-DB{}", if state.did_boot { 1 } else { 0 })?;
-
-    // Iterate through family list
-    let mut current = state.family.as_ref();
-    while let Some(child) = current {
-        writeln!(writer, "REC{}", child.id)?;
-        writeln!(writer, "LEV{}", child.rlevel)?;
-
-        // Write flags
-        for flag_mapping in FLAG_MAPPINGS {
-            if child.flags.contains(flag_mapping.mask) {
-                writeln!(writer, "FL {}", flag_mapping.name)?;
-            }
-        }
-
-        writeln!(writer, "PID{}", child.pid)?;
-        writeln!(writer, "EXS{}", child.exstat)?;
-
-        // Write action
-        let action_name = match child.action {
-            InitAction::Respawn => "respawn",
-            InitAction::Wait => "wait",
-            InitAction::Once => "once",
-            InitAction::Boot => "boot",
-            InitAction::BootWait => "bootwait",
-            InitAction::PowerFail => "powerfail",
-            InitAction::PowerWait => "powerwait",
-            InitAction::PowerOkWait => "powerokwait",
-            InitAction::CtrlAltDel => "ctrlaltdel",
-            InitAction::Off => "off",
-            InitAction::OnDemand => "ondemand",
-            InitAction::InitDefault => "initdefault",
-            InitAction::SysInit => "sysinit",
-            InitAction::PowerFailNow => "powerfailnow",
-            InitAction::KbRequest => "kbrequest",
+    // Leave the ids of this boot where tools collecting logs off the box
+    // can pick them up
+    fn record_boot_id(&mut self) {
+        let Some(boot_id) = bootid::boot_id() else {
+            return;
         };
+        let mut ids = format!("boot_id={}\n", boot_id);
+        if let Ok(machine_id) = std::fs::read_to_string(paths::path(bootid::MACHINE_ID)) {
+            ids.push_str(&format!("machine_id={}\n", machine_id.trim()));
+        }
 
-        writeln!(writer, "AC {}", action_name)?;
-        writeln!(writer, "CMD{}", child.process)?;
-        writeln!(writer, "EOR")?;
-
-        current = child.next.as_ref();
+        let file = paths::path(bootid::BOOT_ID_FILE);
+        if let Some(dir) = std::path::Path::new(&file).parent() {
+            let _ = std::fs::create_dir_all(dir);
+        }
+        match std::fs::write(&file, ids) {
+            Err(e) if self.run_unwritable(&file, &e) => {}
+            Err(e) => self.initlog(LogLevel::Verbose, &format!("cannot write {}: {}", file, e)),
+            Ok(()) => {}
+        }
     }
 
-    writeln!(writer, "END")?;
-    Ok(())
-}
-
-// Re-implementation of get_string in C
-pub fn get_string<R: std::io::BufRead>(reader: &mut R, max_size: usize) -> std::io::Result<String> {
-    let mut result = String::new();
-    let bytes_read = reader.read_line(&mut result)?;
-
-    if bytes_read == 0 {
-        return Ok(String::new());
+    // Whether an inittab with errors is refused, see load_inittab
+    fn strict(&self) -> bool {
+        self.strict.unwrap_or(self.config.strict)
     }
 
-    // Remove newline
-    if result.ends_with('\n') {
-        result.pop();
-        if result.ends_with('\r') {
-            result.pop();
+    // Build the process table from the inittab and the boot profile
+    pub fn read_inittab(&mut self) {
+        let (children, errors) = self.load_inittab();
+        if self.strict() && !errors.is_empty() {
+            self.initlog(LogLevel::Verbose, &format!(
+                "strict mode: {} error(s) in the inittab, starting an emergency shell instead of booting", errors.len()));
+            let shell = self.emergency_shell();
+            self.add_child(shell);
+            return;
+        }
+        if children.is_empty() {
+            self.initlog(LogLevel::Verbose, "no inittab entries, starting an emergency shell");
+            let shell = self.emergency_shell();
+            self.add_child(shell);
+            return;
         }
-    }
 
-    // Truncate if too long
-    if result.len() > max_size {
-        result.truncate(max_size);
+        self.set_family(children);
     }
 
-    Ok(result)
-}
+    // The entries of the inittab, its drop-ins and the boot profile, in
+    // order. What is wrong with them is logged. The errors are returned
+    // too, everything that left out a line or a file: with strict on the
+    // entries aren't used then. Warnings about lines that were taken don't
+    // count.
+    fn load_inittab(&self) -> (Vec<Child>, Vec<String>) {
+        let profile_file = paths::path(profile::PROFILE_FILE);
+        let profile = match profile::Profile::load(&profile_file) {
+            Ok(profile) => profile,
+            Err(e) => {
+                self.initlog(LogLevel::Verbose, &format!("{}: {}, using the inittab only", profile_file, e));
+                profile::Profile::default()
+            }
+        };
 
-// Read and discard data until newline
-pub fn get_void<R: std::io::BufRead>(reader: &mut R) -> std::io::Result<bool> {
-    let mut line = String::new();
-    match reader.read_line(&mut line) {
-        Ok(0) => Ok(false),
-        Ok(_) => Ok(true),
-        Err(e) => Err(e),
+        let mut parser = inittab::Parser::new(self.duplicates);
+        parser.set_process_limit(self.config.process_limit);
+        let files = std::iter::once(paths::path(inittab::INITTAB)).chain(inittab::drop_ins(&paths::path(inittab::INITTAB_DIR)));
+        let mut errors = Vec::new();
+        for file in files {
+            if let Err(e) = parser.add_file(&file) {
+                errors.push(format!("{}: {}", file, e));
+            }
+        }
+        let tab = parser.finish();
+
+        for e in &tab.warnings {
+            self.initlog(LogLevel::Verbose, &e.to_string());
+        }
+        errors.extend(tab.errors.iter().map(|e| e.to_string()));
+        errors.extend(tab.rejected.iter().map(|file| format!("{}: duplicate ids, ignoring the whole file", file)));
+        for e in &errors {
+            self.initlog(LogLevel::Verbose, e);
+        }
+
+        let mut entries = profile.apply(tab.entries);
+        self.check_console_getty(&mut entries);
+        #[cfg(feature = "utmp")]
+        self.check_utmp_ids(&entries);
+
+        let mut children = Vec::new();
+        for entry in entries {
+            match Child::try_from(entry) {
+                Ok(child) => children.push(child),
+                Err(e) => {
+                    let e = format!("{}: {}", inittab::INITTAB, e);
+                    self.initlog(LogLevel::Verbose, &e);
+                    errors.push(e);
+                }
+            }
+        }
+        (children, errors)
     }
-}
 
-// Read the next command from state pipe
-pub fn get_cmd<R: std::io::BufRead>(reader: &mut R) -> std::io::Result<StateToken> {
-    let mut cmd_buf = [0u8; 3];
+    // telinit q: read the inittab again and bring the entries in line with
+    // it, see reread.rs. Returns what changed, one line each.
+    fn reread_inittab(&mut self) -> Vec<String> {
+        let (entries, errors) = self.load_inittab();
+        if self.strict() && !errors.is_empty() {
+            let mut report = vec![format!("strict mode: {} error(s) in the inittab, nothing changed", errors.len())];
+            self.initlog(LogLevel::Verbose, &format!("reread: {}", report[0]));
+            report.extend(errors);
+            return report;
+        }
+        if entries.is_empty() {
+            let report = vec!["the inittab has no entries, nothing changed".to_string()];
+            self.initlog(LogLevel::Verbose, &format!("reread: {}", report[0]));
+            return report;
+        }
+
+        let level = self.curlevel.clone();
+        let changes = reread::diff(self.family.as_deref(), &entries);
+        let mut report = Vec::new();
+        let mut stops = Vec::new();
+        for change in &changes {
+            let id = change.id();
+            let (mut line, stop) = match change {
+                reread::Change::Added(_) => (format!("added \"{}\"", id), false),
+                reread::Change::Removed(_) => (format!("removed \"{}\"", id), true),
+                reread::Change::Modified(_, fields) => {
+                    let new = entries.iter().find(|e| e.id == *id).map(|e| &e.rlevel);
+                    let flags = self.find_child_by_id(id).map(|c| c.flags).unwrap_or(ChildFlags::empty());
+                    let stop = reread::runs_something_else(fields)
+                        || new.is_some_and(|rlevel| !policy::is_due(rlevel, flags, &level));
+                    (format!("\"{}\" changed {}", id, fields.join(", ")), stop)
+                }
+            };
+            if let Some(child) = self.find_child_by_id(id).filter(|c| stop && c.is_running()) {
+                line.push_str(&format!(", stopping pid {}", child.pid));
+                stops.push(id.clone());
+            }
+            self.initlog(LogLevel::Verbose, &format!("reread: {}", line));
+            report.push(line);
+        }
+        if report.is_empty() {
+            report.push("no changes".to_string());
+        }
 
-    match reader.read_exact(&mut cmd_buf) {
-        Ok(()) => {
-            let cmd_str = std::str::from_utf8(&cmd_buf).unwrap_or("   ");
+        for id in &stops {
+            self.stop_child(id);
+        }
 
-            for state_cmd in STATE_COMMANDS {
-                if state_cmd.name == cmd_str {
-                    return Ok(state_cmd.cmd);
+        // The new list in inittab order, with the state of the entries we
+        // had. What init made up itself goes at the end. Nothing but moving
+        // entries happens while the list is out of self, recover() must
+        // find it there after a panic.
+        let mut old = Vec::new();
+        let mut current = self.family.take();
+        while let Some(mut child) = current {
+            current = child.next.take();
+            old.push(*child);
+        }
+        let mut family = Vec::new();
+        for new in entries {
+            match old.iter().position(|c| c.id == new.id) {
+                Some(at) => {
+                    let mut child = old.remove(at);
+                    child.rlevel = new.rlevel;
+                    child.action = new.action;
+                    child.process = new.process;
+                    child.attrs = new.attrs;
+                    child.delay = new.delay;
+                    family.push(child);
                 }
+                None => family.push(new),
             }
+        }
+        let (special, gone): (Vec<Child>, Vec<Child>) = old.into_iter().partition(|child| child.id == SPECIAL_ID);
+        family.extend(special);
+        self.set_family(family);
 
-            Ok(StateToken::Eof)
+        for mut child in gone {
+            if child.timer != 0 {
+                self.timers.cancel(child.timer);
+            }
+            child.close_pidfd();
         }
-        Err(_) => Ok(StateToken::Eof),
+        report
     }
-}
 
-// Read a Child record from the state pipe
-pub fn get_record<R: std::io::BufRead>(reader: &mut R, state: &mut InitState) -> std::io::Result<Option<Child>> {
-    loop {
-        match get_cmd(reader)? {
-            StateToken::End => {
-                get_void(reader)?;
-                return Ok(None);
+    // rye-initctl reread: read the inittab again and send what changed
+    // back in INIT_DATA_SIZE pieces
+    fn reread_request(&mut self, fd: i32, peer: control::Peer) -> bool {
+        let mut buf = Vec::new();
+        for line in self.reread_inittab() {
+            buf.extend_from_slice(line.as_bytes());
+            buf.push(b'\n');
+        }
+        for piece in buf.chunks(initreq::INIT_DATA_SIZE) {
+            let mut reply = InitRequest::new(initreq::INIT_CMD_REREAD);
+            reply.runlevel = piece.len() as i32;
+            reply.data[..piece.len()].copy_from_slice(piece);
+            if let Err(e) = control::control_reply(fd, &reply) {
+                self.initlog(LogLevel::Verbose, &format!("reread: cannot send changes to pid {}: {}", peer.pid, e));
+                return false;
             }
-            StateToken::Rec => break,
-            StateToken::Runlevel => {
-                let line = get_string(reader, 32)?;
-                if let Some(c) = line.chars().next() {
-                    state.curlevel = c;
-                }
+        }
+        true
+    }
+
+    // utmp only keeps the first four bytes of an id. sysvinit refuses
+    // longer ones, we take them but warn when two entries end up in the
+    // same utmp slot.
+    #[cfg(feature = "utmp")]
+    fn check_utmp_ids(&self, entries: &[inittab::Entry]) {
+        let mut seen: Vec<(&str, [u8; utmp::UT_IDSIZE])> = Vec::new();
+        for entry in entries.iter().filter(|e| !e.process.starts_with('+')) {
+            let ut_id = utmp::ut_id(&entry.id);
+            if let Some((other, _)) = seen.iter().find(|(_, id)| *id == ut_id) {
+                self.initlog(LogLevel::Verbose, &format!("ids \"{}\" and \"{}\" share the utmp id \"{}\"",
+                    other, entry.id, utmp::field_str(&ut_id)));
+            } else {
+                seen.push((&entry.id, ut_id));
             }
-            StateToken::ThisLevel => {
-                let line = get_string(reader, 32)?;
-                if let Some(c) = line.chars().next() {
-                    state.curlevel = c;
-                }
+        }
+    }
+
+    // Make sure memory pressure and CPU hotplug can't take init down: out of
+    // the OOM killer's reach, locked in memory and, if configured, on CPUs
+    // that stay. Locked memory doesn't survive a re-exec, so this is done
+    // again after one.
+    fn protect_self(&self) {
+        match std::fs::write(spawn::OOM_SCORE_ADJ, INIT_OOM_SCORE_ADJ.to_string()) {
+            Ok(()) => spawn::reset_oom_score_in_children(),
+            Err(e) => self.initlog(LogLevel::Verbose, &format!("cannot write {}: {}", spawn::OOM_SCORE_ADJ, e)),
+        }
+        if self.config.lock_memory && let Err(e) = self.sys.lock_memory() {
+            self.initlog(LogLevel::Verbose, &format!("cannot lock init in memory: {}", e));
+        }
+        if !self.config.cpu_affinity.is_empty() {
+            match self.sys.set_affinity(&self.config.cpu_affinity) {
+                Ok(()) => spawn::reset_affinity_in_children(),
+                Err(e) => self.initlog(LogLevel::Verbose, &format!("cannot pin init to CPUs {:?}: {}",
+                    self.config.cpu_affinity, e)),
             }
-            StateToken::PrevLevel => {
-                let line = get_string(reader, 32)?;
-                if let Some(c) = line.chars().next() {
-                    state.prevlevel = c;
-                }
+        }
+    }
+
+    // The process table as the policy layer plans runlevel switches with,
+    // in order: policy::starts picks from it front to back
+    fn planned(&self) -> Vec<policy::Planned> {
+        self.children()
+            .map(|child| policy::Planned { action: child.action, flags: child.flags, runlevels: child.rlevel.clone() })
+            .collect()
+    }
+
+    fn any_waiting(&self) -> bool {
+        self.children().any(|child| child.flags.contains(ChildFlags::WAITING))
+    }
+
+    pub fn find_child_by_id(&self, id: &str) -> Option<&Child> {
+        self.children().find(|child| child.id == id)
+    }
+
+    pub fn find_child_by_pid(&self, pid: i32) -> Option<&Child> {
+        self.children().find(|child| child.pid == pid)
+    }
+
+    pub fn find_child_by_pid_mut(&mut self, pid: i32) -> Option<&mut Child> {
+        let mut current = self.family.as_mut();
+        while let Some(child) = current {
+            if child.pid == pid {
+                return Some(child);
+            }
+            current = child.next.as_mut();
+        }
+        None
+    }
+
+    fn find_pid_by_pidfd(&self, pidfd: i32) -> Option<i32> {
+        self.children().find(|child| child.pidfd == pidfd).map(|child| child.pid)
+    }
+
+    // Start receiving exit notifications for a freshly spawned child
+    pub fn watch_child(&self, child: &Child, events: &EventLoop) {
+        if events.watch(EventSource::ChildExit, child.pidfd).is_err() {
+            initdbg!(LogLevel::Verbose, "cannot watch pidfd of {}", child.pid);
+        }
+    }
+
+    fn spawn_env(&self) -> spawn::SpawnEnv {
+        spawn::SpawnEnv {
+            console: self.console_name().to_string(),
+            runlevel: self.curlevel.clone(),
+            prevlevel: self.prevlevel.clone(),
+            extra: self.extra_env.vars.iter().flatten().cloned().collect(),
+            boot: self.boot_env.clone(),
+        }
+    }
+
+    // Fork off the process of an entry. Respawning entries that come back
+    // too often are disabled for a while instead.
+    fn spawn_child(&self, child: &mut Child, events: &EventLoop) {
+        let now = clock::boottime();
+
+        if matches!(child.action, InitAction::Respawn | InitAction::OnDemand) {
+            let too_fast = child.count_start(now);
+            if child.flags.contains(ChildFlags::XECUTED) {
+                child.respawns += 1;
+            }
+
+            if too_fast {
+                self.log_event(LogEvent::RespawnTooFast { id: &child.id });
+                child.flags.remove(ChildFlags::RUNNING);
+                child.flags.insert(ChildFlags::FAILING);
+                child.tm = now;
+                return;
+            }
+        }
+
+        let creds = spawn::credentials(child.attrs.user.as_deref(), child.attrs.group.as_deref()).and_then(|creds| {
+            let context = child.attrs.secontext.as_deref().map(security::ExecContext::new).transpose()?;
+            Ok((creds, context))
+        });
+        let (creds, context) = match creds {
+            Ok(found) => found,
+            Err(e) => {
+                child.flags.remove(ChildFlags::RUNNING | ChildFlags::WAITING);
+                self.initlog(LogLevel::Verbose, &format!("cannot start \"{}\": {}", child.id, e));
+                return;
+            }
+        };
+
+        let tty = child.attrs.tty.as_deref().map(|tty| self.tty_path(tty));
+        let tuning = spawn::Tuning::from_attrs(&child.attrs);
+
+        let env = self.spawn_env();
+        let process = spawn::expand(&child.process, &child.id, &env);
+        initdbg!(LogLevel::Syslog, "\"{}\": {}", child.id, spawn::parse_command(&process));
+
+        let title = self.config.child_titles.then(|| format!("rye:{} {}", child.id, process));
+        let mut spawned = spawn::spawn(&process, creds.as_ref(), tty.as_deref(), context.as_ref(), &tuning, &env,
+                                       title.as_deref());
+        for alternative in &child.fallback {
+            let Err(e @ spawn::SpawnError::Exec(..)) = &spawned else {
+                break;
+            };
+            self.initlog(LogLevel::Verbose, &format!("cannot start \"{}\": {}, trying {}", child.id, e, alternative));
+            let process = spawn::expand(alternative, &child.id, &env);
+            let title = self.config.child_titles.then(|| format!("rye:{} {}", child.id, process));
+            spawned = spawn::spawn(&process, creds.as_ref(), tty.as_deref(), context.as_ref(), &tuning, &env,
+                                   title.as_deref());
+        }
+
+        match spawned {
+            Ok(pid) => {
+                child.pid = pid;
+                child.mark_running();
+                child.mark_executed();
+                child.attach_pidfd();
+                child.attach_group();
+                self.watch_child(child, events);
+                self.child_started(child);
+            }
+            Err(e) => {
+                child.flags.remove(ChildFlags::RUNNING | ChildFlags::WAITING);
+                self.initlog(LogLevel::Verbose, &format!("cannot start \"{}\": {}", child.id, e));
+            }
+        }
+    }
+
+    // Interactive boot: ask before starting a boot entry. No answer means
+    // yes, "a" starts this and everything after it without asking.
+    fn confirm_start(&mut self, child: &Child) -> bool {
+        if !self.confirm || !matches!(child.action, InitAction::SysInit | InitAction::Boot | InitAction::BootWait) {
+            return true;
+        }
+
+        let prompt = format!("\rStart {}? (Y/n/a) ", child.process);
+        match console::console_ask(self.console_name(), &prompt, CONFIRM_TIMEOUT) {
+            Some(answer) if answer.eq_ignore_ascii_case("n") => false,
+            Some(answer) if answer.eq_ignore_ascii_case("a") => {
+                self.confirm = false;
+                true
             }
-            StateToken::GotSign => {
-                let line = get_string(reader, 32)?;
-                if let Ok(val) = line.trim().parse::<u32>() {
-                    if val != 0 {
-                        set_got_signals();
+            _ => true,
+        }
+    }
+
+    // Start an entry whose runlevel has come
+    fn startup(&mut self, child: &mut Child, start: policy::Start, events: &EventLoop) {
+        if !child.flags.contains(ChildFlags::XECUTED) && !self.confirm_start(child) {
+            // Skipped entries count as done
+            child.mark_executed();
+            return;
+        }
+
+        match start {
+            policy::Start::SpawnLater if !self.wait_due(child, child.delay as u64 * 1000) => return,
+            policy::Start::Spawn if child.flags.contains(ChildFlags::XECUTED)
+                && !self.wait_due(child, respawn_backoff(child, clock::boottime()) * 1000) => return,
+            policy::Start::SpawnAndWait => child.flags.insert(ChildFlags::WAITING),
+            _ => {}
+        }
+        if !self.terminals_ready(child, events) {
+            return;
+        }
+        if splash::takes_terminal(child) {
+            self.quit_splash();
+        }
+        self.spawn_child(child, events);
+
+        if let Some(secs) = wait_timeout(&self.config, child)
+            && child.flags.contains(ChildFlags::WAITING) && child.is_running() {
+            let deadline = clock::monotonic_ms() + secs * 1000;
+            self.timers.schedule_at(deadline, Timeout::BootTimeout(child.id.clone(), child.pid));
+        }
+    }
+
+    // The terminals the process of an entry runs on
+    fn terminals(&self, child: &Child) -> Vec<String> {
+        let mut devs = vec![match child.attrs.tty.as_deref() {
+            Some(tty) => self.tty_path(tty),
+            None => self.console_name().to_string(),
+        }];
+        devs.extend(devwait::terminal_args(&child.process));
+        devs
+    }
+
+    // Whether a respawning entry can start as far as its terminals are
+    // concerned: they all exist, or it has waited DEVICE_WAIT for them
+    fn terminals_ready(&mut self, child: &mut Child, events: &EventLoop) -> bool {
+        if !matches!(child.action, InitAction::Respawn | InitAction::OnDemand) {
+            return true;
+        }
+
+        let missing: Vec<String> = self.terminals(child)
+            .into_iter()
+            .filter(|dev| !std::path::Path::new(dev).exists())
+            .collect();
+        if missing.is_empty() {
+            child.dev_wait = 0;
+            return true;
+        }
+
+        let now = clock::monotonic_ms();
+        if child.dev_wait == 0 {
+            child.dev_wait = now;
+            self.timers.schedule_at(now + devwait::DEVICE_WAIT, Timeout::DeviceWait(child.id.clone()));
+            match self.dev_watch.open() {
+                Ok(Some(fd)) => {
+                    if events.watch(EventSource::DevCreated, fd).is_err() {
+                        initdbg!(LogLevel::Verbose, "cannot watch {}", devwait::DEV_DIR);
                     }
                 }
+                Ok(None) => {}
+                Err(e) => initdbg!(LogLevel::Verbose, "cannot watch {}: {}", devwait::DEV_DIR, e),
             }
-            StateToken::WroteWtmpReboot => {
-                let line = get_string(reader, 32)?;
-                if let Ok(val) = line.trim().parse::<i32>() {
-                    state.wrote_wtmp_reboot = val != 0;
-                }
+            self.initlog(LogLevel::Verbose, &format!("\"{}\" waits for {}", child.id, missing.join(", ")));
+            return false;
+        }
+        if now < child.dev_wait + devwait::DEVICE_WAIT {
+            return false;
+        }
+
+        self.initlog(LogLevel::Verbose, &format!("\"{}\": no {} after {} seconds, starting it anyway",
+            child.id, missing.join(", "), devwait::DEVICE_WAIT / 1000));
+        child.dev_wait = 0;
+        true
+    }
+
+    // Whether an entry that has to wait delay_ms before it starts may go
+    // now. The wait runs from the first time we see the entry due.
+    fn wait_due(&mut self, child: &mut Child, delay_ms: u64) -> bool {
+        let now = clock::monotonic_ms();
+        if child.due == 0 {
+            if delay_ms == 0 {
+                return true;
             }
-            StateToken::WroteUtmpReboot => {
-                let line = get_string(reader, 32)?;
-                if let Ok(val) = line.trim().parse::<i32>() {
-                    state.wrote_utmp_reboot = val != 0;
+            child.due = now + delay_ms;
+            child.timer = self.timers.schedule_at(child.due, Timeout::DelayedStart(child.id.clone()));
+        }
+        if now < child.due {
+            return false;
+        }
+        child.due = 0;
+        child.timer = 0;
+        true
+    }
+
+    // Create the timerfd of the timer wheel and watch it
+    pub fn open_timers(&mut self, events: &EventLoop) {
+        if self.timer_fd >= 0 {
+            return;
+        }
+
+        match self.timers.open() {
+            Ok(fd) => {
+                self.timer_fd = fd;
+                if events.watch(EventSource::Timer, fd).is_err() {
+                    initdbg!(LogLevel::Verbose, "cannot watch the timerfd");
                 }
             }
-            StateToken::SlTime => {
-                let line = get_string(reader, 32)?;
-                if let Ok(val) = line.trim().parse::<u64>() {
-                    state.sleep_time = val;
-                }
+            Err(e) => self.initlog(LogLevel::Verbose, &format!("cannot create timerfd: {}", e)),
+        }
+    }
+
+    // Reschedule the timers of entries our predecessor had pending. A boot
+    // entry that is waited for gets its full timeout again.
+    fn restore_timers(&mut self) {
+        let mut current = self.family.as_mut();
+        while let Some(child) = current {
+            if child.due > 0 {
+                child.timer = self.timers.schedule_at(child.due, Timeout::DelayedStart(child.id.clone()));
             }
-            StateToken::DidBoot => {
-                let line = get_string(reader, 32)?;
-                if let Ok(val) = line.trim().parse::<i32>() {
-                    state.did_boot = val != 0;
+            if let Some(secs) = wait_timeout(&self.config, child)
+                && child.flags.contains(ChildFlags::WAITING) && child.is_running() {
+                let deadline = clock::monotonic_ms() + secs * 1000;
+                self.timers.schedule_at(deadline, Timeout::BootTimeout(child.id.clone(), child.pid));
+            }
+            current = child.next.as_mut();
+        }
+    }
+
+    // Handle whatever timers are due
+    fn run_timers(&mut self, events: &EventLoop) {
+        for what in self.timers.expired(clock::monotonic_ms()) {
+            match what {
+                // The entry itself is started by start_if_needed
+                Timeout::DelayedStart(id) => initdbg!(LogLevel::Verbose, "delay of \"{}\" is over", id),
+                Timeout::DeviceWait(id) => initdbg!(LogLevel::Verbose, "\"{}\" waited long enough for its terminal", id),
+                Timeout::Reconcile => {
+                    self.reconcile();
+                    self.timers.schedule_at(clock::monotonic_ms() + RECONCILE_INTERVAL, Timeout::Reconcile);
+                }
+                Timeout::Battery => {
+                    self.check_battery(events);
+                    self.timers.schedule_at(clock::monotonic_ms() + battery::CHECK_INTERVAL, Timeout::Battery);
+                }
+                Timeout::Upgrade => {
+                    self.check_upgrade();
+                    self.timers.schedule_at(clock::monotonic_ms() + upgrade::CHECK_INTERVAL, Timeout::Upgrade);
+                }
+                Timeout::ContainerStop => {
+                    if let Some(how) = self.container_stop.take() {
+                        self.initlog(LogLevel::Verbose, &format!("runlevel {} not done after {} seconds, stopping now",
+                            self.curlevel, self.config.container_stop_timeout));
+                        self.sleep_time = 1;
+                        self.go_down(how);
+                    }
                 }
+                Timeout::BootTimeout(id, pid) => self.boot_timeout(&id, pid, events),
+                Timeout::Checkpoint => {
+                    self.save_checkpoint();
+                    self.timers.schedule_at(clock::monotonic_ms() + checkpoint::CHECKPOINT_INTERVAL, Timeout::Checkpoint);
+                }
+                Timeout::EarlyLog => self.check_early_log(events),
+                Timeout::Syslog => {
+                    self.syslog_retry = 0;
+                    self.flush_syslog();
+                }
+                Timeout::BootDeadline => self.boot_deadline(events),
             }
-            StateToken::WroteWtmpRlevel => {
-                let line = get_string(reader, 32)?;
-                if let Ok(val) = line.trim().parse::<i32>() {
-                    state.wrote_wtmp_rlevel = val != 0;
+        }
+    }
+
+    // Take /dev/log until the syslog daemon comes, see earlylog.rs
+    fn open_early_log(&mut self, events: &EventLoop) {
+        match self.early_log.open() {
+            Ok(Some(fd)) => {
+                if events.watch(EventSource::EarlyLog, fd).is_err() {
+                    initdbg!(LogLevel::Verbose, "cannot watch {}", earlylog::DEV_LOG);
                 }
+                self.timers.schedule_at(clock::monotonic_ms() + earlylog::CHECK_INTERVAL, Timeout::EarlyLog);
+            }
+            Ok(None) => {}
+            Err(e) => self.initlog(LogLevel::Verbose, &format!("cannot bind {}: {}",
+                paths::path(earlylog::DEV_LOG), e)),
+        }
+    }
+
+    // Send the syslog messages that wait for the syslog daemon, and try
+    // again when it may be back if some are left, see initlog.rs
+    fn flush_syslog(&mut self) {
+        if let Some(due) = initlog::flush_syslog().filter(|_| self.syslog_retry == 0) {
+            self.syslog_retry = due;
+            self.timers.schedule_at(due, Timeout::Syslog);
+        }
+    }
+
+    // Give the syslog daemon what was sent to our /dev/log, once it is there
+    fn check_early_log(&mut self, events: &EventLoop) {
+        if self.early_log.taken_over() {
+            if let Some(fd) = self.early_log.fd() {
+                events.unwatch(fd);
+            }
+            self.early_log.close();
+        }
+        match self.early_log.replay() {
+            Ok(0) => {}
+            Ok(n) => initdbg!(LogLevel::Verbose, "passed {} early messages on to the syslog daemon", n),
+            Err(e) => initdbg!(LogLevel::Verbose, "cannot write {}: {}", paths::path(earlylog::DEV_LOG), e),
+        }
+
+        if self.early_log.active() {
+            self.timers.schedule_at(clock::monotonic_ms() + earlylog::CHECK_INTERVAL, Timeout::EarlyLog);
+        } else if self.early_log.dropped > 0 {
+            self.initlog(LogLevel::Verbose, &format!("{} early syslog messages were lost, {} had too many",
+                self.early_log.dropped, earlylog::DEV_LOG));
+        }
+    }
+
+    // An entry init waits for is still running when its timeout is over.
+    // Init goes on without it, so one hanging fsck can't hold up the boot
+    // forever. An entry with a timeout attribute of its own is stopped and
+    // marked TIMEDOUT, its exit status says it failed. For the others
+    // boot-timeout-action says what happens to them.
+    fn boot_timeout(&mut self, id: &str, pid: i32, events: &EventLoop) {
+        let Some(child) = self.find_child_by_id_mut(id) else {
+            return;
+        };
+        if child.pid != pid || !child.flags.contains(ChildFlags::WAITING) {
+            return;
+        }
+        child.flags.remove(ChildFlags::WAITING);
+        let action = child.action;
+
+        if let Some(secs) = child.attrs.timeout.as_deref().and_then(inittab::parse_timeout) {
+            child.flags.insert(ChildFlags::TIMEDOUT);
+            self.log_event(LogEvent::EntryTimedOut { id, secs });
+            self.stop_child(id);
+            return;
+        }
+
+        let secs = self.config.boot_timeout(action).unwrap_or(0);
+        self.log_event(LogEvent::BootEntryTimedOut { id, secs });
+        self.stop_waiting(id, pid, self.config.boot_timeout_action, events);
+    }
+
+    // Go on without an entry init waited for, whose WAITING flag is gone
+    // already, the way `action` says
+    fn stop_waiting(&mut self, id: &str, pid: i32, action: config::BootTimeout, events: &EventLoop) {
+        match action {
+            config::BootTimeout::Continue => {
+                self.initlog(LogLevel::Verbose, &format!("going on without \"{}\", pid {} keeps running", id, pid));
             }
-            StateToken::WroteUtmpRlevel => {
-                let line = get_string(reader, 32)?;
-                if let Ok(val) = line.trim().parse::<i32>() {
-                    state.wrote_utmp_rlevel = val != 0;
+            config::BootTimeout::Kill => self.stop_child(id),
+            config::BootTimeout::Emergency => {
+                self.stop_child(id);
+                // At the start of the list it holds up everything after it
+                if self.find_child_by_id(SPECIAL_ID).is_none() {
+                    self.initlog(LogLevel::Verbose, "starting an emergency shell, boot goes on when it exits");
+                    let mut shell = self.emergency_shell();
+                    self.spawn_child(&mut shell, events);
+                    self.add_child(shell);
                 }
             }
-            StateToken::Eof => {
-                state.oops_error = -1;
-                return Ok(None);
+        }
+    }
+
+    // The boot isn't finished when boot-deadline is over. Say which
+    // entries hold it up, tell whoever boot-deadline-notify names and
+    // do boot-deadline-action to the entries that are running. A boot
+    // stuck at a runlevel nobody switches away from has nothing to wait
+    // for, it is reported all the same.
+    fn boot_deadline(&mut self, events: &EventLoop) {
+        if !self.booting {
+            return;
+        }
+        let secs = self.config.boot_deadline.unwrap_or(0);
+        let waiting: Vec<(EntryId, i32, String)> = self
+            .children()
+            .filter(|child| child.flags.contains(ChildFlags::WAITING))
+            .map(|child| (child.id.clone(), if child.is_running() { child.pid } else { NO_PROCESS }, child.process.clone()))
+            .collect();
+
+        let ids: Vec<String> = waiting.iter().map(|(id, ..)| id.to_string()).collect();
+        self.log_event(LogEvent::BootDeadlineMissed { secs, level: &self.curlevel, waiting: &ids });
+        for (id, pid, process) in &waiting {
+            match *pid {
+                NO_PROCESS => self.initlog(LogLevel::Verbose, &format!("\"{}\" has not started yet: {}", id, process)),
+                pid => self.initlog(LogLevel::Verbose, &format!("\"{}\" is still running as pid {}: {}", id, pid, process)),
             }
-            _ => {
-                get_void(reader)?;
+        }
+
+        if let Some(command) = self.config.on_failure_exec.as_deref().filter(|_| self.config.boot_deadline_hook) {
+            hooks::run_deadline_hook(command, &ids, self);
+        }
+        if self.config.boot_deadline_watchdog {
+            self.start_watchdog();
+        }
+
+        let Some(action) = self.config.boot_deadline_action else {
+            return;
+        };
+        for (id, pid, _) in waiting.iter().filter(|(_, pid, _)| *pid != NO_PROCESS) {
+            if let Some(child) = self.find_child_by_id_mut(id) {
+                child.flags.remove(ChildFlags::WAITING);
+            }
+            self.stop_waiting(id, *pid, action, events);
+        }
+    }
+
+    // Leave a boot that took too long to the hardware watchdog, see
+    // watchdog.rs
+    fn start_watchdog(&mut self) {
+        let device = paths::path(watchdog::WATCHDOG_DEVICE);
+        match watchdog::arm() {
+            Ok((file, timeout)) => {
+                self.watchdog = Some(file);
+                let when = timeout.map(|secs| format!(" in {} seconds", secs)).unwrap_or_default();
+                self.initlog(LogLevel::Verbose, &format!("{} started, it resets the system{} unless the boot finishes",
+                    device, when));
             }
+            Err(e) if e.raw_os_error() == Some(libc::EBUSY) => {
+                self.initlog(LogLevel::Verbose, &format!("cannot start {}, a watchdog daemon has it", device));
+            }
+            Err(e) => self.initlog(LogLevel::Verbose, &format!("cannot start {}: {}", device, e)),
         }
     }
 
-    let mut child = Child::new();
-    child.id = get_string(reader, INITTAB_ID)?;
+    // The runlevel just entered may be the one the boot was heading for:
+    // the runlevel of the initdefault entry, or without one any after S.
+    // Runlevel 0 and 6 end the boot too, there is nothing left to finish.
+    // A watchdog started because the boot took too long is stopped again.
+    fn finish_boot(&mut self) {
+        if !self.booting {
+            return;
+        }
+        let default = self.children().find(|child| child.action == InitAction::InitDefault).map(|child| &child.rlevel);
+        let finished = match &self.curlevel {
+            Runlevel::Classic('S') => false,
+            Runlevel::Classic('0' | '6') => true,
+            level => default.is_none_or(|levels| levels.is_empty() || levels.contains(level)),
+        };
+        if !finished {
+            return;
+        }
 
-    loop {
-        match get_cmd(reader)? {
-            StateToken::Eor => {
-                get_void(reader)?;
-                break;
+        self.booting = false;
+        if let Some(file) = self.watchdog.take() {
+            let device = paths::path(watchdog::WATCHDOG_DEVICE);
+            match watchdog::disarm(file) {
+                Ok(()) => self.initlog(LogLevel::Verbose, &format!("boot finished late, {} stopped", device)),
+                Err(e) => self.initlog(LogLevel::Verbose, &format!("cannot stop {}: {}", device, e)),
             }
-            StateToken::Pid => {
-                let line = get_string(reader, 32)?;
-                if let Ok(pid) = line.trim().parse::<i32>() {
-                    child.pid = pid;
+        }
+    }
+
+    // Check the flags of every entry against its process and repair what
+    // doesn't match, so a missed exit or a state stream from a confused
+    // predecessor can't leave the process table wrong for good. Every
+    // repair is logged, none of them should ever be needed.
+    pub fn reconcile(&mut self) {
+        let mut exited = Vec::new();
+        let mut problems = Vec::new();
+
+        let mut current = self.family.as_mut();
+        while let Some(child) = current {
+            let running = child.is_running();
+            if running && child.pid == NO_PROCESS {
+                problems.push(format!("\"{}\" is running without a pid", child.id));
+                child.close_pidfd();
+                child.mark_zombie();
+            } else if running {
+                let mut status = 0;
+                match unsafe { libc::waitpid(child.pid, &mut status, libc::WNOHANG) } {
+                    0 => {}
+                    pid if pid == child.pid => exited.push((pid, status)),
+                    _ => {
+                        problems.push(format!("\"{}\": pid {} is not ours anymore", child.id, child.pid));
+                        child.release_pid();
+                        child.mark_zombie();
+                        child.flags.remove(ChildFlags::WAITING);
+                    }
                 }
+            } else if child.pid != NO_PROCESS {
+                problems.push(format!("\"{}\" still holds pid {} of a process that is gone", child.id, child.pid));
+                child.release_pid();
+                child.flags.remove(ChildFlags::WAITING);
+            }
+
+            if child.flags.contains(ChildFlags::RUNNING | ChildFlags::ZOMBIE) {
+                problems.push(format!("\"{}\" is running and a zombie", child.id));
+                child.flags.remove(ChildFlags::ZOMBIE);
+            }
+            if child.flags.contains(ChildFlags::WAITING) && !child.is_running() {
+                problems.push(format!("\"{}\" is waited for but not running", child.id));
+                child.flags.remove(ChildFlags::WAITING);
+            }
+            if child.flags.contains(ChildFlags::KILLME) {
+                problems.push(format!("\"{}\" is left marked to be killed", child.id));
+                child.flags.remove(ChildFlags::KILLME);
             }
-            StateToken::Exs => {
-                let line = get_string(reader, 32)?;
-                if let Ok(exstat) = line.trim().parse::<i32>() {
-                    child.exstat = exstat;
+            current = child.next.as_mut();
+        }
+
+        for problem in problems {
+            self.initlog(LogLevel::Syslog, &format!("process table: {}, fixed", problem));
+        }
+        // Exits we missed take the normal way, utmp and rescue included
+        for (pid, status) in exited {
+            self.initlog(LogLevel::Syslog, &format!("process table: exit of pid {} was missed", pid));
+            self.child_exited(pid, status);
+        }
+    }
+
+    // Start whatever should run at the current runlevel, in the order of
+    // the process table, which is inittab order. Only entries that
+    // have to be waited for (wait, bootwait, ...) stop the scan. Everything
+    // else, `once` entries included, is forked right away so independent
+    // jobs run in parallel, and their exits are collected asynchronously.
+    pub fn start_if_needed(&mut self, events: &EventLoop) {
+        let level = self.curlevel.clone();
+        let starts = policy::starts(&self.planned(), &level);
+
+        // The list is out of self while its entries are started. A panic in
+        // there must not leave recover() an empty process table to hand
+        // over, so it is put back before the panic goes on.
+        let mut family = self.family.take();
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let mut current = family.as_mut();
+            let mut n = 0;
+            for (at, start) in starts {
+                while n < at {
+                    current = current.and_then(|child| child.next.as_mut());
+                    n += 1;
                 }
+                let Some(child) = current.as_deref_mut() else {
+                    break;
+                };
+                self.startup(child, start, events);
+                if child.flags.contains(ChildFlags::WAITING) {
+                    break;
+                }
+            }
+        }));
+        self.family = family;
+        if let Err(panic) = result {
+            std::panic::resume_unwind(panic);
+        }
+
+        let blocked = self.any_waiting();
+
+        if !blocked {
+            self.level_entered();
+        }
+    }
+
+    // Nothing is left to wait for at the current runlevel: the switch
+    // is complete
+    fn level_entered(&mut self) {
+        if let Some(started) = self.entering.take() {
+            let millis = started.elapsed().as_millis();
+            self.log_event(LogEvent::RunlevelEntered { level: &self.curlevel, millis });
+            self.log_transition(transitions::Record::Entered { level: &self.curlevel, millis });
+            #[cfg(feature = "metrics")]
+            if let Some(metrics) = &mut self.metrics {
+                metrics.transition(&self.curlevel, millis);
             }
-            StateToken::Lev => {
-                child.rlevel = get_string(reader, RUNLEVEL_LENGTH)?;
+            hooks::run_hooks(hooks::POST_LEVEL_CHANGE, &self.curlevel, &self.prevlevel, self);
+            if self.curlevel != Runlevel::Classic('S') {
+                self.quit_splash();
             }
-            StateToken::Process => {
-                child.process = get_string(reader, PROCESS_LENGTH)?;
+            if !matches!(self.curlevel, Runlevel::Classic('S' | '0' | '6')) {
+                self.start_accounting();
             }
-            StateToken::Flag => {
-                let flag_str = get_string(reader, 32)?;
-                for flag_mapping in FLAG_MAPPINGS {
-                    if flag_mapping.name == flag_str.trim() {
-                        child.flags.insert(flag_mapping.mask);
-                        break;
-                    }
+            self.finish_boot();
+            self.save_checkpoint();
+        }
+
+        if !self.did_boot && self.curlevel == Runlevel::Classic('S') {
+            self.enter_default_level();
+        }
+
+        // A container has nobody to power it off but us, and runlevel 0 or 6
+        // is where the sandbox ends
+        if let Some(how) = self.container_stop.take() {
+            self.go_down(how);
+        } else if self.sandbox {
+            match self.curlevel {
+                Runlevel::Classic('0') => self.go_down(Reboot::PowerOff),
+                Runlevel::Classic('6') => self.go_down(Reboot::Restart),
+                _ => {}
+            }
+        }
+
+        for fd in std::mem::take(&mut self.level_waiters) {
+            control::control_ack(fd, initreq::INIT_ACK_DONE);
+        }
+    }
+
+    // Nothing is waited for in S after a boot, the sysinit and bootwait
+    // entries are done: on to the first runlevel of the initdefault entry.
+    // Without one init stays in S, there is nobody to ask.
+    fn enter_default_level(&mut self) {
+        self.did_boot = true;
+        let default = self.children().find(|child| child.action == InitAction::InitDefault).map(|child| child.rlevel.first());
+        match default {
+            Some(Some(level @ Runlevel::Classic('0' | '6'))) => {
+                self.initlog(LogLevel::Verbose, &format!("initdefault runlevel {} is no runlevel to boot to, staying in S",
+                    level));
+            }
+            Some(Some(level)) => {
+                self.change_runlevel(level, "boot");
+            }
+            Some(None) => self.initlog(LogLevel::Verbose, "initdefault entry without a runlevel, staying in S"),
+            None => self.initlog(LogLevel::Verbose, "no initdefault entry, staying in S"),
+        }
+    }
+
+    // Log one of the well-known init events
+    pub fn log_event(&self, event: LogEvent) {
+        let msg = if self.structured_log {
+            format!("{} {}", event.structured_data(), event.message())
+        } else {
+            event.message()
+        };
+        initlog::initlog_from(event.source(), self.console_name(), event.level(), &msg);
+        if let Some(priority) = event.kmsg_priority().filter(|_| self.config.kmsg) {
+            initlog::log_kmsg(priority, &event.message());
+        }
+    }
+
+    // A child has been spawned, give it an INIT_PROCESS utmp slot
+    pub fn child_started(&self, child: &Child) {
+        self.log_event(LogEvent::EntryStarted { id: &child.id, pid: child.pid });
+        if child.uses_utmp() {
+            self.entry_utmp(&child.id, child.pid, true);
+        }
+    }
+
+    // The utmp slot of an entry's process: INIT_PROCESS while it runs,
+    // DEAD_PROCESS once it is gone
+    #[cfg(feature = "utmp")]
+    fn entry_utmp(&self, id: &str, pid: i32, running: bool) {
+        let ut_type = if running { utmp::INIT_PROCESS } else { utmp::DEAD_PROCESS };
+        self.utmp.write_utmp_wtmp("", id, pid, ut_type as i32, "");
+    }
+
+    #[cfg(not(feature = "utmp"))]
+    fn entry_utmp(&self, _id: &str, _pid: i32, _running: bool) {}
+
+    // A child has been collected with waitpid, record its exit status
+    fn child_exited(&mut self, pid: i32, status: i32) {
+        let mut dead = None;
+        let mut failed = None;
+        let mut unexpected = None;
+        let rescue_pid = self.rescue_pid;
+        let level = self.curlevel.clone();
+
+        match self.find_child_by_pid_mut(pid) {
+            Some(child) => {
+                initdbg!(LogLevel::Syslog, "\"{}\" (pid {}) exited, flags {}", child.id, pid, child.flags.names());
+                child.exstat = status;
+                child.mark_zombie();
+                child.flags.remove(ChildFlags::WAITING);
+                child.release_pid();
+                dead = Some((child.id.clone(), child.uses_utmp()));
+                if !policy::runs_once(child.action) && hooks::abnormal_exit(status)
+                    && !child.flags.contains(ChildFlags::KILLME)
+                    && policy::is_due(&child.rlevel, child.flags, &level) {
+                    unexpected = Some(child.id.clone());
+                }
+                if status != 0 && matches!(child.action, InitAction::SysInit | InitAction::BootWait) {
+                    failed = Some((child.id.clone(), child.process.clone()));
                 }
             }
-            StateToken::Action => {
-                let action_str = get_string(reader, 32)?;
-                child.action = InitAction::from_str(action_str.trim()).unwrap_or(InitAction::Off);
+            None if pid == rescue_pid => {
+                self.rescue_pid = NO_PROCESS;
+                self.initlog(LogLevel::Verbose, "rescue shell exited");
             }
-            _ => {
-                state.oops_error = -1;
-                return Ok(None);
+            None => match self.procs.take(pid) {
+                Some(orphan) => {
+                    let parent = match &orphan.parent {
+                        Some((ppid, name)) => format!(", parent {} (pid {})", name, ppid),
+                        None => String::new(),
+                    };
+                    initdbg!(LogLevel::Syslog, "reaped orphan {} (pid {}{}) status {}",
+                        orphan.info.comm, pid, parent, status);
+                }
+                None => {
+                    initdbg!(LogLevel::Syslog, "reaped unknown child {} status {}", pid, status);
+                }
+            },
+        }
+
+        if let Some((id, uses_utmp)) = dead {
+            self.trace("exit", &[("id", trace::Value::Str(id.to_string())), ("status", trace::Value::Int(status as i64))]);
+            self.log_event(LogEvent::EntryExited { id: &id, pid, status });
+            if uses_utmp {
+                self.entry_utmp(&id, pid, false);
+            }
+            self.reset_terminals(&id);
+        }
+        if let Some((id, process)) = failed {
+            self.log_event(LogEvent::BootEntryFailed { id: &id, status });
+            if let Err(e) = self.splash.details(&id, status) {
+                initdbg!(LogLevel::Verbose, "cannot write {}: {}", paths::path(splash::SPLASH_FIFO), e);
             }
+            self.start_rescue(&id, &process, status);
+        }
+        if let (Some(id), Some(command)) = (unexpected, &self.config.on_failure_exec) {
+            hooks::run_failure_hook(command, &id, status, self);
         }
     }
 
-    Ok(Some(child))
-}
+    // A boot entry failed: offer a shell on the rescue terminal, unless
+    // there is one already
+    fn start_rescue(&mut self, id: &str, process: &str, status: i32) {
+        let Some(vt) = self.rescue_vt.as_deref().map(|vt| self.tty_path(vt)) else {
+            return;
+        };
+        if self.rescue_pid != NO_PROCESS {
+            return;
+        }
 
-fn main() {
-    println!("Copyright 2025 PalindromicBreadLoaf");
-}
\ No newline at end of file
+        if let Err(e) = rescue::write_report(id, process, status) {
+            self.initlog(LogLevel::Verbose, &format!("cannot write {}: {}", paths::path(rescue::RESCUE_REPORT), e));
+        }
+
+        let mut env = self.spawn_env();
+        env.extra.push(format!("RESCUE_ID={}", id));
+        env.extra.push(format!("RESCUE_COMMAND={}", process));
+        env.extra.push(format!("RESCUE_STATUS={}", rescue::describe_status(status)));
+
+        match spawn::spawn(rescue::RESCUE_SHELL, None, Some(&vt), None, &spawn::Tuning::default(), &env, None) {
+            Ok(pid) => {
+                self.rescue_pid = pid;
+                self.initlog(LogLevel::Verbose, &format!("\"{}\" failed, rescue shell on {}", id, vt));
+            }
+            Err(e) => self.initlog(LogLevel::Verbose, &format!("cannot start a rescue shell on {}: {}", vt, e)),
+        }
+    }
+
+    // Collect up to budget children that have exited so far (SIGCHLD
+    // path). Returns whether there may be more.
+    pub fn reap_children(&mut self, budget: usize) -> bool {
+        for _ in 0..budget {
+            let mut status = 0;
+            let pid = unsafe { libc::waitpid(-1, &mut status, libc::WNOHANG) };
+            if pid <= 0 {
+                return false;
+            }
+            self.child_exited(pid, status);
+        }
+        true
+    }
+
+    // The pidfd of a child became readable, so that child has exited
+    pub fn reap_pidfd(&mut self, pidfd: i32) {
+        let pid = match self.find_pid_by_pidfd(pidfd) {
+            Some(pid) if pid > 0 => pid,
+            _ => return,
+        };
+
+        let mut status = 0;
+        if unsafe { libc::waitpid(pid, &mut status, libc::WNOHANG) } == pid {
+            self.child_exited(pid, status);
+        }
+    }
+
+    // Wait up to timeout milliseconds for events and dispatch them. At
+    // most REAP_BATCH children are collected per call, after everything
+    // else that was ready: a storm of exits, from a fork bomb or an entry
+    // that respawns like mad, must not hold up telinit and the signals.
+    // What is left is collected on the next call, which doesn't wait.
+    pub fn handle_events(&mut self, events: &EventLoop, timeout: i32) {
+        // Only debug output names orphans, don't scan /proc for nothing
+        if initlog::debug() {
+            self.procs.refresh(std::time::Duration::from_secs(PROC_SCAN_INTERVAL));
+        }
+
+        for req in std::mem::take(&mut self.deferred) {
+            self.process_request(&req, "fifo");
+        }
+
+        let timeout = if self.reap_more || got_signals() { 0 } else { timeout };
+        let mut exited = Vec::new();
+        for (source, fd) in events.wait(timeout) {
+            match source {
+                EventSource::ChildExit => exited.push(fd),
+                EventSource::InitFifo => {
+                    while let Some(req) = self.read_request() {
+                        self.process_request(&req, "fifo");
+                    }
+                }
+                EventSource::ControlListen => self.accept_control(events),
+                EventSource::ControlConn => self.read_control(fd, events),
+                EventSource::Timer => {}
+                EventSource::DevCreated => self.dev_watch.drain(),
+                EventSource::EarlyLog => self.early_log.drain(),
+            }
+        }
+
+        self.run_timers(events);
+
+        // Pidfds stay readable until their child is collected, the ones
+        // over the budget come back from the next wait
+        let budget = REAP_BATCH.saturating_sub(exited.len());
+        for fd in exited.into_iter().take(REAP_BATCH) {
+            self.reap_pidfd(fd);
+        }
+
+        // Exits that arrived without a pidfd (old kernels) are still
+        // signalled through SIGCHLD
+        if got_signals() || self.reap_more {
+            if !self.reap_more {
+                self.trace("signal", &[("signal", trace::Value::Str("SIGCHLD".to_string()))]);
+            }
+            // Cleared first, a SIGCHLD during the sweep makes another one
+            clear_got_signals();
+            self.reap_more = self.reap_children(budget);
+        }
+
+        if GOT_PWR.swap(false, Ordering::Relaxed) {
+            self.power_signal(events);
+        }
+        if GOT_TERM.swap(false, Ordering::Relaxed) && self.in_container() {
+            self.container_signal(Reboot::PowerOff);
+        }
+        if GOT_CAD.swap(false, Ordering::Relaxed) {
+            if self.in_container() {
+                self.container_signal(Reboot::Restart);
+            } else {
+                self.ctrlaltdel(events);
+            }
+        }
+        if GOT_DEBUG.swap(false, Ordering::Relaxed) {
+            initlog::set_debug(!initlog::debug());
+            self.initlog(LogLevel::Verbose, &format!("debug output {}", if initlog::debug() { "on" } else { "off" }));
+        }
+        if GOT_DIAG.swap(false, Ordering::Relaxed) {
+            self.diag();
+        }
+
+        self.update_metrics();
+        self.update_status();
+        self.update_splash();
+        initlog::flush_suppressed(self.console_name());
+        initlog::flush_console(self.console_name());
+        self.flush_syslog();
+    }
+
+    // Write the diagnostic dump to its file and the console, see diag.rs.
+    // Returns it.
+    fn diag(&self) -> String {
+        let text = diag::report(self);
+        self.initlog(LogLevel::Verbose, &format!("diagnostic dump in {}", paths::path(diag::DIAG_FILE)));
+        if !self.run_read_only && let Err(e) = diag::write_file(&text) {
+            self.initlog(LogLevel::Verbose, &format!("cannot write {}: {}", paths::path(diag::DIAG_FILE), e));
+        }
+        initlog::flush_console(self.console_name());
+        if let Err(e) = diag::write_console(self.console_name(), &text) {
+            initdbg!(LogLevel::Verbose, "cannot write the diagnostic dump to {}: {}", self.console_name(), e);
+        }
+        text
+    }
+
+    // Record an event with --trace
+    fn trace(&self, event: &str, fields: &[(&str, trace::Value)]) {
+        if let Some(trace) = &self.trace {
+            trace.record(event, fields);
+        }
+    }
+
+    // Start a trace with the entries everything after it refers to
+    fn trace_start(&self) {
+        let entries = self.children()
+            .map(|child| trace::entry_line(&child.id, &child.rlevel, child.action, &child.process))
+            .collect();
+        self.trace("start", &[
+            ("runlevel", trace::Value::Str(self.curlevel.to_string())),
+            ("entries", trace::Value::List(entries)),
+        ]);
+    }
+
+    // Add to the runlevel audit trail, see transitions.rs
+    fn log_transition(&self, record: transitions::Record) {
+        if self.run_read_only {
+            return;
+        }
+        if let Err(e) = transitions::append(record) {
+            initdbg!(LogLevel::Verbose, "cannot write {}: {}", paths::path(transitions::TRANSITIONS_LOG), e);
+        }
+    }
+
+    // Bring the status files up to date, see status.rs
+    fn update_status(&mut self) {
+        if self.run_read_only {
+            return;
+        }
+        if let Err(e) = self.status.update(self.family.as_deref()) {
+            initdbg!(LogLevel::Verbose, "cannot write {}: {}", paths::path(status::STATUS_DIR), e);
+        }
+    }
+
+    // Tell a boot splash how far the boot is, see splash.rs
+    fn update_splash(&mut self) {
+        if let Err(e) = self.splash.progress(self.family.as_deref()) {
+            initdbg!(LogLevel::Verbose, "cannot write {}: {}", paths::path(splash::SPLASH_FIFO), e);
+        }
+    }
+
+    fn quit_splash(&mut self) {
+        if let Err(e) = self.splash.quit() {
+            initdbg!(LogLevel::Verbose, "cannot write {}: {}", paths::path(splash::SPLASH_FIFO), e);
+        }
+    }
+
+    // --metrics: keep the metrics file from now on
+    #[cfg(feature = "metrics")]
+    fn enable_metrics(&mut self) {
+        self.metrics = Some(metrics::Metrics::new());
+    }
+
+    #[cfg(not(feature = "metrics"))]
+    fn enable_metrics(&mut self) {
+        self.initlog(LogLevel::Verbose, "--metrics: init is built without metrics");
+    }
+
+    // Bring the metrics file up to date, if we keep one
+    #[cfg(feature = "metrics")]
+    fn update_metrics(&mut self) {
+        let Some(metrics) = &mut self.metrics else {
+            return;
+        };
+        if let Err(e) = metrics.update(self.family.as_deref(), &self.curlevel) {
+            initdbg!(LogLevel::Verbose, "cannot write {}: {}", paths::path(metrics::METRICS_FILE), e);
+        }
+    }
+
+    #[cfg(not(feature = "metrics"))]
+    fn update_metrics(&mut self) {}
+
+    // The console device children and init's own messages should use
+    pub fn console_name(&self) -> &str {
+        self.console_dev.as_deref().unwrap_or(console::CONSOLE)
+    }
+
+    // The device named by a tty attribute
+    pub fn tty_path(&self, tty: &str) -> String {
+        match tty {
+            "console" => self.console_name().to_string(),
+            _ if tty.starts_with('/') => tty.to_string(),
+            _ => format!("/dev/{}", tty),
+        }
+    }
+
+    // Switch to another console device. Children spawned from now on and
+    // our own console output go to the new device.
+    pub fn change_console(&mut self, dev: &str) -> std::io::Result<()> {
+        if !dev.starts_with("/dev/") {
+            return Err(std::io::Error::from(std::io::ErrorKind::InvalidInput));
+        }
+
+        let f = tty::open(dev)?;
+        if tty::sane(&f).is_err() {
+            initdbg!(LogLevel::Verbose, "cannot reset termios on {}", dev);
+        }
+
+        self.console_dev = Some(dev.to_string());
+        Ok(())
+    }
+
+    // /run can't be written, as in some initramfs images and container
+    // runtimes. Boot goes on without the FIFO, the control socket and the
+    // files under /run/rye-init: init is only reached by signals and the
+    // runlevel is only kept in memory. Said once, not for every file.
+    fn run_unwritable(&mut self, path: &str, e: &std::io::Error) -> bool {
+        if !matches!(e.raw_os_error(), Some(libc::EROFS) | Some(libc::EACCES)) {
+            return false;
+        }
+        if !self.run_read_only {
+            self.run_read_only = true;
+            self.initlog(LogLevel::Verbose, &format!("cannot create {}: {}, /run is not writable: \
+                init takes signals only and keeps the runlevel in memory", path, e));
+        }
+        true
+    }
+
+    // Create and open /run/initctl. We open it read-write so the FIFO never
+    // reports end-of-file when the last telinit closes it.
+    pub fn open_fifo(&mut self, events: &EventLoop) {
+        if self.pipe_fd >= 0 || self.run_read_only {
+            return;
+        }
+
+        let fifo = paths::path(initreq::INIT_FIFO);
+        if let Err(e) = nix::unistd::mkfifo(fifo.as_str(), Mode::from_bits_truncate(0o600))
+            && e != nix::errno::Errno::EEXIST && self.run_unwritable(&fifo, &e.into()) {
+            return;
+        }
+
+        let flags = OFlag::O_RDWR | OFlag::O_NONBLOCK | OFlag::O_CLOEXEC;
+        match nix::fcntl::open(fifo.as_str(), flags, Mode::empty()) {
+            Ok(fd) => {
+                self.pipe_fd = fd.into_raw_fd();
+                if events.watch(EventSource::InitFifo, self.pipe_fd).is_err() {
+                    initdbg!(LogLevel::Verbose, "cannot watch {}", fifo);
+                }
+            }
+            Err(e) => {
+                self.initlog(LogLevel::Verbose, &format!("cannot open {}: {}", fifo, e));
+            }
+        }
+    }
+
+    // Open the control socket next to the FIFO
+    pub fn open_control(&mut self, events: &EventLoop) {
+        if self.control_fd >= 0 || self.run_read_only {
+            return;
+        }
+
+        let socket = paths::path(initreq::INIT_SOCKET);
+        match control::control_listen(&socket, self.control_root_only) {
+            Err(e) if self.run_unwritable(&socket, &e.into()) => {}
+            Ok(fd) => {
+                self.control_fd = fd;
+                if events.watch(EventSource::ControlListen, fd).is_err() {
+                    initdbg!(LogLevel::Verbose, "cannot watch {}", socket);
+                }
+            }
+            Err(e) => {
+                self.initlog(LogLevel::Verbose, &format!("cannot open {}: {}", socket, e));
+            }
+        }
+    }
+
+    fn accept_control(&mut self, events: &EventLoop) {
+        while let Some((fd, peer)) = control::control_accept(self.control_fd) {
+            if events.watch(EventSource::ControlConn, fd).is_err() {
+                let _ = nix::unistd::close(fd);
+                continue;
+            }
+            self.control_conns.push((fd, peer));
+        }
+    }
+
+    fn close_control(&mut self, fd: i32, events: &EventLoop) {
+        events.unwatch(fd);
+        let _ = nix::unistd::close(fd);
+        self.control_conns.retain(|&(c, _)| c != fd);
+        self.level_waiters.retain(|&c| c != fd);
+    }
+
+    // Handle the requests on a control connection, logging who sent them
+    fn read_control(&mut self, fd: i32, events: &EventLoop) {
+        let Some(&(_, peer)) = self.control_conns.iter().find(|&&(c, _)| c == fd) else {
+            events.unwatch(fd);
+            return;
+        };
+
+        loop {
+            match control::control_recv(fd) {
+                control::ControlRead::Request(req) if req.cmd == initreq::INIT_CMD_FEATURES => {
+                    // Anybody may ask, it gives nothing away
+                    self.send_features(fd);
+                }
+                control::ControlRead::Request(req) => {
+                    // Requests that only exist on the control socket get an answer
+                    let answer = matches!(req.cmd, initreq::INIT_CMD_RUNLVL |
+                                                   initreq::INIT_CMD_WAITLVL | initreq::INIT_CMD_RELOAD |
+                                                   initreq::INIT_CMD_DUMPSTATE | initreq::INIT_CMD_RESTART |
+                                                   initreq::INIT_CMD_ENABLE | initreq::INIT_CMD_DISABLE |
+                                                   initreq::INIT_CMD_DEBUG | initreq::INIT_CMD_REREAD);
+                    if self.control_root_only && peer.uid != 0 {
+                        self.initlog(LogLevel::Syslog, &format!("refused {} request from uid {} (pid {})",
+                            req.cmd_name(), peer.uid, peer.pid));
+                        if answer {
+                            control::control_refuse(fd, initreq::INIT_REFUSED_PERMISSION);
+                        }
+                        continue;
+                    }
+                    if matches!(req.cmd, initreq::INIT_CMD_RUNLVL | initreq::INIT_CMD_WAITLVL |
+                                         initreq::INIT_CMD_SETENV | initreq::INIT_CMD_UNSETENV |
+                                         initreq::INIT_CMD_RELOAD | initreq::INIT_CMD_DUMPSTATE |
+                                         initreq::INIT_CMD_RESTART | initreq::INIT_CMD_ENABLE |
+                                         initreq::INIT_CMD_DISABLE | initreq::INIT_CMD_DEBUG |
+                                         initreq::INIT_CMD_REREAD |
+                                         initreq::INIT_CMD_POWERFAIL |
+                                         initreq::INIT_CMD_POWERFAILNOW | initreq::INIT_CMD_POWEROK) {
+                        self.initlog(LogLevel::Syslog, &format!("{} request from uid {} (pid {})",
+                            req.cmd_name(), peer.uid, peer.pid));
+                    }
+
+                    let refusal = self.refusal(&req);
+                    let done = if req.cmd == initreq::INIT_CMD_DUMPSTATE {
+                        self.dump_state(fd, peer, &req)
+                    } else if req.cmd == initreq::INIT_CMD_DEBUG {
+                        self.debug_request(fd, peer, &req)
+                    } else if req.cmd == initreq::INIT_CMD_REREAD {
+                        self.reread_request(fd, peer)
+                    } else {
+                        self.process_request(&req, &format!("control uid {} pid {}", peer.uid, peer.pid))
+                    };
+                    if !answer {
+                        continue;
+                    }
+                    if !done {
+                        control::control_refuse(fd, refusal.unwrap_or(initreq::INIT_REFUSED_OTHER));
+                    } else if req.cmd == initreq::INIT_CMD_WAITLVL {
+                        control::control_ack(fd, initreq::INIT_ACK_ACCEPTED);
+                        self.level_waiters.push(fd);
+                    } else {
+                        control::control_ack(fd, initreq::INIT_ACK_DONE);
+                    }
+                }
+                control::ControlRead::Garbage(n) => {
+                    initdbg!(LogLevel::Verbose, "bad message of {} bytes from pid {}", n, peer.pid);
+                }
+                control::ControlRead::Nothing => break,
+                control::ControlRead::Closed => {
+                    self.close_control(fd, events);
+                    break;
+                }
+            }
+        }
+    }
+
+    // Tell a client which control socket extensions we answer
+    fn send_features(&self, fd: i32) {
+        let names: Vec<&str> = initreq::EXTENSIONS.iter().map(|&cmd| InitRequest::new(cmd).cmd_name()).collect();
+        let mut reply = InitRequest::new(initreq::INIT_CMD_FEATURES);
+        reply.runlevel = initreq::CONTROL_VERSION;
+        reply.set_data_str(&names.join(" "));
+        if control::control_reply(fd, &reply).is_ok() {
+            control::control_ack(fd, initreq::INIT_ACK_DONE);
+        }
+    }
+
+    // Write what send_state would hand to the next init, without the
+    // re-exec: to the file named in the request, or back over the control
+    // connection in INIT_DATA_SIZE pieces, each with its length in runlevel.
+    fn dump_state(&self, fd: i32, peer: control::Peer, req: &InitRequest) -> bool {
+        let path = req.data_str();
+        if !path.is_empty() {
+            // Init writes the file as root, so only root gets to pick it
+            if peer.uid != 0 || !path.starts_with('/') {
+                self.initlog(LogLevel::Verbose, &format!("dumpstate: refused to write {}", path));
+                return false;
+            }
+            let written = std::fs::OpenOptions::new()
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .mode(0o600)
+                .open(&path)
+                .and_then(|f| send_state(f, self));
+            return match written {
+                Ok(()) => true,
+                Err(e) => {
+                    self.initlog(LogLevel::Verbose, &format!("dumpstate: cannot write {}: {}", path, e));
+                    false
+                }
+            };
+        }
+
+        let mut buf = Vec::new();
+        if send_state(&mut buf, self).is_err() {
+            return false;
+        }
+        for piece in buf.chunks(initreq::INIT_DATA_SIZE) {
+            let mut reply = InitRequest::new(initreq::INIT_CMD_DUMPSTATE);
+            reply.runlevel = piece.len() as i32;
+            reply.data[..piece.len()].copy_from_slice(piece);
+            if let Err(e) = control::control_reply(fd, &reply) {
+                self.initlog(LogLevel::Verbose, &format!("dumpstate: cannot send state to pid {}: {}", peer.pid, e));
+                return false;
+            }
+        }
+        true
+    }
+
+    // Switch debug output on or off, or send the recent debug messages or
+    // the diagnostic dump back in INIT_DATA_SIZE pieces
+    fn debug_request(&self, fd: i32, peer: control::Peer, req: &InitRequest) -> bool {
+        match req.data_str().as_str() {
+            "on" | "off" => {
+                initlog::set_debug(req.data_str() == "on");
+                self.initlog(LogLevel::Verbose, &format!("debug output {}", req.data_str()));
+                true
+            }
+            what @ ("dump" | "diag") => {
+                let text = match what {
+                    "diag" => self.diag(),
+                    _ => initlog::debug_messages().iter().map(|msg| format!("{}\n", msg)).collect(),
+                };
+                let buf = text.into_bytes();
+                for piece in buf.chunks(initreq::INIT_DATA_SIZE) {
+                    let mut reply = InitRequest::new(initreq::INIT_CMD_DEBUG);
+                    reply.runlevel = piece.len() as i32;
+                    reply.data[..piece.len()].copy_from_slice(piece);
+                    if let Err(e) = control::control_reply(fd, &reply) {
+                        self.initlog(LogLevel::Verbose, &format!("debug: cannot send messages to pid {}: {}", peer.pid, e));
+                        return false;
+                    }
+                }
+                true
+            }
+            _ => false,
+        }
+    }
+
+    // Read one request from the FIFO. None if there is nothing to read or
+    // the request is incomplete, the rest is picked up next time.
+    pub fn read_request(&mut self) -> Option<InitRequest> {
+        if self.pipe_fd < 0 {
+            return None;
+        }
+
+        let mut buf = [0u8; initreq::INIT_REQUEST_SIZE];
+        let fd = unsafe { BorrowedFd::borrow_raw(self.pipe_fd) };
+        while self.fifo_buf.room() > 0 {
+            match nix::unistd::read(fd, &mut buf) {
+                Ok(0) | Err(_) => break,
+                Ok(n) => self.fifo_buf.push(&buf[..n]),
+            }
+        }
+
+        let req = self.fifo_buf.next_request();
+
+        if let Some(dropped) = self.fifo_buf.garbage_warning(clock::boottime()) {
+            self.initlog(LogLevel::Syslog, &format!("dropped {} bytes of garbage on {}", dropped, initreq::INIT_FIFO));
+        }
+
+        req
+    }
+
+    // Act on a request. Returns whether it was carried out.
+    pub fn process_request(&mut self, req: &InitRequest, from: &str) -> bool {
+        if !req.is_valid() {
+            initdbg!(LogLevel::Verbose, "got bogus initrequest");
+            return false;
+        }
+
+        let runlevel = char::from_u32(req.runlevel as u32).filter(|c| c.is_ascii_graphic()).map(String::from);
+        self.trace("request", &[
+            ("cmd", trace::Value::Str(req.cmd_name().to_string())),
+            ("runlevel", trace::Value::Str(runlevel.unwrap_or_default())),
+            ("data", trace::Value::Str(req.data_str())),
+        ]);
+
+        match req.cmd {
+            // telinit q, like sysvinit
+            initreq::INIT_CMD_RUNLVL if matches!(char::from_u32(req.runlevel as u32), Some('q' | 'Q')) => {
+                self.reread_inittab();
+                true
+            }
+            initreq::INIT_CMD_RUNLVL | initreq::INIT_CMD_WAITLVL => {
+                if let Some(reason) = self.refusal(req) {
+                    self.initlog(LogLevel::Verbose, &format!("runlevel request from {} refused: {}",
+                        from, initreq::refusal_reason(reason)));
+                    return false;
+                }
+                match req.level() {
+                    Some(level) if level.is_valid() => {
+                        if req.sleeptime > 0 {
+                            self.sleep_time = req.sleeptime as u64;
+                        }
+                        self.change_runlevel(level, from)
+                    }
+                    _ => {
+                        initdbg!(LogLevel::Verbose, "bad runlevel in initrequest");
+                        false
+                    }
+                }
+            }
+            initreq::INIT_CMD_RELOAD => self.reload_child(&req.data_str()),
+            initreq::INIT_CMD_RESTART => self.restart_child(&req.data_str()),
+            initreq::INIT_CMD_ENABLE => self.enable_child(&req.data_str(), true),
+            initreq::INIT_CMD_DISABLE => self.enable_child(&req.data_str(), false),
+            initreq::INIT_CMD_SETENV | initreq::INIT_CMD_UNSETENV => self.set_env(req),
+            initreq::INIT_CMD_POWERFAIL | initreq::INIT_CMD_POWERFAILNOW | initreq::INIT_CMD_POWEROK => {
+                self.power_event(req.cmd);
+                true
+            }
+            initreq::INIT_CMD_CHANGECONS => {
+                let dev = req.data_str();
+                match self.change_console(&dev) {
+                    Ok(()) => true,
+                    Err(e) => {
+                        initdbg!(LogLevel::Verbose, "cannot switch console to {}: {}", dev, e);
+                        false
+                    }
+                }
+            }
+            _ => {
+                initdbg!(LogLevel::Verbose, "unsupported initrequest {}", req.cmd);
+                false
+            }
+        }
+    }
+
+    // Why a runlevel request can't be done, as one of the INIT_REFUSED_
+    // reasons. While runlevel 0 or 6 is being entered the system is going
+    // down, and only another 0 or 6 is taken.
+    fn refusal(&self, req: &InitRequest) -> Option<i32> {
+        if !matches!(req.cmd, initreq::INIT_CMD_RUNLVL | initreq::INIT_CMD_WAITLVL)
+            || (req.cmd == initreq::INIT_CMD_RUNLVL && matches!(char::from_u32(req.runlevel as u32), Some('q' | 'Q'))) {
+            return None;
+        }
+        let Some(level) = req.level().filter(Runlevel::is_valid) else {
+            return Some(initreq::INIT_REFUSED_RUNLEVEL);
+        };
+        if matches!(level, Runlevel::Named(_)) && !policy::is_ondemand(&level) && !self.is_defined_level(&level) {
+            return Some(initreq::INIT_REFUSED_RUNLEVEL);
+        }
+        let going_down = self.entering.is_some() && matches!(self.curlevel, Runlevel::Classic('0' | '6'));
+        if going_down && !matches!(level, Runlevel::Classic('0' | '6')) {
+            return Some(initreq::INIT_REFUSED_BUSY);
+        }
+        None
+    }
+
+    // Ask a running entry to reload its configuration with the signal from
+    // its reload attribute
+    fn reload_child(&self, id: &str) -> bool {
+        let Some(child) = self.find_child_by_id(id) else {
+            self.initlog(LogLevel::Verbose, &format!("reload: no entry \"{}\"", id));
+            return false;
+        };
+        let Some(sig) = child.attrs.reload.as_deref().and_then(inittab::parse_signal) else {
+            self.initlog(LogLevel::Verbose, &format!("reload: \"{}\" has no reload signal", id));
+            return false;
+        };
+        if !child.is_running() {
+            self.initlog(LogLevel::Verbose, &format!("reload: \"{}\" is not running", id));
+            return false;
+        }
+
+        match child.send_signal(sig) {
+            Ok(()) => true,
+            Err(e) => {
+                self.initlog(LogLevel::Verbose, &format!("reload: cannot signal \"{}\": {}", id, e));
+                false
+            }
+        }
+    }
+
+    pub fn find_child_by_id_mut(&mut self, id: &str) -> Option<&mut Child> {
+        let mut current = self.family.as_mut();
+        while let Some(child) = current {
+            if child.id == id {
+                return Some(child);
+            }
+            current = child.next.as_mut();
+        }
+        None
+    }
+
+    // Stop the process of one entry the way a runlevel switch would
+    fn stop_child(&mut self, id: &str) {
+        let Some(child) = self.find_child_by_id_mut(id) else {
+            return;
+        };
+        if !child.is_running() {
+            return;
+        }
+        child.flags.insert(ChildFlags::KILLME);
+        self.kill_marked(false);
+        if let Some(child) = self.find_child_by_id_mut(id) {
+            child.flags.remove(ChildFlags::KILLME);
+        }
+    }
+
+    // Stop an entry and have it started again. It starts over as far as
+    // respawning too fast is concerned, so this also brings back an entry
+    // that was given up on.
+    fn restart_child(&mut self, id: &str) -> bool {
+        let level = self.curlevel.clone();
+        let Some(child) = self.find_child_by_id(id) else {
+            self.initlog(LogLevel::Verbose, &format!("restart: no entry \"{}\"", id));
+            return false;
+        };
+        if child.flags.contains(ChildFlags::DISABLED) {
+            self.initlog(LogLevel::Verbose, &format!("restart: \"{}\" is disabled", id));
+            return false;
+        }
+        if !policy::is_due(&child.rlevel, child.flags, &level) {
+            self.initlog(LogLevel::Verbose, &format!("restart: \"{}\" is not part of runlevel {}", id, level));
+            return false;
+        }
+
+        self.stop_child(id);
+        if let Some(child) = self.find_child_by_id_mut(id) {
+            child.flags.remove(ChildFlags::XECUTED | ChildFlags::FAILING);
+            child.count = 0;
+        }
+        true
+    }
+
+    // Turn an entry on or off until the next boot. Disabling stops its
+    // process, enabling lets start_if_needed have it again.
+    fn enable_child(&mut self, id: &str, enable: bool) -> bool {
+        if self.find_child_by_id(id).is_none() {
+            let what = if enable { "enable" } else { "disable" };
+            self.initlog(LogLevel::Verbose, &format!("{}: no entry \"{}\"", what, id));
+            return false;
+        }
+
+        if !enable {
+            self.stop_child(id);
+        }
+        if let Some(child) = self.find_child_by_id_mut(id) {
+            if enable {
+                child.flags.remove(ChildFlags::DISABLED | ChildFlags::FAILING);
+                child.count = 0;
+            } else {
+                child.flags.insert(ChildFlags::DISABLED);
+            }
+        }
+        true
+    }
+
+    // INIT_CMD_SETENV and INIT_CMD_UNSETENV: data holds NUL separated
+    // NAME=VALUE (set) or NAME (unset) strings, ended by an empty one. The
+    // variables go into the environment of everything started from now on.
+    // Names starting with INIT_ are init's own.
+    fn set_env(&mut self, req: &InitRequest) -> bool {
+        let mut ok = true;
+        for item in req.data_strings() {
+            let name = item.split('=').next().unwrap_or_default().to_string();
+            if name.is_empty() || name.starts_with("INIT_") {
+                self.initlog(LogLevel::Verbose, &format!("setenv: refused to set \"{}\"", name));
+                ok = false;
+                continue;
+            }
+
+            let vars = &mut self.extra_env.vars;
+            for var in vars.iter_mut() {
+                if var.as_deref().is_some_and(|v| v.split('=').next() == Some(name.as_str())) {
+                    *var = None;
+                }
+            }
+            if req.cmd == initreq::INIT_CMD_UNSETENV || !item.contains('=') {
+                continue;
+            }
+            match vars.iter_mut().find(|var| var.is_none()) {
+                Some(slot) => *slot = Some(item),
+                None => {
+                    self.initlog(LogLevel::Verbose, &format!("setenv: no room for \"{}\"", name));
+                    ok = false;
+                }
+            }
+        }
+        ok
+    }
+
+    // Power events, from a UPS daemon or rye-initctl power: the entries for
+    // the event may run once more. Returns whether there are any.
+    fn power_event(&mut self, cmd: i32) -> bool {
+        let mut found = false;
+        let mut current = self.family.as_mut();
+        while let Some(child) = current {
+            let wanted = match cmd {
+                initreq::INIT_CMD_POWEROK => child.action == InitAction::PowerOkWait,
+                initreq::INIT_CMD_POWERFAILNOW => child.action == InitAction::PowerFailNow,
+                _ => matches!(child.action, InitAction::PowerFail | InitAction::PowerWait),
+            };
+            if wanted {
+                child.flags.remove(ChildFlags::XECUTED);
+                found = true;
+            }
+            current = child.next.as_mut();
+        }
+        found
+    }
+
+    // SIGPWR. Without powerfail entries there is nobody to decide what to
+    // do about it, so the power goes off.
+    fn power_signal(&mut self, events: &EventLoop) {
+        let status = std::fs::read(paths::path(POWER_STATUS)).ok().and_then(|s| s.first().copied());
+        let _ = std::fs::remove_file(paths::path(POWER_STATUS));
+        let cmd = match status {
+            Some(b'O') => initreq::INIT_CMD_POWEROK,
+            Some(b'L') => initreq::INIT_CMD_POWERFAILNOW,
+            _ => initreq::INIT_CMD_POWERFAIL,
+        };
+
+        if !self.power_event(cmd) && cmd != initreq::INIT_CMD_POWEROK {
+            self.initlog(LogLevel::Verbose, "power failure and no powerfail entries, powering off");
+            let command = self.config.poweroff_command.clone();
+            self.run_fallback(&command, Reboot::PowerOff, events);
+        }
+    }
+
+    // battery-threshold: a battery running low is a power failure that
+    // can't wait, see battery.rs
+    fn check_battery(&mut self, events: &EventLoop) {
+        let Some(threshold) = self.config.battery_threshold else {
+            return;
+        };
+        let low = battery::discharging().filter(|&percent| percent < threshold);
+        let Some(percent) = low else {
+            self.battery_low = false;
+            return;
+        };
+        if self.battery_low {
+            return;
+        }
+        self.battery_low = true;
+
+        self.initlog(LogLevel::Verbose, &format!("battery at {}%, below {}%", percent, threshold));
+        if !self.power_event(initreq::INIT_CMD_POWERFAILNOW) {
+            self.initlog(LogLevel::Verbose, "battery low and no powerfailnow entries, powering off");
+            let command = self.config.poweroff_command.clone();
+            self.run_fallback(&command, Reboot::PowerOff, events);
+        }
+    }
+
+    // The init program was replaced, by a package upgrade most likely.
+    // Say so, or with on-upgrade re-exec execute the new one once nothing
+    // depends on this init staying around. See upgrade.rs.
+    fn check_upgrade(&mut self) {
+        let now = upgrade::identify(&self.myname);
+        if now.is_none() || now == self.binary {
+            self.upgraded = None;
+            return;
+        }
+        // Still being written?
+        if now != self.upgraded {
+            self.upgraded = now;
+            return;
+        }
+
+        if !self.config.reexec_on_upgrade {
+            self.initlog(LogLevel::Verbose, &format!("{} was upgraded, the old init keeps running", self.myname));
+            self.binary = now;
+            return;
+        }
+        if self.any_waiting() || self.entering.is_some() || matches!(self.curlevel, Runlevel::Classic('0' | '6')) {
+            return;
+        }
+
+        self.initlog(LogLevel::Verbose, &format!("{} was upgraded, executing it", self.myname));
+        let myname = self.myname.clone();
+        self.record_exit(last_exit::Reason::Upgrade, &myname);
+        if let Err(e) = self.re_exec() {
+            self.initlog(LogLevel::Verbose, &format!("cannot re-exec {}: {}", self.myname, e));
+            self.binary = now;
+        }
+    }
+
+    // Whether SIGTERM and SIGINT stop the container we are process 1 of,
+    // see the container setting
+    fn in_container(&self) -> bool {
+        self.config.container.unwrap_or_else(|| last_exit::container().is_some())
+    }
+
+    // docker stop and friends send SIGTERM, and SIGKILL a few seconds
+    // later. Go through runlevel 0, or 6 for SIGINT, so the services stop
+    // cleanly, and end the container once it is entered or when
+    // container-stop-timeout runs out, whichever comes first.
+    fn container_signal(&mut self, how: Reboot) {
+        if self.container_stop.is_some() {
+            return;
+        }
+        let (level, sig) = match how {
+            Reboot::Restart => (Runlevel::Classic('6'), "SIGINT"),
+            _ => (Runlevel::Classic('0'), "SIGTERM"),
+        };
+        self.initlog(LogLevel::Verbose, &format!("{}, stopping the container", sig));
+        if self.curlevel == level && self.entering.is_none() {
+            self.go_down(how);
+            return;
+        }
+
+        self.container_stop = Some(how);
+        let timeout = self.config.container_stop_timeout;
+        self.timers.schedule_at(clock::monotonic_ms() + timeout * 1000, Timeout::ContainerStop);
+        // Stopping the entries of the old runlevel can't take all of it
+        self.sleep_time = self.sleep_time.min(timeout / 2).max(1);
+        if self.curlevel != level {
+            self.change_runlevel(level, sig);
+        }
+    }
+
+    // Ctrl-Alt-Del. The ctrlaltdel entries run again, without any the
+    // system reboots.
+    fn ctrlaltdel(&mut self, events: &EventLoop) {
+        let mut found = false;
+        let mut current = self.family.as_mut();
+        while let Some(child) = current {
+            if child.action == InitAction::CtrlAltDel {
+                child.flags.remove(ChildFlags::XECUTED);
+                found = true;
+            }
+            current = child.next.as_mut();
+        }
+
+        if !found {
+            let command = self.config.ctrlaltdel_command.clone().unwrap_or_else(|| self.config.reboot_command.clone());
+            self.run_fallback(&command, Reboot::Restart, events);
+        }
+    }
+
+    // Start `command` to power off or reboot. If it can't be executed, on a
+    // diskless system without shutdown for instance, init does it itself.
+    fn run_fallback(&mut self, command: &str, how: Reboot, events: &EventLoop) {
+        let mut child = create_poweroff_child(command);
+        self.spawn_child(&mut child, events);
+        if child.is_running() {
+            self.add_child(child);
+            return;
+        }
+        self.initlog(LogLevel::Verbose, &format!("cannot run \"{}\", going down without it", command));
+        self.go_down(how);
+    }
+
+    // Turn on process accounting, see process-accounting in config.rs.
+    // That waits for the first runlevel after the boot, when the boot
+    // entries have mounted the filesystem the file is on. acct() wants a
+    // file that exists already.
+    fn start_accounting(&mut self) {
+        let Some(file) = self.config.process_accounting.as_deref().map(paths::path) else {
+            return;
+        };
+        if self.accounting {
+            return;
+        }
+
+        let path = std::path::Path::new(&file);
+        let created = path.parent().map_or(Ok(()), std::fs::create_dir_all).and_then(|()| {
+            std::fs::OpenOptions::new().append(true).create(true).mode(0o600).open(path).map(drop)
+        });
+        match created.and_then(|()| self.sys.acct(Some(&file))) {
+            Ok(()) => {
+                self.accounting = true;
+                initdbg!(LogLevel::Syslog, "process accounting to {}", file);
+            }
+            Err(e) => self.initlog(LogLevel::Verbose, &format!("cannot start process accounting to {}: {}", file, e)),
+        }
+    }
+
+    // Turn process accounting off before runlevel 0 or 6 unmounts the
+    // filesystem its file is on. An init executed since it was turned on
+    // doesn't know, so this doesn't go by self.accounting.
+    fn stop_accounting(&mut self) {
+        if self.config.process_accounting.is_none() {
+            return;
+        }
+        self.accounting = false;
+        if let Err(e) = self.sys.acct(None) {
+            self.initlog(LogLevel::Verbose, &format!("cannot stop process accounting: {}", e));
+        }
+    }
+
+    // Stop every process, flush the disks and have the kernel reboot or
+    // power off
+    fn go_down(&mut self, how: Reboot) {
+        self.stop_accounting();
+        self.signal_everybody(Signal::SIGTERM);
+        do_msleep(self.sleep_time * 1000);
+        self.signal_everybody(Signal::SIGKILL);
+        self.sync_disks();
+        // In a PID namespace the kernel ends us instead of the machine. The
+        // sandbox may run in a container without being its process 1.
+        if let Some(manager) = last_exit::container().filter(|_| !self.sandbox) {
+            self.record_exit(last_exit::Reason::ContainerShutdown, &format!("{:?} in a {} container", how, manager));
+        }
+        if let Err(e) = self.sys.reboot(how) {
+            self.initlog(LogLevel::Verbose, &format!("reboot system call failed: {}", e));
+        }
+        if self.sandbox {
+            self.initlog(LogLevel::Verbose, &format!("sandbox: {:?} instead of going down, exiting; system calls: {}",
+                how, sandbox::recorder().calls().join(", ")));
+            std::process::exit(0);
+        }
+    }
+
+    // Send sig to every process but us. In the sandbox everybody is what
+    // descends from us, the rest of the machine isn't ours to stop.
+    fn signal_everybody(&self, sig: Signal) {
+        if !self.sandbox {
+            let _ = signal::kill(nix::unistd::Pid::from_raw(-1), sig);
+            return;
+        }
+        let me = nix::unistd::getpid().as_raw();
+        for pid in procscan::descendants(&procscan::scan(), me) {
+            let _ = signal::kill(nix::unistd::Pid::from_raw(pid), sig);
+        }
+    }
+
+    // Flush the filesystems, within sync-timeout. A disk that takes longer
+    // is probably dying, the reboot goes on without waiting for it.
+    fn sync_disks(&self) {
+        let timeout = self.config.sync_timeout.map(std::time::Duration::from_secs);
+        let started = clock::monotonic_ms();
+        match self.sys.sync(timeout) {
+            Ok(true) => initdbg!(LogLevel::Syslog, "sync took {} ms", clock::monotonic_ms() - started),
+            Ok(false) => self.initlog(LogLevel::Verbose, &format!("sync not done after {} seconds, going on without it",
+                self.config.sync_timeout.unwrap_or(0))),
+            Err(e) => {
+                self.initlog(LogLevel::Verbose, &format!("cannot sync in the background: {}", e));
+                nix::unistd::sync();
+            }
+        }
+    }
+
+    fn any_killme_running(&self) -> bool {
+        self.children().any(|child| child.flags.contains(ChildFlags::KILLME) && child.is_running())
+    }
+
+    // Ask the entries marked KILLME to stop, each with its stop signal, or
+    // kill them
+    fn signal_killme(&self, kill: bool) -> bool {
+        let mut sent = false;
+        let mut current = self.family.as_ref();
+        while let Some(child) = current {
+            if child.flags.contains(ChildFlags::KILLME) && child.pid > 0 {
+                let sig = if kill { Signal::SIGKILL } else { child.stop_signal() };
+                if child.send_stop_signal(sig).is_ok() {
+                    sent = true;
+                }
+            }
+            current = child.next.as_ref();
+        }
+        sent
+    }
+
+    // A request to go to runlevel 0 or 6
+    fn is_shutdown_request(req: &InitRequest) -> bool {
+        req.is_valid()
+            && (req.cmd == initreq::INIT_CMD_RUNLVL || req.cmd == initreq::INIT_CMD_WAITLVL)
+            && (req.runlevel == '0' as i32 || req.runlevel == '6' as i32)
+    }
+
+    // Send all entries marked KILLME their stop signal, SIGTERM unless they
+    // have a stopsig attribute, wait up to sleep_time seconds for them to go
+    // away and SIGKILL whatever is left.
+    //
+    // When shutting down, a second request for 0 or 6 during the grace
+    // period cuts it short. Other requests are kept for later.
+    pub fn kill_marked(&mut self, shutdown: bool) {
+        if !self.signal_killme(false) {
+            return;
+        }
+
+        let mut waited = 0;
+        while waited < self.sleep_time * 1000 && self.any_killme_running() {
+            do_msleep(MINI_SLEEP);
+            self.reap_more = self.reap_children(REAP_BATCH);
+
+            if shutdown {
+                let mut hurry = false;
+                while let Some(req) = self.read_request() {
+                    if Self::is_shutdown_request(&req) {
+                        hurry = true;
+                    } else {
+                        self.deferred.push(req);
+                    }
+                }
+                if hurry {
+                    self.initlog(LogLevel::Verbose, "shutdown requested again, killing remaining processes now");
+                    break;
+                }
+            }
+            waited += MINI_SLEEP;
+        }
+
+        self.signal_killme(true);
+    }
+
+    // Early boot: make sure the clock isn't years behind
+    pub fn sanitize_clock(&mut self) {
+        match clock::check_clock() {
+            Ok(Some((old, new))) => {
+                self.initlog(LogLevel::Verbose, &format!("clock was at {}, stepped forward to {}", old, new));
+            }
+            Ok(None) => {}
+            Err(e) => {
+                self.initlog(LogLevel::Verbose, &format!("clock is behind but cannot be set: {}", e));
+            }
+        }
+    }
+
+    // Whether any entry of the inittab mentions the runlevel
+    fn is_defined_level(&self, level: &Runlevel) -> bool {
+        let mut current = self.family.as_ref();
+        while let Some(child) = current {
+            if child.should_run_at_level(level) {
+                return true;
+            }
+            current = child.next.as_ref();
+        }
+        false
+    }
+
+    // Switch to another runlevel: stop what doesn't belong there. Starting
+    // the entries of the new runlevel is left to start_if_needed. Returns
+    // false if there is no such runlevel. by says who asked for it, for the
+    // audit trail.
+    pub fn change_runlevel(&mut self, level: Runlevel, by: &str) -> bool {
+        self.trace("level", &[("runlevel", trace::Value::Str(level.to_string()))]);
+
+        // The ondemand levels only start their entries
+        if policy::is_ondemand(&level) {
+            let demands = policy::demands(&self.planned(), &level);
+            let mut current = self.family.as_mut();
+            let mut n = 0;
+            while let Some(child) = current {
+                if demands.contains(&n) {
+                    child.flags.insert(ChildFlags::DEMAND);
+                }
+                n += 1;
+                current = child.next.as_mut();
+            }
+            return true;
+        }
+
+        if matches!(level, Runlevel::Named(_)) && !self.is_defined_level(&level) {
+            self.initlog(LogLevel::Verbose, &format!("no such runlevel: {}", level));
+            return false;
+        }
+
+        self.log_event(LogEvent::RunlevelSwitch { level: &level });
+        self.log_transition(transitions::Record::Switch { from: &self.curlevel, to: &level, by });
+        self.entering = Some(std::time::Instant::now());
+        // Whoever switched first decided where the boot goes
+        self.did_boot = true;
+        hooks::run_hooks(hooks::PRE_LEVEL_CHANGE, &level, &self.curlevel, self);
+
+        let stops = policy::stops(&self.planned(), &level);
+        let mut stopped = Vec::new();
+        let mut current = self.family.as_mut();
+        let mut n = 0;
+        while let Some(child) = current {
+            if stops.contains(&n) {
+                child.flags.insert(ChildFlags::KILLME);
+                stopped.push(child.id.clone());
+            }
+            n += 1;
+            current = child.next.as_mut();
+        }
+
+        let shutdown = matches!(level, Runlevel::Classic('0' | '6'));
+        if shutdown {
+            self.stop_accounting();
+        }
+        self.kill_marked(shutdown);
+        if self.config.hangup_ttys {
+            self.hangup_terminals(&stopped);
+        }
+
+        // A delay that hasn't run out starts over in the new runlevel
+        let mut current = self.family.as_mut();
+        while let Some(child) = current {
+            child.flags.remove(ChildFlags::KILLME);
+            if child.timer != 0 {
+                self.timers.cancel(child.timer);
+            }
+            child.due = 0;
+            child.timer = 0;
+            current = child.next.as_mut();
+        }
+
+        self.prevlevel = std::mem::replace(&mut self.curlevel, level);
+        self.record_runlevel();
+        self.set_title();
+        self.save_checkpoint();
+        true
+    }
+
+    // Hang up the terminals of stopped gettys and whatever ran on them, so
+    // a process left over from a login session that still holds one open
+    // can't read what is typed at the next login. The console stays.
+    fn hangup_terminals(&self, ids: &[EntryId]) {
+        for id in ids {
+            let Some(child) = self.find_child_by_id(id) else {
+                continue;
+            };
+            if !matches!(child.action, InitAction::Respawn | InitAction::OnDemand) {
+                continue;
+            }
+            for dev in self.terminals(child) {
+                if dev == self.console_name() || dev == console::CONSOLE {
+                    continue;
+                }
+                if let Err(e) = tty::hangup(&dev) {
+                    initdbg!(LogLevel::Syslog, "cannot hang up {}: {}", dev, e);
+                }
+            }
+        }
+    }
+
+    // The getty or login of an entry died, maybe in the middle of a full
+    // screen program that left its terminal raw and without echo. Put the
+    // terminal back into a sane state before the next one starts on it.
+    fn reset_terminals(&self, id: &str) {
+        let Some(child) = self.find_child_by_id(id) else {
+            return;
+        };
+        if !matches!(child.action, InitAction::Respawn | InitAction::OnDemand)
+            || (child.attrs.tty.is_none() && devwait::terminal_args(&child.process).is_empty()) {
+            return;
+        }
+        for dev in self.terminals(child) {
+            match tty::open_nonblocking(&dev) {
+                Ok(f) => {
+                    if let Err(e) = tty::sane(&f) {
+                        initdbg!(LogLevel::Syslog, "cannot reset {}: {}", dev, e);
+                    }
+                }
+                Err(e) => initdbg!(LogLevel::Syslog, "cannot open {}: {}", dev, e),
+            }
+        }
+    }
+
+    // "init [3]" in ps, like sysvinit
+    fn set_title(&self) {
+        if let Err(e) = self.sys.set_title(&format!("init [{}]", self.curlevel)) {
+            initdbg!(LogLevel::Verbose, "cannot set the process title: {}", e);
+        }
+    }
+
+    // Tell utmp and the runlevel file about the new runlevel
+    fn record_runlevel(&self) {
+        // utmp only has room for classic runlevels: current + 256 * previous
+        #[cfg(feature = "utmp")]
+        if let Some(cur) = self.curlevel.as_char() {
+            let prev = self.prevlevel.as_char().unwrap_or('N');
+            let pid = cur as i32 + 256 * prev as i32;
+            self.utmp.write_utmp_wtmp("runlevel", "~~", pid, utmp::RUN_LVL as i32, "~");
+        }
+
+        if self.run_read_only {
+            return;
+        }
+        let file = paths::path(runlevel::RUNLEVEL_FILE);
+        if let Err(e) = runlevel::write_file(&file, &self.prevlevel, &self.curlevel) {
+            initdbg!(LogLevel::Verbose, "cannot write {}: {}", file, e);
+        }
+    }
+
+    pub fn remove_child_by_pid(&mut self, pid: i32) -> Option<Child> {
+        let mut current = &mut self.family;
+        while let Some(child) = current {
+            if child.pid == pid {
+                let mut removed = current.take().unwrap();
+                *current = removed.next.take();
+                return Some(*removed);
+            }
+            current = &mut current.as_mut().unwrap().next;
+        }
+        None
+    }
+}
+
+impl InitLogger for InitState {
+    fn initlog(&self, level: LogLevel, msg: &str) {
+        initlog::initlog(self.console_name(), level, msg);
+    }
+}
+
+// FreeBSD specific code
+#[cfg(target_os = "freebsd")]
+mod freebsd_compat {
+    const UTMP_FILE: &str = "/var/run/utmp";
+    const RUN_LVL: i32 = 1;
+
+    #[repr(C)]
+    pub struct Utmp {
+        pub ut_id: [u8; 4],
+    }
+}
+
+// TODO: Implement prototypes
+pub trait InitLogger {
+    fn initlog(&self, level: LogLevel, msg: &str);
+}
+
+pub trait UtmpWriter {
+    fn write_utmp_wtmp(&self, user: &str, id: &str, pid: i32, entry_type: i32, line: &str);
+    fn write_wtmp(&self, user: &str, id: &str, pid: i32, entry_type: i32, line: &str);
+}
+
+pub trait TerminalController {
+    fn set_term(&self, how: i32);
+    fn print(&self, msg: &str);
+}
+
+pub trait WallMessenger {
+    fn wall(&self, text: &str, remote: bool);
+}
+
+// Signal handler helpers
+pub fn set_got_cont() {
+    GOT_CONT.store(true, Ordering::Relaxed);
+}
+
+pub fn got_cont() -> bool {
+    GOT_CONT.load(Ordering::Relaxed)
+}
+
+pub fn clear_got_cont() {
+    GOT_CONT.store(false, Ordering::Relaxed);
+}
+
+// SIGPWR comes from a UPS daemon or a container manager, SIGINT from the
+// kernel on Ctrl-Alt-Del, SIGTERM from a container manager, SIGQUIT from
+// somebody who wants the diagnostic dump. SIGCHLD is for the children
+// without a pidfd: orphans, hooks, the rescue shell and everything on
+// kernels without pidfds.
+extern "C" fn handle_signal(sig: libc::c_int) {
+    match sig {
+        libc::SIGCHLD => GOT_SIGNALS.store(true, Ordering::Relaxed),
+        libc::SIGPWR => GOT_PWR.store(true, Ordering::Relaxed),
+        libc::SIGINT => GOT_CAD.store(true, Ordering::Relaxed),
+        libc::SIGTERM => GOT_TERM.store(true, Ordering::Relaxed),
+        libc::SIGQUIT => GOT_DIAG.store(true, Ordering::Relaxed),
+        s if s == libc::SIGRTMIN() + DEBUG_SIGNAL => GOT_DEBUG.store(true, Ordering::Relaxed),
+        _ => {}
+    }
+}
+
+pub fn install_signal_handlers(sys: &dyn Sys) {
+    let action = SigAction::new(SigHandler::Handler(handle_signal), SaFlags::SA_RESTART, SigSet::empty());
+    for sig in [Signal::SIGPWR, Signal::SIGINT, Signal::SIGTERM, Signal::SIGQUIT] {
+        let _ = unsafe { signal::sigaction(sig, &action) };
+    }
+    // Stopped children are none of our business
+    let chld = SigAction::new(SigHandler::Handler(handle_signal), SaFlags::SA_RESTART | SaFlags::SA_NOCLDSTOP,
+        SigSet::empty());
+    let _ = unsafe { signal::sigaction(Signal::SIGCHLD, &chld) };
+    // Real-time signals have no nix::Signal
+    unsafe { libc::signal(libc::SIGRTMIN() + DEBUG_SIGNAL, handle_signal as *const () as libc::sighandler_t) };
+    // Have Ctrl-Alt-Del sent to us instead of rebooting on the spot
+    let _ = sys.reboot(Reboot::DisableCad);
+}
+
+pub fn set_got_signals() {
+    GOT_SIGNALS.store(true, Ordering::Relaxed);
+}
+
+pub fn got_signals() -> bool {
+    GOT_SIGNALS.load(Ordering::Relaxed)
+}
+
+pub fn clear_got_signals() {
+    GOT_SIGNALS.store(false, Ordering::Relaxed);
+}
+
+// The kernel command line split into words. Double quotes keep blanks in
+// a value, rye_init.emergency="/bin/busybox sh" for instance.
+fn cmdline_words(cmdline: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut quoted = false;
+    for c in cmdline.chars() {
+        match c {
+            '"' => quoted = !quoted,
+            c if c.is_whitespace() && !quoted => {
+                if !word.is_empty() {
+                    words.push(std::mem::take(&mut word));
+                }
+            }
+            c => word.push(c),
+        }
+    }
+    if !word.is_empty() {
+        words.push(word);
+    }
+    words
+}
+
+// A shell on the console for when there is nothing else to run. The first
+// of `shells` that can be executed is started.
+pub fn create_emergency_shell(shells: &[String]) -> Child {
+    let (process, fallback) = shells.split_first().map_or(("/bin/sh", &[][..]), |(first, rest)| (first.as_str(), rest));
+    Child::builder(special_id())
+        .flags(ChildFlags::WAITING)
+        .rlevel(RunlevelSet::parse("S"))
+        .process(process)
+        .fallback(fallback)
+        .build()
+}
+
+
+// The entry that powers off or reboots when the inittab has none for it
+pub fn create_poweroff_child(process: &str) -> Child {
+    Child::builder(special_id())
+        .rlevel(RunlevelSet::parse("S"))
+        .process(process)
+        .build()
+}
+
+// The id of the entries init makes up itself
+fn special_id() -> EntryId {
+    EntryId::new(SPECIAL_ID).expect("SPECIAL_ID fits")
+}
+
+pub fn is_power_action(action: InitAction) -> bool {
+    matches!(action,
+        InitAction::PowerWait |
+        InitAction::PowerFail |
+        InitAction::PowerOkWait |
+        InitAction::PowerFailNow |
+        InitAction::CtrlAltDel
+    )
+}
+
+// Sleep function - sleeps for specified milliseconds
+pub fn do_msleep(msec: u64) {
+    std::thread::sleep(std::time::Duration::from_millis(msec));
+}
+
+// Non-failing memory allocation
+pub fn imalloc(size: usize) -> Vec<u8> {
+    loop {
+        match std::panic::catch_unwind(|| vec![0u8; size]) {
+            Ok(vec) => return vec,
+            Err(_) => {
+                eprintln!("out of memory");
+                do_msleep(SHORT_SLEEP);
+            }
+        }
+    }
+}
+
+// String duplication
+pub fn istrdup(s: &str) -> String {
+    loop {
+        match std::panic::catch_unwind(|| s.to_string()) {
+            Ok(string) => return string,
+            Err(_) => {
+                eprintln!("out of memory");
+                do_msleep(SHORT_SLEEP);
+            }
+        }
+    }
+}
+
+// Send state information to a file descriptor
+pub fn send_state<W: std::io::Write>(writer: W, state: &InitState) -> std::io::Result<()> {
+    let mut out = state::State {
+        runlevel: state.curlevel.clone(),
+        thislevel: state.curlevel.clone(), // thislevel same as curlevel in our implementation
+        prevlevel: state.prevlevel.clone(),
+        got_signals: got_signals(),
+        wrote_wtmp_reboot: state.wrote_wtmp_reboot,
+        wrote_utmp_reboot: state.wrote_utmp_reboot,
+        wrote_wtmp_rlevel: state.wrote_wtmp_rlevel,
+        wrote_utmp_rlevel: state.wrote_utmp_rlevel,
+        sleep_time: state.sleep_time,
+        did_boot: state.did_boot,
+        records: Vec::new(),
+        skipped: Vec::new(),
+    };
+
+    let mut current = state.family.as_ref();
+    while let Some(child) = current {
+        out.records.push(state::Record {
+            id: child.id.to_string(),
+            rlevel: child.rlevel.to_string(),
+            flags: child.flags,
+            pid: child.pid,
+            exstat: child.exstat,
+            action: child.action,
+            process: child.process.clone(),
+            attrs: child.attrs.clone(),
+            delay: child.delay,
+            due: child.due,
+            tm: child.tm,
+            count: child.count,
+            respawns: child.respawns,
+        });
+        current = child.next.as_ref();
+    }
+
+    state::serialize(writer, &out)
+}
+
+// Read the state of our predecessor back in
+pub fn receive_state<R: std::io::BufRead>(reader: &mut R, state: &mut InitState) {
+    let received = match state::deserialize(reader) {
+        Ok(received) => received,
+        Err(_) => {
+            state.oops_error = -1;
+            return;
+        }
+    };
+
+    state.curlevel = received.thislevel;
+    state.prevlevel = received.prevlevel;
+    state.wrote_wtmp_reboot = received.wrote_wtmp_reboot;
+    state.wrote_utmp_reboot = received.wrote_utmp_reboot;
+    state.wrote_wtmp_rlevel = received.wrote_wtmp_rlevel;
+    state.wrote_utmp_rlevel = received.wrote_utmp_rlevel;
+    state.sleep_time = received.sleep_time;
+    state.did_boot = received.did_boot;
+    if received.got_signals {
+        set_got_signals();
+    }
+    if !received.skipped.is_empty() {
+        state.initlog(LogLevel::Verbose, &format!("state from the previous init: skipped unknown {}",
+            received.skipped.join(", ")));
+    }
+
+    let mut children = Vec::new();
+    for rec in received.records {
+        let Some(id) = EntryId::new(&rec.id) else {
+            state.initlog(LogLevel::Verbose, &format!("state from the previous init: bad id \"{}\"", rec.id));
+            continue;
+        };
+        let mut child = Child::new(id);
+        child.rlevel = RunlevelSet::parse(&rec.rlevel);
+        child.flags = rec.flags;
+        child.pid = rec.pid;
+        child.exstat = rec.exstat;
+        child.action = rec.action;
+        child.process = rec.process;
+        child.attrs = rec.attrs;
+        child.delay = rec.delay;
+        child.due = rec.due;
+        if rec.tm > 0 {
+            child.tm = rec.tm;
+            child.count = rec.count;
+        }
+        child.respawns = rec.respawns;
+
+        // pidfds do not survive the re-exec, open them again
+        child.attach_pidfd();
+        child.attach_group();
+        children.push(child);
+    }
+    state.set_family(children);
+}
+
+// Seconds to wait before starting an entry again that keeps exiting. Its
+// first respawn in a TESTTIME window is right away, the ones after that
+// wait 1, 2, 4, ... up to MAX_BACKOFF seconds. The MAXSPAWN limit still
+// applies. (count already includes the first start.) `now` is in seconds
+// of CLOCK_BOOTTIME.
+fn respawn_backoff(child: &Child, now: u64) -> u64 {
+    if !matches!(child.action, InitAction::Respawn | InitAction::OnDemand)
+        || child.count <= 1
+        || child.tm + TESTTIME <= now
+    {
+        return 0;
+    }
+    (1u64 << (child.count - 2).min(8)).min(MAX_BACKOFF)
+}
+
+// How long init waits for an entry: its timeout attribute, or else the
+// timeout of the config file for its action
+fn wait_timeout(config: &config::Config, child: &Child) -> Option<u64> {
+    child.attrs.timeout.as_deref().and_then(inittab::parse_timeout).or_else(|| config.boot_timeout(child.action))
+}
+
+// Get going as process 1, or pick up where the previous init left off
+fn boot(state: &mut InitState, events: &EventLoop) {
+    let cmdline = std::fs::read_to_string("/proc/cmdline").unwrap_or_default();
+    state.parse_cmdline(&cmdline);
+    state.boot_env = bootenv::scrub(&bootenv::read_allowlist(), &cmdline);
+    #[cfg(feature = "utmp")]
+    {
+        state.utmp.wtmp_format = utmp::read_wtmp_format(&paths::path(utmp::WTMP_CONF));
+    }
+    let (config, errors) = config::Config::load();
+    state.config = config;
+    initlog::set_rate_limit(state.config.console_limit);
+    for e in errors {
+        state.initlog(LogLevel::Verbose, &e);
+    }
+    state.protect_self();
+
+    if state.receive_from_pipe() || state.receive_checkpoint() {
+        state.reload = true;
+        let mut current = state.family.as_ref();
+        while let Some(child) = current {
+            state.watch_child(child, events);
+            current = child.next.as_ref();
+        }
+        state.restore_timers();
+        state.reconcile();
+    } else {
+        state.sanitize_clock();
+        state.record_boot_id();
+        #[cfg(feature = "utmp")]
+        if let Err(e) = state.utmp.boot_cleanup() {
+            initdbg!(LogLevel::Verbose, "cannot clean utmp: {}", e);
+        }
+        state.read_inittab();
+        state.booting = state.config.boot_deadline.is_some();
+    }
+    state.trace_start();
+    state.set_title();
+
+    state.open_fifo(events);
+    state.open_control(events);
+    state.open_timers(events);
+    if state.config.early_syslog {
+        state.open_early_log(events);
+    }
+    state.timers.schedule_at(clock::monotonic_ms() + RECONCILE_INTERVAL, Timeout::Reconcile);
+    state.timers.schedule_at(clock::monotonic_ms() + checkpoint::CHECKPOINT_INTERVAL, Timeout::Checkpoint);
+    state.binary = upgrade::identify(&state.myname);
+    state.timers.schedule_at(clock::monotonic_ms() + upgrade::CHECK_INTERVAL, Timeout::Upgrade);
+    if state.config.battery_threshold.is_some() {
+        state.timers.schedule_at(clock::monotonic_ms() + battery::CHECK_INTERVAL, Timeout::Battery);
+    }
+    if let Some(secs) = state.config.boot_deadline.filter(|_| state.booting) {
+        state.timers.schedule_at(clock::monotonic_ms() + secs * 1000, Timeout::BootDeadline);
+    }
+}
+
+fn main() {
+    let sandbox = sandbox::requested();
+    if nix::unistd::getpid().as_raw() != INITPID && !sandbox {
+        let args: Vec<String> = std::env::args().skip(1).collect();
+        let name = std::env::args().next().unwrap_or_default();
+        match name.rsplit('/').next() {
+            Some("runlevel") => std::process::exit(runlevel_cmd::runlevel(&args)),
+            Some("rye-initctl") => std::process::exit(initctl::initctl(&args)),
+            #[cfg(feature = "utmp")]
+            Some("last") => std::process::exit(last::last(&args)),
+            #[cfg(feature = "wall")]
+            Some("wall") => std::process::exit(wall::wall(&args)),
+            #[cfg(feature = "shutdown")]
+            Some("shutdown") => std::process::exit(shutdown_cmd::shutdown(&args)),
+            _ => {}
+        }
+        if args.first().map(String::as_str) == Some("--version") {
+            std::process::exit(version::version(&args[1..]));
+        }
+        if args.first().map(String::as_str) == Some("--replay") {
+            std::process::exit(trace::replay(&args[1..]));
+        }
+
+        // Like sysvinit, anything else but process 1 acts as telinit
+        if args.is_empty() {
+            println!("Copyright 2025 PalindromicBreadLoaf");
+            return;
+        }
+        std::process::exit(telinit::telinit(&args));
+    }
+
+    panic::install_hook();
+    if !sandbox {
+        security::load_policy();
+    }
+
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if let Some(root) = args.iter().skip_while(|a| *a != "--root").nth(1) {
+        paths::set_root(root);
+    }
+
+    let mut state = InitState::new();
+    if sandbox {
+        if let Err(e) = sandbox::become_reaper() {
+            eprintln!("rye-init: {}: {}", sandbox::SANDBOX_OPTION, e);
+            std::process::exit(1);
+        }
+        state.sandbox = true;
+        state.sys = sandbox::recorder();
+        state.console_dev = Some(sandbox::console());
+    }
+    state.parse_args(&args);
+    state.args = args;
+    let events = loop {
+        match EventLoop::new() {
+            Ok(events) => break events,
+            Err(e) => {
+                state.initlog(LogLevel::Verbose, &format!("cannot create event loop: {}", e));
+                do_msleep(SHORT_SLEEP);
+            }
+        }
+    };
+
+    install_signal_handlers(state.sys);
+    boot(&mut state, &events);
+
+    // A panic must not take process 1 down with it
+    loop {
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| loop {
+            state.start_if_needed(&events);
+            state.handle_events(&events, SHORT_SLEEP as i32);
+        }));
+
+        if result.is_err() {
+            state.recover(&events);
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use std::os::fd::AsRawFd;
+    use std::os::unix::process::CommandExt;
+
+    // However the table is kept, it hands the entries back in the order
+    // they were given in
+    #[test]
+    fn family_keeps_its_order() {
+        let ids = ["z9", "l3", "b2", "a7", "m5"];
+        let mut state = InitState::new();
+        state.set_family(ids.iter().map(|id| Child::new(EntryId::new(id).unwrap())).collect());
+        assert_eq!(state.children().map(|child| child.id.as_str()).collect::<Vec<_>>(), ids);
+    }
+
+    fn respawning(tm: u64) -> Child {
+        let mut child = Child::new(EntryId::new("r1").unwrap());
+        child.action = InitAction::Respawn;
+        child.rlevel = RunlevelSet::parse("2345");
+        child.process = "/sbin/getty tty1".to_string();
+        child.tm = tm;
+        child
+    }
+
+    // The window is on CLOCK_BOOTTIME: the wall clock may be stepped either
+    // way, by hand or by NTP, without the window seeing it
+    #[test]
+    fn respawn_window_ignores_the_wall_clock() {
+        let child = Child::new(EntryId::new("r1").unwrap());
+        assert!(child.tm.abs_diff(clock::boottime()) <= 1);
+        assert!(child.tm.abs_diff(clock::now()) > TESTTIME);
+
+        let mut child = respawning(child.tm);
+        let now = child.tm;
+        let too_fast: Vec<bool> = (0..MAXSPAWN as u64).map(|n| child.count_start(now + n)).collect();
+        assert_eq!(too_fast.iter().position(|&f| f), Some(MAXSPAWN as usize - 1));
+    }
+
+    // Time spent suspended counts on CLOCK_BOOTTIME: an entry that kept
+    // exiting right before a night in suspend starts over afterwards
+    #[test]
+    fn suspend_closes_the_respawn_window() {
+        let now = 100_000;
+        let mut child = respawning(now);
+        for n in 0..5 {
+            assert!(!child.count_start(now + n));
+        }
+        assert_eq!(child.count, 5);
+        assert_eq!(respawn_backoff(&child, now + 5), MAX_BACKOFF);
+
+        let resumed = now + 8 * 3600;
+        assert_eq!(respawn_backoff(&child, resumed), 0);
+        assert!(!child.count_start(resumed));
+        assert_eq!((child.count, child.tm), (0, resumed));
+    }
+
+    // CLOCK_BOOTTIME goes on counting across an exec, so the window is
+    // handed to the new init as it is: a storm that began before a
+    // re-exec is still caught after it
+    #[test]
+    fn respawn_window_survives_re_exec() {
+        let now = clock::boottime();
+        let mut child = respawning(now - 30);
+        for n in 0..MAXSPAWN as u64 - 1 {
+            assert!(!child.count_start(now - 30 + n));
+        }
+        let mut old = InitState::new();
+        old.set_family(vec![child]);
+        let mut stream = Vec::new();
+        send_state(&mut stream, &old).unwrap();
+
+        let mut new = InitState::new();
+        receive_state(&mut stream.as_slice(), &mut new);
+        let child = new.find_child_by_id_mut("r1").unwrap();
+        assert_eq!((child.tm, child.count), (now - 30, MAXSPAWN - 1));
+        assert!(child.count_start(now));
+    }
+
+    // A shell that leaves two helpers behind. They hold on to the write end
+    // of the pipe, so it only reads EOF once they are gone as well.
+    #[test]
+    fn stop_reaches_grandchildren() {
+        let (rd, wr) = nix::unistd::pipe().unwrap();
+        let mut shell = std::process::Command::new("/bin/sh")
+            .args(["-c", "sleep 100 & sleep 100 & echo up; wait"])
+            .stdout(wr)
+            .process_group(0)
+            .spawn()
+            .unwrap();
+        let mut rd = std::fs::File::from(rd);
+        let mut up = [0u8; 3];
+        rd.read_exact(&mut up).unwrap();
+        assert_eq!(&up, b"up\n");
+
+        let mut child = Child::new(EntryId::new("t1").unwrap());
+        child.pid = shell.id() as i32;
+        child.attach_group();
+        assert_eq!(child.pgid, child.pid);
+        child.send_stop_signal(Signal::SIGTERM).unwrap();
+        shell.wait().unwrap();
+
+        let mut pfd = libc::pollfd { fd: rd.as_raw_fd(), events: libc::POLLIN, revents: 0 };
+        let ready = unsafe { libc::poll(&mut pfd, 1, 5000) };
+        assert_eq!(ready, 1, "the helpers outlived the stop");
+        assert_eq!(rd.read(&mut up).unwrap(), 0);
+    }
+}
@@ -0,0 +1,122 @@
+// Init health in the Prometheus text format
+//
+// With --metrics init keeps METRICS_FILE up to date for a node exporter's
+// textfile collector: per entry how often it was respawned, whether it is
+// disabled for respawning too fast and how its process last exited, and
+// how long entering each runlevel took. The file is only rewritten when
+// its content changes, through a temporary file and a rename so a reader
+// never sees half of it.
+
+use std::fmt::Write as _;
+
+use rye_init::runlevel::Runlevel;
+use rye_init::state::ChildFlags;
+
+use crate::Child;
+
+pub const METRICS_FILE: &str = "/run/rye-init/metrics";
+
+#[derive(Debug, Default)]
+pub struct Metrics {
+    transitions: Vec<(Runlevel, u128)>, // Last time taken to enter each runlevel, in ms
+    written: String,                    // What METRICS_FILE has now
+}
+
+// Quote a label value
+fn label(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+// The exit code of a wait status, 128 + the signal for a killed process
+// like a shell shows it
+fn exit_code(status: i32) -> i32 {
+    if libc::WIFSIGNALED(status) {
+        128 + libc::WTERMSIG(status)
+    } else {
+        libc::WEXITSTATUS(status)
+    }
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Metrics::default()
+    }
+
+    // A runlevel switch has completed
+    pub fn transition(&mut self, level: &Runlevel, millis: u128) {
+        match self.transitions.iter_mut().find(|(l, _)| l == level) {
+            Some(t) => t.1 = millis,
+            None => self.transitions.push((level.clone(), millis)),
+        }
+    }
+
+    pub fn render(&self, family: Option<&Child>, curlevel: &Runlevel) -> String {
+        let mut entries = Vec::new();
+        let mut current = family;
+        while let Some(child) = current {
+            entries.push(child);
+            current = child.next.as_deref();
+        }
+
+        let mut out = String::new();
+        header(&mut out, "rye_init_entry_respawns_total", "counter",
+            "Times an inittab entry was started again after its process exited.");
+        for child in &entries {
+            let _ = writeln!(out, "rye_init_entry_respawns_total{{id=\"{}\"}} {}", label(&child.id), child.respawns);
+        }
+
+        header(&mut out, "rye_init_entry_failing", "gauge",
+            "1 if an inittab entry is disabled for respawning too fast.");
+        for child in &entries {
+            let _ = writeln!(out, "rye_init_entry_failing{{id=\"{}\"}} {}", label(&child.id), child.is_failing() as u8);
+        }
+
+        header(&mut out, "rye_init_entry_running", "gauge",
+            "1 if the process of an inittab entry is running.");
+        for child in &entries {
+            let _ = writeln!(out, "rye_init_entry_running{{id=\"{}\"}} {}", label(&child.id), child.is_running() as u8);
+        }
+
+        header(&mut out, "rye_init_entry_last_exit_code", "gauge",
+            "Exit code of the last process of an inittab entry, 128 + signal if it was killed.");
+        for child in entries.iter().filter(|c| c.flags.contains(ChildFlags::XECUTED) && !c.is_running()) {
+            let _ = writeln!(out, "rye_init_entry_last_exit_code{{id=\"{}\"}} {}", label(&child.id), exit_code(child.exstat));
+        }
+
+        header(&mut out, "rye_init_runlevel_transition_seconds", "gauge",
+            "How long the last switch to a runlevel took until nothing was left to wait for.");
+        for (level, millis) in &self.transitions {
+            let _ = writeln!(out, "rye_init_runlevel_transition_seconds{{runlevel=\"{}\"}} {:.3}",
+                label(&level.to_string()), *millis as f64 / 1000.0);
+        }
+
+        header(&mut out, "rye_init_runlevel", "gauge", "The current runlevel.");
+        let _ = writeln!(out, "rye_init_runlevel{{runlevel=\"{}\"}} 1", label(&curlevel.to_string()));
+
+        out
+    }
+
+    // Rewrite METRICS_FILE if anything changed
+    pub fn update(&mut self, family: Option<&Child>, curlevel: &Runlevel) -> std::io::Result<()> {
+        let text = self.render(family, curlevel);
+        if text == self.written {
+            return Ok(());
+        }
+
+        let path = rye_init::paths::path(METRICS_FILE);
+        let path = std::path::Path::new(&path);
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let tmp = path.with_extension("tmp");
+        std::fs::write(&tmp, &text)?;
+        std::fs::rename(&tmp, path)?;
+        self.written = text;
+        Ok(())
+    }
+}
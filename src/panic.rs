@@ -0,0 +1,29 @@
+// What init does when it panics
+//
+// The kernel panics when process 1 dies ("Attempted to kill init"), so a
+// panic must never unwind out of main. The hook below reports the panic on
+// the console and in the kernel log, main catches the unwind and recovers.
+
+use std::sync::Mutex;
+
+use crate::console;
+use crate::initlog;
+
+// What the last panic said, for the exit record
+static LAST_PANIC: Mutex<Option<String>> = Mutex::new(None);
+
+pub fn last_panic() -> Option<String> {
+    LAST_PANIC.lock().ok()?.clone()
+}
+
+pub fn install_hook() {
+    std::panic::set_hook(Box::new(|info| {
+        let msg = format!("{}", info).replace('\n', " ");
+        initlog::log_console(console::CONSOLE, &msg);
+        // At priority crit
+        initlog::log_kmsg(2, &msg);
+        if let Ok(mut last) = LAST_PANIC.lock() {
+            *last = Some(msg);
+        }
+    }));
+}
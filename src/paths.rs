@@ -0,0 +1,38 @@
+//! Where rye-init keeps its files.
+//!
+//! The paths of the files rye-init reads and writes are compiled in as
+//! constants next to the code that uses them, [`crate::inittab::INITTAB`],
+//! [`crate::initreq::INIT_FIFO`] and so on. Anything that opens one of them
+//! goes through [`path`], which puts an alternate root in front: `--root DIR`
+//! on init's command line or `RYE_INIT_ROOT=DIR` in the environment. Image
+//! build pipelines and tests can so run the real code against a staging
+//! directory.
+//!
+//! Devices, `/proc`, `/sys` and the programs init starts are not moved.
+
+use std::sync::OnceLock;
+
+/// Environment variable naming the alternate root.
+pub const ROOT_ENV: &str = "RYE_INIT_ROOT";
+
+static ROOT: OnceLock<String> = OnceLock::new();
+
+fn clean(dir: &str) -> String {
+    dir.trim_end_matches('/').to_string()
+}
+
+/// Use `dir` as the root. Only works before the first path has been looked
+/// up, returns false if that is too late.
+pub fn set_root(dir: &str) -> bool {
+    ROOT.set(clean(dir)).is_ok()
+}
+
+/// The alternate root, empty for `/`.
+pub fn root() -> &'static str {
+    ROOT.get_or_init(|| std::env::var(ROOT_ENV).map(|dir| clean(&dir)).unwrap_or_default())
+}
+
+/// `path` under the alternate root.
+pub fn path(path: &str) -> String {
+    format!("{}{}", root(), path)
+}
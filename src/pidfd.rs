@@ -0,0 +1,78 @@
+// pidfd helpers.
+//
+// A pid can be recycled by an unrelated process as soon as the child has been
+// reaped, so signalling a pid that was looked up earlier is racy. A pidfd
+// always refers to the process it was opened for; once that process is gone,
+// pidfd_send_signal() fails with ESRCH instead of hitting a stranger.
+//
+// pidfds need Linux 5.3 or newer. On older kernels (and other systems)
+// pidfd_open() returns -1 and callers fall back to plain kill().
+
+use nix::errno::Errno;
+use nix::sys::signal::{self, Signal};
+use nix::unistd::Pid;
+
+// Open a pidfd for pid. Returns -1 if pidfds are not available.
+#[cfg(target_os = "linux")]
+pub fn pidfd_open(pid: i32) -> i32 {
+    if pid <= 0 {
+        return -1;
+    }
+
+    let fd = unsafe { libc::syscall(libc::SYS_pidfd_open, pid, libc::O_CLOEXEC) };
+    if fd < 0 {
+        return -1;
+    }
+
+    fd as i32
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn pidfd_open(_pid: i32) -> i32 {
+    -1
+}
+
+// Send a signal through a pidfd
+#[cfg(target_os = "linux")]
+pub fn pidfd_send_signal(pidfd: i32, sig: Signal) -> nix::Result<()> {
+    let res = unsafe {
+        libc::syscall(
+            libc::SYS_pidfd_send_signal,
+            pidfd,
+            sig as libc::c_int,
+            std::ptr::null::<libc::siginfo_t>(),
+            0,
+        )
+    };
+    Errno::result(res).map(drop)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn pidfd_send_signal(_pidfd: i32, _sig: Signal) -> nix::Result<()> {
+    Err(Errno::ENOSYS)
+}
+
+pub fn pidfd_close(pidfd: i32) {
+    if pidfd >= 0 {
+        unsafe {
+            libc::close(pidfd);
+        }
+    }
+}
+
+// Signal a process, preferring the pidfd if we have one. Only when the kernel
+// does not know about pidfd_send_signal do we fall back to the classic pid.
+pub fn send_signal(pidfd: i32, pid: i32, sig: Signal) -> nix::Result<()> {
+    if pidfd >= 0 {
+        match pidfd_send_signal(pidfd, sig) {
+            Err(Errno::ENOSYS) => {}
+            res => return res,
+        }
+    }
+
+    if pid <= 0 {
+        return Err(Errno::ESRCH);
+    }
+
+    signal::kill(Pid::from_raw(pid), sig)
+}
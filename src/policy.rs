@@ -0,0 +1,356 @@
+//! What init does with an inittab entry.
+//!
+//! Whether an entry is started, waited for or stopped only depends on its
+//! action, its flags, its runlevels and the runlevel init is in. The
+//! decisions are made here, the main loop just carries them out, so a new
+//! action only has to be taught to this module.
+//!
+//! The same goes for a whole runlevel switch: [`stops`], [`demands`] and
+//! [`starts`] are what init acts on, and [`plan`] strings them together
+//! into what a switch would do, for `rye-initctl plan`.
+
+use crate::inittab::InitAction;
+use crate::runlevel::{Runlevel, RunlevelSet};
+use crate::state::ChildFlags;
+
+/// What to do about an entry at the current runlevel.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Start {
+    /// Leave it alone.
+    Skip,
+    /// Start it and carry on.
+    Spawn,
+    /// Start it and start nothing after it until it has exited.
+    SpawnAndWait,
+    /// Start it once its delay has passed.
+    SpawnLater,
+}
+
+/// An entry as far as a runlevel switch is concerned.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Planned {
+    pub action: InitAction,
+    pub flags: ChildFlags,
+    pub runlevels: RunlevelSet,
+}
+
+/// One step of a runlevel switch, by index into the entries.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Step {
+    /// Stop the entry.
+    Stop(usize),
+    /// Start the entry. After [`Start::SpawnAndWait`] nothing else starts
+    /// until it has exited.
+    Start(usize, Start),
+}
+
+/// Whether init waits for entries with this action to exit before it
+/// starts the entries that follow.
+pub fn waits(action: InitAction) -> bool {
+    matches!(
+        action,
+        InitAction::SysInit
+            | InitAction::BootWait
+            | InitAction::Wait
+            | InitAction::PowerWait
+            | InitAction::PowerFailNow
+            | InitAction::PowerOkWait
+            | InitAction::CtrlAltDel
+    )
+}
+
+/// Whether entries with this action run once rather than being restarted
+/// whenever they exit.
+pub fn runs_once(action: InitAction) -> bool {
+    !matches!(
+        action,
+        InitAction::Respawn | InitAction::OnDemand | InitAction::Off | InitAction::InitDefault
+    )
+}
+
+/// Whether an entry belongs to `level`, either through its runlevels or
+/// because one of its ondemand levels was requested.
+pub fn is_due(runlevels: &RunlevelSet, flags: ChildFlags, level: &Runlevel) -> bool {
+    let demand = flags.contains(ChildFlags::DEMAND) && level.as_char().is_none_or(|c| !"#*Ss".contains(c));
+    runlevels.contains(level) || demand
+}
+
+/// What to do about an entry when init looks for work at `level`.
+pub fn start(action: InitAction, flags: ChildFlags, runlevels: &RunlevelSet, level: &Runlevel) -> Start {
+    if flags.intersects(ChildFlags::RUNNING | ChildFlags::FAILING | ChildFlags::DISABLED)
+        || !is_due(runlevels, flags, level)
+    {
+        return Start::Skip;
+    }
+
+    match action {
+        InitAction::Off | InitAction::InitDefault => Start::Skip,
+        _ if runs_once(action) && flags.contains(ChildFlags::XECUTED) => Start::Skip,
+        _ if waits(action) => Start::SpawnAndWait,
+        InitAction::Delayed => Start::SpawnLater,
+        _ => Start::Spawn,
+    }
+}
+
+/// Whether an entry is meant to be active at `level`, started there or
+/// left running if it already is. Entries that are never started, `off`
+/// and `initdefault`, are not.
+pub fn runs_at(action: InitAction, flags: ChildFlags, runlevels: &RunlevelSet, level: &Runlevel) -> bool {
+    !matches!(action, InitAction::Off | InitAction::InitDefault) && is_due(runlevels, flags, level)
+}
+
+/// The entries to stop when init switches to `level`, in order. None for
+/// the ondemand levels, they only start entries.
+pub fn stops(entries: &[Planned], level: &Runlevel) -> Vec<usize> {
+    if is_ondemand(level) {
+        return Vec::new();
+    }
+    entries.iter().enumerate().filter(|(_, e)| stop_at(e.flags, &e.runlevels, level)).map(|(n, _)| n).collect()
+}
+
+/// The entries that get [`ChildFlags::DEMAND`] when an ondemand level
+/// (`a`, `b` or `c`) is requested.
+pub fn demands(entries: &[Planned], level: &Runlevel) -> Vec<usize> {
+    if !is_ondemand(level) {
+        return Vec::new();
+    }
+    entries.iter().enumerate().filter(|(_, e)| e.runlevels.contains(level)).map(|(n, _)| n).collect()
+}
+
+/// Whether `level` is one of the ondemand levels, which don't change the
+/// runlevel.
+pub fn is_ondemand(level: &Runlevel) -> bool {
+    matches!(level.as_char(), Some('A'..='C'))
+}
+
+/// The entries one pass over the process table starts at `level`, in
+/// order. The pass ends at an entry that is still waited for, and init
+/// ends it early when it has to wait for one it started.
+pub fn starts(entries: &[Planned], level: &Runlevel) -> Vec<(usize, Start)> {
+    let mut found = Vec::new();
+    for (n, e) in entries.iter().enumerate() {
+        if e.flags.contains(ChildFlags::WAITING) {
+            break;
+        }
+        match start(e.action, e.flags, &e.runlevels, level) {
+            Start::Skip => {}
+            how => found.push((n, how)),
+        }
+    }
+    found
+}
+
+/// Everything a switch from `current` to `target` does, in order: the
+/// entries stopped, then those started. The entries init waits for are
+/// taken to exit, so what comes after them is in the plan too.
+pub fn plan(mut entries: Vec<Planned>, current: &Runlevel, target: &Runlevel) -> Vec<Step> {
+    let mut steps = Vec::new();
+
+    for n in demands(&entries, target) {
+        entries[n].flags.insert(ChildFlags::DEMAND);
+    }
+    let level = if is_ondemand(target) { current } else { target };
+
+    for n in stops(&entries, target) {
+        entries[n].flags.remove(ChildFlags::RUNNING | ChildFlags::WAITING);
+        steps.push(Step::Stop(n));
+    }
+
+    loop {
+        let mut waited = false;
+        for (n, how) in starts(&entries, level) {
+            entries[n].flags.insert(ChildFlags::RUNNING | ChildFlags::XECUTED);
+            steps.push(Step::Start(n, how));
+            if how == Start::SpawnAndWait {
+                entries[n].flags.remove(ChildFlags::RUNNING);
+                waited = true;
+                break;
+            }
+        }
+        if !waited {
+            return steps;
+        }
+    }
+}
+
+/// Actions of other inits and misspellings of ours that have a close
+/// enough action here. Entries using them are kept, with a warning.
+pub const LEGACY_ACTIONS: &[(&str, InitAction)] = &[
+    ("askfirst", InitAction::Respawn),          // busybox, without the "press enter" prompt
+    ("power-fail", InitAction::PowerFail),
+    ("pwrfail", InitAction::PowerFail),
+    ("powerfail-now", InitAction::PowerFailNow),
+    ("powerok", InitAction::PowerOkWait),
+    ("power-ok-wait", InitAction::PowerOkWait),
+    ("pwrwait", InitAction::PowerWait),
+    ("power-wait", InitAction::PowerWait),
+    ("ctrl-alt-del", InitAction::CtrlAltDel),
+    ("ctrlaltdelete", InitAction::CtrlAltDel),
+    ("kbdrequest", InitAction::KbRequest),
+    ("boot-wait", InitAction::BootWait),
+    ("on-demand", InitAction::OnDemand),
+];
+
+/// The action to use instead of an unsupported `name` from
+/// [`LEGACY_ACTIONS`], ignoring case. `None` for an action that is simply
+/// unknown.
+pub fn legacy_action(name: &str) -> Option<InitAction> {
+    let name = name.to_lowercase();
+    LEGACY_ACTIONS.iter().find(|(legacy, _)| *legacy == name).map(|&(_, action)| action)
+}
+
+/// Whether a running entry has to be stopped when init switches to
+/// `level`. Entries started on demand keep running.
+pub fn stop_at(flags: ChildFlags, runlevels: &RunlevelSet, level: &Runlevel) -> bool {
+    flags.contains(ChildFlags::RUNNING)
+        && !flags.contains(ChildFlags::DEMAND)
+        && !runlevels.contains(level)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use InitAction::*;
+
+    const NONE: ChildFlags = ChildFlags::empty();
+    const RUNNING: ChildFlags = ChildFlags::RUNNING;
+    const DEMAND: ChildFlags = ChildFlags::DEMAND;
+    const XECUTED: ChildFlags = ChildFlags::XECUTED;
+
+    #[test]
+    fn start_decisions() {
+        #[rustfmt::skip]
+        let table = [
+            // action      flags                      runlevels level  decision
+            (Respawn,      NONE,                      "2345",   '3',   Start::Spawn),
+            (Respawn,      NONE,                      "2345",   '1',   Start::Skip),
+            (Respawn,      XECUTED,                   "2345",   '3',   Start::Spawn),
+            (Respawn,      RUNNING,                   "2345",   '3',   Start::Skip),
+            (Respawn,      ChildFlags::FAILING,       "2345",   '3',   Start::Skip),
+            (Respawn,      ChildFlags::DISABLED,      "2345",   '3',   Start::Skip),
+            (Once,         NONE,                      "3",      '3',   Start::Spawn),
+            (Once,         XECUTED,                   "3",      '3',   Start::Skip),
+            (Boot,         NONE,                      "S",      'S',   Start::Spawn),
+            (Wait,         NONE,                      "3",      '3',   Start::SpawnAndWait),
+            (Wait,         XECUTED,                   "3",      '3',   Start::Skip),
+            (SysInit,      NONE,                      "S",      'S',   Start::SpawnAndWait),
+            (SysInit,      NONE,                      "",       'S',   Start::Skip),
+            (BootWait,     NONE,                      "S",      'S',   Start::SpawnAndWait),
+            (CtrlAltDel,   NONE,                      "3",      '3',   Start::SpawnAndWait),
+            (PowerWait,    NONE,                      "3",      '3',   Start::SpawnAndWait),
+            (PowerFail,    NONE,                      "3",      '3',   Start::Spawn),
+            (KbRequest,    NONE,                      "3",      '3',   Start::Spawn),
+            (Delayed,      NONE,                      "3",      '3',   Start::SpawnLater),
+            (Delayed,      XECUTED,                   "3",      '3',   Start::Skip),
+            (Off,          NONE,                      "3",      '3',   Start::Skip),
+            (InitDefault,  NONE,                      "3",      '3',   Start::Skip),
+            (OnDemand,     NONE,                      "A",      '3',   Start::Skip),
+            (OnDemand,     DEMAND,                    "A",      '3',   Start::Spawn),
+            (OnDemand,     DEMAND.union(XECUTED),     "A",      '3',   Start::Spawn),
+            (OnDemand,     DEMAND,                    "A",      'S',   Start::Skip),
+            (Once,         DEMAND,                    "A",      '2',   Start::Spawn),
+        ];
+        for (action, flags, runlevels, level, expected) in table {
+            let got = start(action, flags, &RunlevelSet::parse(runlevels), &Runlevel::from(level));
+            assert_eq!(got, expected, "{:?} {:?} {:?} at {}", action, flags, runlevels, level);
+        }
+    }
+
+    #[test]
+    fn runs_at_decisions() {
+        #[rustfmt::skip]
+        let table = [
+            (Respawn,      NONE,    "2345", '3', true),
+            (Respawn,      NONE,    "2345", '1', false),
+            (Once,         XECUTED, "3",    '3', true),
+            (OnDemand,     DEMAND,  "A",    '3', true),
+            (OnDemand,     DEMAND,  "A",    'S', false),
+            (Off,          NONE,    "3",    '3', false),
+            (InitDefault,  NONE,    "3",    '3', false),
+        ];
+        for (action, flags, runlevels, level, expected) in table {
+            let got = runs_at(action, flags, &RunlevelSet::parse(runlevels), &Runlevel::from(level));
+            assert_eq!(got, expected, "{:?} {:?} {:?} at {}", action, flags, runlevels, level);
+        }
+    }
+
+    #[test]
+    fn stop_decisions() {
+        #[rustfmt::skip]
+        let table = [
+            (RUNNING,                "23", '1', true),
+            (RUNNING,                "23", '3', false),
+            (NONE,                   "23", '1', false),
+            (RUNNING.union(DEMAND),  "A",  '1', false),
+            (RUNNING,                "S",  '3', true),
+        ];
+        for (flags, runlevels, level, expected) in table {
+            let got = stop_at(flags, &RunlevelSet::parse(runlevels), &Runlevel::from(level));
+            assert_eq!(got, expected, "{:?} {:?} at {}", flags, runlevels, level);
+        }
+    }
+
+    fn entry(action: InitAction, flags: ChildFlags, runlevels: &str) -> Planned {
+        Planned { action, flags, runlevels: RunlevelSet::parse(runlevels) }
+    }
+
+    #[test]
+    fn switch_plan() {
+        let entries = vec![
+            entry(Respawn, RUNNING, "2"),
+            entry(Wait, NONE, "3"),
+            entry(Respawn, NONE, "23"),
+            entry(Once, NONE, "3"),
+            entry(Respawn, RUNNING, "23"),
+        ];
+        assert_eq!(plan(entries, &Runlevel::from('2'), &Runlevel::from('3')), [
+            Step::Stop(0),
+            Step::Start(1, Start::SpawnAndWait),
+            Step::Start(2, Start::Spawn),
+            Step::Start(3, Start::Spawn),
+        ]);
+    }
+
+    #[test]
+    fn ondemand_plan() {
+        let entries = vec![entry(Respawn, RUNNING, "2"), entry(OnDemand, NONE, "a"), entry(OnDemand, NONE, "b")];
+        assert_eq!(stops(&entries, &Runlevel::from('a')), [] as [usize; 0]);
+        assert_eq!(demands(&entries, &Runlevel::from('a')), [1]);
+        assert_eq!(plan(entries, &Runlevel::from('2'), &Runlevel::from('a')), [Step::Start(1, Start::Spawn)]);
+    }
+
+    // Entries of a runlevel start in the order of the inittab, not in that
+    // of their ids or their actions
+    #[test]
+    fn starts_in_inittab_order() {
+        let inittab = crate::inittab::parse_str(concat!(
+            "id:3:initdefault:\n",
+            "z9:3:once:/bin/z9\n",
+            "l3:3:wait:/etc/rc 3\n",
+            "b2:23:respawn:/bin/b2\n",
+            "x1:2:respawn:/bin/x1\n",
+            "a7:345:respawn:/bin/a7\n",
+            "m5:3:once:/bin/m5\n",
+        ));
+        let entries: Vec<Planned> = inittab.entries.iter()
+            .map(|e| Planned { action: e.action, flags: NONE, runlevels: RunlevelSet::parse(&e.runlevels) })
+            .collect();
+        let ids = |started: Vec<usize>| started.into_iter().map(|n| inittab.entries[n].id.as_str()).collect::<Vec<_>>();
+
+        let first_pass = starts(&entries, &Runlevel::from('3')).into_iter().map(|(n, _)| n).collect();
+        assert_eq!(ids(first_pass), ["z9", "l3", "b2", "a7", "m5"]);
+        let started = plan(entries, &Runlevel::from('S'), &Runlevel::from('3')).into_iter()
+            .map(|step| match step {
+                Step::Start(n, _) => n,
+                Step::Stop(n) => panic!("stops {}", n),
+            })
+            .collect();
+        assert_eq!(ids(started), ["z9", "l3", "b2", "a7", "m5"]);
+    }
+
+    #[test]
+    fn pass_ends_at_waited_entry() {
+        let entries = vec![entry(Once, NONE, "3"), entry(Wait, ChildFlags::WAITING, "3"), entry(Once, NONE, "3")];
+        assert_eq!(starts(&entries, &Runlevel::from('3')), [(0, Start::Spawn)]);
+    }
+}
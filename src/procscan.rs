@@ -0,0 +1,227 @@
+//! Reading the process table from `/proc`.
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// Where the proc filesystem is mounted.
+pub const PROC: &str = "/proc";
+
+/// The per-process flag of kernel threads in `/proc/<pid>/stat`.
+const PF_KTHREAD: u64 = 0x00200000;
+
+/// What we know about a process.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProcInfo {
+    pub pid: i32,
+    pub ppid: i32,
+    /// The name from `/proc/<pid>/stat`, at most 15 characters.
+    pub comm: String,
+    /// The process state: R, S, D, Z, ...
+    pub state: char,
+    /// The session the process belongs to.
+    pub session: i32,
+    /// Whether this is a kernel thread rather than a process.
+    pub kthread: bool,
+    /// The command line, arguments separated by spaces. Empty for kernel
+    /// threads and zombies.
+    pub cmdline: String,
+}
+
+/// Read `/proc/<pid>`. None if the process is gone.
+pub fn read_proc(pid: i32) -> Option<ProcInfo> {
+    read_proc_in(PROC, pid)
+}
+
+/// Read `<proc>/<pid>`, for a proc filesystem mounted elsewhere or a copy
+/// of one.
+pub fn read_proc_in(proc: &str, pid: i32) -> Option<ProcInfo> {
+    let stat = std::fs::read_to_string(format!("{}/{}/stat", proc, pid)).ok()?;
+
+    // comm may contain anything including spaces and parentheses, it ends
+    // at the last ')'
+    let open = stat.find('(')?;
+    let close = stat.rfind(')')?;
+    let comm = stat.get(open + 1..close)?.to_string();
+    let mut rest = stat.get(close + 1..)?.split_whitespace();
+    let state = rest.next()?.chars().next()?;
+    let ppid = rest.next()?.parse().ok()?;
+    let _pgrp = rest.next()?;
+    let session = rest.next()?.parse().ok()?;
+    let flags: u64 = rest.nth(2)?.parse().ok()?;
+
+    let cmdline = std::fs::read(format!("{}/{}/cmdline", proc, pid))
+        .map(|raw| {
+            raw.split(|&b| b == 0)
+                .filter(|a| !a.is_empty())
+                .map(|a| String::from_utf8_lossy(a).into_owned())
+                .collect::<Vec<_>>()
+                .join(" ")
+        })
+        .unwrap_or_default();
+
+    Some(ProcInfo { pid, ppid, comm, state, session, kthread: flags & PF_KTHREAD != 0, cmdline })
+}
+
+/// The pids of all processes.
+pub fn pids() -> Vec<i32> {
+    pids_in(PROC)
+}
+
+/// The pids in `proc`.
+pub fn pids_in(proc: &str) -> Vec<i32> {
+    match std::fs::read_dir(proc) {
+        Ok(entries) => entries
+            .flatten()
+            .filter_map(|e| e.file_name().to_str()?.parse().ok())
+            .collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+/// All processes that could be read.
+pub fn scan() -> Vec<ProcInfo> {
+    scan_in(PROC)
+}
+
+/// All processes in `proc` that could be read.
+pub fn scan_in(proc: &str) -> Vec<ProcInfo> {
+    pids_in(proc).into_iter().filter_map(|pid| read_proc_in(proc, pid)).collect()
+}
+
+/// The pids of the children of `pid`, their children and so on, as far
+/// as `procs` knows them.
+pub fn descendants(procs: &[ProcInfo], pid: i32) -> Vec<i32> {
+    let mut found = vec![pid];
+    let mut n = 0;
+    while n < found.len() {
+        let parent = found[n];
+        found.extend(procs.iter().filter(|p| p.ppid == parent).map(|p| p.pid));
+        n += 1;
+    }
+    found.remove(0);
+    found
+}
+
+/// Device and inode of the root directory of a process, to tell whether
+/// two processes run in the same root. None if we may not look.
+pub fn root_id(pid: i32) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+
+    let meta = std::fs::metadata(format!("{}/{}/root", PROC, pid)).ok()?;
+    Some((meta.dev(), meta.ino()))
+}
+
+/// A process init may have to reap one day.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Orphan {
+    pub info: ProcInfo,
+    /// The parent it had before it was reparented to init, if we saw it.
+    pub parent: Option<(i32, String)>,
+}
+
+/// Remembers processes for a while, so that when init reaps a child it
+/// didn't start it can still say what it was.
+#[derive(Debug, Default)]
+pub struct ProcCache {
+    procs: HashMap<i32, Orphan>,
+    scanned: Option<Instant>,
+}
+
+impl ProcCache {
+    pub fn new() -> Self {
+        ProcCache::default()
+    }
+
+    /// Scan `/proc` again unless the last scan is less than `interval`
+    /// ago. Processes that are gone are forgotten. Zombies are still in
+    /// `/proc`, so whatever init has to reap is still known.
+    pub fn refresh(&mut self, interval: Duration) {
+        if self.scanned.is_some_and(|t| t.elapsed() < interval) {
+            return;
+        }
+        self.scanned = Some(Instant::now());
+
+        let me = std::process::id() as i32;
+        let procs = scan();
+        let names: HashMap<i32, String> = procs.iter().map(|p| (p.pid, p.comm.clone())).collect();
+
+        let mut fresh = HashMap::new();
+        for mut info in procs {
+            let old = self.procs.remove(&info.pid);
+
+            // Once reparented to us, keep what we knew about the parent
+            let parent = if info.ppid == me {
+                old.as_ref().and_then(|o| o.parent.clone())
+            } else {
+                Some((info.ppid, names.get(&info.ppid).cloned().unwrap_or_default()))
+            };
+
+            // A zombie has lost its command line, keep the one we saw
+            if info.cmdline.is_empty() && let Some(old) = &old {
+                info.cmdline = old.info.cmdline.clone();
+            }
+
+            fresh.insert(info.pid, Orphan { info, parent });
+        }
+
+        self.procs = fresh;
+    }
+
+    /// Forget a reaped process and return what we knew about it.
+    pub fn take(&mut self, pid: i32) -> Option<Orphan> {
+        self.procs.remove(&pid)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sys::{Kernel, Sys};
+    use std::io::{BufRead, BufReader};
+    use std::process::{Command, Stdio};
+
+    #[test]
+    fn descendants_follow_ppid() {
+        let proc = |pid, ppid| ProcInfo {
+            pid, ppid, comm: String::new(), state: 'S', session: 0, kthread: false, cmdline: String::new(),
+        };
+        let procs = [proc(10, 1), proc(11, 10), proc(12, 11), proc(13, 1), proc(14, 10)];
+        assert_eq!(descendants(&procs, 10), [11, 14, 12]);
+        assert_eq!(descendants(&procs, 13), [] as [i32; 0]);
+    }
+
+    // A shell backgrounds a sleep and exits, the sleep is reparented to us
+    // as it would be to init, and the cache still names its old parent
+    // when it is reaped
+    #[test]
+    fn reaped_orphan_keeps_its_parent() {
+        Kernel.set_subreaper().unwrap();
+        let mut sh = Command::new("/bin/sh")
+            .args(["-c", "sleep 30 & echo $!; read x"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let sh_pid = sh.id() as i32;
+        let mut line = String::new();
+        BufReader::new(sh.stdout.take().unwrap()).read_line(&mut line).unwrap();
+        let orphan_pid: i32 = line.trim().parse().unwrap();
+
+        let mut cache = ProcCache::new();
+        cache.refresh(Duration::ZERO);
+        drop(sh.stdin.take());
+        sh.wait().unwrap();
+        assert_eq!(read_proc(orphan_pid).map(|p| p.ppid), Some(std::process::id() as i32));
+
+        cache.refresh(Duration::ZERO);
+        unsafe { libc::kill(orphan_pid, libc::SIGTERM) };
+        let mut status = 0;
+        assert_eq!(unsafe { libc::waitpid(orphan_pid, &mut status, 0) }, orphan_pid);
+
+        let orphan = cache.take(orphan_pid).unwrap();
+        assert_eq!(orphan.info.comm, "sleep");
+        assert_eq!(orphan.info.cmdline, "sleep 30");
+        assert_eq!(orphan.parent, Some((sh_pid, "sh".to_string())));
+        assert_eq!(cache.take(orphan_pid), None);
+    }
+}
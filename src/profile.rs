@@ -0,0 +1,181 @@
+//! Boot profiles.
+//!
+//! With the default `inittab` profile everything init runs comes from
+//! `/etc/inittab`. The `openrc` and `lsb` profiles generate the entries
+//! that start the rc system instead, so rye-init can be dropped into an
+//! OpenRC or sysv-rc userland without editing the inittab. Entries for
+//! gettys, ctrlaltdel and so on still come from the inittab.
+//!
+//! The profile is read from [`PROFILE_FILE`]:
+//!
+//! ```text
+//! # inittab, openrc or lsb
+//! profile openrc
+//! # runlevel  rc argument
+//! map 2 nonetwork
+//! map 3 default
+//! ```
+
+use std::io;
+
+use crate::inittab::{Attributes, Entry, InitAction};
+
+/// Where the boot profile is configured.
+pub const PROFILE_FILE: &str = "/etc/rye-init/profile";
+
+/// Path of the OpenRC binary.
+pub const OPENRC: &str = "/sbin/openrc";
+/// Path of the sysv-rc runlevel script.
+pub const LSB_RC: &str = "/etc/init.d/rc";
+/// Path of the sysv-rc single user / boot script.
+pub const LSB_RCS: &str = "/etc/init.d/rcS";
+
+/// How the rc system gets started.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BootProfile {
+    /// Everything comes from the inittab.
+    Inittab,
+    /// `openrc sysinit`, `openrc boot`, then `openrc <level>` per runlevel.
+    OpenRc,
+    /// `rcS` at boot, then `rc <level>` per runlevel.
+    Lsb,
+}
+
+/// The boot profile and its runlevel mapping.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Profile {
+    pub kind: BootProfile,
+    /// The argument passed to the rc system for each runlevel.
+    pub runlevels: Vec<(char, String)>,
+}
+
+impl Default for Profile {
+    fn default() -> Self {
+        Profile {
+            kind: BootProfile::Inittab,
+            runlevels: Vec::new(),
+        }
+    }
+}
+
+// The mapping Gentoo ships in its inittab
+const OPENRC_RUNLEVELS: &[(char, &str)] = &[
+    ('0', "shutdown"),
+    ('1', "single"),
+    ('2', "nonetwork"),
+    ('3', "default"),
+    ('4', "default"),
+    ('5', "default"),
+    ('6', "reboot"),
+];
+
+impl Profile {
+    /// A profile with the default runlevel mapping for `kind`.
+    pub fn new(kind: BootProfile) -> Self {
+        let runlevels = match kind {
+            BootProfile::Inittab => Vec::new(),
+            BootProfile::OpenRc => OPENRC_RUNLEVELS.iter().map(|&(l, a)| (l, a.to_string())).collect(),
+            BootProfile::Lsb => "0123456".chars().map(|l| (l, l.to_string())).collect(),
+        };
+        Profile { kind, runlevels }
+    }
+
+    /// Parse a profile file. Unknown lines are an error.
+    pub fn parse_str(s: &str) -> Result<Self, String> {
+        let mut profile = Profile::default();
+        let mut maps = Vec::new();
+
+        for (n, line) in s.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let words: Vec<&str> = line.split_whitespace().collect();
+            match words.as_slice() {
+                ["profile", "inittab"] => profile = Profile::new(BootProfile::Inittab),
+                ["profile", "openrc"] => profile = Profile::new(BootProfile::OpenRc),
+                ["profile", "lsb"] => profile = Profile::new(BootProfile::Lsb),
+                ["map", level, arg] if level.chars().count() == 1 => {
+                    maps.push((level.chars().next().unwrap_or('?'), arg.to_string()));
+                }
+                _ => return Err(format!("line {}: can't parse \"{}\"", n + 1, line)),
+            }
+        }
+
+        // map lines may come before the profile line
+        for (level, arg) in maps {
+            match profile.runlevels.iter_mut().find(|(l, _)| *l == level) {
+                Some(slot) => slot.1 = arg,
+                None => profile.runlevels.push((level, arg)),
+            }
+        }
+
+        Ok(profile)
+    }
+
+    /// Read [`PROFILE_FILE`] or another profile. A missing file means the
+    /// inittab profile.
+    pub fn load(path: &str) -> io::Result<Self> {
+        match std::fs::read_to_string(path) {
+            Ok(s) => Self::parse_str(&s).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Profile::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// The entries this profile adds to the inittab.
+    pub fn entries(&self) -> Vec<Entry> {
+        let entry = |id: String, runlevels: &str, action, process: String| Entry {
+            id,
+            runlevels: runlevels.to_string(),
+            action,
+            process,
+            attrs: Attributes::default(),
+            delay: 0,
+        };
+
+        let mut entries = match self.kind {
+            BootProfile::Inittab => return Vec::new(),
+            BootProfile::OpenRc => vec![
+                entry("si".to_string(), "", InitAction::SysInit, format!("{} sysinit", OPENRC)),
+                entry("rc".to_string(), "", InitAction::BootWait, format!("{} boot", OPENRC)),
+            ],
+            BootProfile::Lsb => vec![
+                entry("si".to_string(), "", InitAction::SysInit, LSB_RCS.to_string()),
+            ],
+        };
+
+        let rc = match self.kind {
+            BootProfile::OpenRc => OPENRC,
+            _ => LSB_RC,
+        };
+
+        for (level, arg) in &self.runlevels {
+            // Single user mode is entered through S as well
+            let levels = if *level == '1' { "S1".to_string() } else { level.to_string() };
+            entries.push(entry(format!("l{}", level), &levels, InitAction::Wait, format!("{} {}", rc, arg)));
+        }
+
+        entries
+    }
+
+    /// Merge the generated entries into those of the inittab. The
+    /// generated ones win: inittab entries with the same id and any
+    /// sysinit/boot/bootwait entries are left out.
+    pub fn apply(&self, inittab: Vec<Entry>) -> Vec<Entry> {
+        if self.kind == BootProfile::Inittab {
+            return inittab;
+        }
+
+        let mut entries = self.entries();
+        let generated: Vec<String> = entries.iter().map(|e| e.id.clone()).collect();
+
+        entries.extend(inittab.into_iter().filter(|e| {
+            !generated.contains(&e.id)
+                && !matches!(e.action, InitAction::SysInit | InitAction::Boot | InitAction::BootWait)
+        }));
+
+        entries
+    }
+}
@@ -0,0 +1,92 @@
+// Reading the inittab again: telinit q, rye-initctl reread
+//
+// Init compares its entries with the ones the inittab has now, by id, and
+// says what changes before it does anything about it, one line each:
+//
+//   added "tty7"
+//   removed "ups", stopping pid 812
+//   "ng" changed process, attributes, stopping pid 901
+//   "nt" changed delay
+//
+// The lines are logged, and sent back to rye-initctl reread. A removed
+// entry's process is stopped and the entry forgotten. A changed entry
+// keeps its state, respawn counters included. Its process is stopped when
+// what it runs changed, its action, command or attributes, or when it is
+// no longer part of the current runlevel; a respawn entry then comes back
+// with the new command. Added entries start like any other. Entries init
+// made up itself, the emergency shell for instance, are left alone. An
+// inittab without entries changes nothing.
+
+use rye_init::inittab::EntryId;
+
+use crate::{Child, SPECIAL_ID};
+
+pub enum Change {
+    Added(EntryId),
+    Removed(EntryId),
+    Modified(EntryId, Vec<&'static str>),
+}
+
+impl Change {
+    pub fn id(&self) -> &EntryId {
+        match self {
+            Change::Added(id) | Change::Removed(id) | Change::Modified(id, _) => id,
+        }
+    }
+}
+
+// The fields of an entry that differ in its new version
+fn changed_fields(old: &Child, new: &Child) -> Vec<&'static str> {
+    let mut fields = Vec::new();
+    if old.rlevel != new.rlevel {
+        fields.push("runlevels");
+    }
+    if old.action != new.action {
+        fields.push("action");
+    }
+    if old.process != new.process {
+        fields.push("process");
+    }
+    if old.attrs != new.attrs {
+        fields.push("attributes");
+    }
+    if old.delay != new.delay {
+        fields.push("delay");
+    }
+    fields
+}
+
+// Whether a running process no longer is what its entry says
+pub fn runs_something_else(fields: &[&str]) -> bool {
+    fields.iter().any(|f| matches!(*f, "action" | "process" | "attributes"))
+}
+
+// What turns the entries we have into the new ones, in the order of the
+// old list and then the new one
+pub fn diff(family: Option<&Child>, entries: &[Child]) -> Vec<Change> {
+    let mut changes = Vec::new();
+    let mut ids = Vec::new();
+
+    let mut current = family;
+    while let Some(child) = current {
+        current = child.next.as_deref();
+        if child.id == SPECIAL_ID {
+            continue;
+        }
+        ids.push(&child.id);
+        match entries.iter().find(|e| e.id == child.id) {
+            None => changes.push(Change::Removed(child.id.clone())),
+            Some(new) => {
+                let fields = changed_fields(child, new);
+                if !fields.is_empty() {
+                    changes.push(Change::Modified(child.id.clone(), fields));
+                }
+            }
+        }
+    }
+
+    for entry in entries.iter().filter(|e| !ids.contains(&&e.id)) {
+        changes.push(Change::Added(entry.id.clone()));
+    }
+    changes
+}
@@ -0,0 +1,63 @@
+// The rescue console
+//
+// With --rescue-vt, a sysinit or bootwait entry that fails gets an
+// interactive shell on that terminal while the boot goes on without it.
+// The shell starts by showing what failed: the entry, its command, how it
+// exited and the end of the boot log (see bootlogd), which is the closest
+// thing to its output init has. Built without bootlogd, there is no log. The same report stays in RESCUE_REPORT,
+// and RESCUE_ID, RESCUE_COMMAND and RESCUE_STATUS are in the environment.
+
+use std::io::Write;
+
+use rye_init::paths;
+#[cfg(feature = "bootlogd")]
+use rye_init::bootlogd;
+
+pub const RESCUE_REPORT: &str = "/run/rye-init/rescue";
+
+// Lines from the end of the boot log that go into the report
+#[cfg(feature = "bootlogd")]
+const LOG_LINES: usize = 20;
+
+// Show the report, then hand the terminal to an interactive shell
+pub const RESCUE_SHELL: &str = "cat /run/rye-init/rescue; exec /bin/sh -i";
+
+// How a process ended, from its wait status
+pub fn describe_status(status: i32) -> String {
+    if libc::WIFEXITED(status) {
+        format!("exit status {}", libc::WEXITSTATUS(status))
+    } else if libc::WIFSIGNALED(status) {
+        format!("killed by signal {}", libc::WTERMSIG(status))
+    } else {
+        format!("wait status {}", status)
+    }
+}
+
+// The last lines of the boot log, if bootlogd keeps one
+#[cfg(feature = "bootlogd")]
+fn boot_log_tail() -> Option<String> {
+    let log = std::fs::read(paths::path(bootlogd::LOGFILE)).ok()?;
+    let log = String::from_utf8_lossy(&log);
+    let lines: Vec<&str> = log.lines().collect();
+    Some(lines[lines.len().saturating_sub(LOG_LINES)..].join("\n"))
+}
+
+// Write the report the rescue shell starts with
+pub fn write_report(id: &str, process: &str, status: i32) -> std::io::Result<()> {
+    let report = paths::path(RESCUE_REPORT);
+    if let Some(dir) = std::path::Path::new(&report).parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let mut f = std::fs::File::create(&report)?;
+
+    writeln!(f, "\nrye-init: boot entry \"{}\" failed, the boot continues without it.", id)?;
+    writeln!(f, "  command: {}", process)?;
+    writeln!(f, "  result:  {}", describe_status(status))?;
+    #[cfg(feature = "bootlogd")]
+    match boot_log_tail() {
+        Some(tail) => writeln!(f, "\nEnd of {}:\n{}", bootlogd::LOGFILE, tail)?,
+        None => writeln!(f, "\nNo boot log in {}.", bootlogd::LOGFILE)?,
+    }
+    writeln!(f, "\nThis report is kept in {}.\n", RESCUE_REPORT)?;
+    Ok(())
+}
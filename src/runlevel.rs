@@ -0,0 +1,200 @@
+//! Runlevels.
+//!
+//! Besides the classic single character runlevels (0-6, S and the ondemand
+//! levels A-C) the inittab may name its own, e.g. `rescue`, `net` or `gui`.
+//! In the runlevels field of an inittab entry names are separated by
+//! commas: `gt:5,gui:respawn:/usr/bin/xdm`. A field without commas that
+//! consists of classic runlevels only keeps its old meaning, one runlevel
+//! per character.
+//!
+//! Init also writes the previous and the current runlevel to
+//! [`RUNLEVEL_FILE`], for systems without a usable utmp.
+
+use std::fmt;
+use std::io;
+
+use crate::inittab::RUNLEVEL_LENGTH;
+
+/// Where init records the previous and the current runlevel, as `N 5`.
+pub const RUNLEVEL_FILE: &str = "/run/rye-init/runlevel";
+
+/// A classic or a named runlevel.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Runlevel {
+    Classic(char),
+    Named(String),
+}
+
+/// Whether `c` is one of the classic runlevels.
+pub fn is_valid_runlevel(c: char) -> bool {
+    matches!(c, '0'..='6' | 'S' | 's' | 'A'..='C' | 'a'..='c')
+}
+
+pub fn normalize_runlevel(c: char) -> char {
+    match c {
+        's' => 'S',
+        'a' => 'A',
+        'b' => 'B',
+        'c' => 'C',
+        _ => c,
+    }
+}
+
+fn is_valid_name(s: &str) -> bool {
+    s.len() > 1
+        && s.len() <= RUNLEVEL_LENGTH
+        && s.starts_with(|c: char| c.is_ascii_alphabetic())
+        && s.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+impl Runlevel {
+    /// Parse a runlevel as given to telinit or found in the state stream.
+    /// Any single character is taken as is, so the `N` init uses for "no
+    /// previous runlevel" survives a round trip.
+    pub fn parse(s: &str) -> Option<Self> {
+        let mut chars = s.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) => Some(Runlevel::Classic(normalize_runlevel(c))),
+            _ if is_valid_name(s) => Some(Runlevel::Named(s.to_string())),
+            _ => None,
+        }
+    }
+
+    /// The character of a classic runlevel.
+    pub fn as_char(&self) -> Option<char> {
+        match self {
+            Runlevel::Classic(c) => Some(*c),
+            Runlevel::Named(_) => None,
+        }
+    }
+
+    /// Whether the runlevel may be switched to.
+    pub fn is_valid(&self) -> bool {
+        match self {
+            Runlevel::Classic(c) => is_valid_runlevel(*c),
+            Runlevel::Named(name) => is_valid_name(name),
+        }
+    }
+}
+
+impl From<char> for Runlevel {
+    fn from(c: char) -> Self {
+        Runlevel::Classic(normalize_runlevel(c))
+    }
+}
+
+impl fmt::Display for Runlevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Runlevel::Classic(c) => write!(f, "{}", c),
+            Runlevel::Named(name) => f.write_str(name),
+        }
+    }
+}
+
+/// The classic runlevels in the order of the bits of [`RunlevelSet`].
+const CLASSIC: &str = "0123456SABC";
+
+/// The runlevels of an inittab entry. The classic ones are a bit each,
+/// named ones (and odd characters from an old state stream) are listed.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RunlevelSet {
+    classic: u16,
+    other: Vec<Runlevel>,
+}
+
+impl RunlevelSet {
+    /// The runlevels of a runlevels field, see [`parse_levels`].
+    pub fn parse(field: &str) -> Self {
+        let mut set = RunlevelSet::default();
+        for level in parse_levels(field) {
+            set.insert(level);
+        }
+        set
+    }
+
+    pub fn insert(&mut self, level: Runlevel) {
+        match level.as_char().and_then(|c| CLASSIC.find(c)) {
+            Some(bit) => self.classic |= 1 << bit,
+            None if !self.other.contains(&level) => self.other.push(level),
+            None => {}
+        }
+    }
+
+    pub fn contains(&self, level: &Runlevel) -> bool {
+        match level.as_char().and_then(|c| CLASSIC.find(c)) {
+            Some(bit) => self.classic & (1 << bit) != 0,
+            None => self.other.contains(level),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.classic == 0 && self.other.is_empty()
+    }
+
+    /// The first of the runlevels: the classic ones in [`CLASSIC`] order,
+    /// then the named ones as they were listed.
+    pub fn first(&self) -> Option<Runlevel> {
+        CLASSIC
+            .chars()
+            .enumerate()
+            .find(|(bit, _)| self.classic & (1 << bit) != 0)
+            .map(|(_, c)| Runlevel::Classic(c))
+            .or_else(|| self.other.first().cloned())
+    }
+}
+
+/// Written back as a runlevels field: one character per runlevel when
+/// they are all classic, separated by commas otherwise.
+impl fmt::Display for RunlevelSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut classic = CLASSIC.chars().enumerate().filter(|(bit, _)| self.classic & (1 << bit) != 0).map(|(_, c)| c);
+        if self.other.is_empty() {
+            return classic.try_for_each(|c| write!(f, "{}", c));
+        }
+
+        let levels: Vec<String> = classic.map(String::from).chain(self.other.iter().map(Runlevel::to_string)).collect();
+        f.write_str(&levels.join(","))
+    }
+}
+
+/// The runlevels listed in the runlevels field of an inittab entry.
+pub fn parse_levels(field: &str) -> Vec<Runlevel> {
+    if !field.contains(',') && field.chars().all(is_valid_runlevel) {
+        return field.chars().map(Runlevel::from).collect();
+    }
+
+    field
+        .split(',')
+        .map(|s| s.trim())
+        .filter_map(Runlevel::parse)
+        .collect()
+}
+
+/// Whether an entry with runlevels field `field` runs in `level`.
+pub fn field_contains(field: &str, level: &Runlevel) -> bool {
+    parse_levels(field).contains(level)
+}
+
+/// Replace the runlevel file at `path`. Readers see either the old or the
+/// new contents, never a partial write.
+pub fn write_file(path: &str, prev: &Runlevel, cur: &Runlevel) -> io::Result<()> {
+    if let Some(dir) = std::path::Path::new(path).parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+
+    let tmp = format!("{}.new", path);
+    std::fs::write(&tmp, format!("{} {}\n", prev, cur))?;
+    std::fs::rename(&tmp, path)
+}
+
+/// Read the previous and the current runlevel from a runlevel file.
+pub fn read_file(path: &str) -> io::Result<(Runlevel, Runlevel)> {
+    let s = std::fs::read_to_string(path)?;
+    let mut words = s.split_whitespace().map(Runlevel::parse);
+
+    match (words.next(), words.next()) {
+        (Some(Some(prev)), Some(Some(cur))) => Ok((prev, cur)),
+        _ => Err(io::Error::new(io::ErrorKind::InvalidData, "malformed runlevel file")),
+    }
+}
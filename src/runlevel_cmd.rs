@@ -0,0 +1,49 @@
+// runlevel: print the previous and the current runlevel. Init behaves like
+// this when it is started under the name runlevel.
+//
+// The RUN_LVL record in utmp is what sysvinit's runlevel reads. Where there
+// is no utmp (musl, containers, a read-only /var) the file init keeps in
+// /run has the same information, and named runlevels only show up there.
+// Built without utmp, that file is all there is.
+
+use rye_init::paths;
+use rye_init::runlevel::{self, Runlevel};
+
+#[cfg(feature = "utmp")]
+use crate::utmp;
+
+// The runlevels from the RUN_LVL record of a utmp file
+#[cfg(feature = "utmp")]
+fn from_utmp(path: &str) -> Option<(Runlevel, Runlevel)> {
+    let rec = utmp::read_records(path).ok()?.into_iter().rev().find(|r| r.ut_type == utmp::RUN_LVL)?;
+
+    let cur = char::from_u32((rec.pid % 256) as u32)?;
+    let prev = match rec.pid / 256 {
+        0 => 'N',
+        n => char::from_u32(n as u32)?,
+    };
+    Some((Runlevel::Classic(prev), Runlevel::Classic(cur)))
+}
+
+#[cfg(not(feature = "utmp"))]
+fn from_utmp(_path: &str) -> Option<(Runlevel, Runlevel)> {
+    None
+}
+
+pub fn runlevel(args: &[String]) -> i32 {
+    #[cfg(feature = "utmp")]
+    let path = args.first().cloned().unwrap_or_else(|| paths::path(utmp::UTMP_FILE));
+    #[cfg(not(feature = "utmp"))]
+    let path = args.first().cloned().unwrap_or_default();
+
+    match from_utmp(&path).or_else(|| runlevel::read_file(&paths::path(runlevel::RUNLEVEL_FILE)).ok()) {
+        Some((prev, cur)) => {
+            println!("{} {}", prev, cur);
+            0
+        }
+        None => {
+            println!("unknown");
+            1
+        }
+    }
+}
@@ -0,0 +1,107 @@
+// Running init as an ordinary process, for tests
+//
+// "rye-init --sandbox [--root DIR] ..." runs the main loop without being
+// process 1. Init makes itself the child subreaper, so what the entries
+// leave behind is reparented to it as it would be to process 1, and makes
+// sure a double-forked grandchild really comes back before it goes on: a
+// sandbox that doesn't reap like process 1 would test something else. The
+// calls that change the machine go to a Recorder, the console is the
+// terminal we were started on, and going down stops our own descendants
+// instead of every process there is and ends the sandbox, with the calls
+// that were recorded as the last console message.
+
+use std::io;
+use std::sync::OnceLock;
+
+use nix::sys::wait::{waitpid, WaitStatus};
+use nix::unistd::{ForkResult, Pid};
+use rye_init::sys::{Kernel, Recorder, Sys};
+
+pub const SANDBOX_OPTION: &str = "--sandbox";
+const NO_CONSOLE: &str = "/dev/null";   // Console when we weren't started on a terminal
+
+static RECORDER: OnceLock<Recorder> = OnceLock::new();
+
+// How long the grandchild outlives its parent in check_reaping (ms)
+const ORPHAN_LIFETIME: u32 = 50;
+
+// Whether we were asked to run in the sandbox, wherever the option is in
+// our arguments
+pub fn requested() -> bool {
+    std::env::args().skip(1).any(|arg| arg == SANDBOX_OPTION)
+}
+
+// Become the subreaper of everything we start, and check that it works
+pub fn become_reaper() -> io::Result<()> {
+    Kernel.set_subreaper()?;
+    check_reaping()
+}
+
+// What the sandbox does instead of the calls that change the machine
+pub fn recorder() -> &'static Recorder {
+    RECORDER.get_or_init(Recorder::default)
+}
+
+// The terminal on our standard input, the machine's console isn't ours
+pub fn console() -> String {
+    match nix::unistd::ttyname(std::io::stdin()) {
+        Ok(tty) => tty.display().to_string(),
+        Err(_) => NO_CONSOLE.to_string(),
+    }
+}
+
+// Fork a child that forks a grandchild and exits at once, and wait for
+// both. The grandchild, an orphan by then, only comes to us if we are its
+// subreaper.
+fn check_reaping() -> io::Result<()> {
+    let (rd, wr) = nix::unistd::pipe()?;
+    let child = match unsafe { nix::unistd::fork() }? {
+        ForkResult::Child => {
+            drop(rd);
+            let code = match unsafe { nix::unistd::fork() } {
+                Ok(ForkResult::Child) => {
+                    unsafe { libc::usleep(ORPHAN_LIFETIME * 1000) };
+                    0
+                }
+                Ok(ForkResult::Parent { child }) => {
+                    match nix::unistd::write(&wr, &child.as_raw().to_ne_bytes()) {
+                        Ok(4) => 0,
+                        _ => 1,
+                    }
+                }
+                Err(_) => 1,
+            };
+            unsafe { libc::_exit(code) }
+        }
+        ForkResult::Parent { child } => child,
+    };
+    drop(wr);
+
+    let mut buf = [0u8; 4];
+    let n = nix::unistd::read(&rd, &mut buf);
+    let _ = waitpid(child, None);
+    if n != Ok(4) {
+        return Err(io::Error::other("the test child could not start a grandchild"));
+    }
+
+    let grandchild = Pid::from_raw(i32::from_ne_bytes(buf));
+    match waitpid(grandchild, None) {
+        Ok(WaitStatus::Exited(..)) => Ok(()),
+        Ok(status) => Err(io::Error::other(format!("the orphaned grandchild ended as {:?}", status))),
+        Err(nix::errno::Errno::ECHILD) => Err(io::Error::other("an orphaned grandchild was not reparented to us")),
+        Err(e) => Err(e.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The check forks a child that leaves an orphaned grandchild behind,
+    // which only comes back to us through PR_SET_CHILD_SUBREAPER
+    #[test]
+    fn orphaned_grandchild_is_reaped() {
+        Kernel.set_subreaper().unwrap();
+        check_reaping().unwrap();
+    }
+}
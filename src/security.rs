@@ -0,0 +1,97 @@
+// Mandatory access control
+//
+// Both parts are optional cargo features. With "selinux" init loads the
+// SELinux policy before it does anything else, like sysvinit does, and the
+// secontext attribute of an entry is the SELinux context its process is
+// executed in. With "apparmor" the attribute names the AppArmor profile
+// instead. Without either an entry with a secontext attribute is not
+// started at all: running it unconfined is not what the inittab asks for.
+
+use std::ffi::CString;
+
+#[cfg(feature = "selinux")]
+#[link(name = "selinux")]
+unsafe extern "C" {
+    fn is_selinux_enabled() -> libc::c_int;
+    fn selinux_init_load_policy(enforce: *mut libc::c_int) -> libc::c_int;
+    fn setexeccon(con: *const libc::c_char) -> libc::c_int;
+}
+
+// Set in the environment once the policy is loaded, for the init we exec
+#[cfg(feature = "selinux")]
+const SELINUX_INIT: &str = "SELINUX_INIT";
+
+// Where a process names the AppArmor profile of its next exec. The second
+// one is for kernels without the AppArmor specific directory.
+#[cfg(all(feature = "apparmor", not(feature = "selinux")))]
+const APPARMOR_EXEC: &[&std::ffi::CStr] = &[c"/proc/self/attr/apparmor/exec", c"/proc/self/attr/exec"];
+
+// Load the SELinux policy on the first boot of init and execute init again
+// in its domain. Halts if that fails while the machine is enforcing.
+#[cfg(feature = "selinux")]
+pub fn load_policy() {
+    use std::os::unix::process::CommandExt;
+
+    if std::env::var_os(SELINUX_INIT).is_some() || unsafe { is_selinux_enabled() } == 1 {
+        return;
+    }
+
+    let mut enforce = 0;
+    if unsafe { selinux_init_load_policy(&mut enforce) } == 0 {
+        let mut args = std::env::args_os();
+        let myname = args.next().unwrap_or_else(|| crate::INIT_PROGRAM.into());
+        let err = std::process::Command::new(&myname).args(args).env(SELINUX_INIT, "YES").exec();
+        eprintln!("init: cannot execute {} after loading the SELinux policy: {}", myname.to_string_lossy(), err);
+    } else if enforce > 0 {
+        eprintln!("Unable to load SELinux Policy. Machine is in enforcing mode. Halting now.");
+        std::process::exit(1);
+    }
+}
+
+#[cfg(not(feature = "selinux"))]
+pub fn load_policy() {}
+
+// The context a child is executed in, prepared before the fork so the
+// child only has to make a system call
+pub struct ExecContext {
+    #[cfg_attr(not(any(feature = "selinux", feature = "apparmor")), allow(dead_code))]
+    arg: CString,
+}
+
+impl ExecContext {
+    pub fn new(context: &str) -> Result<Self, String> {
+        if !cfg!(any(feature = "selinux", feature = "apparmor")) {
+            return Err(format!("secontext {}: init is built without SELinux and AppArmor support", context));
+        }
+        // AppArmor takes a command rather than just the profile
+        let arg = if cfg!(feature = "selinux") { context.to_string() } else { format!("exec {}", context) };
+        let arg = CString::new(arg).map_err(|_| format!("bad secontext {}", context))?;
+        Ok(ExecContext { arg })
+    }
+
+    // Make the next exec of this process enter the context. Runs in the
+    // child between fork and exec.
+    #[cfg(feature = "selinux")]
+    pub fn apply(&self) -> bool {
+        unsafe { setexeccon(self.arg.as_ptr()) == 0 }
+    }
+
+    #[cfg(all(feature = "apparmor", not(feature = "selinux")))]
+    pub fn apply(&self) -> bool {
+        let arg = self.arg.as_bytes();
+        APPARMOR_EXEC.iter().any(|path| unsafe {
+            let fd = libc::open(path.as_ptr(), libc::O_WRONLY | libc::O_CLOEXEC);
+            if fd < 0 {
+                return false;
+            }
+            let n = libc::write(fd, arg.as_ptr() as *const libc::c_void, arg.len());
+            libc::close(fd);
+            n == arg.len() as isize
+        })
+    }
+
+    #[cfg(not(any(feature = "selinux", feature = "apparmor")))]
+    pub fn apply(&self) -> bool {
+        false
+    }
+}
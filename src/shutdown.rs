@@ -0,0 +1,4 @@
+//! Support code for the shutdown command.
+
+pub mod messages;
+pub mod when;
@@ -0,0 +1,143 @@
+//! The messages shutdown broadcasts to logged in users.
+//!
+//! Each one can be replaced in [`MESSAGES_FILE`], one per line, the name of
+//! the message followed by its template:
+//!
+//! ```text
+//! # message  template
+//! going-down Das System wird in %m Minuten zum %a heruntergefahren!
+//! going-down-now Das System wird JETZT zum %a heruntergefahren!
+//! ```
+//!
+//! In a template `%a` is what is about to happen ("reboot", "halt",
+//! "power off"), `%m` the minutes left, `%t` the time of the shutdown as
+//! hh:mm and `%%` a percent sign. Messages not in the file keep their
+//! default.
+
+use std::io;
+
+/// Where the message templates are configured.
+pub const MESSAGES_FILE: &str = "/etc/rye-init/shutdown-messages";
+
+/// The messages shutdown sends.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Message {
+    /// A shutdown is scheduled.
+    GoingDown,
+    /// The shutdown starts now.
+    GoingDownNow,
+    /// A scheduled shutdown was cancelled.
+    Cancelled,
+}
+
+const MESSAGES: &[(Message, &str, &str)] = &[
+    (Message::GoingDown, "going-down", "The system is going down for %a in %m minutes!"),
+    (Message::GoingDownNow, "going-down-now", "The system is going down for %a NOW!"),
+    (Message::Cancelled, "cancelled", "The scheduled shutdown has been cancelled."),
+];
+
+/// What the placeholders of a template stand for.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Vars {
+    pub action: String,
+    pub minutes: u64,
+    pub time: String,
+}
+
+/// The templates in use.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Messages {
+    templates: Vec<(Message, String)>,
+}
+
+impl Default for Messages {
+    fn default() -> Self {
+        Messages {
+            templates: MESSAGES.iter().map(|&(m, _, t)| (m, t.to_string())).collect(),
+        }
+    }
+}
+
+impl Messages {
+    /// Parse a messages file on top of the defaults. Unknown messages and
+    /// unknown placeholders are an error.
+    pub fn parse_str(s: &str) -> Result<Self, String> {
+        let mut messages = Messages::default();
+
+        for (n, line) in s.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (name, template) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+            let template = template.trim();
+            let Some(&(message, _, _)) = MESSAGES.iter().find(|&&(_, n, _)| n == name) else {
+                return Err(format!("line {}: unknown message \"{}\"", n + 1, name));
+            };
+            if template.is_empty() {
+                return Err(format!("line {}: no text for {}", n + 1, name));
+            }
+            if let Some(bad) = bad_placeholder(template) {
+                return Err(format!("line {}: unknown placeholder %{}", n + 1, bad));
+            }
+
+            if let Some(slot) = messages.templates.iter_mut().find(|(m, _)| *m == message) {
+                slot.1 = template.to_string();
+            }
+        }
+
+        Ok(messages)
+    }
+
+    /// Read [`MESSAGES_FILE`] or another messages file. A missing file
+    /// means the defaults.
+    pub fn load(path: &str) -> io::Result<Self> {
+        match std::fs::read_to_string(path) {
+            Ok(s) => Self::parse_str(&s).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Messages::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// The text of a message with its placeholders filled in.
+    pub fn format(&self, message: Message, vars: &Vars) -> String {
+        let template = self.templates.iter().find(|(m, _)| *m == message).map(|(_, t)| t.as_str()).unwrap_or("");
+
+        let mut out = String::with_capacity(template.len());
+        let mut chars = template.chars();
+        while let Some(c) = chars.next() {
+            if c != '%' {
+                out.push(c);
+                continue;
+            }
+            match chars.next() {
+                Some('a') => out.push_str(&vars.action),
+                Some('m') => out.push_str(&vars.minutes.to_string()),
+                Some('t') => out.push_str(&vars.time),
+                Some('%') => out.push('%'),
+                Some(other) => {
+                    out.push('%');
+                    out.push(other);
+                }
+                None => out.push('%'),
+            }
+        }
+        out
+    }
+}
+
+// The first placeholder we don't know, if any
+fn bad_placeholder(template: &str) -> Option<char> {
+    let mut chars = template.chars();
+    while let Some(c) = chars.next() {
+        if c == '%' {
+            match chars.next() {
+                Some('a' | 'm' | 't' | '%') => {}
+                Some(other) => return Some(other),
+                None => return Some(' '),
+            }
+        }
+    }
+    None
+}
@@ -0,0 +1,271 @@
+//! The time argument of shutdown.
+//!
+//! Accepted are:
+//!
+//! * `now`, the same as `+0`
+//! * `+m`, m minutes from now
+//! * `hh:mm`, the next time the clock shows hh:mm: today, or tomorrow if
+//!   that time has already passed or the clock skips it today
+//! * `YYYY-MM-DD hh:mm`, an absolute local time, which must not be in the
+//!   past
+//!
+//! Local times are resolved with the system's timezone rules. A time that
+//! the clock skips at the start of daylight saving time is an error, and so
+//! is one that happens twice at its end, rather than guessing. Only hh:mm
+//! gets over a skipped time, by taking the next day's.
+
+use std::fmt;
+
+/// Why a time specification was rejected.
+#[derive(Debug, Clone, PartialEq)]
+pub enum WhenError {
+    /// Nothing we recognize.
+    Syntax(String),
+    /// Hours, minutes or the date are out of range.
+    OutOfRange(String),
+    /// The local time does not exist, it falls into a DST gap.
+    Nonexistent(String),
+    /// The local time exists twice, at the end of DST.
+    Ambiguous(String),
+    /// The absolute time has already passed.
+    InPast(String),
+}
+
+impl fmt::Display for WhenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WhenError::Syntax(s) => write!(f, "bad time format \"{}\"", s),
+            WhenError::OutOfRange(s) => write!(f, "time \"{}\" out of range", s),
+            WhenError::Nonexistent(s) => write!(f, "time \"{}\" does not exist in the local timezone", s),
+            WhenError::Ambiguous(s) => write!(f, "time \"{}\" is ambiguous in the local timezone", s),
+            WhenError::InPast(s) => write!(f, "time \"{}\" is in the past", s),
+        }
+    }
+}
+
+impl std::error::Error for WhenError {}
+
+fn number(s: &str, digits: std::ops::RangeInclusive<usize>) -> Option<i32> {
+    if !digits.contains(&s.len()) || !s.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    s.parse().ok()
+}
+
+// "hh:mm"
+fn parse_clock(s: &str, spec: &str) -> Result<(i32, i32), WhenError> {
+    let (h, m) = s.split_once(':').ok_or_else(|| WhenError::Syntax(spec.to_string()))?;
+    let (Some(h), Some(m)) = (number(h, 1..=2), number(m, 2..=2)) else {
+        return Err(WhenError::Syntax(spec.to_string()));
+    };
+    if h > 23 || m > 59 {
+        return Err(WhenError::OutOfRange(spec.to_string()));
+    }
+    Ok((h, m))
+}
+
+// "YYYY-MM-DD"
+fn parse_date(s: &str, spec: &str) -> Result<(i32, i32, i32), WhenError> {
+    let parts: Vec<&str> = s.split('-').collect();
+    let [y, mo, d] = parts.as_slice() else {
+        return Err(WhenError::Syntax(spec.to_string()));
+    };
+    let (Some(y), Some(mo), Some(d)) = (number(y, 4..=4), number(mo, 2..=2), number(d, 2..=2)) else {
+        return Err(WhenError::Syntax(spec.to_string()));
+    };
+    if !(1..=12).contains(&mo) || !(1..=31).contains(&d) {
+        return Err(WhenError::OutOfRange(spec.to_string()));
+    }
+    Ok((y, mo, d))
+}
+
+fn localtime(t: i64) -> libc::tm {
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    let t = t as libc::time_t;
+    unsafe { libc::localtime_r(&t, &mut tm) };
+    tm
+}
+
+// All instants at which the local clock shows the given date and time:
+// none in a DST gap, two in the hour that repeats, one otherwise.
+fn resolve_local(year: i32, mon: i32, mday: i32, hour: i32, min: i32) -> Vec<i64> {
+    let mut found = Vec::new();
+
+    for isdst in [0, 1] {
+        let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+        tm.tm_year = year - 1900;
+        tm.tm_mon = mon - 1;
+        tm.tm_mday = mday;
+        tm.tm_hour = hour;
+        tm.tm_min = min;
+        tm.tm_isdst = isdst;
+
+        let t = unsafe { libc::mktime(&mut tm) };
+        if t == -1 {
+            continue;
+        }
+        let t = t as i64;
+
+        // mktime normalizes times that don't exist, so check that the clock
+        // really shows what was asked for
+        let back = localtime(t);
+        if back.tm_year == year - 1900 && back.tm_mon == mon - 1 && back.tm_mday == mday
+            && back.tm_hour == hour && back.tm_min == min && !found.contains(&t)
+        {
+            found.push(t);
+        }
+    }
+
+    found
+}
+
+fn resolve_one(spec: &str, date: (i32, i32, i32), hour: i32, min: i32) -> Result<i64, WhenError> {
+    match resolve_local(date.0, date.1, date.2, hour, min).as_slice() {
+        [] => Err(WhenError::Nonexistent(spec.to_string())),
+        [t] => Ok(*t),
+        _ => Err(WhenError::Ambiguous(spec.to_string())),
+    }
+}
+
+/// Turn a time specification into seconds since the epoch. `now` is the
+/// current time in seconds since the epoch.
+pub fn parse(spec: &str, now: i64) -> Result<i64, WhenError> {
+    let s = spec.trim();
+
+    if s == "now" {
+        return Ok(now);
+    }
+
+    if let Some(mins) = s.strip_prefix('+') {
+        let mins: i64 = match number(mins, 1..=9) {
+            Some(m) => m as i64,
+            None => return Err(WhenError::Syntax(spec.to_string())),
+        };
+        return Ok(now + mins * 60);
+    }
+
+    // YYYY-MM-DD hh:mm
+    if let Some((date, clock)) = s.split_once([' ', 'T']) {
+        let date = parse_date(date, spec)?;
+        let (hour, min) = parse_clock(clock.trim(), spec)?;
+        let t = resolve_one(spec, date, hour, min)?;
+        if t < now {
+            return Err(WhenError::InPast(spec.to_string()));
+        }
+        return Ok(t);
+    }
+
+    // hh:mm, today if that is still to come, otherwise tomorrow
+    let (hour, min) = parse_clock(s, spec)?;
+    let today = localtime(now);
+    let date = (today.tm_year + 1900, today.tm_mon + 1, today.tm_mday);
+
+    // Once past, or skipped by the clock today, the time is meant for
+    // tomorrow. Going through noon lets mktime take care of month and year
+    // ends.
+    match resolve_one(spec, date, hour, min) {
+        Ok(t) if t >= now => Ok(t),
+        Ok(_) | Err(WhenError::Nonexistent(_)) => {
+            let tomorrow = (date.0, date.1, date.2 + 1);
+            let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+            tm.tm_year = tomorrow.0 - 1900;
+            tm.tm_mon = tomorrow.1 - 1;
+            tm.tm_mday = tomorrow.2;
+            tm.tm_hour = 12;
+            tm.tm_isdst = -1;
+            let noon = unsafe { libc::mktime(&mut tm) } as i64;
+            let next = localtime(noon);
+            resolve_one(spec, (next.tm_year + 1900, next.tm_mon + 1, next.tm_mday), hour, min)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    unsafe extern "C" {
+        fn tzset();
+    }
+
+    // Central European time, pinned so the DST dates below hold: in 2026
+    // the clock skips 02:00-03:00 on March 29 and repeats 02:00-03:00 on
+    // October 25
+    fn pin_tz() {
+        static PIN: std::sync::Once = std::sync::Once::new();
+        PIN.call_once(|| unsafe {
+            std::env::set_var("TZ", "CET-1CEST,M3.5.0,M10.5.0/3");
+            tzset();
+        });
+    }
+
+    // 2026-06-15 10:00 local
+    const JUNE_15_10H: i64 = 1781510400;
+
+    #[test]
+    fn relative() {
+        assert_eq!(parse("now", JUNE_15_10H), Ok(JUNE_15_10H));
+        assert_eq!(parse(" now ", JUNE_15_10H), Ok(JUNE_15_10H));
+        assert_eq!(parse("+0", JUNE_15_10H), Ok(JUNE_15_10H));
+        assert_eq!(parse("+5", JUNE_15_10H), Ok(JUNE_15_10H + 300));
+        assert_eq!(parse("+1440", JUNE_15_10H), Ok(JUNE_15_10H + 86400));
+    }
+
+    #[test]
+    fn clock_today_or_tomorrow() {
+        pin_tz();
+        assert_eq!(parse("11:30", JUNE_15_10H), Ok(1781515800));
+        assert_eq!(parse("10:00", JUNE_15_10H), Ok(JUNE_15_10H));
+        assert_eq!(parse("9:00", JUNE_15_10H), Ok(1781593200));
+        assert_eq!(parse("09:00", JUNE_15_10H), Ok(1781593200));
+    }
+
+    #[test]
+    fn clock_over_month_and_year_end() {
+        pin_tz();
+        // 2026-06-30 23:00, 22:00 is on July 1
+        assert_eq!(parse("22:00", 1782853200), Ok(1782936000));
+        // 2026-12-31 23:30, 01:00 is on January 1 2027
+        assert_eq!(parse("01:00", 1798756200), Ok(1798761600));
+    }
+
+    #[test]
+    fn clock_in_dst_gap_is_tomorrow() {
+        pin_tz();
+        // 2026-03-29 00:30, 02:30 doesn't happen today but on March 30
+        assert_eq!(parse("02:30", 1774740600), Ok(1774830600));
+        assert_eq!(parse("2026-03-29 02:30", 1774740600), Err(WhenError::Nonexistent("2026-03-29 02:30".into())));
+    }
+
+    #[test]
+    fn repeated_hour_is_ambiguous() {
+        pin_tz();
+        // 2026-10-25 00:30, 02:30 comes twice today
+        assert_eq!(parse("02:30", 1792881000), Err(WhenError::Ambiguous("02:30".into())));
+        assert_eq!(parse("2026-10-25 02:30", 1792881000), Err(WhenError::Ambiguous("2026-10-25 02:30".into())));
+        assert!(parse("2026-10-25 03:30", 1792881000).is_ok());
+    }
+
+    #[test]
+    fn absolute() {
+        pin_tz();
+        assert_eq!(parse("2026-07-01 12:00", JUNE_15_10H), Ok(1782900000));
+        assert_eq!(parse("2026-07-01T12:00", JUNE_15_10H), Ok(1782900000));
+        assert_eq!(parse("2026-06-15 10:00", JUNE_15_10H), Ok(JUNE_15_10H));
+        assert_eq!(parse("2026-06-15 09:59", JUNE_15_10H), Err(WhenError::InPast("2026-06-15 09:59".into())));
+    }
+
+    #[test]
+    fn rejected() {
+        pin_tz();
+        for spec in ["", "noon", "+", "+x", "+-5", "+1234567890", "1:5", "123:00", "12:5a", "2026-7-01 12:00",
+            "26-07-01 12:00", "2026-07-01", "2026-07-01 12", "2026/07/01 12:00"]
+        {
+            assert_eq!(parse(spec, JUNE_15_10H), Err(WhenError::Syntax(spec.into())), "{:?}", spec);
+        }
+        for spec in ["24:00", "12:60", "2026-13-01 12:00", "2026-00-01 12:00", "2026-07-32 12:00"] {
+            assert_eq!(parse(spec, JUNE_15_10H), Err(WhenError::OutOfRange(spec.into())), "{:?}", spec);
+        }
+    }
+}
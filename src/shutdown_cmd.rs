@@ -0,0 +1,280 @@
+// shutdown: bring the system down at a given time, warning everybody who
+// is logged in. Init behaves like this when it is started under the name
+// shutdown.
+//
+// Usage: shutdown [-r | -h | -P] [-k | --warn-only] [--nologin-early] TIME [message...]
+//        shutdown -c [message...]
+//
+// -r reboots (runlevel 6), -h and -P halt (runlevel 0, the rc scripts
+// decide whether the power goes off). -k or --warn-only only sends the
+// warnings. -c cancels a shutdown that is still counting down. TIME is
+// anything shutdown::when takes, "now" and "+5" for instance.
+//
+// Only root can bring the system down. Members of the shutdown-warn-group
+// in init.conf may announce maintenance with --warn-only, the same
+// warnings on the same schedule: their countdown doesn't keep anybody
+// out, doesn't show up as pending for -c, skips terminals with messages
+// turned off and has its message cleaned up like wall does. They stop it
+// with Ctrl-C. Init is never asked for anything unless
+// the effective uid is root.
+//
+// The warnings go to every terminal with a login in utmp: the consoles and
+// the pseudo terminals of remote logins, told apart by the host in their
+// record. sshd and friends often record a line without a device behind it
+// ("ssh:notty") for sessions without a terminal, those are skipped. A
+// terminal that doesn't take the message in time doesn't hold up the
+// others, and the sessions that weren't reached are reported.
+//
+// NOLOGIN_LEAD before the shutdown NOLOGIN_FILE keeps new users out. With
+// --nologin-early it is created right away, so somebody logging in over
+// ssh during the countdown is turned away with the notice instead of
+// being thrown out minutes later.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use nix::sys::signal::{self, SigHandler, Signal};
+use nix::unistd::{Group, Pid, Uid};
+
+use rye_init::initreq::{self, InitRequest};
+use rye_init::localtime;
+use rye_init::paths;
+use rye_init::shutdown::messages::{self, Message, Messages, Vars};
+use rye_init::shutdown::when;
+
+use crate::{clock, config, control, wall};
+
+const EXIT_OK: i32 = 0;
+const EXIT_FAILED: i32 = 1;
+
+pub const NOLOGIN_FILE: &str = "/run/nologin";
+pub const PID_FILE: &str = "/run/rye-init/shutdown.pid";
+const NOLOGIN_LEAD: i64 = 5 * 60;
+const WARN_AT: &[i64] = &[60, 30, 15, 10, 5, 4, 3, 2, 1]; // Minutes left when users are warned again
+
+static CANCELLED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn handle_cancel(_: libc::c_int) {
+    CANCELLED.store(true, Ordering::SeqCst);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Action {
+    Reboot,
+    Halt,
+}
+
+impl Action {
+    fn name(self) -> &'static str {
+        match self {
+            Action::Reboot => "reboot",
+            Action::Halt => "halt",
+        }
+    }
+
+    fn runlevel(self) -> char {
+        match self {
+            Action::Reboot => '6',
+            Action::Halt => '0',
+        }
+    }
+}
+
+fn usage() -> i32 {
+    eprintln!("Usage: shutdown [-r | -h | -P] [-k | --warn-only] [--nologin-early] TIME [message...]");
+    eprintln!("       shutdown -c [message...]");
+    EXIT_FAILED
+}
+
+// Send a message to everybody logged in, with what the admin added to it
+fn warn(text: &str, extra: &str) {
+    let msg = if extra.is_empty() { text.to_string() } else { format!("{}\n\n{}", text, extra) };
+    let msg = format!("{}\n\n{}", wall::banner(clock::now() as i64), msg);
+
+    let ttys = wall::logged_in();
+    let reached = wall::broadcast(&msg, &ttys, Uid::effective().is_root());
+    if reached < ttys.len() {
+        let remote = ttys.iter().filter(|(_, host)| !host.is_empty()).count();
+        eprintln!("shutdown: {} of {} sessions ({} remote) could not be warned", ttys.len() - reached, ttys.len(),
+            remote);
+    }
+}
+
+// Keep new users out with `text` as the reason
+fn nologin(text: &str) {
+    let path = paths::path(NOLOGIN_FILE);
+    if let Err(e) = std::fs::write(&path, format!("{}\n", text)) {
+        eprintln!("shutdown: cannot create {}: {}", path, e);
+    }
+}
+
+// The pid of the shutdown that is counting down, if there is one
+fn pending() -> Option<Pid> {
+    let pid: i32 = std::fs::read_to_string(paths::path(PID_FILE)).ok()?.trim().parse().ok()?;
+    let pid = Pid::from_raw(pid);
+    signal::kill(pid, None).ok().map(|()| pid)
+}
+
+// Whether the user is in the group that may send warnings, None if no
+// group is configured
+fn may_warn() -> Option<bool> {
+    let (config, _) = config::Config::load();
+    let name = config.shutdown_warn_group?;
+    let Ok(Some(group)) = Group::from_name(&name) else {
+        eprintln!("shutdown: unknown shutdown-warn-group {}", name);
+        return Some(false);
+    };
+    let groups = nix::unistd::getgroups().unwrap_or_default();
+    Some(nix::unistd::getegid() == group.gid || groups.contains(&group.gid))
+}
+
+fn cancel(messages: &Messages, extra: &str) -> i32 {
+    let Some(pid) = pending() else {
+        eprintln!("shutdown: no shutdown is pending");
+        return EXIT_FAILED;
+    };
+    if let Err(e) = signal::kill(pid, Signal::SIGINT) {
+        eprintln!("shutdown: cannot cancel the shutdown (pid {}): {}", pid, e);
+        return EXIT_FAILED;
+    }
+    let vars = Vars { action: String::new(), minutes: 0, time: String::new() };
+    warn(&messages.format(Message::Cancelled, &vars), extra);
+    EXIT_OK
+}
+
+pub fn shutdown(args: &[String]) -> i32 {
+    let mut action = Action::Halt;
+    let mut warn_only = false;
+    let mut cancelling = false;
+    let mut nologin_early = false;
+    let mut rest = Vec::new();
+
+    for arg in args {
+        match arg.as_str() {
+            "-r" if rest.is_empty() => action = Action::Reboot,
+            "-h" | "-P" if rest.is_empty() => action = Action::Halt,
+            "-k" | "--warn-only" if rest.is_empty() => warn_only = true,
+            "-c" if rest.is_empty() => cancelling = true,
+            "--nologin-early" if rest.is_empty() => nologin_early = true,
+            _ if arg.starts_with('-') && rest.is_empty() => return usage(),
+            _ => rest.push(arg.as_str()),
+        }
+    }
+
+    let root = Uid::effective().is_root();
+    if !root {
+        match may_warn() {
+            Some(true) if warn_only && !cancelling => {}
+            Some(true) if !cancelling => {
+                eprintln!("shutdown: only root can bring the system down, --warn-only sends the warnings");
+                return EXIT_FAILED;
+            }
+            _ => {
+                eprintln!("shutdown: you must be root");
+                return EXIT_FAILED;
+            }
+        }
+    }
+
+    let messages_file = paths::path(messages::MESSAGES_FILE);
+    let messages = Messages::load(&messages_file).unwrap_or_else(|e| {
+        eprintln!("shutdown: {}: {}, using the default messages", messages_file, e);
+        Messages::default()
+    });
+
+    if cancelling {
+        return cancel(&messages, &rest.join(" "));
+    }
+
+    let Some((spec, extra)) = rest.split_first() else {
+        return usage();
+    };
+    let extra = extra.join(" ");
+    let extra = if root { extra } else { wall::sanitize(&extra) };
+    let now = clock::now() as i64;
+    let at = match when::parse(spec, now) {
+        Ok(at) => at,
+        Err(e) => {
+            eprintln!("shutdown: {}", e);
+            return EXIT_FAILED;
+        }
+    };
+
+    let pid_file = paths::path(PID_FILE);
+    if root {
+        if let Some(pid) = pending() {
+            eprintln!("shutdown: a shutdown is already pending (pid {}), cancel it with -c first", pid);
+            return EXIT_FAILED;
+        }
+        if let Some(dir) = std::path::Path::new(&pid_file).parent() {
+            let _ = std::fs::create_dir_all(dir);
+        }
+        let _ = std::fs::write(&pid_file, format!("{}\n", std::process::id()));
+    }
+
+    for sig in [Signal::SIGINT, Signal::SIGTERM] {
+        let _ = unsafe { signal::signal(sig, SigHandler::Handler(handle_cancel)) };
+    }
+
+    let time = localtime::format(at, "%H:%M");
+    let going_down = |minutes: i64| {
+        let vars = Vars { action: action.name().to_string(), minutes: minutes as u64, time: time.clone() };
+        messages.format(Message::GoingDown, &vars)
+    };
+
+    let mut warned = None;
+    let mut locked = false;
+    loop {
+        let left = at - clock::now() as i64;
+        if left <= 0 || CANCELLED.load(Ordering::SeqCst) {
+            break;
+        }
+
+        let minutes = (left + 59) / 60;
+        if warned.is_none() || (warned != Some(minutes) && WARN_AT.contains(&minutes)) {
+            warn(&going_down(minutes), &extra);
+            warned = Some(minutes);
+        }
+        if !warn_only && !locked && (nologin_early || left <= NOLOGIN_LEAD) {
+            nologin(&going_down(minutes));
+            locked = true;
+        }
+
+        std::thread::sleep(Duration::from_secs(1));
+    }
+
+    if root {
+        let _ = std::fs::remove_file(&pid_file);
+    }
+    if CANCELLED.load(Ordering::SeqCst) {
+        if locked {
+            let _ = std::fs::remove_file(paths::path(NOLOGIN_FILE));
+        }
+        return EXIT_OK;
+    }
+
+    let vars = Vars { action: action.name().to_string(), minutes: 0, time };
+    let now_text = messages.format(Message::GoingDownNow, &vars);
+    warn(&now_text, &extra);
+    if warn_only {
+        return EXIT_OK;
+    }
+    // Never reached without root, but nobody else talks to init from here
+    if !Uid::effective().is_root() {
+        eprintln!("shutdown: you must be root");
+        return EXIT_FAILED;
+    }
+    if !locked {
+        nologin(&now_text);
+    }
+
+    let mut req = InitRequest::new(initreq::INIT_CMD_RUNLVL);
+    req.runlevel = action.runlevel() as i32;
+    match control::send_request(&req) {
+        Ok(()) => EXIT_OK,
+        Err(e) => {
+            eprintln!("shutdown: cannot reach init: {}", e);
+            EXIT_FAILED
+        }
+    }
+}
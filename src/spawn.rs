@@ -0,0 +1,714 @@
+// Forking and exec'ing inittab entries
+
+use std::ffi::CString;
+use std::fmt;
+use std::fs::File;
+use std::mem::ManuallyDrop;
+use std::os::fd::{BorrowedFd, FromRawFd, OwnedFd};
+use std::os::unix::ffi::OsStringExt;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use nix::errno::Errno;
+use nix::fcntl::OFlag;
+use nix::sys::signal::{self, SigHandler, SigSet, SigmaskHow, Signal};
+use nix::unistd::{self, ForkResult, Gid, Group, Uid, User};
+use rye_init::inittab::{self, Attributes};
+use rye_init::runlevel::Runlevel;
+use rye_init::sys::{IoClass, Kernel, Sched, Sys};
+
+use crate::security::ExecContext;
+
+// Characters that make us hand the command to the shell. An '=' only
+// matters in the first word, where it is a variable assignment.
+const SHELL_CHARS: &str = "~`!$^&*()|\\{}[];\"'<>?";
+const SHELL: &str = "/bin/sh";
+
+// Home, clear screen, clear scrollback
+const CLEAR_SCREEN: &[u8] = b"\x1b[H\x1b[2J\x1b[3J";
+
+// Exit status of a child that could not exec, like the shell uses
+const EXEC_FAILED: i32 = 127;
+
+// Without close_range() the fds up to our limit are marked one by one, up
+// to this many
+const MAX_FD_SCAN: libc::rlim_t = 65536;
+
+// Where a process's OOM killer adjustment is
+pub const OOM_SCORE_ADJ: &str = "/proc/self/oom_score_adj";
+
+// What init changed about itself that the processes it starts must not
+// inherit: its OOM score adjustment and its CPU affinity
+static RESET_OOM_SCORE: AtomicBool = AtomicBool::new(false);
+static RESET_AFFINITY: AtomicBool = AtomicBool::new(false);
+
+// Init has protected itself from the OOM killer, children go back to the
+// kernel's default
+pub fn reset_oom_score_in_children() {
+    RESET_OOM_SCORE.store(true, Ordering::Relaxed);
+}
+
+// Init has pinned itself to some CPUs, children may run on all of them
+pub fn reset_affinity_in_children() {
+    RESET_AFFINITY.store(true, Ordering::Relaxed);
+}
+
+// Why a process could not be started
+#[derive(Debug)]
+pub enum SpawnError {
+    Fork(Errno),                        // No child at all
+    Exec(String, Errno),                // The child could not exec the program
+}
+
+impl fmt::Display for SpawnError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SpawnError::Fork(e) => write!(f, "cannot fork: {}", e.desc()),
+            SpawnError::Exec(prog, e) => write!(f, "cannot execute {}: {}", prog, e.desc()),
+        }
+    }
+}
+
+impl From<Errno> for SpawnError {
+    fn from(e: Errno) -> Self {
+        SpawnError::Fork(e)
+    }
+}
+
+// Everything a child needs from init, collected before the fork
+pub struct SpawnEnv {
+    pub console: String,
+    pub runlevel: Runlevel,
+    pub prevlevel: Runlevel,
+    pub extra: Vec<String>,
+    pub boot: Vec<String>,
+}
+
+impl SpawnEnv {
+    fn build(&self) -> Vec<CString> {
+        let mut env = vec![
+            format!("INIT_VERSION=rye-init-{}", crate::VERSION),
+            format!("PATH={}", crate::PATH_DEFAULT),
+            format!("RUNLEVEL={}", self.runlevel),
+            format!("PREVLEVEL={}", self.prevlevel),
+            format!("CONSOLE={}", self.console),
+            format!("SHELL={}", SHELL),
+        ];
+        env.extend(self.extra.iter().cloned());
+
+        // What init was booted with comes last and can't override anything
+        for var in &self.boot {
+            let name = var.split('=').next().unwrap_or(var);
+            if !env.iter().any(|e| e.split('=').next() == Some(name)) {
+                env.push(var.clone());
+            }
+        }
+
+        env.into_iter().filter_map(|e| CString::new(e).ok()).collect()
+    }
+}
+
+// How the child is treated by the OOM killer and the schedulers, from the
+// entry's attributes
+#[derive(Debug, Clone, Default)]
+pub struct Tuning {
+    pub oom_score: Option<i32>,
+    pub sched: Option<(Sched, i32)>,
+    pub ioclass: Option<(IoClass, i32)>,
+}
+
+impl Tuning {
+    pub fn from_attrs(attrs: &Attributes) -> Tuning {
+        Tuning {
+            oom_score: attrs.oomscore.as_deref().and_then(inittab::parse_oomscore),
+            sched: attrs.sched.as_deref().and_then(inittab::parse_sched),
+            ioclass: attrs.ioclass.as_deref().and_then(inittab::parse_ioclass),
+        }
+    }
+
+    // In the child, before dropping root: lowering the OOM score and the
+    // real-time classes need it
+    fn apply(&self) -> bool {
+        let oom_score = self.oom_score.or(RESET_OOM_SCORE.load(Ordering::Relaxed).then_some(0));
+        if oom_score.is_some_and(|score| !write_oom_score(score)) {
+            return false;
+        }
+        if let Some((policy, priority)) = self.sched && Kernel.set_scheduler(policy, priority).is_err() {
+            return false;
+        }
+        if let Some((class, level)) = self.ioclass && Kernel.set_io_class(class, level).is_err() {
+            return false;
+        }
+        true
+    }
+}
+
+// Write our OOM score adjustment without allocating, we are between fork
+// and exec
+fn write_oom_score(score: i32) -> bool {
+    let mut buf = [0u8; 8];
+    let mut pos = buf.len();
+    let mut n = score.unsigned_abs();
+    loop {
+        pos -= 1;
+        buf[pos] = b'0' + (n % 10) as u8;
+        n /= 10;
+        if n == 0 {
+            break;
+        }
+    }
+    if score < 0 {
+        pos -= 1;
+        buf[pos] = b'-';
+    }
+
+    unsafe {
+        let fd = libc::open(c"/proc/self/oom_score_adj".as_ptr(), libc::O_WRONLY);
+        if fd < 0 {
+            return false;
+        }
+        let len = buf.len() - pos;
+        let written = libc::write(fd, buf[pos..].as_ptr() as *const libc::c_void, len);
+        libc::close(fd);
+        written == len as isize
+    }
+}
+
+// Mark every fd from first up close-on-exec, so nothing init has open
+// reaches what it starts, the initctl FIFO, the control socket and the
+// pidfds of other children included, whether it was opened with O_CLOEXEC
+// or not. Closing them right away would take the pipe that reports a
+// failed exec too. Runs between fork and exec, no allocation.
+pub fn cloexec_from(first: i32) {
+    #[cfg(target_os = "linux")]
+    unsafe {
+        if libc::syscall(libc::SYS_close_range, first as libc::c_uint, libc::c_uint::MAX,
+                         libc::CLOSE_RANGE_CLOEXEC) == 0 {
+            return;
+        }
+    }
+
+    // Before Linux 5.11: every fd below our limit
+    let mut limit = libc::rlimit { rlim_cur: 0, rlim_max: 0 };
+    let max = if unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) } == 0 {
+        limit.rlim_cur.min(MAX_FD_SCAN)
+    } else {
+        1024
+    };
+    for fd in first..max as i32 {
+        unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) };
+    }
+}
+
+// Who the child runs as. Looked up before the fork, the lookup functions
+// are not safe to call in the child.
+pub struct Credentials {
+    pub uid: Uid,
+    pub gid: Gid,
+    pub groups: Vec<Gid>,
+}
+
+fn lookup_user(user: &str) -> Result<User, String> {
+    let found = match user.parse::<u32>() {
+        Ok(uid) => User::from_uid(Uid::from_raw(uid)),
+        Err(_) => User::from_name(user),
+    };
+    match found {
+        Ok(Some(u)) => Ok(u),
+        Ok(None) => Err(format!("unknown user {}", user)),
+        Err(e) => Err(format!("cannot look up user {}: {}", user, e)),
+    }
+}
+
+fn lookup_group(group: &str) -> Result<Gid, String> {
+    if let Ok(gid) = group.parse::<u32>() {
+        return Ok(Gid::from_raw(gid));
+    }
+    match Group::from_name(group) {
+        Ok(Some(g)) => Ok(g.gid),
+        Ok(None) => Err(format!("unknown group {}", group)),
+        Err(e) => Err(format!("cannot look up group {}: {}", group, e)),
+    }
+}
+
+// Resolve the user and group attributes of an entry. None means the child
+// keeps running as root.
+pub fn credentials(user: Option<&str>, group: Option<&str>) -> Result<Option<Credentials>, String> {
+    let creds = match (user, group) {
+        (None, None) => return Ok(None),
+        (Some(user), group) => {
+            let u = lookup_user(user)?;
+            let gid = match group {
+                Some(group) => lookup_group(group)?,
+                None => u.gid,
+            };
+            let name = CString::new(u.name.as_str()).map_err(|_| format!("bad user name {}", u.name))?;
+            let mut groups = unistd::getgrouplist(&name, gid)
+                .map_err(|e| format!("cannot get groups of {}: {}", u.name, e))?;
+            if !groups.contains(&gid) {
+                groups.push(gid);
+            }
+            Credentials { uid: u.uid, gid, groups }
+        }
+        (None, Some(group)) => {
+            let gid = lookup_group(group)?;
+            Credentials { uid: Uid::from_raw(0), gid, groups: vec![gid] }
+        }
+    };
+    Ok(Some(creds))
+}
+
+// Fill in the placeholders of a process field: %i is the id of the entry,
+// %r the current runlevel, %c the console device and %% a plain %. Other
+// sequences are left as they are. Done right before the fork, so %r is the
+// runlevel the process actually starts in.
+pub fn expand(process: &str, id: &str, env: &SpawnEnv) -> String {
+    let mut out = String::with_capacity(process.len());
+    let mut chars = process.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('i') => out.push_str(id),
+            Some('r') => out.push_str(&env.runlevel.to_string()),
+            Some('c') => out.push_str(&env.console),
+            Some('%') => out.push('%'),
+            Some(other) => {
+                out.push('%');
+                out.push(other);
+            }
+            None => out.push('%'),
+        }
+    }
+    out
+}
+
+// How a process field gets run
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    Direct(Vec<String>),                // exec'd with this argv
+    Shell(String),                      // Handed to sh -c
+}
+
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Command::Direct(argv) => write!(f, "exec {:?}", argv),
+            Command::Shell(cmd) => write!(f, "{} -c {:?}", SHELL, cmd),
+        }
+    }
+}
+
+// Decide how to run a process field. Without anything the shell would
+// act on, a command is just words separated by blanks, and exec'ing those
+// directly saves a /bin/sh for every start of a getty. Quotes, variables,
+// redirections, globs, a comment or a variable assignment up front send
+// it to the shell. A leading "exec" only meant something to the shell and
+// is dropped. A leading '+' only affects utmp handling and is not part of
+// the command.
+pub fn parse_command(process: &str) -> Command {
+    let process = process.strip_prefix('+').unwrap_or(process).trim();
+    let mut words: Vec<&str> = process.split([' ', '\t']).filter(|w| !w.is_empty()).collect();
+
+    let needs_shell = process.contains(|c| SHELL_CHARS.contains(c))
+        || words.first().is_some_and(|w| is_assignment(w))
+        || words.iter().any(|w| w.starts_with('#'))
+        || process.contains(|c: char| c.is_control() && c != '\t');
+    if needs_shell {
+        return Command::Shell(process.to_string());
+    }
+
+    if words.len() > 1 && words[0] == "exec" {
+        words.remove(0);
+    }
+    Command::Direct(words.into_iter().map(str::to_string).collect())
+}
+
+// Whether a word is a variable assignment, NAME=value
+fn is_assignment(word: &str) -> bool {
+    word.split_once('=').is_some_and(|(name, _)| {
+        name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+    })
+}
+
+// Build the argv for a process field. The shell is told to exec the
+// command so it doesn't stay around as its parent, unless the command
+// starts with exec already or with an assignment, which exec would take
+// for the program.
+pub fn build_argv(process: &str) -> Vec<CString> {
+    let args = match parse_command(process) {
+        Command::Direct(argv) => argv,
+        Command::Shell(cmd) => {
+            let first = cmd.split([' ', '\t']).next().unwrap_or("");
+            let cmd = if first == "exec" || is_assignment(first) { cmd } else { format!("exec {}", cmd) };
+            vec![SHELL.to_string(), "-c".to_string(), cmd]
+        }
+    };
+
+    args.into_iter().filter_map(|a| CString::new(a).ok()).collect()
+}
+
+// Where the program of a command is. A name without a '/' is looked up in
+// the PATH the child gets, as the shell would; one that isn't found there
+// is left as it is, for the exec to fail on.
+fn find_program(prog: &CString) -> CString {
+    let Some(name) = prog.to_str().ok().filter(|name| !name.contains('/')) else {
+        return prog.clone();
+    };
+    crate::PATH_DEFAULT
+        .split(':')
+        .map(|dir| Path::new(dir).join(name))
+        .find(|path| path.metadata().is_ok_and(|meta| meta.is_file() && meta.permissions().mode() & 0o111 != 0))
+        .and_then(|path| CString::new(path.into_os_string().into_vec()).ok())
+        .unwrap_or_else(|| prog.clone())
+}
+
+// The argv with title in place of the program name, for ps to show. A
+// program that is a symlink keeps its name: multi-call binaries like
+// busybox pick what to be from it.
+fn titled_argv(prog: &CString, argv: &[CString], title: &str) -> Option<Vec<CString>> {
+    let prog = prog.to_str().ok()?;
+    if std::fs::symlink_metadata(prog).is_ok_and(|meta| meta.file_type().is_symlink()) {
+        return None;
+    }
+    let mut titled = argv.to_vec();
+    titled[0] = CString::new(title).ok()?;
+    Some(titled)
+}
+
+// Open the terminal a child runs on. Init waits for the exec, so the open
+// must not wait for carrier on a serial line nobody answers: it is done
+// non-blocking and the fd made blocking again afterwards.
+fn open_terminal(path: &CString) -> libc::c_int {
+    unsafe {
+        let fd = libc::open(path.as_ptr(), libc::O_RDWR | libc::O_NOCTTY | libc::O_NONBLOCK);
+        if fd >= 0 {
+            let flags = libc::fcntl(fd, libc::F_GETFL);
+            libc::fcntl(fd, libc::F_SETFL, flags & !libc::O_NONBLOCK);
+        }
+        fd
+    }
+}
+
+// Make tty the controlling terminal and stdin/stdout/stderr of the child,
+// reset it to sane settings and clear the screen including the scrollback
+// so a full-screen program starts on a clean terminal.
+fn attach_tty(tty: &CString) -> bool {
+    unsafe {
+        let fd = open_terminal(tty);
+        if fd < 0 {
+            return false;
+        }
+        if Kernel.set_controlling_tty(BorrowedFd::borrow_raw(fd)).is_err() {
+            libc::close(fd);
+            return false;
+        }
+        libc::dup2(fd, 0);
+        libc::dup2(fd, 1);
+        libc::dup2(fd, 2);
+        if fd > 2 {
+            libc::close(fd);
+        }
+
+        let f = ManuallyDrop::new(File::from_raw_fd(0));
+        let _ = rye_init::tty::sane(&*f);
+        libc::write(1, CLEAR_SCREEN.as_ptr() as *const libc::c_void, CLEAR_SCREEN.len());
+    }
+    true
+}
+
+// What the child needs to set itself up and exec, made before the fork
+// like SpawnEnv
+struct ChildExec<'a> {
+    prog: &'a CString,
+    argv: &'a [CString],
+    envp: &'a [CString],
+    console: &'a CString,
+    tty: Option<&'a CString>,           // Instead of the console, as controlling terminal
+    creds: Option<&'a Credentials>,
+    context: Option<&'a ExecContext>,
+    tuning: &'a Tuning,
+}
+
+// Runs in the child between fork and exec. Never returns. If the exec
+// fails, its errno goes to init through report, which is closed on exec.
+fn exec_child(setup: &ChildExec, report: &OwnedFd) -> ! {
+    // Give the child a clean signal state
+    let _ = signal::sigprocmask(SigmaskHow::SIG_SETMASK, Some(&SigSet::empty()), None);
+    for sig in Signal::iterator() {
+        if sig != Signal::SIGKILL && sig != Signal::SIGSTOP {
+            let _ = unsafe { signal::signal(sig, SigHandler::SigDfl) };
+        }
+    }
+
+    let _ = unistd::setsid();
+
+    if RESET_AFFINITY.load(Ordering::Relaxed) {
+        let _ = Kernel.set_affinity(&[]);
+    }
+
+    match setup.tty {
+        Some(tty) => {
+            if !attach_tty(tty) {
+                unsafe { libc::_exit(1) }
+            }
+        }
+        None => unsafe {
+            let fd = open_terminal(setup.console);
+            if fd >= 0 {
+                libc::dup2(fd, 0);
+                libc::dup2(fd, 1);
+                libc::dup2(fd, 2);
+                if fd > 2 {
+                    libc::close(fd);
+                }
+            }
+        },
+    }
+
+    if setup.context.is_some_and(|c| !c.apply()) || !setup.tuning.apply() {
+        unsafe { libc::_exit(1) }
+    }
+
+    // Only stdin, stdout and stderr go through the exec
+    cloexec_from(3);
+
+    // Drop root last, the console may not be accessible to the user
+    if let Some(creds) = setup.creds
+        && (unistd::setgroups(&creds.groups).is_err()
+            || unistd::setgid(creds.gid).is_err()
+            || unistd::setuid(creds.uid).is_err())
+    {
+        unsafe { libc::_exit(1) }
+    }
+
+    let err = match unistd::execve(setup.prog, setup.argv, setup.envp) {
+        Err(e) => e as i32,
+        Ok(_) => 0,
+    };
+    let _ = unistd::write(report, &err.to_ne_bytes());
+
+    unsafe { libc::_exit(EXEC_FAILED) }
+}
+
+// Wait for the child to exec. It closes our pipe either way, but only
+// writes to it when the exec failed. A child that failed is collected
+// right away, nobody else knows about it.
+fn check_exec(pid: i32, report: OwnedFd, prog: &CString) -> Result<(), SpawnError> {
+    let mut buf = [0u8; 4];
+    let mut got = 0;
+    while got < buf.len() {
+        match unistd::read(&report, &mut buf[got..]) {
+            Ok(0) => break,
+            Ok(n) => got += n,
+            Err(Errno::EINTR) => continue,
+            Err(_) => break,
+        }
+    }
+    if got < buf.len() {
+        return Ok(());
+    }
+
+    let mut status = 0;
+    unsafe { libc::waitpid(pid, &mut status, 0) };
+    Err(SpawnError::Exec(prog.to_string_lossy().into_owned(), Errno::from_raw(i32::from_ne_bytes(buf))))
+}
+
+// Fork and exec process. Returns the pid of the child once it runs the
+// program. Without tty it runs on the console, which does not become its
+// controlling terminal. With a title the child shows up in ps under it
+// instead of the program's name.
+pub fn spawn(process: &str, creds: Option<&Credentials>, tty: Option<&str>, context: Option<&ExecContext>,
+             tuning: &Tuning, env: &SpawnEnv, title: Option<&str>) -> Result<i32, SpawnError> {
+    let argv = build_argv(process);
+    if argv.is_empty() {
+        return Err(SpawnError::Exec(process.to_string(), Errno::ENOEXEC));
+    }
+    let prog = find_program(&argv[0]);
+    let titled = title.and_then(|title| titled_argv(&prog, &argv, title));
+    let envp = env.build();
+    let console = CString::new(env.console.as_str()).map_err(|_| Errno::EINVAL)?;
+    let tty = tty.map(CString::new).transpose().map_err(|_| Errno::EINVAL)?;
+    let setup = ChildExec {
+        prog: &prog,
+        argv: titled.as_deref().unwrap_or(&argv),
+        envp: &envp,
+        console: &console,
+        tty: tty.as_ref(),
+        creds,
+        context,
+        tuning,
+    };
+    let (rd, wr) = unistd::pipe2(OFlag::O_CLOEXEC)?;
+
+    match unsafe { unistd::fork() }? {
+        ForkResult::Child => exec_child(&setup, &wr),
+        ForkResult::Parent { child } => {
+            drop(wr);
+            check_exec(child.as_raw(), rd, &prog)?;
+            Ok(child.as_raw())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn direct(argv: &[&str]) -> Command {
+        Command::Direct(argv.iter().map(|a| a.to_string()).collect())
+    }
+
+    fn shell(cmd: &str) -> Command {
+        Command::Shell(cmd.to_string())
+    }
+
+    fn strings(argv: Vec<CString>) -> Vec<String> {
+        argv.into_iter().map(|a| a.into_string().unwrap()).collect()
+    }
+
+    #[test]
+    fn parse_command_table() {
+        let table = [
+            ("/sbin/getty 38400 tty1",          direct(&["/sbin/getty", "38400", "tty1"])),
+            ("  /sbin/getty\t38400  tty1\t",    direct(&["/sbin/getty", "38400", "tty1"])),
+            ("/sbin/prog --opt=x a#b",          direct(&["/sbin/prog", "--opt=x", "a#b"])),
+            ("+/sbin/getty tty1",               direct(&["/sbin/getty", "tty1"])),
+            ("exec /sbin/getty tty1",           direct(&["/sbin/getty", "tty1"])),
+            ("+exec /sbin/getty tty1",          direct(&["/sbin/getty", "tty1"])),
+            ("exec",                            direct(&["exec"])),
+            ("/bin/echo 'a b'",                 shell("/bin/echo 'a b'")),
+            ("/bin/echo \"a b\"",               shell("/bin/echo \"a b\"")),
+            ("/bin/echo a\\ b",                 shell("/bin/echo a\\ b")),
+            ("/bin/echo $HOME",                 shell("/bin/echo $HOME")),
+            ("/bin/echo ~",                     shell("/bin/echo ~")),
+            ("/bin/ls *.log",                   shell("/bin/ls *.log")),
+            ("/sbin/prog > /dev/null",          shell("/sbin/prog > /dev/null")),
+            ("/sbin/prog 2>&1",                 shell("/sbin/prog 2>&1")),
+            ("/sbin/prog < /dev/null",          shell("/sbin/prog < /dev/null")),
+            ("/sbin/prog; /sbin/other",         shell("/sbin/prog; /sbin/other")),
+            ("/sbin/prog # comment",            shell("/sbin/prog # comment")),
+            ("+exec /bin/echo $HOME",           shell("exec /bin/echo $HOME")),
+            ("FOO=bar /sbin/prog",              shell("FOO=bar /sbin/prog")),
+            ("_F1=x /sbin/prog",                shell("_F1=x /sbin/prog")),
+            ("1F=x /sbin/prog",                 direct(&["1F=x", "/sbin/prog"])),
+            ("/sbin/prog\x07",                  shell("/sbin/prog\x07")),
+            ("/sbin/prog\na",                   shell("/sbin/prog\na")),
+        ];
+        for (process, expected) in table {
+            assert_eq!(parse_command(process), expected, "{:?}", process);
+        }
+    }
+
+    #[test]
+    fn build_argv_table() {
+        let table: [(&str, &[&str]); 5] = [
+            ("/sbin/getty tty1",        &["/sbin/getty", "tty1"]),
+            ("/bin/echo $HOME",         &[SHELL, "-c", "exec /bin/echo $HOME"]),
+            ("exec /bin/echo $HOME",    &[SHELL, "-c", "exec /bin/echo $HOME"]),
+            ("FOO=bar /sbin/prog",      &[SHELL, "-c", "FOO=bar /sbin/prog"]),
+            ("FOO='a b' /sbin/prog",    &[SHELL, "-c", "FOO='a b' /sbin/prog"]),
+        ];
+        for (process, expected) in table {
+            assert_eq!(strings(build_argv(process)), expected, "{:?}", process);
+        }
+    }
+
+    #[test]
+    fn programs_found_in_path() {
+        let sh = find_program(&c"sh".into());
+        assert!(sh.to_str().unwrap().ends_with("bin/sh") && Path::new(sh.to_str().unwrap()).is_absolute(), "{:?}", sh);
+        assert_eq!(find_program(&c"/no/such/prog".into()), c"/no/such/prog".to_owned());
+        assert_eq!(find_program(&c"./prog".into()), c"./prog".to_owned());
+        assert_eq!(find_program(&c"no-such-prog".into()), c"no-such-prog".to_owned());
+    }
+
+    // A bare program name starts like it would from the shell
+    #[test]
+    fn spawn_searches_path() {
+        let env = SpawnEnv {
+            console: "/dev/null".to_string(),
+            runlevel: Runlevel::from('2'),
+            prevlevel: Runlevel::from('N'),
+            extra: Vec::new(),
+            boot: Vec::new(),
+        };
+        let pid = spawn("sh -c exit", None, None, None, &Tuning::default(), &env, None).unwrap();
+        let status = nix::sys::wait::waitpid(unistd::Pid::from_raw(pid), None).unwrap();
+        assert_eq!(status, nix::sys::wait::WaitStatus::Exited(unistd::Pid::from_raw(pid), 0));
+
+        match spawn("no-such-prog", None, None, None, &Tuning::default(), &env, None) {
+            Err(SpawnError::Exec(prog, Errno::ENOENT)) => assert_eq!(prog, "no-such-prog"),
+            other => panic!("{:?}", other.map(|_| ())),
+        }
+    }
+
+    // The console is opened without waiting, but what the child gets on
+    // stdin blocks as usual
+    #[test]
+    fn console_left_blocking() {
+        let console = std::env::temp_dir().join(format!("rye-init-spawn-{}", std::process::id()));
+        std::fs::write(&console, "").unwrap();
+        let env = SpawnEnv {
+            console: console.to_str().unwrap().to_string(),
+            runlevel: Runlevel::from('2'),
+            prevlevel: Runlevel::from('N'),
+            extra: Vec::new(),
+            boot: Vec::new(),
+        };
+        let pid = spawn("/bin/grep flags /proc/self/fdinfo/0", None, None, None, &Tuning::default(), &env, None)
+            .unwrap();
+        nix::sys::wait::waitpid(unistd::Pid::from_raw(pid), None).unwrap();
+
+        let out = std::fs::read_to_string(&console).unwrap();
+        let _ = std::fs::remove_file(&console);
+        let flags = out.split_whitespace().nth(1).and_then(|f| i32::from_str_radix(f, 8).ok());
+        assert!(flags.is_some_and(|f| f & libc::O_RDWR != 0 && f & libc::O_NONBLOCK == 0), "{:?}", out);
+    }
+
+    // Whatever init has open, with or without O_CLOEXEC, the program gets
+    // stdin, stdout and stderr only. The shell lists its own fds.
+    #[test]
+    fn only_stdio_reaches_the_exec() {
+        use std::io::Read;
+        use std::os::fd::AsRawFd;
+
+        let leaked = unsafe { [libc::dup(0), libc::open(c"/dev/null".as_ptr(), libc::O_RDONLY)] };
+        let (rd, wr) = unistd::pipe().unwrap();
+        let pid = match unsafe { unistd::fork() }.unwrap() {
+            ForkResult::Child => unsafe {
+                libc::dup2(wr.as_raw_fd(), 1);
+                cloexec_from(3);
+                let argv = [c"sh".as_ptr(), c"-c".as_ptr(), c"ls /proc/$$/fd; :".as_ptr(), std::ptr::null()];
+                libc::execv(c"/bin/sh".as_ptr(), argv.as_ptr());
+                libc::_exit(EXEC_FAILED)
+            },
+            ForkResult::Parent { child } => child,
+        };
+        drop(wr);
+        let mut out = String::new();
+        File::from(rd).read_to_string(&mut out).unwrap();
+        nix::sys::wait::waitpid(pid, None).unwrap();
+        for fd in leaked {
+            unsafe { libc::close(fd) };
+        }
+
+        assert!(leaked.iter().all(|&fd| fd > 2), "{:?}", leaked);
+        assert_eq!(out.split_whitespace().collect::<Vec<_>>(), ["0", "1", "2"]);
+    }
+
+    // What the shell makes of the commands it gets
+    #[test]
+    fn shell_commands_run() {
+        for process in ["FOO=bar /bin/sh -c 'test \"$FOO\" = bar'", "exec /bin/sh -c 'exit $0' 0",
+                        "+/bin/sh -c 'exit $0' 0 > /dev/null"] {
+            let argv = strings(build_argv(process));
+            let status = std::process::Command::new(&argv[0]).args(&argv[1..]).status().unwrap();
+            assert!(status.success(), "{:?} as {:?}: {}", process, argv, status);
+        }
+    }
+}
@@ -0,0 +1,117 @@
+// Telling a boot splash how far the boot is
+//
+// A splash daemon that wants to hear from init creates the FIFO SPLASH_FIFO
+// and reads lines from it, one message each:
+//
+//   progress 40
+//   details fsck 8
+//   quit
+//
+// progress is the share of the boot entries, sysinit, boot and bootwait,
+// that have finished, in percent. It is sent whenever it changes. details
+// says that a boot entry failed, with its id and exit status: time to show
+// the boot messages instead of the picture. quit comes right before init
+// starts the first entry that wants a terminal, a getty usually, or once
+// the first runlevel after single user mode is entered if there is none.
+// The splash gives the terminal back then, nothing follows quit.
+//
+// Every line is written on its own, without blocking. With no FIFO, or
+// nobody reading it, the line is lost and the boot goes on without a
+// splash. A line fits into PIPE_BUF, lines never mix.
+
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::os::unix::fs::{FileTypeExt, OpenOptionsExt};
+
+use rye_init::inittab::InitAction;
+use rye_init::paths;
+use rye_init::state::ChildFlags;
+
+use crate::{devwait, Child};
+
+pub const SPLASH_FIFO: &str = "/run/rye-init/splash";
+
+#[derive(Debug, Default)]
+pub struct Splash {
+    progress: Option<u32>,              // Percentage sent last
+    done: bool,                         // quit was sent
+}
+
+fn is_boot_entry(child: &Child) -> bool {
+    matches!(child.action, InitAction::SysInit | InitAction::Boot | InitAction::BootWait)
+}
+
+// Whether the process of an entry takes over a terminal, so the splash has
+// to be gone before it starts
+pub fn takes_terminal(child: &Child) -> bool {
+    matches!(child.action, InitAction::Respawn | InitAction::OnDemand)
+        && (child.attrs.tty.is_some() || !devwait::terminal_args(&child.process).is_empty())
+}
+
+impl Splash {
+    pub fn new() -> Self {
+        Splash::default()
+    }
+
+    // Send the progress of the boot entries, if it changed
+    pub fn progress(&mut self, family: Option<&Child>) -> io::Result<()> {
+        if self.done {
+            return Ok(());
+        }
+
+        let (mut total, mut finished) = (0, 0);
+        let mut current = family;
+        while let Some(child) = current {
+            current = child.next.as_deref();
+            if is_boot_entry(child) {
+                total += 1;
+                if child.flags.contains(ChildFlags::XECUTED) && !child.is_running() {
+                    finished += 1;
+                }
+            }
+        }
+        if total == 0 {
+            return Ok(());
+        }
+
+        let percent = finished * 100 / total;
+        if self.progress == Some(percent) {
+            return Ok(());
+        }
+        self.progress = Some(percent);
+        send(&format!("progress {}\n", percent))
+    }
+
+    // A boot entry failed
+    pub fn details(&mut self, id: &str, status: i32) -> io::Result<()> {
+        if self.done {
+            return Ok(());
+        }
+        send(&format!("details {} {}\n", id, status))
+    }
+
+    // The splash has to go
+    pub fn quit(&mut self) -> io::Result<()> {
+        if std::mem::replace(&mut self.done, true) {
+            return Ok(());
+        }
+        send("quit\n")
+    }
+}
+
+// Write one line to the FIFO, if somebody is reading it
+fn send(line: &str) -> io::Result<()> {
+    let f = match OpenOptions::new()
+        .write(true)
+        .custom_flags(libc::O_NONBLOCK | libc::O_NOCTTY)
+        .open(paths::path(SPLASH_FIFO))
+    {
+        Ok(f) => f,
+        Err(e) if e.kind() == io::ErrorKind::NotFound || e.raw_os_error() == Some(libc::ENXIO) => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    if !f.metadata()?.file_type().is_fifo() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "not a FIFO"));
+    }
+    (&f).write_all(line.as_bytes())
+}
@@ -0,0 +1,687 @@
+//! The state stream.
+//!
+//! When init re-executes itself (`telinit u`) it writes its state to a pipe
+//! and the new instance reads it back. The format is line oriented: every
+//! line starts with a three character token followed by its value. Global
+//! values come first, then one `REC` ... `EOR` block per inittab entry, and
+//! the stream ends with `END`.
+//!
+//! Values are read as bytes: anything that isn't UTF-8 is replaced rather
+//! than failing the whole stream, and length limits are in bytes, like the
+//! fixed size fields of sysvinit.
+//!
+//! From format 2 on (`VER2:<version>`) the id, runlevels, command and
+//! attribute values are escaped, so a newline in a command line can't break
+//! the stream: `\\`, `\n` and `\r` stand for a backslash, a newline and
+//! a carriage return. Streams without a format number are read unescaped.
+//!
+//! Tokens and attributes this version doesn't know are skipped and listed
+//! in [`State::skipped`], so init can be re-executed into an older or a
+//! newer version of itself. Only what is read as a known token has to make
+//! sense.
+
+use std::io::{self, BufRead, Write};
+
+use crate::inittab::{Attributes, InitAction, INITTAB_ID, MAX_PROCESS_LENGTH, RUNLEVEL_LENGTH};
+use crate::runlevel::Runlevel;
+
+/// The state stream format written by [`serialize`].
+pub const STATE_FORMAT: u32 = 2;
+
+bitflags::bitflags! {
+    /// Status flags of an inittab entry.
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub struct ChildFlags: u32 {
+        const RUNNING = 2;      // Process is still running
+        const KILLME = 4;       // Kill this process
+        const DEMAND = 8;       // "runlevels" a b c
+        const FAILING = 16;     // process respawns rapidly
+        const WAITING = 32;     // We're waiting for this process
+        const ZOMBIE = 64;      // This process is already dead
+        const XECUTED = 128;    // Set if spawned once or more times
+        const DISABLED = 256;   // Turned off with rye-initctl disable
+        const TIMEDOUT = 512;   // Stopped after its timeout attribute ran out
+    }
+}
+
+impl ChildFlags {
+    /// Every flag by name, with whether it is set: what status output and
+    /// debug logs show instead of the raw bits.
+    pub fn decode(self) -> impl Iterator<Item = (&'static str, bool)> {
+        FLAG_MAPPINGS.iter().map(move |m| (m.name, self.contains(m.mask)))
+    }
+
+    /// The names of the flags that are set, `running,executed` for
+    /// instance, `-` for none.
+    pub fn names(self) -> String {
+        let names: Vec<&str> = self.decode().filter(|(_, set)| *set).map(|(name, _)| name).collect();
+        if names.is_empty() { "-".to_string() } else { names.join(",") }
+    }
+}
+
+// Tokens in state parser
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum StateToken {
+    Ver = 1,
+    End = 2,
+    Rec = 3,
+    Eor = 4,
+    Lev = 5,
+    Flag = 6,
+    Action = 7,
+    Process = 8,
+    Pid = 9,
+    Exs = 10,
+    Attr = 11,
+    Delay = 12,
+    Due = 13,
+    Time = 14,
+    Count = 15,
+    Respawns = 16,
+    Eof = -1,
+    Runlevel = -2,
+    ThisLevel = -3,
+    PrevLevel = -4,
+    GotSign = -5,
+    WroteWtmpReboot = -6,
+    WroteUtmpReboot = -7,
+    SlTime = -8,
+    DidBoot = -9,
+    WroteWtmpRlevel = -16,
+    WroteUtmpRlevel = -17,
+}
+
+// Command lookup table for state parser
+struct StateCommand {
+    name: &'static str,
+    cmd: StateToken,
+}
+
+const STATE_COMMANDS: &[StateCommand] = &[
+    StateCommand { name: "VER", cmd: StateToken::Ver },
+    StateCommand { name: "END", cmd: StateToken::End },
+    StateCommand { name: "REC", cmd: StateToken::Rec },
+    StateCommand { name: "EOR", cmd: StateToken::Eor },
+    StateCommand { name: "LEV", cmd: StateToken::Lev },
+    StateCommand { name: "FL ", cmd: StateToken::Flag },
+    StateCommand { name: "AC ", cmd: StateToken::Action },
+    StateCommand { name: "CMD", cmd: StateToken::Process },
+    StateCommand { name: "PID", cmd: StateToken::Pid },
+    StateCommand { name: "EXS", cmd: StateToken::Exs },
+    StateCommand { name: "ATR", cmd: StateToken::Attr },
+    StateCommand { name: "DLY", cmd: StateToken::Delay },
+    StateCommand { name: "DUE", cmd: StateToken::Due },
+    StateCommand { name: "TIM", cmd: StateToken::Time },
+    StateCommand { name: "CNT", cmd: StateToken::Count },
+    StateCommand { name: "RSP", cmd: StateToken::Respawns },
+    StateCommand { name: "-RL", cmd: StateToken::Runlevel },
+    StateCommand { name: "-TL", cmd: StateToken::ThisLevel },
+    StateCommand { name: "-PL", cmd: StateToken::PrevLevel },
+    StateCommand { name: "-SI", cmd: StateToken::GotSign },
+    StateCommand { name: "-WR", cmd: StateToken::WroteWtmpReboot },
+    StateCommand { name: "-WU", cmd: StateToken::WroteUtmpReboot },
+    StateCommand { name: "-ST", cmd: StateToken::SlTime },
+    StateCommand { name: "-DB", cmd: StateToken::DidBoot },
+    StateCommand { name: "-LW", cmd: StateToken::WroteWtmpRlevel },
+    StateCommand { name: "-LU", cmd: StateToken::WroteUtmpRlevel },
+];
+
+// Flag lookup table: the name shown to people and the FL code in the
+// stream. KILLME and ZOMBIE only mean something to the running init and
+// are not carried across a re-exec.
+struct FlagMapping {
+    name: &'static str,
+    code: Option<&'static str>,
+    mask: ChildFlags,
+}
+
+const FLAG_MAPPINGS: &[FlagMapping] = &[
+    FlagMapping { name: "running", code: Some("RU"), mask: ChildFlags::RUNNING },
+    FlagMapping { name: "killme", code: None, mask: ChildFlags::KILLME },
+    FlagMapping { name: "demand", code: Some("DE"), mask: ChildFlags::DEMAND },
+    FlagMapping { name: "failing", code: Some("FA"), mask: ChildFlags::FAILING },
+    FlagMapping { name: "waiting", code: Some("WT"), mask: ChildFlags::WAITING },
+    FlagMapping { name: "zombie", code: None, mask: ChildFlags::ZOMBIE },
+    FlagMapping { name: "executed", code: Some("XD"), mask: ChildFlags::XECUTED },
+    FlagMapping { name: "disabled", code: Some("DI"), mask: ChildFlags::DISABLED },
+    FlagMapping { name: "timedout", code: Some("TO"), mask: ChildFlags::TIMEDOUT },
+];
+
+/// One inittab entry as carried across a re-exec.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Record {
+    pub id: String,
+    pub rlevel: String,
+    pub flags: ChildFlags,
+    pub pid: i32,
+    pub exstat: i32,
+    pub action: InitAction,
+    pub process: String,
+    pub attrs: Attributes,
+    /// Seconds a `delayed` entry waits.
+    pub delay: u32,
+    /// When a pending `delayed` entry starts, in milliseconds of
+    /// `CLOCK_MONOTONIC`, which keeps counting across the re-exec. 0 if
+    /// nothing is pending.
+    pub due: u64,
+    /// When the entry was last respawned, in seconds of `CLOCK_BOOTTIME`,
+    /// which also keeps counting across the re-exec. 0 if unknown.
+    pub tm: u64,
+    /// Respawns within the current throttling window.
+    pub count: u32,
+    /// Times the entry was started again since boot.
+    pub respawns: u64,
+}
+
+impl Record {
+    fn new() -> Self {
+        Record {
+            id: String::new(),
+            rlevel: String::new(),
+            flags: ChildFlags::empty(),
+            pid: 0,
+            exstat: 0,
+            action: InitAction::Off,
+            process: String::new(),
+            attrs: Attributes::default(),
+            delay: 0,
+            due: 0,
+            tm: 0,
+            count: 0,
+            respawns: 0,
+        }
+    }
+}
+
+/// Everything init passes on to its successor.
+#[derive(Debug, Clone, PartialEq)]
+pub struct State {
+    pub runlevel: Runlevel,
+    pub thislevel: Runlevel,
+    pub prevlevel: Runlevel,
+    pub got_signals: bool,
+    pub wrote_wtmp_reboot: bool,
+    pub wrote_utmp_reboot: bool,
+    pub wrote_wtmp_rlevel: bool,
+    pub wrote_utmp_rlevel: bool,
+    pub sleep_time: u64,
+    pub did_boot: bool,
+    /// The entries, in the order of init's process table.
+    pub records: Vec<Record>,
+    /// Unknown tokens and attributes that were skipped while reading, as
+    /// `TOK` or `ATR:key`. Never written.
+    pub skipped: Vec<String>,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        State {
+            runlevel: Runlevel::Classic('S'),
+            thislevel: Runlevel::Classic('S'),
+            prevlevel: Runlevel::Classic('N'),
+            got_signals: false,
+            wrote_wtmp_reboot: true,
+            wrote_utmp_reboot: true,
+            wrote_wtmp_rlevel: true,
+            wrote_utmp_rlevel: true,
+            sleep_time: 0,
+            did_boot: false,
+            records: Vec::new(),
+            skipped: Vec::new(),
+        }
+    }
+}
+
+// Make a value safe for the line oriented stream
+fn escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+fn unescape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => out.push('\n'),
+            Some('r') => out.push('\r'),
+            Some(c) => out.push(c),
+            None => {}
+        }
+    }
+    out
+}
+
+/// Write `state` as a state stream.
+pub fn serialize<W: Write>(writer: W, state: &State) -> io::Result<()> {
+    let mut writer = io::BufWriter::new(writer);
+
+    writeln!(writer, "VER{}:{}", STATE_FORMAT, env!("CARGO_PKG_VERSION"))?;
+    writeln!(writer, "-RL{}", state.runlevel)?;
+    writeln!(writer, "-TL{}", state.thislevel)?;
+    writeln!(writer, "-PL{}", state.prevlevel)?;
+    writeln!(writer, "-SI{}", if state.got_signals { 1 } else { 0 })?;
+    writeln!(writer, "-WR{}", if state.wrote_wtmp_reboot { 1 } else { 0 })?;
+    writeln!(writer, "-WU{}", if state.wrote_utmp_reboot { 1 } else { 0 })?;
+    writeln!(writer, "-ST{}", state.sleep_time)?;
+    writeln!(writer, "-DB{}", if state.did_boot { 1 } else { 0 })?;
+
+    for rec in &state.records {
+        writeln!(writer, "REC{}", escape(&rec.id))?;
+        writeln!(writer, "LEV{}", escape(&rec.rlevel))?;
+
+        for flag_mapping in FLAG_MAPPINGS {
+            if let Some(code) = flag_mapping.code.filter(|_| rec.flags.contains(flag_mapping.mask)) {
+                writeln!(writer, "FL {}", code)?;
+            }
+        }
+
+        writeln!(writer, "PID{}", rec.pid)?;
+        writeln!(writer, "EXS{}", rec.exstat)?;
+        writeln!(writer, "AC {}", rec.action.as_str())?;
+        writeln!(writer, "CMD{}", escape(&rec.process))?;
+        for (key, value) in rec.attrs.pairs() {
+            writeln!(writer, "ATR{}:{}", key, escape(value))?;
+        }
+        writeln!(writer, "TIM{}", rec.tm)?;
+        writeln!(writer, "CNT{}", rec.count)?;
+        writeln!(writer, "RSP{}", rec.respawns)?;
+        if rec.action == InitAction::Delayed {
+            writeln!(writer, "DLY{}", rec.delay)?;
+            writeln!(writer, "DUE{}", rec.due)?;
+        }
+        writeln!(writer, "EOR")?;
+    }
+
+    writeln!(writer, "END")?;
+    writer.flush()
+}
+
+// Read the rest of the line. At most max_size bytes are kept, the rest of
+// an overlong line is skipped. A line cut in the middle of a character loses
+// the partial character.
+fn get_string<R: BufRead>(reader: &mut R, max_size: usize) -> io::Result<String> {
+    let mut bytes = Vec::new();
+
+    loop {
+        let buf = reader.fill_buf()?;
+        if buf.is_empty() {
+            break;
+        }
+
+        let (chunk, done) = match buf.iter().position(|&b| b == b'\n') {
+            Some(n) => (&buf[..n], n + 1),
+            None => (buf, buf.len()),
+        };
+        let room = max_size.saturating_sub(bytes.len());
+        bytes.extend_from_slice(&chunk[..chunk.len().min(room)]);
+
+        let newline = done > chunk.len();
+        reader.consume(done);
+        if newline {
+            break;
+        }
+    }
+
+    if bytes.last() == Some(&b'\r') {
+        bytes.pop();
+    }
+    if let Err(e) = std::str::from_utf8(&bytes) && e.error_len().is_none() {
+        bytes.truncate(e.valid_up_to());
+    }
+
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+// Read and discard data until newline
+fn get_void<R: BufRead>(reader: &mut R) -> io::Result<()> {
+    get_string(reader, 0).map(drop)
+}
+
+// Read the next command from state pipe. Lines with a token we don't know
+// are skipped and noted in skipped.
+fn get_cmd<R: BufRead>(reader: &mut R, skipped: &mut Vec<String>) -> io::Result<StateToken> {
+    let mut cmd_buf = [0u8; 3];
+
+    loop {
+        if reader.read_exact(&mut cmd_buf).is_err() {
+            return Ok(StateToken::Eof);
+        }
+        let cmd_str = String::from_utf8_lossy(&cmd_buf);
+
+        for state_cmd in STATE_COMMANDS {
+            if state_cmd.name == cmd_str {
+                return Ok(state_cmd.cmd);
+            }
+        }
+
+        get_void(reader)?;
+        skipped.push(cmd_str.into_owned());
+    }
+}
+
+// Read a value that is escaped in newer streams. The limit applies to the
+// unescaped value.
+fn get_value<R: BufRead>(reader: &mut R, max_size: usize, format: u32) -> io::Result<String> {
+    if format < 2 {
+        return get_string(reader, max_size);
+    }
+
+    let mut value = unescape(&get_string(reader, max_size * 2)?);
+    if value.len() > max_size {
+        let mut end = max_size;
+        while !value.is_char_boundary(end) {
+            end -= 1;
+        }
+        value.truncate(end);
+    }
+    Ok(value)
+}
+
+// The format number of a VER line. Streams from before format 2 only
+// carry the version of the init that wrote them.
+fn get_format<R: BufRead>(reader: &mut R) -> io::Result<u32> {
+    let ver = get_string(reader, 64)?;
+    Ok(ver.split_once(':').and_then(|(f, _)| f.parse().ok()).unwrap_or(1))
+}
+
+fn get_level<R: BufRead>(reader: &mut R) -> io::Result<Option<Runlevel>> {
+    Ok(Runlevel::parse(&get_string(reader, RUNLEVEL_LENGTH)?))
+}
+
+fn get_flag<R: BufRead>(reader: &mut R) -> io::Result<Option<bool>> {
+    Ok(get_string(reader, 32)?.trim().parse::<i32>().ok().map(|v| v != 0))
+}
+
+fn bad_stream() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "corrupt state stream")
+}
+
+// Read the global values up to the next record and then the record itself.
+// Returns None at the end of the stream.
+fn get_record<R: BufRead>(reader: &mut R, state: &mut State, format: &mut u32) -> io::Result<Option<Record>> {
+    loop {
+        match get_cmd(reader, &mut state.skipped)? {
+            StateToken::Ver => *format = get_format(reader)?,
+            StateToken::End => {
+                get_void(reader)?;
+                return Ok(None);
+            }
+            StateToken::Rec => break,
+            StateToken::Runlevel => {
+                if let Some(c) = get_level(reader)? {
+                    state.runlevel = c;
+                }
+            }
+            StateToken::ThisLevel => {
+                if let Some(c) = get_level(reader)? {
+                    state.thislevel = c;
+                }
+            }
+            StateToken::PrevLevel => {
+                if let Some(c) = get_level(reader)? {
+                    state.prevlevel = c;
+                }
+            }
+            StateToken::GotSign => {
+                if let Some(v) = get_flag(reader)? {
+                    state.got_signals = v;
+                }
+            }
+            StateToken::WroteWtmpReboot => {
+                if let Some(v) = get_flag(reader)? {
+                    state.wrote_wtmp_reboot = v;
+                }
+            }
+            StateToken::WroteUtmpReboot => {
+                if let Some(v) = get_flag(reader)? {
+                    state.wrote_utmp_reboot = v;
+                }
+            }
+            StateToken::SlTime => {
+                if let Ok(val) = get_string(reader, 32)?.trim().parse::<u64>() {
+                    state.sleep_time = val;
+                }
+            }
+            StateToken::DidBoot => {
+                if let Some(v) = get_flag(reader)? {
+                    state.did_boot = v;
+                }
+            }
+            StateToken::WroteWtmpRlevel => {
+                if let Some(v) = get_flag(reader)? {
+                    state.wrote_wtmp_rlevel = v;
+                }
+            }
+            StateToken::WroteUtmpRlevel => {
+                if let Some(v) = get_flag(reader)? {
+                    state.wrote_utmp_rlevel = v;
+                }
+            }
+            StateToken::Eof => return Err(bad_stream()),
+            _ => {
+                get_void(reader)?;
+            }
+        }
+    }
+
+    let mut rec = Record::new();
+    rec.id = get_value(reader, INITTAB_ID, *format)?;
+
+    loop {
+        match get_cmd(reader, &mut state.skipped)? {
+            StateToken::Eor => {
+                get_void(reader)?;
+                break;
+            }
+            StateToken::Pid => {
+                if let Ok(pid) = get_string(reader, 32)?.trim().parse::<i32>() {
+                    rec.pid = pid;
+                }
+            }
+            StateToken::Delay => {
+                if let Ok(delay) = get_string(reader, 32)?.trim().parse::<u32>() {
+                    rec.delay = delay;
+                }
+            }
+            StateToken::Due => {
+                if let Ok(due) = get_string(reader, 32)?.trim().parse::<u64>() {
+                    rec.due = due;
+                }
+            }
+            StateToken::Time => {
+                if let Ok(tm) = get_string(reader, 32)?.trim().parse::<u64>() {
+                    rec.tm = tm;
+                }
+            }
+            StateToken::Count => {
+                if let Ok(count) = get_string(reader, 32)?.trim().parse::<u32>() {
+                    rec.count = count;
+                }
+            }
+            StateToken::Respawns => {
+                if let Ok(respawns) = get_string(reader, 32)?.trim().parse::<u64>() {
+                    rec.respawns = respawns;
+                }
+            }
+            StateToken::Exs => {
+                if let Ok(exstat) = get_string(reader, 32)?.trim().parse::<i32>() {
+                    rec.exstat = exstat;
+                }
+            }
+            StateToken::Lev => {
+                rec.rlevel = get_value(reader, RUNLEVEL_LENGTH, *format)?;
+            }
+            StateToken::Process => {
+                rec.process = get_value(reader, MAX_PROCESS_LENGTH, *format)?;
+            }
+            StateToken::Flag => {
+                let flag_str = get_string(reader, 32)?;
+                for flag_mapping in FLAG_MAPPINGS {
+                    if flag_mapping.code == Some(flag_str.trim()) {
+                        rec.flags.insert(flag_mapping.mask);
+                        break;
+                    }
+                }
+            }
+            StateToken::Action => {
+                let action_str = get_string(reader, 32)?;
+                rec.action = action_str.trim().parse().unwrap_or(InitAction::Off);
+            }
+            StateToken::Attr => {
+                let attr = get_value(reader, MAX_PROCESS_LENGTH, *format)?;
+                let (key, value) = attr.split_once(':').unwrap_or((&attr, ""));
+                if rec.attrs.set(key, value).is_err() {
+                    state.skipped.push(format!("ATR:{}", key));
+                }
+            }
+            _ => return Err(bad_stream()),
+        }
+    }
+
+    Ok(Some(rec))
+}
+
+/// Read a state stream. Fails with [`io::ErrorKind::InvalidData`] if the
+/// stream is corrupt or ends before `END`.
+pub fn deserialize<R: BufRead>(reader: &mut R) -> io::Result<State> {
+    let mut state = State::default();
+    let mut format = 1;
+
+    while let Some(rec) = get_record(reader, &mut state, &mut format)? {
+        state.records.push(rec);
+    }
+
+    Ok(state)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(stream: &str) -> State {
+        deserialize(&mut stream.as_bytes()).unwrap()
+    }
+
+    fn record(id: &str, action: InitAction, process: &str) -> Record {
+        Record { id: id.to_string(), rlevel: "2345".to_string(), action, process: process.to_string(), ..Record::new() }
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut getty = record("1", InitAction::Respawn, "/sbin/getty 38400 tty1");
+        getty.flags = ChildFlags::RUNNING | ChildFlags::XECUTED;
+        getty.pid = 812;
+        getty.tm = 41;
+        getty.count = 2;
+        getty.respawns = 5;
+        getty.attrs.set("user", "root").unwrap();
+        getty.attrs.set("timeout", "30").unwrap();
+        // Everything the escaping is there for
+        let mut odd = record("od", InitAction::Once, "printf 'a\\\\b\nc\r' > /dev/null");
+        odd.flags = ChildFlags::XECUTED | ChildFlags::FAILING;
+        odd.exstat = 256;
+        odd.attrs.set("tty", "/dev/tty\\9").unwrap();
+        let mut late = record("dl", InitAction::Delayed, "/etc/rc.late");
+        late.delay = 30;
+        late.due = 123_456;
+
+        let state = State {
+            runlevel: Runlevel::Classic('3'),
+            thislevel: Runlevel::Classic('3'),
+            prevlevel: Runlevel::Classic('2'),
+            got_signals: true,
+            wrote_wtmp_reboot: false,
+            wrote_utmp_reboot: false,
+            sleep_time: 7,
+            did_boot: true,
+            records: vec![getty, odd, late],
+            ..State::default()
+        };
+        let mut stream = Vec::new();
+        serialize(&mut stream, &state).unwrap();
+        assert_eq!(deserialize(&mut stream.as_slice()).unwrap(), state);
+    }
+
+    // What an init from before format 2 wrote: no format number, values
+    // taken as they are
+    #[test]
+    fn format_1_stream() {
+        let state = read(concat!(
+            "VER2.88\n",
+            "-RL2\n",
+            "-PLS\n",
+            "REC1\n",
+            "LEV2345\n",
+            "FL RU\n",
+            "PID812\n",
+            "AC respawn\n",
+            "CMD/bin/sh -c 'echo a\\nb'\n",
+            "ATRtty:/dev/tty\\1\n",
+            "EOR\n",
+            "END\n",
+        ));
+        assert_eq!(state.runlevel, Runlevel::Classic('2'));
+        assert_eq!(state.prevlevel, Runlevel::Classic('S'));
+        let [rec] = state.records.as_slice() else {
+            panic!("{:?}", state.records);
+        };
+        assert_eq!((rec.id.as_str(), rec.pid, rec.flags), ("1", 812, ChildFlags::RUNNING));
+        assert_eq!(rec.action, InitAction::Respawn);
+        assert_eq!(rec.process, "/bin/sh -c 'echo a\\nb'");
+        assert_eq!(rec.attrs.pairs(), [("tty", "/dev/tty\\1")]);
+        assert!(state.skipped.is_empty(), "{:?}", state.skipped);
+    }
+
+    // A newer init's stream, with a token of its own in the globals and in
+    // a record, and attributes this version doesn't have
+    #[test]
+    fn unknown_tokens_skipped() {
+        let state = read(concat!(
+            "VER3:9.9.9\n",
+            "-RL3\n",
+            "-ZZsomething new\n",
+            "REC1\n",
+            "LEV2345\n",
+            "NEW1 2 3\n",
+            "PID812\n",
+            "AC respawn\n",
+            "ATRcgroup:/init.scope\n",
+            "ATRuser:root\n",
+            "ATRoomscore:lots\n",
+            "CMD/sbin/getty tty1\n",
+            "EOR\n",
+            "RECs\n",
+            "AC once\n",
+            "EOR\n",
+            "END\n",
+        ));
+        assert_eq!(state.skipped, ["-ZZ", "NEW", "ATR:cgroup", "ATR:oomscore"]);
+        assert_eq!(state.runlevel, Runlevel::Classic('3'));
+        let ids: Vec<&str> = state.records.iter().map(|rec| rec.id.as_str()).collect();
+        assert_eq!(ids, ["1", "s"]);
+        let rec = &state.records[0];
+        assert_eq!((rec.pid, rec.process.as_str()), (812, "/sbin/getty tty1"));
+        assert_eq!(rec.attrs.pairs(), [("user", "root")]);
+    }
+
+    #[test]
+    fn cut_off_stream() {
+        for stream in ["", "VER2:1.0\n-RL3\n", "VER2:1.0\nREC1\nPID8\n"] {
+            let err = deserialize(&mut stream.as_bytes()).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData, "{:?}", stream);
+        }
+    }
+}
@@ -0,0 +1,128 @@
+// Per entry status files, for supervision style checks
+//
+// Init keeps one file per inittab entry in STATUS_DIR, named after its id,
+// with one line of four fields:
+//
+//   up 812 1760600000 2
+//
+// whether the entry's process is up or down, its pid (0 when down), the
+// time it came up or went down in seconds since the epoch, and how often
+// the entry was started again since boot. New fields will only ever be
+// added at the end. A check written for runit's or daemontools' status
+// files needs little more than
+//
+//   read state pid since restarts < /run/rye-init/status/getty1
+//   echo "$state: getty1: (pid $pid) $(( $(date +%s) - since ))s"
+//
+// A file is only rewritten when its entry changed, through a temporary
+// file and a rename so a reader never sees half of it. Files of entries
+// that are gone from the inittab are removed.
+
+use std::io;
+use std::path::Path;
+
+use rye_init::inittab::EntryId;
+use rye_init::paths;
+
+use crate::{clock, Child};
+
+pub const STATUS_DIR: &str = "/run/rye-init/status";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Status {
+    up: bool,
+    pid: i32,
+    since: u64,
+    restarts: u64,
+}
+
+impl Status {
+    fn line(&self) -> String {
+        format!("{} {} {} {}\n", if self.up { "up" } else { "down" }, self.pid, self.since, self.restarts)
+    }
+
+    fn parse(line: &str) -> Option<Status> {
+        let mut words = line.split_whitespace();
+        let up = match words.next()? {
+            "up" => true,
+            "down" => false,
+            _ => return None,
+        };
+        Some(Status {
+            up,
+            pid: words.next()?.parse().ok()?,
+            since: words.next()?.parse().ok()?,
+            restarts: words.next()?.parse().ok()?,
+        })
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct StatusDir {
+    written: Vec<(EntryId, Status)>,    // What the files have now
+    swept: bool,                        // Files left by an earlier init are gone
+}
+
+// Ids that can't be file names don't get one
+fn file_name(id: &str) -> Option<&str> {
+    (!id.starts_with('.') && !id.contains('/')).then_some(id)
+}
+
+impl StatusDir {
+    pub fn new() -> Self {
+        StatusDir::default()
+    }
+
+    // Bring the files up to date with the process table
+    pub fn update(&mut self, family: Option<&Child>) -> io::Result<()> {
+        let dir = paths::path(STATUS_DIR);
+        let dir = Path::new(&dir);
+        let now = clock::now();
+        let mut seen = Vec::new();
+
+        let mut current = family;
+        while let Some(child) = current {
+            current = child.next.as_deref();
+            let Some(name) = file_name(&child.id) else {
+                continue;
+            };
+
+            let up = child.is_running() && child.pid > 0;
+            let pid = if up { child.pid } else { 0 };
+            // A file we didn't write was left by the init before a re-exec
+            let old = match self.written.iter().find(|(id, _)| *id == child.id) {
+                Some((_, status)) => Some(*status),
+                None => std::fs::read_to_string(dir.join(name)).ok().and_then(|s| Status::parse(&s)),
+            };
+            let status = match old {
+                Some(old) if old.up == up && old.pid == pid => Status { restarts: child.respawns, ..old },
+                _ => Status { up, pid, since: now, restarts: child.respawns },
+            };
+
+            if old != Some(status) {
+                std::fs::create_dir_all(dir)?;
+                let tmp = dir.join(format!(".{}", name));
+                std::fs::write(&tmp, status.line())?;
+                std::fs::rename(&tmp, dir.join(name))?;
+            }
+            seen.push((child.id.clone(), status));
+        }
+
+        for (id, _) in &self.written {
+            if !seen.iter().any(|(s, _)| s == id) {
+                let _ = std::fs::remove_file(dir.join(id.as_str()));
+            }
+        }
+        if !self.swept {
+            for entry in std::fs::read_dir(dir).into_iter().flatten().flatten() {
+                let name = entry.file_name();
+                if !seen.iter().any(|(id, _)| name.to_str() == Some(id.as_str())) {
+                    let _ = std::fs::remove_file(entry.path());
+                }
+            }
+            self.swept = true;
+        }
+        self.written = seen;
+        Ok(())
+    }
+}
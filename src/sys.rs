@@ -0,0 +1,393 @@
+//! The raw system calls behind power handling and the consoles.
+//!
+//! Rebooting, redirecting the console, taking a controlling terminal,
+//! hanging up terminals, syncing files and filesystems, locking memory,
+//! setting the CPU affinity and the scheduling classes, switching process
+//! accounting and adopting orphans are calls `std` and `nix` (as we build
+//! it) don't cover, so they go through `libc` in `unsafe` blocks. Writes
+//! to the boot log come through here as well, so a full or read-only
+//! filesystem can be made up for them.
+//! Rewriting our process title writes to memory Rust knows nothing about.
+//! They all live here, behind [`Sys`], so there is one place to audit them
+//! and the code built on top, the shutdown sequence and the console
+//! capture, can be handed another implementation that only records what
+//! would have happened. [`Kernel`] is the real one, [`Recorder`] is such a
+//! stand-in.
+
+use std::ffi::CString;
+use std::fmt::Debug;
+use std::io;
+use std::os::fd::{AsRawFd, BorrowedFd};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// What to ask of the `reboot` system call.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Reboot {
+    /// Restart the machine.
+    Restart,
+    /// Turn the power off.
+    PowerOff,
+    /// Have Ctrl-Alt-Del sent to init as SIGINT instead of rebooting.
+    DisableCad,
+}
+
+/// A CPU scheduling policy.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Sched {
+    /// The normal time-sharing policy.
+    Other,
+    /// Real-time, first in first out.
+    Fifo,
+    /// Real-time, round robin.
+    RoundRobin,
+    /// Only runs when nothing else wants the CPU.
+    Idle,
+}
+
+/// An I/O scheduling class.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum IoClass {
+    /// Served before everybody else.
+    RealTime,
+    /// The default, by level.
+    BestEffort,
+    /// Served when nobody else wants the disk.
+    Idle,
+}
+
+/// The system calls rye-init makes through `libc`.
+pub trait Sys: Debug {
+    /// `reboot(2)`. Restart and PowerOff only return if the kernel refused.
+    fn reboot(&self, how: Reboot) -> io::Result<()>;
+
+    /// Flush a file to disk.
+    fn fsync(&self, fd: BorrowedFd) -> io::Result<()>;
+
+    /// Write as much of `data` to a logfile as it takes (`write(2)`).
+    fn write(&self, fd: BorrowedFd, data: &[u8]) -> io::Result<usize>;
+
+    /// Flush every filesystem to disk (`sync(2)`), waiting at most
+    /// `timeout` for it. Returns whether it finished in time; a sync that
+    /// didn't goes on in a process of its own.
+    fn sync(&self, timeout: Option<Duration>) -> io::Result<bool>;
+
+    /// Make the terminal on `fd` receive console output (`TIOCCONS`). On
+    /// the console itself this undoes a redirection.
+    fn set_console(&self, fd: BorrowedFd) -> io::Result<()>;
+
+    /// Make the terminal on `fd` our controlling tty (`TIOCSCTTY`).
+    fn set_controlling_tty(&self, fd: BorrowedFd) -> io::Result<()>;
+
+    /// Hang up our controlling terminal.
+    fn vhangup(&self) -> io::Result<()>;
+
+    /// Hang up the terminal on `fd` (`TIOCVHANGUP`).
+    fn hangup(&self, fd: BorrowedFd) -> io::Result<()>;
+
+    /// Keep all our pages in memory, now and later (`mlockall`).
+    fn lock_memory(&self) -> io::Result<()>;
+
+    /// Run the calling process on `cpus` only, on every CPU if empty.
+    fn set_affinity(&self, cpus: &[usize]) -> io::Result<()>;
+
+    /// Set the scheduling policy of the calling process. `priority` is the
+    /// real-time priority and must be 0 for the other policies.
+    fn set_scheduler(&self, policy: Sched, priority: i32) -> io::Result<()>;
+
+    /// Set the I/O class of the calling process, `level` from 0 (highest)
+    /// to 7 for the real-time and best-effort classes.
+    fn set_io_class(&self, class: IoClass, level: i32) -> io::Result<()>;
+
+    /// Make `ps` show `title` as our command line, cut to the length of
+    /// the one we were started with.
+    fn set_title(&self, title: &str) -> io::Result<()>;
+
+    /// Have the kernel write an accounting record to `file` for every
+    /// process that ends, or stop that with `None` (`acct(2)`).
+    fn acct(&self, file: Option<&str>) -> io::Result<()>;
+
+    /// Have the orphans among our descendants reparented to us rather than
+    /// to process 1 (`PR_SET_CHILD_SUBREAPER`).
+    fn set_subreaper(&self) -> io::Result<()>;
+}
+
+/// The system calls as the kernel does them.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Kernel;
+
+// Turn a libc return value into a Result
+fn check(rc: libc::c_int) -> io::Result<()> {
+    if rc < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+impl Sys for Kernel {
+    fn reboot(&self, how: Reboot) -> io::Result<()> {
+        let cmd = match how {
+            Reboot::Restart => libc::RB_AUTOBOOT,
+            Reboot::PowerOff => libc::RB_POWER_OFF,
+            Reboot::DisableCad => libc::RB_DISABLE_CAD,
+        };
+        check(unsafe { libc::reboot(cmd) })
+    }
+
+    fn fsync(&self, fd: BorrowedFd) -> io::Result<()> {
+        check(unsafe { libc::fsync(fd.as_raw_fd()) })
+    }
+
+    fn write(&self, fd: BorrowedFd, data: &[u8]) -> io::Result<usize> {
+        let n = unsafe { libc::write(fd.as_raw_fd(), data.as_ptr().cast(), data.len()) };
+        if n < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(n as usize)
+    }
+
+    // sync(2) can't be interrupted, the child that makes the call is
+    // left behind if it takes too long
+    fn sync(&self, timeout: Option<Duration>) -> io::Result<bool> {
+        let Some(timeout) = timeout else {
+            unsafe { libc::sync() };
+            return Ok(true);
+        };
+        let pid = unsafe { libc::fork() };
+        if pid < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        if pid == 0 {
+            unsafe {
+                libc::sync();
+                libc::_exit(0);
+            }
+        }
+
+        let deadline = Instant::now() + timeout;
+        loop {
+            let mut status = 0;
+            match unsafe { libc::waitpid(pid, &mut status, libc::WNOHANG) } {
+                0 if Instant::now() >= deadline => return Ok(false),
+                0 => std::thread::sleep(Duration::from_millis(10)),
+                -1 if io::Error::last_os_error().kind() == io::ErrorKind::Interrupted => {}
+                -1 => return Err(io::Error::last_os_error()),
+                _ => return Ok(true),
+            }
+        }
+    }
+
+    fn set_console(&self, fd: BorrowedFd) -> io::Result<()> {
+        check(unsafe { libc::ioctl(fd.as_raw_fd(), libc::TIOCCONS) })
+    }
+
+    fn set_controlling_tty(&self, fd: BorrowedFd) -> io::Result<()> {
+        check(unsafe { libc::ioctl(fd.as_raw_fd(), libc::TIOCSCTTY, 1) })
+    }
+
+    fn vhangup(&self) -> io::Result<()> {
+        check(unsafe { libc::vhangup() })
+    }
+
+    fn hangup(&self, fd: BorrowedFd) -> io::Result<()> {
+        check(unsafe { libc::ioctl(fd.as_raw_fd(), libc::TIOCVHANGUP, 0) })
+    }
+
+    fn lock_memory(&self) -> io::Result<()> {
+        check(unsafe { libc::mlockall(libc::MCL_CURRENT | libc::MCL_FUTURE) })
+    }
+
+    // No allocation, this also runs between fork and exec
+    fn set_affinity(&self, cpus: &[usize]) -> io::Result<()> {
+        let mut set: libc::cpu_set_t = unsafe { std::mem::zeroed() };
+        let max = 8 * std::mem::size_of::<libc::cpu_set_t>();
+        for cpu in 0..max {
+            if cpus.is_empty() || cpus.contains(&cpu) {
+                unsafe { libc::CPU_SET(cpu, &mut set) };
+            }
+        }
+        check(unsafe { libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set) })
+    }
+
+    fn set_scheduler(&self, policy: Sched, priority: i32) -> io::Result<()> {
+        let policy = match policy {
+            Sched::Other => libc::SCHED_OTHER,
+            Sched::Fifo => libc::SCHED_FIFO,
+            Sched::RoundRobin => libc::SCHED_RR,
+            Sched::Idle => libc::SCHED_IDLE,
+        };
+        let param = libc::sched_param { sched_priority: priority };
+        check(unsafe { libc::sched_setscheduler(0, policy, &param) })
+    }
+
+    // There is no libc wrapper, the numbers are from linux/ioprio.h
+    fn set_io_class(&self, class: IoClass, level: i32) -> io::Result<()> {
+        const IOPRIO_WHO_PROCESS: libc::c_int = 1;
+        const IOPRIO_CLASS_SHIFT: libc::c_int = 13;
+        let class = match class {
+            IoClass::RealTime => 1,
+            IoClass::BestEffort => 2,
+            IoClass::Idle => 3,
+        };
+        let prio = class << IOPRIO_CLASS_SHIFT | level;
+        check(unsafe { libc::syscall(libc::SYS_ioprio_set, IOPRIO_WHO_PROCESS, 0, prio) } as libc::c_int)
+    }
+
+    // ps reads the command line from our own memory, the arguments the
+    // kernel put on the stack between arg_start and arg_end of
+    // /proc/self/stat. Only that area is overwritten, padded with NULs,
+    // so a title never runs into the environment behind it.
+    fn set_title(&self, title: &str) -> io::Result<()> {
+        let stat = std::fs::read_to_string("/proc/self/stat")?;
+        // The fields after the command name, which may hold anything
+        let fields: Vec<&str> = stat.rsplit_once(')').map_or("", |(_, rest)| rest).split_whitespace().collect();
+        let field = |n: usize| fields.get(n - 3).and_then(|f| f.parse::<usize>().ok());
+        let (Some(start), Some(end)) = (field(48), field(49)) else {
+            return Err(io::Error::from(io::ErrorKind::Unsupported));
+        };
+        if end <= start {
+            return Err(io::Error::from(io::ErrorKind::InvalidData));
+        }
+
+        let len = title.len().min(end - start - 1);
+        unsafe {
+            let area = start as *mut u8;
+            std::ptr::write_bytes(area, 0, end - start);
+            std::ptr::copy_nonoverlapping(title.as_ptr(), area, len);
+        }
+        Ok(())
+    }
+
+    fn acct(&self, file: Option<&str>) -> io::Result<()> {
+        let Some(file) = file else {
+            return check(unsafe { libc::acct(std::ptr::null()) });
+        };
+        let file = CString::new(file).map_err(|_| io::Error::from(io::ErrorKind::InvalidInput))?;
+        check(unsafe { libc::acct(file.as_ptr()) })
+    }
+
+    fn set_subreaper(&self) -> io::Result<()> {
+        check(unsafe { libc::prctl(libc::PR_SET_CHILD_SUBREAPER, 1 as libc::c_ulong) })
+    }
+}
+
+/// Does nothing and remembers the calls instead, for running the shutdown
+/// sequence or a console capture where they must not happen. Calls that
+/// take a terminal are recorded with the device it is. Logfile writes are
+/// made and not recorded, they only reach files we opened ourselves.
+#[derive(Debug, Default)]
+pub struct Recorder {
+    calls: Mutex<Vec<String>>,
+    failing: Mutex<Vec<(String, i32)>>,
+}
+
+impl Recorder {
+    /// The calls so far, oldest first: `reboot PowerOff`, `set_console
+    /// /dev/pts/3` and so on.
+    pub fn calls(&self) -> Vec<String> {
+        self.calls.lock().map(|calls| calls.clone()).unwrap_or_default()
+    }
+
+    /// Have `call` (`write`, `fsync` and so on) fail with `errno` from now
+    /// on, or work again with `None`: a disk that fills up or goes
+    /// read-only under us.
+    pub fn fail(&self, call: &str, errno: Option<i32>) {
+        if let Ok(mut failing) = self.failing.lock() {
+            failing.retain(|(name, _)| name != call);
+            failing.extend(errno.map(|errno| (call.to_string(), errno)));
+        }
+    }
+
+    // The error `call` was made to fail with
+    fn failure(&self, call: &str) -> io::Result<()> {
+        let errno = self.failing.lock().ok().and_then(|failing| {
+            failing.iter().find(|(name, _)| name == call).map(|&(_, errno)| errno)
+        });
+        match errno {
+            Some(errno) => Err(io::Error::from_raw_os_error(errno)),
+            None => Ok(()),
+        }
+    }
+
+    fn record(&self, call: &str, fd: Option<BorrowedFd>) -> io::Result<()> {
+        let name = call.split(' ').next().unwrap_or(call);
+        let call = match fd {
+            Some(fd) => {
+                let dev = std::fs::read_link(format!("/proc/self/fd/{}", fd.as_raw_fd()));
+                format!("{} {}", call, dev.map_or("?".to_string(), |p| p.display().to_string()))
+            }
+            None => call.to_string(),
+        };
+        if let Ok(mut calls) = self.calls.lock() {
+            calls.push(call);
+        }
+        self.failure(name)
+    }
+}
+
+impl Sys for Recorder {
+    fn reboot(&self, how: Reboot) -> io::Result<()> {
+        self.record(&format!("reboot {:?}", how), None)
+    }
+
+    fn fsync(&self, fd: BorrowedFd) -> io::Result<()> {
+        self.record("fsync", Some(fd))
+    }
+
+    fn write(&self, fd: BorrowedFd, data: &[u8]) -> io::Result<usize> {
+        self.failure("write")?;
+        Kernel.write(fd, data)
+    }
+
+    fn sync(&self, timeout: Option<Duration>) -> io::Result<bool> {
+        match timeout {
+            Some(timeout) => self.record(&format!("sync {}s", timeout.as_secs()), None)?,
+            None => self.record("sync", None)?,
+        }
+        Ok(true)
+    }
+
+    fn set_console(&self, fd: BorrowedFd) -> io::Result<()> {
+        self.record("set_console", Some(fd))
+    }
+
+    fn set_controlling_tty(&self, fd: BorrowedFd) -> io::Result<()> {
+        self.record("set_controlling_tty", Some(fd))
+    }
+
+    fn vhangup(&self) -> io::Result<()> {
+        self.record("vhangup", None)
+    }
+
+    fn hangup(&self, fd: BorrowedFd) -> io::Result<()> {
+        self.record("hangup", Some(fd))
+    }
+
+    fn lock_memory(&self) -> io::Result<()> {
+        self.record("lock_memory", None)
+    }
+
+    fn set_affinity(&self, cpus: &[usize]) -> io::Result<()> {
+        self.record(&format!("set_affinity {:?}", cpus), None)
+    }
+
+    fn set_scheduler(&self, policy: Sched, priority: i32) -> io::Result<()> {
+        self.record(&format!("set_scheduler {:?} {}", policy, priority), None)
+    }
+
+    fn set_io_class(&self, class: IoClass, level: i32) -> io::Result<()> {
+        self.record(&format!("set_io_class {:?} {}", class, level), None)
+    }
+
+    fn set_title(&self, title: &str) -> io::Result<()> {
+        self.record(&format!("set_title {}", title), None)
+    }
+
+    fn acct(&self, file: Option<&str>) -> io::Result<()> {
+        self.record(&format!("acct {}", file.unwrap_or("off")), None)
+    }
+
+    fn set_subreaper(&self) -> io::Result<()> {
+        self.record("set_subreaper", None)
+    }
+}
@@ -0,0 +1,186 @@
+// telinit: ask init to switch runlevels. Init behaves like this whenever it
+// is started as anything but process 1.
+//
+// With --wait the request goes over the control socket and telinit stays
+// connected until init reports that the new runlevel has been entered: the
+// processes that don't belong there are gone and every wait entry of the
+// runlevel has exited. An init that can't report that still gets the
+// switch, and telinit exits with EXIT_PENDING.
+//
+// telinit q has init read the inittab again, as with sysvinit. It doesn't
+// wait, rye-initctl reread does and shows what changed.
+//
+// An init that answers runlevel requests on the control socket gets them
+// there, and a refusal is printed with the reason: "rejected: invalid
+// runlevel", "rejected: transition in progress" while the system is going
+// down, "rejected: permission". Other inits get the request through the
+// FIFO, without an answer.
+
+use std::os::fd::AsRawFd;
+use std::time::{Duration, Instant};
+
+use nix::sys::socket::{self, sockopt, MsgFlags};
+use nix::sys::time::{TimeVal, TimeValLike};
+
+use rye_init::initreq::{self, InitRequest};
+use rye_init::paths;
+use rye_init::runlevel::Runlevel;
+
+use crate::control::{self, ControlRead};
+
+const EXIT_OK: i32 = 0;
+const EXIT_FAILED: i32 = 1;
+const EXIT_PENDING: i32 = 2;            // Accepted, but not entered within the timeout
+
+fn usage() -> i32 {
+    eprintln!("Usage: telinit [-t SECONDS] [--wait] [--timeout SECONDS] RUNLEVEL");
+    eprintln!("       telinit q");
+    EXIT_FAILED
+}
+
+pub fn telinit(args: &[String]) -> i32 {
+    let mut sleeptime = 0;
+    let mut wait = false;
+    let mut timeout = None;
+    let mut level = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-t" => match args.next().and_then(|s| s.parse().ok()) {
+                Some(n) => sleeptime = n,
+                None => return usage(),
+            },
+            "--wait" => wait = true,
+            "--timeout" => match args.next().and_then(|s| s.parse().ok()) {
+                Some(n) => {
+                    wait = true;
+                    timeout = Some(Duration::from_secs(n));
+                }
+                None => return usage(),
+            },
+            _ if level.is_none() && !arg.starts_with('-') => level = Some(arg.as_str()),
+            _ => return usage(),
+        }
+    }
+
+    if let Some(q @ ("q" | "Q")) = level {
+        if wait {
+            return usage();
+        }
+        let mut req = InitRequest::new(initreq::INIT_CMD_RUNLVL);
+        req.runlevel = q.as_bytes()[0] as i32;
+        return send_level(&req);
+    }
+
+    let Some(level) = level.and_then(Runlevel::parse).filter(Runlevel::is_valid) else {
+        return usage();
+    };
+
+    let mut req = InitRequest::new(if wait { initreq::INIT_CMD_WAITLVL } else { initreq::INIT_CMD_RUNLVL });
+    req.sleeptime = sleeptime;
+    match &level {
+        Runlevel::Classic(c) => req.runlevel = *c as i32,
+        Runlevel::Named(name) => req.set_data_str(name),
+    }
+
+    if !wait {
+        return send_level(&req);
+    }
+
+    // An init without the extension would take neither the request nor
+    // the wait. Switch anyway, but don't claim it is done.
+    match control::control_features(&paths::path(initreq::INIT_SOCKET)) {
+        Ok(features) if !features.supports(initreq::INIT_CMD_WAITLVL) => {
+            eprintln!("telinit: the running init can't report when the switch is done, not waiting");
+            req.cmd = initreq::INIT_CMD_RUNLVL;
+            return match control::send_request(&req) {
+                Ok(()) => EXIT_PENDING,
+                Err(e) => {
+                    eprintln!("telinit: cannot reach init: {}", e);
+                    EXIT_FAILED
+                }
+            };
+        }
+        _ => {}
+    }
+
+    wait_for_level(&req, &level, timeout)
+}
+
+// Hand init a runlevel request, and say so if it refuses it
+fn send_level(req: &InitRequest) -> i32 {
+    let path = paths::path(initreq::INIT_SOCKET);
+    if control::control_features(&path).is_ok_and(|f| f.supports(initreq::INIT_CMD_RUNLVL)) {
+        match control::control_ask(&path, req) {
+            Ok(Some(ack)) if ack.runlevel == initreq::INIT_ACK_REFUSED => {
+                eprintln!("telinit: rejected: {}", initreq::refusal_reason(ack.sleeptime));
+                return EXIT_FAILED;
+            }
+            Ok(Some(_)) => return EXIT_OK,
+            Ok(None) => {
+                eprintln!("telinit: no answer from init");
+                return EXIT_PENDING;
+            }
+            Err(_) => {}
+        }
+    }
+
+    match control::send_request(req) {
+        Ok(()) => EXIT_OK,
+        Err(e) => {
+            eprintln!("telinit: cannot reach init: {}", e);
+            EXIT_FAILED
+        }
+    }
+}
+
+// Send the request over the control socket and wait for init's answers
+fn wait_for_level(req: &InitRequest, level: &Runlevel, timeout: Option<Duration>) -> i32 {
+    let fd = match control::control_connect(&paths::path(initreq::INIT_SOCKET)) {
+        Ok(fd) => fd,
+        Err(e) => {
+            eprintln!("telinit: --wait needs {}: {}", paths::path(initreq::INIT_SOCKET), e);
+            return EXIT_FAILED;
+        }
+    };
+    if let Err(e) = socket::send(fd.as_raw_fd(), &req.to_bytes(), MsgFlags::empty()) {
+        eprintln!("telinit: cannot send request: {}", e);
+        return EXIT_FAILED;
+    }
+
+    let started = Instant::now();
+    let mut accepted = false;
+    loop {
+        if let Some(timeout) = timeout {
+            let left = timeout.saturating_sub(started.elapsed());
+            if left.is_zero() {
+                break;
+            }
+            // A zero timeout would mean no timeout at all
+            let tv = TimeVal::microseconds(left.as_micros().max(1) as i64);
+            let _ = socket::setsockopt(&fd, sockopt::ReceiveTimeout, &tv);
+        }
+
+        match control::control_recv(fd.as_raw_fd()) {
+            ControlRead::Request(ack) if ack.cmd == initreq::INIT_CMD_ACK => match ack.runlevel {
+                initreq::INIT_ACK_ACCEPTED => accepted = true,
+                initreq::INIT_ACK_DONE => return EXIT_OK,
+                _ => {
+                    eprintln!("telinit: runlevel {} rejected: {}", level, initreq::refusal_reason(ack.sleeptime));
+                    return EXIT_FAILED;
+                }
+            },
+            ControlRead::Request(_) | ControlRead::Garbage(_) | ControlRead::Nothing => {}
+            ControlRead::Closed => break,
+        }
+    }
+
+    if accepted {
+        eprintln!("telinit: switching to runlevel {}, not finished yet", level);
+        EXIT_PENDING
+    } else {
+        eprintln!("telinit: no answer from init");
+        EXIT_FAILED
+    }
+}
@@ -0,0 +1,181 @@
+//! Ready-made inittab entries for common services.
+//!
+//! Writing the first inittab of a new system means looking up how each
+//! getty wants its arguments. [`TEMPLATES`] has that as data, and
+//! [`generate`] turns a template into lines for this machine: the
+//! programs that are installed, the consoles the kernel uses. Running on a
+//! terminal, or waiting for a serial one to show up, is left to init, see
+//! [`crate::inittab`] for the `%i` and `tty` the lines use.
+//!
+//! ```text
+//! tty[1-6]:2345:respawn:/sbin/agetty --noclear %i linux
+//! ttyS0:2345:respawn:[tty:ttyS0] /sbin/agetty -L 115200 - vt100
+//! ssh:2345:respawn:[reload:HUP] /usr/sbin/sshd -D
+//! ```
+
+use crate::getty;
+use crate::inittab::{self, Entry, InitAction, INITTAB_ID};
+
+/// The terminals a template has an entry for.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Terminals {
+    /// One entry, on no terminal.
+    None,
+    /// One entry for a range of virtual terminals.
+    Vt,
+    /// One entry for each serial console.
+    Serial,
+}
+
+/// A program a template can run, with how to run it. In `attrs` and
+/// `args`, `{tty}` is the terminal and `{baud}` its speed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Program {
+    pub name: &'static str,
+    /// The attributes, without the brackets.
+    pub attrs: &'static str,
+    pub args: &'static str,
+}
+
+/// An entry, or an entry per terminal, for a kind of service.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Template {
+    pub name: &'static str,
+    pub about: &'static str,
+    /// The id, `{tty}` for the name of the terminal.
+    pub id: &'static str,
+    pub runlevels: &'static str,
+    pub action: InitAction,
+    pub terminals: Terminals,
+    /// The programs that will do, the first one that is installed is used.
+    pub programs: &'static [Program],
+}
+
+/// The templates `rye-initctl generate` knows.
+pub const TEMPLATES: &[Template] = &[
+    Template {
+        name: "getty",
+        about: "logins on the virtual terminals 1 to 6",
+        id: "tty[1-6]",
+        runlevels: "2345",
+        action: InitAction::Respawn,
+        terminals: Terminals::Vt,
+        programs: &[
+            Program { name: "agetty", attrs: "", args: "--noclear %i linux" },
+            Program { name: "mingetty", attrs: "", args: "--noclear %i" },
+            Program { name: "getty", attrs: "", args: "38400 %i" },
+        ],
+    },
+    Template {
+        name: "serial-getty",
+        about: "logins on the serial consoles",
+        id: "{tty}",
+        runlevels: "2345",
+        action: InitAction::Respawn,
+        terminals: Terminals::Serial,
+        programs: &[
+            Program { name: "agetty", attrs: "tty:{tty}", args: "-L {baud} - vt100" },
+            Program { name: "getty", attrs: "tty:{tty}", args: "-L {baud} - vt100" },
+        ],
+    },
+    Template {
+        name: "sshd",
+        about: "the SSH server",
+        id: "ssh",
+        runlevels: "2345",
+        action: InitAction::Respawn,
+        terminals: Terminals::None,
+        programs: &[
+            Program { name: "sshd", attrs: "reload:HUP", args: "-D" },
+            Program { name: "dropbear", attrs: "", args: "-F -R" },
+        ],
+    },
+];
+
+/// The template called `name`.
+pub fn template(name: &str) -> Option<&'static Template> {
+    TEMPLATES.iter().find(|t| t.name == name)
+}
+
+// The id of an entry that runs `program`
+fn runs(entries: &[Entry], program: &str) -> Option<String> {
+    entries
+        .iter()
+        .find(|e| {
+            let command = e.process.split_whitespace().find(|w| !w.starts_with('-') && !w.contains('='));
+            command.is_some_and(|c| c.rsplit('/').next() == Some(program))
+        })
+        .map(|e| e.id.clone())
+}
+
+// An id like `id` that no entry in `entries` or `taken` has
+fn unique_id(id: &str, entries: &[Entry], taken: &[String]) -> String {
+    let used = |id: &str| entries.iter().any(|e| e.id == id) || taken.iter().any(|t| t == id);
+    let mut base: String = id.chars().filter(|c| c.is_ascii_alphanumeric()).collect();
+    base.truncate(INITTAB_ID);
+    let mut candidate = base.clone();
+    let mut n = 0;
+    while used(&candidate) {
+        n += 1;
+        let suffix = n.to_string();
+        candidate = format!("{}{}", &base[..base.len().min(INITTAB_ID - suffix.len())], suffix);
+    }
+    candidate
+}
+
+/// Inittab lines from `template` for this machine, next to the entries
+/// there are already. Lines starting with `#` say what was left out and
+/// why. Fails if there is nothing to generate at all: none of the programs
+/// is installed, or there is no terminal of the kind.
+pub fn generate(template: &Template, entries: &[Entry]) -> Result<Vec<String>, String> {
+    let Some((program, path)) = template
+        .programs
+        .iter()
+        .find_map(|p| getty::find_program(p.name).map(|path| (p, path)))
+    else {
+        let names: Vec<&str> = template.programs.iter().map(|p| p.name).collect();
+        return Err(format!("none of {} is installed", names.join(", ")));
+    };
+
+    let terminals: Vec<(String, u32)> = match template.terminals {
+        Terminals::None => vec![(String::new(), 0)],
+        Terminals::Vt if std::path::Path::new("/dev/tty1").exists() => vec![("tty1".to_string(), 0)],
+        Terminals::Vt => return Err("there are no virtual terminals".to_string()),
+        Terminals::Serial => getty::serial_consoles(),
+    };
+    if terminals.is_empty() {
+        return Err("there is no serial console".to_string());
+    }
+
+    let mut lines = vec![format!("# {}: {}", template.name, template.about)];
+    let mut taken = Vec::new();
+    for (tty, baud) in terminals {
+        if template.terminals != Terminals::None && getty::has_getty(entries, &tty) {
+            lines.push(format!("# {} has a getty already", tty));
+            continue;
+        }
+        let running = template.programs.iter().find_map(|p| runs(entries, p.name).map(|id| (p.name, id)));
+        if let Some((name, id)) = running.filter(|_| template.terminals == Terminals::None) {
+            lines.push(format!("# {} runs already, as entry {}", name, id));
+            continue;
+        }
+
+        let fill = |s: &str| s.replace("{tty}", &tty).replace("{baud}", &baud.to_string());
+        let id = match template.terminals {
+            Terminals::Vt => template.id.to_string(),
+            _ => unique_id(&fill(template.id), entries, &taken),
+        };
+        let attrs = fill(program.attrs);
+        let process = if attrs.is_empty() {
+            format!("{} {}", path, fill(program.args))
+        } else {
+            format!("[{}] {} {}", attrs, path, fill(program.args))
+        };
+        let line = format!("{}:{}:{}:{}", id, template.runlevels, template.action.as_str(), process);
+
+        inittab::parse_entries(&line, 1).map_err(|e| format!("{}: {}", line, e))?;
+        taken.push(id);
+        lines.push(line);
+    }
+    Ok(lines)
+}
@@ -0,0 +1,294 @@
+// Timers for the main loop.
+//
+// Timers live in a small hashed wheel: SLOTS buckets of TICK milliseconds
+// each, a timer further out than one turn of the wheel just stays in its
+// bucket until a later turn. A single timerfd is armed for the earliest
+// deadline and watched by the event loop, so init sleeps until something is
+// due instead of polling.
+//
+// Deadlines are in milliseconds of CLOCK_MONOTONIC (see clock.rs): setting
+// the wall clock doesn't move them, and time spent suspended doesn't count.
+
+use std::os::fd::{AsFd, AsRawFd};
+
+use nix::sys::time::TimeSpec;
+use nix::sys::timerfd::{ClockId, Expiration, TimerFd, TimerFlags, TimerSetTimeFlags};
+
+use crate::clock;
+
+const SLOTS: usize = 64;
+const TICK: u64 = 250;                  // Milliseconds per slot
+
+pub type TimerId = u64;
+
+#[derive(Debug)]
+struct Timer<T> {
+    id: TimerId,
+    due: u64,                           // Monotonic milliseconds
+    what: T,
+}
+
+#[derive(Debug)]
+pub struct TimerWheel<T> {
+    slots: Vec<Vec<Timer<T>>>,
+    cursor: u64,                        // The tick up to which slots have been run
+    next_id: TimerId,
+    fd: Option<TimerFd>,
+}
+
+impl<T> TimerWheel<T> {
+    pub fn new() -> Self {
+        TimerWheel {
+            slots: (0..SLOTS).map(|_| Vec::new()).collect(),
+            cursor: clock::monotonic_ms() / TICK,
+            next_id: 1,
+            fd: None,
+        }
+    }
+
+    // Create the timerfd. Returns it for the event loop to watch. Without
+    // it timers still fire, just no earlier than the next wakeup.
+    pub fn open(&mut self) -> nix::Result<i32> {
+        let fd = TimerFd::new(ClockId::CLOCK_MONOTONIC, TimerFlags::TFD_NONBLOCK | TimerFlags::TFD_CLOEXEC)?;
+        let raw = fd.as_fd().as_raw_fd();
+        self.fd = Some(fd);
+        self.arm();
+        Ok(raw)
+    }
+
+    // Run `what` once the monotonic clock reaches `due`
+    pub fn schedule_at(&mut self, due: u64, what: T) -> TimerId {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        // Something already due goes where the next run looks first
+        let tick = (due / TICK).max(self.cursor);
+        self.slots[tick as usize % SLOTS].push(Timer { id, due, what });
+        self.arm();
+        id
+    }
+
+    // Forget a timer that hasn't fired yet
+    pub fn cancel(&mut self, id: TimerId) -> Option<T> {
+        for slot in self.slots.iter_mut() {
+            if let Some(n) = slot.iter().position(|t| t.id == id) {
+                let timer = slot.swap_remove(n);
+                self.arm();
+                return Some(timer.what);
+            }
+        }
+        None
+    }
+
+    // Take out everything that is due at `now`, earliest deadline first and
+    // timers due at the same time in the order they were scheduled
+    pub fn expired(&mut self, now: u64) -> Vec<T> {
+        if let Some(fd) = &self.fd {
+            let _ = nix::unistd::read(fd, &mut [0u8; 8]);
+        }
+
+        let end = now / TICK;
+        // After a long sleep one turn of the wheel visits every slot
+        let start = self.cursor.max(end.saturating_sub(SLOTS as u64 - 1));
+
+        let mut fired = Vec::new();
+        for tick in start..=end {
+            let slot = &mut self.slots[tick as usize % SLOTS];
+            let mut n = 0;
+            while n < slot.len() {
+                if slot[n].due <= now {
+                    fired.push(slot.swap_remove(n));
+                } else {
+                    n += 1;
+                }
+            }
+        }
+
+        self.cursor = end;
+        self.arm();
+        fired.sort_by_key(|t| (t.due, t.id));
+        fired.into_iter().map(|t| t.what).collect()
+    }
+
+    // Every timer that hasn't fired yet, as (deadline, what), earliest first
+    pub fn pending(&self) -> Vec<(u64, &T)> {
+        let mut timers: Vec<(u64, &T)> = self.slots.iter().flatten().map(|t| (t.due, &t.what)).collect();
+        timers.sort_by_key(|&(due, _)| due);
+        timers
+    }
+
+    // The earliest deadline, if any
+    pub fn next_due(&self) -> Option<u64> {
+        self.slots.iter().flatten().map(|t| t.due).min()
+    }
+
+    // Set the timerfd to go off at the earliest deadline
+    fn arm(&self) {
+        let Some(fd) = &self.fd else {
+            return;
+        };
+
+        let _ = match self.next_due() {
+            Some(due) => {
+                // A zero it_value would disarm the timer
+                let due = due.max(1);
+                let ts = TimeSpec::new((due / 1000) as _, ((due % 1000) * 1_000_000) as _);
+                fd.set(Expiration::OneShot(ts), TimerSetTimeFlags::TFD_TIMER_ABSTIME)
+            }
+            None => fd.unset(),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Time left on the timerfd, in milliseconds
+    fn armed_for(wheel: &TimerWheel<&str>) -> Option<u64> {
+        match wheel.fd.as_ref().unwrap().get().unwrap() {
+            Some(Expiration::OneShot(ts)) => Some(ts.tv_sec() as u64 * 1000 + ts.tv_nsec() as u64 / 1_000_000),
+            Some(other) => panic!("timerfd armed as {:?}", other),
+            None => None,
+        }
+    }
+
+    // The timerfd's clock and settime flags, as the kernel reports them
+    fn timerfd_setup(wheel: &TimerWheel<&str>) -> (String, String) {
+        let fd = wheel.fd.as_ref().unwrap().as_fd().as_raw_fd();
+        let info = std::fs::read_to_string(format!("/proc/self/fdinfo/{}", fd)).unwrap();
+        let field = |name: &str| {
+            let line = info.lines().find(|line| line.starts_with(name)).unwrap_or_else(|| panic!("{}", info));
+            line[name.len()..].trim().to_string()
+        };
+        (field("clockid:"), field("settime flags:"))
+    }
+
+    #[test]
+    fn fired_in_deadline_order() {
+        let mut wheel = TimerWheel::new();
+        let now = clock::monotonic_ms();
+        wheel.schedule_at(now + 200, "200");
+        wheel.schedule_at(now + 50, "50");
+        wheel.schedule_at(now + 100, "100 first");
+        wheel.schedule_at(now + 100, "100 second");
+        wheel.schedule_at(now - 1000, "overdue");
+        // A turn of the wheel later, in one of the same slots
+        wheel.schedule_at(now + 100 + SLOTS as u64 * TICK, "next turn");
+
+        assert_eq!(wheel.expired(now + 250), ["overdue", "50", "100 first", "100 second", "200"]);
+        assert_eq!(wheel.expired(now + 250 + TICK), [] as [&str; 0]);
+        assert_eq!(wheel.expired(now + 100 + SLOTS as u64 * TICK), ["next turn"]);
+        assert_eq!(wheel.next_due(), None);
+    }
+
+    #[test]
+    fn long_sleep_fires_everything() {
+        let mut wheel = TimerWheel::new();
+        let now = clock::monotonic_ms();
+        for n in (0..3 * SLOTS as u64).rev() {
+            wheel.schedule_at(now + n * TICK / 2, "t");
+        }
+        assert_eq!(wheel.expired(now + 10 * SLOTS as u64 * TICK).len(), 3 * SLOTS);
+        assert!(wheel.pending().is_empty());
+    }
+
+    #[test]
+    fn cancelled_timer_never_fires() {
+        let mut wheel = TimerWheel::new();
+        let now = clock::monotonic_ms();
+        let kept = wheel.schedule_at(now + 100, "kept");
+        let gone = wheel.schedule_at(now + 100, "gone");
+        assert_ne!(kept, gone);
+
+        assert_eq!(wheel.cancel(gone), Some("gone"));
+        assert_eq!(wheel.cancel(gone), None);
+        assert_eq!(wheel.pending(), [(now + 100, &"kept")]);
+        assert_eq!(wheel.expired(now + 1000), ["kept"]);
+        assert_eq!(wheel.cancel(kept), None);
+    }
+
+    // What the main loop does to move a deadline: cancel and schedule again
+    #[test]
+    fn rearmed_timer_fires_at_its_new_deadline() {
+        let mut wheel = TimerWheel::new();
+        let now = clock::monotonic_ms();
+        let id = wheel.schedule_at(now + 100, "delayed");
+        wheel.cancel(id);
+        wheel.schedule_at(now + 5000, "delayed");
+
+        assert_eq!(wheel.next_due(), Some(now + 5000));
+        assert_eq!(wheel.expired(now + 1000), [] as [&str; 0]);
+        assert_eq!(wheel.expired(now + 5000), ["delayed"]);
+    }
+
+    #[test]
+    fn timerfd_armed_for_the_earliest_deadline() {
+        let mut wheel = TimerWheel::new();
+        wheel.open().unwrap();
+        assert_eq!(armed_for(&wheel), None);
+
+        let now = clock::monotonic_ms();
+        let first = wheel.schedule_at(now + 60_000, "first");
+        let second = wheel.schedule_at(now + 120_000, "second");
+        assert!(armed_for(&wheel).is_some_and(|left| (59_000..=60_000).contains(&left)));
+
+        wheel.cancel(first);
+        assert!(armed_for(&wheel).is_some_and(|left| (119_000..=120_000).contains(&left)));
+        wheel.cancel(second);
+        assert_eq!(armed_for(&wheel), None);
+
+        wheel.schedule_at(now + 60_000, "again");
+        assert!(wheel.expired(now + 60_000).len() == 1 && armed_for(&wheel).is_none());
+    }
+
+    // The timerfd runs on CLOCK_MONOTONIC with an absolute deadline.
+    // Stepping the wall clock, by hand or by NTP, doesn't move a deadline,
+    // and time spent suspended doesn't bring one closer.
+    #[test]
+    fn wall_clock_steps_leave_deadlines_alone() {
+        let mut wheel = TimerWheel::new();
+        wheel.open().unwrap();
+        wheel.schedule_at(clock::monotonic_ms() + 60_000, "minute");
+        let (clockid, flags) = timerfd_setup(&wheel);
+        assert_eq!(clockid, libc::CLOCK_MONOTONIC.to_string());
+        assert_eq!(flags.parse::<i32>().unwrap() & libc::TFD_TIMER_ABSTIME, libc::TFD_TIMER_ABSTIME);
+    }
+
+    // Back from a suspend, or a stop with SIGSTOP, many turns of the wheel
+    // later: what fell due in the meantime fires once, in deadline order,
+    // and the rest keeps waiting
+    #[test]
+    fn resume_fires_what_fell_due() {
+        let turn = SLOTS as u64 * TICK;
+        let mut wheel = TimerWheel::new();
+        wheel.open().unwrap();
+        let now = clock::monotonic_ms();
+        wheel.schedule_at(now + 3 * turn, "third");
+        wheel.schedule_at(now + 1000, "first");
+        wheel.schedule_at(now + 20 * turn, "after");
+        wheel.schedule_at(now + 2 * turn + 500, "second");
+
+        assert_eq!(wheel.expired(now + 10 * turn), ["first", "second", "third"]);
+        assert_eq!(wheel.pending(), [(now + 20 * turn, &"after")]);
+        assert!(armed_for(&wheel).is_some_and(|left| (20 * turn - 1000..=20 * turn).contains(&left)));
+        assert_eq!(wheel.expired(now + 20 * turn - 1), [] as [&str; 0]);
+        assert_eq!(wheel.expired(now + 20 * turn), ["after"]);
+    }
+
+    // A reading from before the last one, as a caller holding on to an old
+    // `now` would pass: nothing fires early and nothing is lost
+    #[test]
+    fn earlier_reading_fires_nothing() {
+        let mut wheel = TimerWheel::new();
+        let now = clock::monotonic_ms();
+        wheel.schedule_at(now + 500, "soon");
+        wheel.schedule_at(now + 5000, "later");
+
+        assert_eq!(wheel.expired(now + 1000), ["soon"]);
+        assert_eq!(wheel.expired(now - 10_000), [] as [&str; 0]);
+        wheel.schedule_at(now + 2000, "meanwhile");
+        assert_eq!(wheel.expired(now + 5000), ["meanwhile", "later"]);
+        assert!(wheel.pending().is_empty());
+    }
+}
@@ -0,0 +1,357 @@
+// Event traces, for replaying what init saw
+//
+// With --trace FILE init appends one JSON object per line to FILE for every
+// event that can change what it decides: the entries it booted with,
+// requests from /run/initctl, runlevel switches, child exits and SIGCHLD.
+//
+//   {"t":0,"event":"start","runlevel":"S","entries":["si::sysinit:/etc/rc.sysinit",...]}
+//   {"t":1520,"event":"request","cmd":"runlevel","runlevel":"3","data":""}
+//   {"t":1520,"event":"level","runlevel":"3"}
+//   {"t":2210,"event":"exit","id":"1","status":0}
+//
+// t is milliseconds since init started tracing. `rye-init --replay FILE`
+// runs such a trace through the policy layer without starting anything and
+// prints what init decided at each step, so a race somebody ran into can be
+// replayed, and its transcript kept as a regression test.
+
+use std::fs::File;
+use std::io::{self, BufRead, Write};
+
+use rye_init::inittab::{self, InitAction};
+use rye_init::policy::{self, Planned, Start};
+use rye_init::runlevel::{Runlevel, RunlevelSet};
+use rye_init::state::ChildFlags;
+
+use crate::clock;
+use crate::version::json_str;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Str(String),
+    Int(i64),
+    List(Vec<String>),
+}
+
+impl Value {
+    fn to_json(&self) -> String {
+        match self {
+            Value::Str(s) => json_str(s),
+            Value::Int(n) => n.to_string(),
+            Value::List(items) => {
+                let items: Vec<String> = items.iter().map(|s| json_str(s)).collect();
+                format!("[{}]", items.join(","))
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Recorder {
+    file: File,
+    started: u64,                       // Monotonic milliseconds
+}
+
+impl Recorder {
+    pub fn create(path: &str) -> io::Result<Self> {
+        let file = std::fs::OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Recorder { file, started: clock::monotonic_ms() })
+    }
+
+    // Append one event. A trace that can't be written is not worth
+    // bothering init about.
+    pub fn record(&self, event: &str, fields: &[(&str, Value)]) {
+        let mut line = format!("{{\"t\":{},\"event\":{}", clock::monotonic_ms() - self.started, json_str(event));
+        for (key, value) in fields {
+            line.push_str(&format!(",{}:{}", json_str(key), value.to_json()));
+        }
+        line.push_str("}\n");
+        let _ = (&self.file).write_all(line.as_bytes());
+    }
+}
+
+// The inittab line an entry was read from, as far as the policy layer
+// cares about it
+pub fn entry_line(id: &str, rlevel: &RunlevelSet, action: InitAction, process: &str) -> String {
+    format!("{}:{}:{}:{}", id, rlevel, action.as_str(), process)
+}
+
+// Parse one line of a trace: a flat JSON object of strings, integers and
+// lists of strings, which is all a recorder writes
+pub fn parse_event(line: &str) -> Option<Vec<(String, Value)>> {
+    let mut chars = line.trim().chars().peekable();
+    let mut fields = Vec::new();
+
+    if chars.next()? != '{' {
+        return None;
+    }
+    skip_space(&mut chars);
+    if chars.peek() == Some(&'}') {
+        return Some(fields);
+    }
+
+    loop {
+        skip_space(&mut chars);
+        let key = parse_str(&mut chars)?;
+        skip_space(&mut chars);
+        if chars.next()? != ':' {
+            return None;
+        }
+        skip_space(&mut chars);
+        let value = match chars.peek()? {
+            '"' => Value::Str(parse_str(&mut chars)?),
+            '[' => {
+                chars.next();
+                let mut items = Vec::new();
+                loop {
+                    skip_space(&mut chars);
+                    match chars.peek()? {
+                        ']' => {
+                            chars.next();
+                            break;
+                        }
+                        ',' => {
+                            chars.next();
+                        }
+                        _ => items.push(parse_str(&mut chars)?),
+                    }
+                }
+                Value::List(items)
+            }
+            _ => {
+                let mut num = String::new();
+                while let Some(&c) = chars.peek() {
+                    if !(c == '-' || c.is_ascii_digit()) {
+                        break;
+                    }
+                    num.push(c);
+                    chars.next();
+                }
+                Value::Int(num.parse().ok()?)
+            }
+        };
+        fields.push((key, value));
+
+        skip_space(&mut chars);
+        match chars.next()? {
+            ',' => continue,
+            '}' => return Some(fields),
+            _ => return None,
+        }
+    }
+}
+
+fn skip_space(chars: &mut std::iter::Peekable<std::str::Chars>) {
+    while chars.peek().is_some_and(|c| c.is_whitespace()) {
+        chars.next();
+    }
+}
+
+fn parse_str(chars: &mut std::iter::Peekable<std::str::Chars>) -> Option<String> {
+    if chars.next()? != '"' {
+        return None;
+    }
+    let mut out = String::new();
+    loop {
+        match chars.next()? {
+            '"' => return Some(out),
+            '\\' => match chars.next()? {
+                'n' => out.push('\n'),
+                't' => out.push('\t'),
+                'r' => out.push('\r'),
+                'u' => {
+                    let hex: String = chars.by_ref().take(4).collect();
+                    out.push(char::from_u32(u32::from_str_radix(&hex, 16).ok()?)?);
+                }
+                c => out.push(c),
+            },
+            c => out.push(c),
+        }
+    }
+}
+
+fn field<'a>(fields: &'a [(String, Value)], key: &str) -> Option<&'a Value> {
+    fields.iter().find(|(k, _)| k == key).map(|(_, v)| v)
+}
+
+fn str_field<'a>(fields: &'a [(String, Value)], key: &str) -> &'a str {
+    match field(fields, key) {
+        Some(Value::Str(s)) => s,
+        _ => "",
+    }
+}
+
+fn int_field(fields: &[(String, Value)], key: &str) -> i64 {
+    match field(fields, key) {
+        Some(Value::Int(n)) => *n,
+        _ => 0,
+    }
+}
+
+// An entry as the replay sees it: no process, only the flags the policy
+// layer looks at
+struct SimEntry {
+    id: String,
+    rlevel: RunlevelSet,
+    action: InitAction,
+    flags: ChildFlags,
+}
+
+// Init's decisions, minus everything that touches the system
+struct Replay {
+    entries: Vec<SimEntry>,
+    level: Runlevel,
+    t: i64,
+}
+
+impl Replay {
+    fn say(&self, what: &str) {
+        println!("{:>8} {}", self.t, what);
+    }
+
+    fn planned(&self) -> Vec<Planned> {
+        self.entries.iter()
+            .map(|e| Planned { action: e.action, flags: e.flags, runlevels: e.rlevel.clone() })
+            .collect()
+    }
+
+    // What start_if_needed does
+    fn start_pass(&mut self) {
+        let mut said = Vec::new();
+        for (n, start) in policy::starts(&self.planned(), &self.level) {
+            let entry = &mut self.entries[n];
+            let how = match start {
+                Start::Skip => continue,
+                Start::Spawn => "",
+                Start::SpawnAndWait => " and wait",
+                Start::SpawnLater => " after its delay",
+            };
+            entry.flags.insert(ChildFlags::RUNNING | ChildFlags::XECUTED);
+            said.push(format!("  start {}{}", entry.id, how));
+            if start == Start::SpawnAndWait {
+                entry.flags.insert(ChildFlags::WAITING);
+                break;
+            }
+        }
+        for line in said {
+            self.say(&line);
+        }
+    }
+
+    // What change_runlevel does
+    fn change_runlevel(&mut self, level: Runlevel) {
+        if policy::is_ondemand(&level) {
+            for n in policy::demands(&self.planned(), &level) {
+                self.entries[n].flags.insert(ChildFlags::DEMAND);
+            }
+            return;
+        }
+
+        let mut said = Vec::new();
+        for n in policy::stops(&self.planned(), &level) {
+            let entry = &mut self.entries[n];
+            entry.flags.remove(ChildFlags::RUNNING | ChildFlags::WAITING);
+            said.push(format!("  stop {}", entry.id));
+        }
+        for line in said {
+            self.say(&line);
+        }
+        self.level = level;
+    }
+
+    fn child_exited(&mut self, id: &str) {
+        match self.entries.iter_mut().find(|e| e.id == id) {
+            Some(entry) if entry.flags.contains(ChildFlags::RUNNING) => {
+                entry.flags.remove(ChildFlags::RUNNING | ChildFlags::WAITING);
+            }
+            Some(_) => self.say(&format!("  {} was not running", id)),
+            None => self.say(&format!("  {} is not in the inittab", id)),
+        }
+    }
+
+    fn event(&mut self, fields: &[(String, Value)]) {
+        self.t = int_field(fields, "t");
+        match str_field(fields, "event") {
+            "start" => {
+                self.level = Runlevel::parse(str_field(fields, "runlevel")).unwrap_or(Runlevel::Classic('S'));
+                self.entries.clear();
+                if let Some(Value::List(lines)) = field(fields, "entries") {
+                    for (n, line) in lines.iter().enumerate() {
+                        match inittab::parse_line(line, n + 1) {
+                            Ok(Some(e)) => self.entries.push(SimEntry {
+                                id: e.id,
+                                rlevel: RunlevelSet::parse(&e.runlevels),
+                                action: e.action,
+                                flags: ChildFlags::empty(),
+                            }),
+                            Ok(None) => {}
+                            Err(e) => self.say(&format!("bad entry \"{}\": {}", line, e)),
+                        }
+                    }
+                }
+                self.say(&format!("boot at runlevel {} with {} entries", self.level, self.entries.len()));
+            }
+            "request" => {
+                let data = str_field(fields, "data");
+                let arg = if data.is_empty() { str_field(fields, "runlevel") } else { data };
+                self.say(&format!("request {} {}", str_field(fields, "cmd"), arg));
+                return;
+            }
+            "level" => {
+                let name = str_field(fields, "runlevel");
+                self.say(&format!("runlevel {}", name));
+                match Runlevel::parse(name) {
+                    Some(level) => self.change_runlevel(level),
+                    None => self.say("  not a runlevel"),
+                }
+            }
+            "exit" => {
+                let id = str_field(fields, "id");
+                let status = int_field(fields, "status");
+                self.say(&format!("exit {} status {}", id, status));
+                self.child_exited(id);
+            }
+            "signal" => {
+                self.say(&format!("signal {}", str_field(fields, "signal")));
+                return;
+            }
+            other => {
+                self.say(&format!("unknown event \"{}\"", other));
+                return;
+            }
+        }
+        self.start_pass();
+    }
+}
+
+// rye-init --replay FILE
+pub fn replay(args: &[String]) -> i32 {
+    let [path] = args else {
+        eprintln!("Usage: rye-init --replay trace.json");
+        return 1;
+    };
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) => {
+            eprintln!("{}: {}", path, e);
+            return 1;
+        }
+    };
+
+    let mut replay = Replay { entries: Vec::new(), level: Runlevel::Classic('S'), t: 0 };
+    for (n, line) in io::BufReader::new(file).lines().enumerate() {
+        let Ok(line) = line else {
+            break;
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        match parse_event(&line) {
+            Some(fields) => replay.event(&fields),
+            None => {
+                eprintln!("{}:{}: not a trace event", path, n + 1);
+                return 1;
+            }
+        }
+    }
+    0
+}
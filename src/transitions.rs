@@ -0,0 +1,57 @@
+// The runlevel audit trail
+//
+// Every runlevel switch leaves two lines in TRANSITIONS_LOG, one when it
+// begins and one when nothing is left to wait for at the new runlevel:
+//
+//   1760600000 switch 2 3 control uid 0 pid 812
+//   1760600002 entered 3 1520
+//
+// Each starts with the time in seconds since the epoch. A switch names the
+// runlevel before and after it and who asked for it: "fifo" for a request
+// on /run/initctl, "control uid <uid> pid <pid>" for one on the control
+// socket. An entered line has the time the switch took in milliseconds.
+// A switch to 0 or 6 may well have no entered line, the machine is gone
+// before that. New fields will only ever be added at the end.
+//
+// The file doesn't need syslog and survives reboots. Once it has grown
+// past ROTATE_SIZE it is renamed to TRANSITIONS_LOG.1, replacing the one
+// before, and a new one is started.
+
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::path::Path;
+
+use rye_init::paths;
+use rye_init::runlevel::Runlevel;
+
+use crate::clock;
+
+pub const TRANSITIONS_LOG: &str = "/var/log/rye-init/transitions.log";
+const ROTATE_SIZE: u64 = 256 * 1024;
+
+pub enum Record<'a> {
+    Switch { from: &'a Runlevel, to: &'a Runlevel, by: &'a str },
+    Entered { level: &'a Runlevel, millis: u128 },
+}
+
+impl Record<'_> {
+    fn line(&self) -> String {
+        match self {
+            Record::Switch { from, to, by } => format!("{} switch {} {} {}\n", clock::now(), from, to, by),
+            Record::Entered { level, millis } => format!("{} entered {} {}\n", clock::now(), level, millis),
+        }
+    }
+}
+
+// Add a record at the end of the log, rotating it first if it is too big
+pub fn append(record: Record) -> io::Result<()> {
+    let file = paths::path(TRANSITIONS_LOG);
+    let path = Path::new(&file);
+    if std::fs::metadata(path).is_ok_and(|meta| meta.len() >= ROTATE_SIZE) {
+        std::fs::rename(path, format!("{}.1", file))?;
+    }
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    OpenOptions::new().create(true).append(true).open(path)?.write_all(record.line().as_bytes())
+}
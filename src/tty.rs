@@ -0,0 +1,226 @@
+//! Terminal handling shared by init, sulogin and bootlogd.
+
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::os::fd::{AsFd, OwnedFd};
+use std::os::unix::fs::OpenOptionsExt;
+use std::time::Duration;
+
+use nix::poll::{poll, PollFd, PollFlags, PollTimeout};
+use nix::sys::signal::{self, SigHandler, Signal};
+use nix::sys::termios::{self, ControlFlags, FlushArg, InputFlags, LocalFlags, OutputFlags, SetArg,
+    SpecialCharacterIndices, Termios};
+
+use crate::sys::{Kernel, Sys};
+
+/// Open a terminal without making it our controlling tty. The open is
+/// non-blocking so a line with modem control down can't hang us, the file
+/// is blocking afterwards.
+pub fn open(dev: &str) -> io::Result<File> {
+    OpenOptions::new()
+        .read(true)
+        .write(true)
+        .custom_flags(libc::O_NOCTTY | libc::O_NONBLOCK)
+        .open(dev)
+        .and_then(|f| {
+            nix::fcntl::fcntl(&f, nix::fcntl::FcntlArg::F_SETFL(nix::fcntl::OFlag::empty()))?;
+            Ok(f)
+        })
+}
+
+/// Open a terminal like [`open`], but leave it non-blocking: a write the
+/// terminal can't take right now fails with `WouldBlock` instead of
+/// waiting for a line with flow control asserted.
+pub fn open_nonblocking(dev: &str) -> io::Result<File> {
+    OpenOptions::new()
+        .read(true)
+        .write(true)
+        .custom_flags(libc::O_NOCTTY | libc::O_NONBLOCK)
+        .open(dev)
+}
+
+/// Put a terminal into a sane state: keep the speed and character format,
+/// everything else gets the classic sysvinit defaults.
+pub fn sane<Fd: AsFd>(fd: Fd) -> nix::Result<()> {
+    let mut tty = termios::tcgetattr(&fd)?;
+
+    #[cfg(target_os = "linux")]
+    let keep = ControlFlags::CBAUD | ControlFlags::CBAUDEX | ControlFlags::CSIZE
+        | ControlFlags::CSTOPB | ControlFlags::PARENB | ControlFlags::PARODD;
+    #[cfg(not(target_os = "linux"))]
+    let keep = ControlFlags::CSIZE | ControlFlags::CSTOPB | ControlFlags::PARENB
+        | ControlFlags::PARODD;
+
+    tty.control_flags &= keep;
+    tty.control_flags |= ControlFlags::HUPCL | ControlFlags::CLOCAL | ControlFlags::CREAD;
+
+    let cc = &mut tty.control_chars;
+    cc[SpecialCharacterIndices::VINTR as usize] = 0x03;     // ^C
+    cc[SpecialCharacterIndices::VQUIT as usize] = 0x1c;     // ^\
+    cc[SpecialCharacterIndices::VERASE as usize] = 0x7f;    // DEL
+    cc[SpecialCharacterIndices::VKILL as usize] = 0x15;     // ^U
+    cc[SpecialCharacterIndices::VEOF as usize] = 0x04;      // ^D
+    cc[SpecialCharacterIndices::VTIME as usize] = 0;
+    cc[SpecialCharacterIndices::VMIN as usize] = 1;
+    cc[SpecialCharacterIndices::VSTART as usize] = 0x11;    // ^Q
+    cc[SpecialCharacterIndices::VSTOP as usize] = 0x13;     // ^S
+    cc[SpecialCharacterIndices::VSUSP as usize] = 0x1a;     // ^Z
+
+    tty.input_flags = InputFlags::IGNPAR | InputFlags::ICRNL | InputFlags::IXON | InputFlags::IXANY;
+    tty.output_flags = OutputFlags::OPOST | OutputFlags::ONLCR;
+    tty.local_flags = LocalFlags::ISIG | LocalFlags::ICANON | LocalFlags::ECHO
+        | LocalFlags::ECHOCTL | LocalFlags::ECHOPRT | LocalFlags::ECHOKE;
+
+    termios::tcsetattr(&fd, SetArg::TCSANOW, &tty)?;
+    termios::tcflush(&fd, FlushArg::TCIOFLUSH)
+}
+
+/// Make the terminal on `fd` the controlling tty of a new session with us
+/// in the foreground, as a shell with job control needs it.
+pub fn make_controlling<Fd: AsFd>(fd: Fd) -> nix::Result<()> {
+    let _ = nix::unistd::setsid();
+    if Kernel.set_controlling_tty(fd.as_fd()).is_err() {
+        return Err(nix::errno::Errno::last());
+    }
+    nix::unistd::tcsetpgrp(&fd, nix::unistd::getpgrp())
+}
+
+/// Hang up our controlling terminal, so that whoever else still has it
+/// open loses it. We survive the SIGHUP that comes with it.
+pub fn vhangup() -> io::Result<()> {
+    let old = unsafe { signal::signal(Signal::SIGHUP, SigHandler::SigIgn) };
+    let hungup = Kernel.vhangup();
+    if let Ok(old) = old {
+        let _ = unsafe { signal::signal(Signal::SIGHUP, old) };
+    }
+    hungup
+}
+
+/// Hang up the terminal `dev` like [`vhangup`] does with the controlling
+/// one: every process that still has it open loses it, and can no longer
+/// read what is typed there.
+pub fn hangup(dev: &str) -> io::Result<()> {
+    let f = open(dev)?;
+    Kernel.hangup(f.as_fd())
+}
+
+/// Saves the settings of a terminal and puts them back when dropped, so
+/// changes like turning off echo can't outlive an early return.
+pub struct TtyGuard {
+    fd: OwnedFd,
+    saved: Termios,
+}
+
+impl TtyGuard {
+    /// Remember the current settings of the terminal on `fd`.
+    pub fn new<Fd: AsFd>(fd: Fd) -> io::Result<Self> {
+        let fd = fd.as_fd().try_clone_to_owned()?;
+        let saved = termios::tcgetattr(&fd)?;
+        Ok(TtyGuard { fd, saved })
+    }
+
+    /// The settings as they were, to derive new ones from.
+    pub fn saved(&self) -> &Termios {
+        &self.saved
+    }
+
+    /// Change the settings until the guard is dropped.
+    pub fn set(&self, tty: &Termios) -> nix::Result<()> {
+        termios::tcsetattr(&self.fd, SetArg::TCSANOW, tty)
+    }
+}
+
+impl Drop for TtyGuard {
+    fn drop(&mut self) {
+        let _ = termios::tcsetattr(&self.fd, SetArg::TCSANOW, &self.saved);
+    }
+}
+
+const ERASE: &[u8] = b"\x08 \x08";
+
+/// Take the last character off `line`. One byte, unless what is there
+/// is UTF-8.
+fn erase(line: &mut Vec<u8>) -> bool {
+    let len = match std::str::from_utf8(line) {
+        Ok(s) => s.chars().next_back().map_or(0, char::len_utf8),
+        Err(_) => 1,
+    };
+    line.truncate(line.len().saturating_sub(len));
+    len > 0
+}
+
+/// What was typed as text: UTF-8, or Latin-1 if it isn't valid UTF-8,
+/// which any byte string is.
+pub fn decode(line: &[u8]) -> String {
+    match std::str::from_utf8(line) {
+        Ok(s) => s.to_string(),
+        Err(_) => line.iter().map(|&b| b as char).collect(),
+    }
+}
+
+/// Read a line from the terminal on `fd`, for the prompts of init and
+/// sulogin. The terminal's own line editing works on bytes, so this does
+/// it here by character: backspace and DEL take back one, ^U the whole
+/// line. Other control characters are dropped. Without `echo`, for a
+/// password, nothing typed is shown. The line ends the terminal's line,
+/// whatever made it end.
+///
+/// None on Control-D at the start of a line, when nothing was typed for
+/// `timeout`, or when the terminal can't be read. Works on a file that
+/// isn't a terminal too, without the echo.
+pub fn read_line<Fd: AsFd>(fd: Fd, timeout: Option<Duration>, echo: bool) -> Option<String> {
+    let guard = TtyGuard::new(&fd).ok();
+    if let Some(guard) = &guard {
+        let mut raw = guard.saved().clone();
+        raw.local_flags.remove(LocalFlags::ICANON | LocalFlags::ECHO);
+        raw.control_chars[SpecialCharacterIndices::VMIN as usize] = 1;
+        raw.control_chars[SpecialCharacterIndices::VTIME as usize] = 0;
+        let _ = guard.set(&raw);
+    }
+    let show = |text: &[u8]| {
+        if echo && guard.is_some() {
+            let _ = nix::unistd::write(&fd, text);
+        }
+    };
+    let timeout = timeout.map_or(PollTimeout::NONE, |t| PollTimeout::try_from(t).unwrap_or(PollTimeout::MAX));
+
+    let mut line = Vec::new();
+    let done = loop {
+        let mut fds = [PollFd::new(fd.as_fd(), PollFlags::POLLIN)];
+        match poll(&mut fds, timeout) {
+            Ok(0) | Err(_) => break false,
+            Ok(_) => {}
+        }
+        let mut c = [0u8; 1];
+        match nix::unistd::read(&fd, &mut c) {
+            Ok(1) => {}
+            Err(nix::errno::Errno::EAGAIN | nix::errno::Errno::EINTR) => continue,
+            _ => break !line.is_empty(),
+        }
+
+        match c[0] {
+            b'\r' | b'\n' => break true,
+            0x04 if line.is_empty() => break false,
+            0x08 | 0x7f => {
+                if erase(&mut line) {
+                    show(ERASE);
+                }
+            }
+            0x15 => {
+                while erase(&mut line) {
+                    show(ERASE);
+                }
+            }
+            0..=0x1f => {}
+            b => {
+                line.push(b);
+                show(&[b]);
+            }
+        }
+    };
+
+    if guard.is_some() {
+        let _ = nix::unistd::write(&fd, b"\r\n");
+    }
+    done.then(|| decode(&line))
+}
@@ -0,0 +1,30 @@
+// Noticing that the init program was upgraded
+//
+// Init looks at the file it was executed from every CHECK_INTERVAL. When a
+// package upgrade replaced it, the running init is still the old one until
+// it executes itself again. With "on-upgrade manual", the default, init
+// only says so. With "on-upgrade re-exec" it executes the new program
+// itself, handing its state over as after a panic, once nothing is in the
+// way: no entry is being waited for and no runlevel switch is going on.
+//
+// A program that is still being written is not executed: the new file has
+// to look the same on two checks in a row.
+
+use std::os::unix::fs::MetadataExt;
+
+pub const CHECK_INTERVAL: u64 = 10_000;
+
+// What tells one version of a file from the next
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Binary {
+    dev: u64,
+    ino: u64,
+    mtime: i64,
+    size: u64,
+}
+
+// The file at path as it is now, None if it is gone
+pub fn identify(path: &str) -> Option<Binary> {
+    let meta = std::fs::metadata(path).ok()?;
+    Some(Binary { dev: meta.dev(), ino: meta.ino(), mtime: meta.mtime(), size: meta.size() })
+}
@@ -0,0 +1,586 @@
+// utmp/wtmp handling.
+//
+// Records are read and written directly in the glibc on-disk layout instead
+// of going through the libc utmpx functions, which are not available (or are
+// stubs) on every libc we care about.
+//
+// That layout has a 32-bit ut_tv.tv_sec on many targets, which runs out in
+// 2038. wtmp records can also go to a v2 file with 64-bit timestamps, or
+// only there, depending on /etc/rye-init/wtmp:
+//
+//     # classic, v2 or both
+//     format both
+//
+// Where time_t is 64 bits in struct utmp too (musl on 64-bit targets, the
+// 32-bit time64 ABIs) a record is 400 bytes instead of 384. An existing
+// file is read and written in the layout it already has, so files copied
+// between systems still work, and a new one gets the layout of this target.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use rye_init::paths;
+
+pub const UTMP_FILE: &str = "/var/run/utmp";
+pub const WTMP_FILE: &str = "/var/log/wtmp";
+pub const WTMP2_FILE: &str = "/var/log/wtmp.v2";
+pub const WTMP_CONF: &str = "/etc/rye-init/wtmp";
+
+// Values for ut_type
+pub const EMPTY: i16 = 0;
+pub const RUN_LVL: i16 = 1;
+pub const BOOT_TIME: i16 = 2;
+// 3 and 4 mark a change of the system time, init never writes them
+pub const INIT_PROCESS: i16 = 5;
+pub const LOGIN_PROCESS: i16 = 6;
+pub const USER_PROCESS: i16 = 7;
+pub const DEAD_PROCESS: i16 = 8;
+pub const ACCOUNTING: i16 = 9;
+
+// Mode and group of a freshly created utmp
+const UTMP_MODE: u32 = 0o664;
+const UTMP_GROUP: &str = "utmp";
+
+// Field sizes and the record size of struct utmp
+pub const UT_LINESIZE: usize = 32;
+pub const UT_IDSIZE: usize = 4;
+pub const UT_NAMESIZE: usize = 32;
+pub const UT_HOSTSIZE: usize = 256;
+pub const UTMP_SIZE: usize = 384;
+pub const UTMP64_SIZE: usize = 400;
+
+// A record dated this far after now wasn't written by this system
+const MAX_FUTURE: i64 = 366 * 86400;
+
+// A v2 record: the fields of struct utmp that last(1) and friends use,
+// little endian, with 64-bit seconds
+pub const WTMP2_SIZE: usize = 348;
+
+// How a utmp file lays out its records
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Layout {
+    Time32,                             // ut_tv with 32-bit fields, 384 bytes
+    Time64,                             // ut_tv with 64-bit fields, 400 bytes
+}
+
+impl Layout {
+    // What the libc of this target uses
+    pub fn native() -> Self {
+        if cfg!(all(target_env = "musl", target_pointer_width = "64")) {
+            Layout::Time64
+        } else {
+            Layout::Time32
+        }
+    }
+
+    pub fn size(self) -> usize {
+        match self {
+            Layout::Time32 => UTMP_SIZE,
+            Layout::Time64 => UTMP64_SIZE,
+        }
+    }
+
+    // Guess the layout of a file from its contents. The size has to be a
+    // multiple of the record size, and every record must have a known
+    // ut_type. When both layouts fit, the native one wins.
+    pub fn detect(data: &[u8]) -> Self {
+        let fits = |layout: Layout| {
+            let size = layout.size();
+            data.len().is_multiple_of(size)
+                && data.chunks(size).all(|r| (EMPTY..=ACCOUNTING).contains(&i16::from_ne_bytes([r[0], r[1]])))
+        };
+
+        let native = Layout::native();
+        let other = match native {
+            Layout::Time32 => Layout::Time64,
+            Layout::Time64 => Layout::Time32,
+        };
+        if data.is_empty() || fits(native) || !fits(other) {
+            native
+        } else {
+            other
+        }
+    }
+
+    // The layout of an open file, the native one if it is empty
+    pub fn of_file(f: &mut File) -> io::Result<Self> {
+        let mut data = Vec::new();
+        f.seek(SeekFrom::Start(0))?;
+        f.read_to_end(&mut data)?;
+        f.seek(SeekFrom::Start(0))?;
+        Ok(Layout::detect(&data))
+    }
+}
+
+// Which wtmp files records are appended to
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WtmpFormat {
+    Classic,
+    V2,
+    Both,
+}
+
+#[derive(Debug, Clone)]
+pub struct UtmpRecord {
+    pub ut_type: i16,
+    pub pid: i32,
+    pub line: [u8; UT_LINESIZE],
+    pub id: [u8; UT_IDSIZE],
+    pub user: [u8; UT_NAMESIZE],
+    pub host: [u8; UT_HOSTSIZE],
+    pub exit_termination: i16,
+    pub exit_status: i16,
+    pub session: i32,
+    pub tv_sec: i64,
+    pub tv_usec: i32,
+    pub addr_v6: [i32; 4],
+}
+
+// Copy a string into a fixed size, NUL padded field (like strncpy)
+fn fill(field: &mut [u8], s: &str) {
+    field.fill(0);
+    let len = std::cmp::min(s.len(), field.len());
+    field[..len].copy_from_slice(&s.as_bytes()[..len]);
+}
+
+// The ut_id of an inittab entry, as sysvinit makes it: the first
+// UT_IDSIZE bytes of the id, NUL padded. getty and login find the slot of
+// their entry by it, so two entries whose ids start with the same four
+// bytes share one.
+pub fn ut_id(id: &str) -> [u8; UT_IDSIZE] {
+    let mut field = [0; UT_IDSIZE];
+    fill(&mut field, id);
+    field
+}
+
+// Read a fixed size field back into a string
+pub fn field_str(field: &[u8]) -> String {
+    let end = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+    String::from_utf8_lossy(&field[..end]).into_owned()
+}
+
+impl UtmpRecord {
+    pub fn new(ut_type: i16) -> Self {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+
+        UtmpRecord {
+            ut_type,
+            pid: 0,
+            line: [0; UT_LINESIZE],
+            id: [0; UT_IDSIZE],
+            user: [0; UT_NAMESIZE],
+            host: [0; UT_HOSTSIZE],
+            exit_termination: 0,
+            exit_status: 0,
+            session: 0,
+            tv_sec: now.as_secs() as i64,
+            tv_usec: now.subsec_micros() as i32,
+            addr_v6: [0; 4],
+        }
+    }
+
+    pub fn set_line(&mut self, s: &str) {
+        fill(&mut self.line, s);
+    }
+
+    pub fn set_id(&mut self, s: &str) {
+        self.id = ut_id(s);
+    }
+
+    pub fn set_user(&mut self, s: &str) {
+        fill(&mut self.user, s);
+    }
+
+    pub fn set_host(&mut self, s: &str) {
+        fill(&mut self.host, s);
+    }
+
+    // Decode a record, buf is layout.size() bytes
+    pub fn from_bytes(buf: &[u8], layout: Layout) -> Self {
+        let i16_at = |off: usize| i16::from_ne_bytes([buf[off], buf[off + 1]]);
+        let i32_at = |off: usize| i32::from_ne_bytes([buf[off], buf[off + 1], buf[off + 2], buf[off + 3]]);
+        let i64_at = |off: usize| {
+            let mut b = [0u8; 8];
+            b.copy_from_slice(&buf[off..off + 8]);
+            i64::from_ne_bytes(b)
+        };
+
+        let mut rec = UtmpRecord::new(i16_at(0));
+        rec.pid = i32_at(4);
+        rec.line.copy_from_slice(&buf[8..40]);
+        rec.id.copy_from_slice(&buf[40..44]);
+        rec.user.copy_from_slice(&buf[44..76]);
+        rec.host.copy_from_slice(&buf[76..332]);
+        rec.exit_termination = i16_at(332);
+        rec.exit_status = i16_at(334);
+        rec.session = i32_at(336);
+        let addr = match layout {
+            Layout::Time32 => {
+                rec.tv_sec = i32_at(340) as i64;
+                rec.tv_usec = i32_at(344);
+                348
+            }
+            Layout::Time64 => {
+                rec.tv_sec = i64_at(344);
+                rec.tv_usec = i64_at(352) as i32;
+                360
+            }
+        };
+        for (n, v) in rec.addr_v6.iter_mut().enumerate() {
+            *v = i32_at(addr + n * 4);
+        }
+        rec
+    }
+
+    pub fn to_bytes(&self, layout: Layout) -> Vec<u8> {
+        let mut buf = vec![0u8; layout.size()];
+        buf[0..2].copy_from_slice(&self.ut_type.to_ne_bytes());
+        buf[4..8].copy_from_slice(&self.pid.to_ne_bytes());
+        buf[8..40].copy_from_slice(&self.line);
+        buf[40..44].copy_from_slice(&self.id);
+        buf[44..76].copy_from_slice(&self.user);
+        buf[76..332].copy_from_slice(&self.host);
+        buf[332..334].copy_from_slice(&self.exit_termination.to_ne_bytes());
+        buf[334..336].copy_from_slice(&self.exit_status.to_ne_bytes());
+        buf[336..340].copy_from_slice(&self.session.to_ne_bytes());
+        let addr = match layout {
+            Layout::Time32 => {
+                buf[340..344].copy_from_slice(&(self.tv_sec as i32).to_ne_bytes());
+                buf[344..348].copy_from_slice(&self.tv_usec.to_ne_bytes());
+                348
+            }
+            Layout::Time64 => {
+                buf[344..352].copy_from_slice(&self.tv_sec.to_ne_bytes());
+                buf[352..360].copy_from_slice(&(self.tv_usec as i64).to_ne_bytes());
+                360
+            }
+        };
+        for (n, v) in self.addr_v6.iter().enumerate() {
+            buf[addr + n * 4..addr + 4 + n * 4].copy_from_slice(&v.to_ne_bytes());
+        }
+        buf
+    }
+
+    // The v2 form of the record, stamped with the full time
+    pub fn to_v2_bytes(&self, time: std::time::Duration) -> [u8; WTMP2_SIZE] {
+        let mut buf = [0u8; WTMP2_SIZE];
+        buf[0..2].copy_from_slice(&self.ut_type.to_le_bytes());
+        buf[4..8].copy_from_slice(&self.pid.to_le_bytes());
+        buf[8..16].copy_from_slice(&(time.as_secs() as i64).to_le_bytes());
+        buf[16..20].copy_from_slice(&time.subsec_micros().to_le_bytes());
+        buf[24..56].copy_from_slice(&self.line);
+        buf[56..60].copy_from_slice(&self.id);
+        buf[60..92].copy_from_slice(&self.user);
+        buf[92..348].copy_from_slice(&self.host);
+        buf
+    }
+
+    pub fn is_process(&self) -> bool {
+        matches!(self.ut_type, INIT_PROCESS | LOGIN_PROCESS | USER_PROCESS | DEAD_PROCESS)
+    }
+
+    // Same slot as far as pututline() is concerned
+    fn same_slot(&self, other: &UtmpRecord) -> bool {
+        if self.is_process() {
+            other.is_process() && self.id == other.id
+        } else {
+            self.ut_type == other.ut_type
+        }
+    }
+}
+
+// What could be read from a utmp style file
+#[derive(Debug, Clone)]
+pub struct Scan {
+    pub layout: Layout,
+    pub records: Vec<UtmpRecord>,
+    pub damaged: Vec<(u64, usize)>,     // Offset and length of what was skipped
+}
+
+// Whether a record can be real: a known type and, unless it is an empty
+// slot, a time that can be
+fn plausible(rec: &UtmpRecord, now: i64) -> bool {
+    if rec.ut_type == EMPTY {
+        return rec.pid >= 0;
+    }
+    (RUN_LVL..=ACCOUNTING).contains(&rec.ut_type)
+        && rec.pid >= 0
+        && (0..1_000_000).contains(&rec.tv_usec)
+        && (1..=now + MAX_FUTURE).contains(&rec.tv_sec)
+}
+
+// Where reading can pick up again after damage: a plausible record that
+// isn't an empty slot, which a misaligned read finds in every run of zeros
+fn anchor(rec: &UtmpRecord, now: i64) -> bool {
+    rec.ut_type != EMPTY && plausible(rec, now)
+}
+
+fn scan_layout(data: &[u8], layout: Layout, now: i64) -> Scan {
+    let size = layout.size();
+    let mut scan = Scan { layout, records: Vec::new(), damaged: Vec::new() };
+    let mut bad_start = None;
+    let mut off = 0;
+
+    while off + size <= data.len() {
+        let rec = UtmpRecord::from_bytes(&data[off..off + size], layout);
+        let good = if bad_start.is_some() { anchor(&rec, now) } else { plausible(&rec, now) };
+        if !good {
+            bad_start.get_or_insert(off);
+            off += 1;
+            continue;
+        }
+        if let Some(start) = bad_start.take() {
+            scan.damaged.push((start as u64, off - start));
+        }
+        scan.records.push(rec);
+        off += size;
+    }
+
+    // What is left is too short for a record, cut off by a crash
+    let start = bad_start.unwrap_or(off);
+    if start < data.len() {
+        scan.damaged.push((start as u64, data.len() - start));
+    }
+    scan
+}
+
+// Read the records out of the contents of a utmp style file. Records that
+// can't be real, a boot that crashed halfway through a write for instance,
+// are skipped and reading goes on after them. A file that is damaged in
+// one layout and less so in the other is taken to have the other.
+pub fn scan(data: &[u8]) -> Scan {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs() as i64;
+    let layout = Layout::detect(data);
+    let found = scan_layout(data, layout, now);
+    if found.damaged.is_empty() {
+        return found;
+    }
+
+    let other = match layout {
+        Layout::Time32 => Layout::Time64,
+        Layout::Time64 => Layout::Time32,
+    };
+    let alternative = scan_layout(data, other, now);
+    let damage = |scan: &Scan| scan.damaged.iter().map(|(_, len)| len).sum::<usize>();
+    if damage(&alternative) < damage(&found) {
+        alternative
+    } else {
+        found
+    }
+}
+
+// Read the records of a utmp style file, in whatever layout it has,
+// telling what had to be skipped
+pub fn read_scan(path: &str) -> io::Result<Scan> {
+    Ok(scan(&std::fs::read(path)?))
+}
+
+// Read all records from a utmp style file, in whatever layout it has.
+// Damaged records are left out.
+pub fn read_records(path: &str) -> io::Result<Vec<UtmpRecord>> {
+    Ok(read_file(path)?.1)
+}
+
+fn read_file(path: &str) -> io::Result<(Layout, Vec<UtmpRecord>)> {
+    let scan = read_scan(path)?;
+    Ok((scan.layout, scan.records))
+}
+
+// Write records to a new file, which must not exist yet
+pub fn write_records(path: &str, layout: Layout, records: &[UtmpRecord]) -> io::Result<()> {
+    let mut f = OpenOptions::new().write(true).create_new(true).mode(UTMP_MODE).open(path)?;
+    for rec in records {
+        f.write_all(&rec.to_bytes(layout))?;
+    }
+    f.sync_all()
+}
+
+// Find the utmp record occupying the same slot as rec
+pub fn find_slot(path: &str, rec: &UtmpRecord) -> Option<UtmpRecord> {
+    read_records(path).ok()?.into_iter().find(|r| r.same_slot(rec))
+}
+
+// Write a record into utmp, overwriting the slot it belongs to or
+// appending it if there is none yet, like pututline(): an entry that is
+// started again reuses the slot with its ut_id, whatever is in it now
+pub fn put_utmp(path: &str, rec: &UtmpRecord) -> io::Result<()> {
+    let mut f = OpenOptions::new().read(true).write(true).open(path)?;
+    let layout = Layout::of_file(&mut f)?;
+    let mut buf = vec![0u8; layout.size()];
+    let mut offset = 0u64;
+
+    loop {
+        match f.read_exact(&mut buf) {
+            Ok(()) => {
+                if UtmpRecord::from_bytes(&buf, layout).same_slot(rec) {
+                    break;
+                }
+                offset += layout.size() as u64;
+            }
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e),
+        }
+    }
+
+    f.seek(SeekFrom::Start(offset))?;
+    f.write_all(&rec.to_bytes(layout))
+}
+
+// Append a record to wtmp. Like sysvinit we only log if the file exists.
+pub fn append_wtmp(path: &str, rec: &UtmpRecord) -> io::Result<()> {
+    let mut f = OpenOptions::new().read(true).append(true).open(path)?;
+    let layout = Layout::of_file(&mut f)?;
+    f.write_all(&rec.to_bytes(layout))
+}
+
+// Append a record to a v2 wtmp, again only if the file exists
+pub fn append_wtmp2(path: &str, rec: &UtmpRecord) -> io::Result<()> {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    let mut f = OpenOptions::new().append(true).open(path)?;
+    f.write_all(&rec.to_v2_bytes(now))
+}
+
+// Read the wtmp format from the config file. Without one, or with one we
+// can't make sense of, only the classic wtmp is written.
+pub fn read_wtmp_format(path: &str) -> WtmpFormat {
+    let conf = std::fs::read_to_string(path).unwrap_or_default();
+    let mut format = WtmpFormat::Classic;
+
+    for line in conf.lines() {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            ["format", "classic"] => format = WtmpFormat::Classic,
+            ["format", "v2"] => format = WtmpFormat::V2,
+            ["format", "both"] => format = WtmpFormat::Both,
+            _ => {}
+        }
+    }
+
+    format
+}
+
+// Create utmp with the usual owner and permissions
+fn create_utmp(path: &str) -> io::Result<File> {
+    let f = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(UTMP_MODE)
+        .open(path)?;
+
+    // The umask may have eaten some of the mode bits
+    f.set_permissions(std::fs::Permissions::from_mode(UTMP_MODE))?;
+
+    if let Ok(Some(group)) = nix::unistd::Group::from_name(UTMP_GROUP) {
+        let _ = nix::unistd::fchown(&f, Some(nix::unistd::Uid::from_raw(0)), Some(group.gid));
+    }
+
+    Ok(f)
+}
+
+// Called at boot: nobody can be logged in yet, so whatever the previous
+// boot left behind is stale. The boot record is kept, process slots are
+// turned into DEAD_PROCESS so their ids can be reused, everything else
+// goes away. A missing utmp is created.
+pub fn clean_utmp(path: &str) -> io::Result<()> {
+    let (layout, records) = match read_file(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => (Layout::native(), Vec::new()),
+        Err(e) => return Err(e),
+    };
+
+    let mut f = create_utmp(path)?;
+
+    for mut rec in records {
+        match rec.ut_type {
+            BOOT_TIME => {}
+            INIT_PROCESS | LOGIN_PROCESS | USER_PROCESS | DEAD_PROCESS => {
+                rec.ut_type = DEAD_PROCESS;
+                rec.pid = 0;
+                rec.user.fill(0);
+                rec.host.fill(0);
+                rec.session = 0;
+            }
+            _ => continue,
+        }
+        f.write_all(&rec.to_bytes(layout))?;
+    }
+
+    Ok(())
+}
+
+fn kernel_release() -> String {
+    nix::sys::utsname::uname()
+        .map(|u| u.release().to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+#[derive(Debug)]
+pub struct Utmp {
+    pub utmp_path: String,
+    pub wtmp_path: String,
+    pub wtmp2_path: String,
+    pub wtmp_format: WtmpFormat,
+}
+
+impl Utmp {
+    pub fn new() -> Self {
+        Utmp {
+            utmp_path: paths::path(UTMP_FILE),
+            wtmp_path: paths::path(WTMP_FILE),
+            wtmp2_path: paths::path(WTMP2_FILE),
+            wtmp_format: WtmpFormat::Classic,
+        }
+    }
+
+    // Append to whichever wtmp files are configured
+    fn log_wtmp(&self, rec: &UtmpRecord) {
+        if self.wtmp_format != WtmpFormat::V2 {
+            let _ = append_wtmp(&self.wtmp_path, rec);
+        }
+        if self.wtmp_format != WtmpFormat::Classic {
+            let _ = append_wtmp2(&self.wtmp2_path, rec);
+        }
+    }
+
+    // Tidy up utmp at sysinit time
+    pub fn boot_cleanup(&self) -> io::Result<()> {
+        clean_utmp(&self.utmp_path)
+    }
+
+    fn make_record(&self, user: &str, id: &str, pid: i32, entry_type: i32, line: &str) -> UtmpRecord {
+        let mut rec = UtmpRecord::new(entry_type as i16);
+        rec.pid = pid;
+        rec.set_id(id);
+        rec.set_user(user);
+        rec.set_line(line);
+
+        // The kernel version goes into ut_host for boot and runlevel records
+        if rec.ut_type == RUN_LVL || rec.ut_type == BOOT_TIME {
+            rec.set_host(&kernel_release());
+        }
+
+        // A dead process keeps the line it had, so last(1) can match it up
+        if rec.ut_type == DEAD_PROCESS && line.is_empty() && let Some(old) = find_slot(&self.utmp_path, &rec) {
+            rec.line = old.line;
+        }
+
+        rec
+    }
+}
+
+impl crate::UtmpWriter for Utmp {
+    fn write_utmp_wtmp(&self, user: &str, id: &str, pid: i32, entry_type: i32, line: &str) {
+        let rec = self.make_record(user, id, pid, entry_type, line);
+        self.log_wtmp(&rec);
+        let _ = put_utmp(&self.utmp_path, &rec);
+    }
+
+    fn write_wtmp(&self, user: &str, id: &str, pid: i32, entry_type: i32, line: &str) {
+        let rec = self.make_record(user, id, pid, entry_type, line);
+        self.log_wtmp(&rec);
+    }
+}
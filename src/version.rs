@@ -0,0 +1,119 @@
+// --version: which init this is, what it was built with and where it looks
+// for things. With --json the same as one JSON object, for bug reports and
+// scripts that need to tell implementations apart.
+
+use rye_init::{initreq, inittab, paths, profile, runlevel, state};
+
+use crate::{battery, checkpoint, config, diag, earlylog, hooks, last_exit, splash, status, transitions};
+#[cfg(feature = "metrics")]
+use crate::metrics;
+#[cfg(feature = "utmp")]
+use crate::utmp;
+
+// Optional parts of the build and whether they are in this one
+const FEATURES: &[(&str, bool)] = &[
+    ("freebsd", cfg!(target_os = "freebsd")),
+    ("selinux", cfg!(feature = "selinux")),
+    ("apparmor", cfg!(feature = "apparmor")),
+    ("utmp", cfg!(feature = "utmp")),
+    ("wall", cfg!(feature = "wall")),
+    ("shutdown", cfg!(feature = "shutdown")),
+    ("bootlogd", cfg!(feature = "bootlogd")),
+    ("metrics", cfg!(feature = "metrics")),
+];
+
+// Compiled-in paths, by what they are for. All but the init program move
+// with --root.
+const PATHS: &[(&str, &str)] = &[
+    ("init", crate::INIT_PROGRAM),
+    ("config", config::CONFIG_FILE),
+    ("inittab", inittab::INITTAB),
+    ("inittab_dir", inittab::INITTAB_DIR),
+    ("profile", profile::PROFILE_FILE),
+    ("hooks_dir", hooks::HOOKS_DIR),
+    #[cfg(feature = "metrics")]
+    ("metrics", metrics::METRICS_FILE),
+    ("power_supply", battery::POWER_SUPPLY),
+    ("fifo", initreq::INIT_FIFO),
+    ("control_socket", initreq::INIT_SOCKET),
+    ("runlevel_file", runlevel::RUNLEVEL_FILE),
+    ("last_exit", last_exit::LAST_EXIT),
+    ("status_dir", status::STATUS_DIR),
+    ("diag", diag::DIAG_FILE),
+    ("checkpoint", checkpoint::CHECKPOINT_FILE),
+    ("splash_fifo", splash::SPLASH_FIFO),
+    ("dev_log", earlylog::DEV_LOG),
+    ("transitions_log", transitions::TRANSITIONS_LOG),
+    #[cfg(feature = "utmp")]
+    ("utmp", utmp::UTMP_FILE),
+    #[cfg(feature = "utmp")]
+    ("wtmp", utmp::WTMP_FILE),
+    #[cfg(feature = "utmp")]
+    ("wtmp_v2", utmp::WTMP2_FILE),
+    #[cfg(feature = "utmp")]
+    ("wtmp_conf", utmp::WTMP_CONF),
+];
+
+// Quote a string for JSON
+pub fn json_str(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+// Where a path from PATHS is for this run
+fn located(what: &str, path: &str) -> String {
+    if what == "init" {
+        path.to_string()
+    } else {
+        paths::path(path)
+    }
+}
+
+fn enabled() -> Vec<&'static str> {
+    FEATURES.iter().filter(|(_, on)| *on).map(|(name, _)| *name).collect()
+}
+
+fn print_json() {
+    let features: Vec<String> = enabled().into_iter().map(json_str).collect();
+    let paths: Vec<String> = PATHS.iter().map(|(k, v)| format!("{}:{}", json_str(k), json_str(&located(k, v)))).collect();
+
+    println!(
+        "{{\"name\":\"rye-init\",\"version\":{},\"features\":[{}],\"protocols\":{{\"initreq_magic\":{},\"control\":{},\"state\":{}}},\"paths\":{{{}}}}}",
+        json_str(crate::VERSION),
+        features.join(","),
+        initreq::INIT_MAGIC,
+        initreq::CONTROL_VERSION,
+        state::STATE_FORMAT,
+        paths.join(","),
+    );
+}
+
+fn print_text() {
+    println!("rye-init version {}", crate::VERSION);
+    let features = enabled();
+    println!("features: {}", if features.is_empty() { "none".to_string() } else { features.join(" ") });
+    println!("protocols: initreq magic {:#010x}, control {}, state stream {}",
+        initreq::INIT_MAGIC, initreq::CONTROL_VERSION, state::STATE_FORMAT);
+    for (what, path) in PATHS {
+        println!("{}: {}", what, located(what, path));
+    }
+}
+
+pub fn version(args: &[String]) -> i32 {
+    if args.iter().any(|a| a == "--json") {
+        print_json();
+    } else {
+        print_text();
+    }
+    0
+}
@@ -0,0 +1,212 @@
+// wall: write a message to the terminal of everybody logged in. Init
+// behaves like this when it is started under the name wall.
+//
+// Usage: wall [-n] [--no-sanitize] [message... | file]
+//
+// Without arguments the message comes from standard input, a single
+// argument that names a readable file is read from there. -n leaves out
+// the "Broadcast message from" banner.
+//
+// Whatever the sender typed ends up on other users' terminals, so control
+// characters other than newline and tab are removed: nobody gets to send
+// escape sequences that retitle a window, remap keys or hide text. The
+// message is also cut to MAX_LINES lines of at most MAX_BYTES in total.
+// Root can send it untouched with --no-sanitize; -n is for root only too,
+// the banner is what says who sent a message.
+//
+// Terminals whose owner turned messages off (mesg n, no group write
+// permission) only get messages from root. A terminal that doesn't take
+// the message within WRITE_TIMEOUT is skipped, a hung session must not
+// hold up the rest.
+
+use std::fs::OpenOptions;
+use std::io::{self, Read, Write};
+use std::os::fd::AsFd;
+use std::os::unix::fs::{MetadataExt, OpenOptionsExt};
+use std::time::{Duration, Instant};
+
+use nix::poll::{poll, PollFd, PollFlags, PollTimeout};
+use nix::unistd::{Uid, User};
+
+use rye_init::{localtime, paths};
+
+use crate::utmp;
+
+const EXIT_OK: i32 = 0;
+const EXIT_FAILED: i32 = 1;
+
+pub const MAX_BYTES: usize = 4096;
+pub const MAX_LINES: usize = 40;
+const WRITE_TIMEOUT: Duration = Duration::from_secs(2);
+
+fn usage() -> i32 {
+    eprintln!("Usage: wall [-n] [--no-sanitize] [message... | file]");
+    EXIT_FAILED
+}
+
+// Remove what a terminal would act on rather than show, and cut the
+// message to size
+pub fn sanitize(msg: &str) -> String {
+    let mut out = String::with_capacity(msg.len().min(MAX_BYTES));
+    let mut lines = 1;
+
+    for c in msg.chars() {
+        if c.is_control() && c != '\n' && c != '\t' {
+            continue;
+        }
+        if c == '\n' {
+            if lines == MAX_LINES {
+                break;
+            }
+            lines += 1;
+        }
+        if out.len() + c.len_utf8() > MAX_BYTES {
+            break;
+        }
+        out.push(c);
+    }
+    out
+}
+
+// The first line of a broadcast
+pub fn banner(now: i64) -> String {
+    let user = User::from_uid(Uid::current())
+        .ok()
+        .flatten()
+        .map(|u| u.name)
+        .unwrap_or_else(|| Uid::current().to_string());
+    let host = nix::sys::utsname::uname()
+        .map(|u| u.nodename().to_string_lossy().into_owned())
+        .unwrap_or_else(|_| "localhost".to_string());
+    let tty = nix::unistd::ttyname(io::stdin().as_fd())
+        .map(|p| p.to_string_lossy().trim_start_matches("/dev/").to_string())
+        .unwrap_or_else(|_| "somewhere".to_string());
+
+    format!("Broadcast message from {}@{} ({}) ({}):",
+        user, host, tty, localtime::format(now, "%a %b %e %H:%M:%S %Y"))
+}
+
+// The terminals of everybody logged in, with the host they logged in
+// from, empty for a local login
+pub fn logged_in() -> Vec<(String, String)> {
+    let mut ttys: Vec<(String, String)> = Vec::new();
+    let records = utmp::read_records(&paths::path(utmp::UTMP_FILE)).unwrap_or_default();
+
+    for rec in records.iter().filter(|r| r.ut_type == utmp::USER_PROCESS) {
+        let line = utmp::field_str(&rec.line);
+        if line.is_empty() || line.contains("..") || ttys.iter().any(|(l, _)| *l == line) {
+            continue;
+        }
+        // A session that ended without cleaning up its record
+        if rec.pid > 0 && nix::sys::signal::kill(nix::unistd::Pid::from_raw(rec.pid), None) == Err(nix::errno::Errno::ESRCH) {
+            continue;
+        }
+        // sshd records "ssh:notty" for a session without a terminal
+        if !std::path::Path::new(&tty_device(&line)).exists() {
+            continue;
+        }
+        ttys.push((line, utmp::field_str(&rec.host)));
+    }
+    ttys
+}
+
+// The device of a utmp line: "pts/3" is /dev/pts/3
+pub fn tty_device(line: &str) -> String {
+    if line.starts_with('/') {
+        line.to_string()
+    } else {
+        format!("/dev/{}", line)
+    }
+}
+
+// Write to one terminal without blocking for longer than WRITE_TIMEOUT
+pub fn write_tty(dev: &str, text: &[u8], force: bool) -> io::Result<()> {
+    let mut f = OpenOptions::new()
+        .write(true)
+        .custom_flags(libc::O_NOCTTY | libc::O_NONBLOCK)
+        .open(dev)?;
+
+    if !force && f.metadata()?.mode() & libc::S_IWGRP == 0 {
+        return Err(io::Error::new(io::ErrorKind::PermissionDenied, "messages are off"));
+    }
+
+    let deadline = Instant::now() + WRITE_TIMEOUT;
+    let mut done = 0;
+    while done < text.len() {
+        match f.write(&text[done..]) {
+            Ok(n) => done += n,
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::Interrupted => {
+                let left = deadline.saturating_duration_since(Instant::now());
+                if left.is_zero() {
+                    return Err(io::Error::new(io::ErrorKind::TimedOut, "terminal doesn't take output"));
+                }
+                let mut fds = [PollFd::new(f.as_fd(), PollFlags::POLLOUT)];
+                let _ = poll(&mut fds, PollTimeout::try_from(left).unwrap_or(PollTimeout::MAX));
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+// Put `msg` on the terminals in `ttys`, as a terminal in raw mode needs
+// it. Returns how many got it.
+pub fn broadcast(msg: &str, ttys: &[(String, String)], force: bool) -> usize {
+    let mut text = String::from("\r\n");
+    for line in msg.trim_end_matches('\n').split('\n') {
+        text.push_str(line);
+        text.push_str("\r\n");
+    }
+    text.push_str("\r\n");
+
+    ttys.iter()
+        .filter(|(line, _)| write_tty(&tty_device(line), text.as_bytes(), force).is_ok())
+        .count()
+}
+
+pub fn wall(args: &[String]) -> i32 {
+    let root = Uid::effective().is_root();
+    let mut nobanner = false;
+    let mut raw = false;
+    let mut words = Vec::new();
+
+    for arg in args {
+        match arg.as_str() {
+            "-n" | "--nobanner" => nobanner = true,
+            "--no-sanitize" => raw = true,
+            "--" => {}
+            _ if arg.starts_with('-') && arg.len() > 1 => return usage(),
+            _ => words.push(arg.as_str()),
+        }
+    }
+    if (nobanner || raw) && !root {
+        eprintln!("wall: -n and --no-sanitize are for root only");
+        return EXIT_FAILED;
+    }
+
+    let mut msg = String::new();
+    let read = match words.as_slice() {
+        [] => io::stdin().read_to_string(&mut msg).map(|_| ()),
+        [file] if std::path::Path::new(file).is_file() => {
+            std::fs::read(file).map(|data| msg = String::from_utf8_lossy(&data).into_owned())
+        }
+        _ => {
+            msg = words.join(" ");
+            Ok(())
+        }
+    };
+    if let Err(e) = read {
+        eprintln!("wall: {}", e);
+        return EXIT_FAILED;
+    }
+
+    if !raw {
+        msg = sanitize(&msg);
+    }
+    if !nobanner {
+        msg = format!("{}\n\n{}", banner(crate::clock::now() as i64), msg);
+    }
+
+    broadcast(&msg, &logged_in(), root);
+    EXIT_OK
+}
@@ -0,0 +1,41 @@
+// The hardware watchdog, for a boot that doesn't finish
+//
+// With "boot-deadline-notify watchdog" in the config file init opens
+// WATCHDOG_DEVICE once the boot deadline is over, and never writes to it:
+// the board resets when the watchdog's own timeout runs out. A boot that
+// still finishes before that disarms it again with the magic close. While
+// a watchdog daemon has the device open init can't take it, it only says
+// so.
+
+use std::fs::File;
+use std::io::{self, Write};
+use std::os::fd::AsRawFd;
+use std::os::unix::fs::OpenOptionsExt;
+
+use rye_init::paths;
+
+pub const WATCHDOG_DEVICE: &str = "/dev/watchdog";
+
+// _IOR('W', 7, int) from linux/watchdog.h
+const WDIOC_GETTIMEOUT: u32 = 0x8004_5707;
+
+// Start the watchdog. Returns the open device and the seconds until it
+// resets the board, if the driver tells.
+pub fn arm() -> io::Result<(File, Option<u32>)> {
+    let file = std::fs::OpenOptions::new()
+        .write(true)
+        .custom_flags(libc::O_CLOEXEC)
+        .open(paths::path(WATCHDOG_DEVICE))?;
+    let mut secs: libc::c_int = 0;
+    let timeout = match unsafe { libc::ioctl(file.as_raw_fd(), WDIOC_GETTIMEOUT as _, &mut secs) } {
+        0 => u32::try_from(secs).ok(),
+        _ => None,
+    };
+    Ok((file, timeout))
+}
+
+// Stop the watchdog again. A driver built with nowayout ignores this and
+// resets the board anyway.
+pub fn disarm(mut file: File) -> io::Result<()> {
+    file.write_all(b"V")
+}
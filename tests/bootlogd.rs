@@ -0,0 +1,305 @@
+// The bootlogd capture loop driven from a pty pair made here instead of
+// /dev/console. The transcripts in tests/fixtures/bootlogd are what
+// reached a console during a boot, byte for byte: colours, ANSI art,
+// progress bars redrawn with CR, text that isn't ASCII. Each is written
+// to the slave side in chunks of several sizes, so escape sequences and
+// UTF-8 characters get split between reads, and the logfile bootlogd
+// writes must come out the same every time: <name>.log holds its lines
+// without their time stamps.
+
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use nix::pty::openpty;
+use nix::sys::termios::{self, SetArg};
+
+use rye_init::bootlogd::{self, Bootlogd, DropPolicy, Options};
+use rye_init::paths;
+
+// Chunk sizes a transcript is written in. 1 splits everything.
+const CHUNKS: &[usize] = &[1, 3, 7, 64, 4096];
+
+// Time between chunks, for the capture loop to read them one by one
+const CHUNK_PAUSE: Duration = Duration::from_micros(200);
+
+static LOGS: AtomicUsize = AtomicUsize::new(0);
+
+// The default options of the bootlogd command
+fn options() -> Options {
+    Options {
+        syncalot: false,
+        print_escape_characters: false,
+        create_log: true,
+        tag_console: false,
+        stop_after: None,
+        collapse_cr: false,
+        truncate: false,
+        quiet_consoles: Vec::new(),
+        buffer_size: bootlogd::RINGBUF_SIZE,
+        split_per_console: false,
+        console_queue: bootlogd::CONSOLE_QUEUE,
+        console_drop: DropPolicy::Newest,
+        max_console_failures: bootlogd::MAX_CONSOLE_FAILURES,
+    }
+}
+
+// Our own root, so a boot-done file of the machine we run on doesn't end
+// the capture early
+fn scratch() -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("rye-init-bootlogd-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    paths::set_root(dir.to_str().unwrap());
+    dir
+}
+
+fn fixture(name: &str) -> Vec<u8> {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/bootlogd").join(name);
+    std::fs::read(&path).unwrap_or_else(|e| panic!("{}: {}", path.display(), e))
+}
+
+// Capture `transcript` into a logfile of its own and return what was logged
+fn capture(transcript: &[u8], chunk: usize, options: Options) -> Vec<u8> {
+    let logfile = scratch().join(format!("boot.{}", LOGS.fetch_add(1, Ordering::Relaxed)));
+    capture_into(&logfile, transcript, chunk, options);
+    let log = std::fs::read(&logfile).unwrap();
+    let _ = std::fs::remove_file(&logfile);
+    log
+}
+
+// Run the capture loop on a fresh pty pair while `transcript` is written
+// to the slave side `chunk` bytes at a time. Closing the slave ends the
+// capture.
+fn capture_into(logfile: &Path, transcript: &[u8], chunk: usize, options: Options) {
+    let pty = openpty(None, None).unwrap();
+
+    // Nothing between the transcript and bootlogd may change it
+    let mut raw = termios::tcgetattr(&pty.slave).unwrap();
+    termios::cfmakeraw(&mut raw);
+    termios::tcsetattr(&pty.slave, SetArg::TCSANOW, &raw).unwrap();
+
+    let null = OpenOptions::new().write(true).open("/dev/null").unwrap();
+    let mut logger = Bootlogd::new(File::from(pty.master), vec![("/dev/null".to_string(), null)],
+        logfile.to_str().unwrap(), options);
+
+    let mut slave = File::from(pty.slave);
+    let data = transcript.to_vec();
+    let writer = std::thread::spawn(move || {
+        for piece in data.chunks(chunk) {
+            slave.write_all(piece).unwrap();
+            std::thread::sleep(CHUNK_PAUSE);
+        }
+    });
+    logger.run().unwrap();
+    writer.join().unwrap();
+}
+
+// The lines of the one session in a logfile, without their time stamps
+fn session(log: &[u8]) -> Vec<u8> {
+    let boots = bootlogd::boots(log);
+    let [(Some(_), lines)] = boots.as_slice() else {
+        panic!("not one session with a separator: {:?}", String::from_utf8_lossy(log));
+    };
+    strip_stamps(lines)
+}
+
+fn strip_stamps(lines: &[u8]) -> Vec<u8> {
+    let mut text = Vec::new();
+    for line in lines.split_inclusive(|&b| b == b'\n') {
+        let (_, stamp) = bootlogd::line_time(line)
+            .unwrap_or_else(|| panic!("line without a time stamp: {:?}", String::from_utf8_lossy(line)));
+        text.extend_from_slice(&line[stamp..]);
+    }
+    text
+}
+
+fn check(name: &str, expected: &str, options: impl Fn() -> Options) {
+    let transcript = fixture(&format!("{}.transcript", name));
+    let expected = fixture(expected);
+    for &chunk in CHUNKS {
+        let log = session(&capture(&transcript, chunk, options()));
+        assert!(log == expected, "{} in chunks of {}:\n{}\nexpected:\n{}", name, chunk,
+            String::from_utf8_lossy(&log), String::from_utf8_lossy(&expected));
+    }
+}
+
+#[test]
+fn lsb_init_colours_are_stripped() {
+    check("lsb-init", "lsb-init.log", options);
+}
+
+#[test]
+fn ansi_art_keeps_its_characters() {
+    check("ansi-art", "ansi-art.log", options);
+}
+
+#[test]
+fn utf8_split_between_reads() {
+    check("utf8", "utf8.log", options);
+}
+
+#[test]
+fn progress_bars_run_on() {
+    check("progress", "progress.log", options);
+}
+
+#[test]
+fn progress_bars_collapsed() {
+    check("progress", "progress.collapsed.log", || Options { collapse_cr: true, ..options() });
+}
+
+#[test]
+fn escapes_kept_with_e() {
+    let transcript = fixture("lsb-init.transcript");
+    let options = || Options { print_escape_characters: true, ..options() };
+    for &chunk in CHUNKS {
+        assert_eq!(session(&capture(&transcript, chunk, options())), transcript, "in chunks of {}", chunk);
+    }
+}
+
+#[test]
+fn sessions_are_appended() {
+    let logfile = scratch().join("appended");
+    let _ = std::fs::remove_file(&logfile);
+    let transcript = fixture("utf8.transcript");
+    for _ in 0..2 {
+        capture_into(&logfile, &transcript, 64, options());
+    }
+
+    let log = std::fs::read(&logfile).unwrap();
+    let boots = bootlogd::boots(&log);
+    assert_eq!(boots.len(), 2);
+    assert!(boots.iter().all(|(time, lines)| time.is_some() && strip_stamps(lines) == transcript));
+
+    // --truncate starts the file over
+    capture_into(&logfile, &transcript, 64, Options { truncate: true, ..options() });
+    assert_eq!(session(&std::fs::read(&logfile).unwrap()), transcript);
+    let _ = std::fs::remove_file(&logfile);
+}
+
+// Init writing to the console bootlogd captures while bootlogd doesn't
+// read: init in the sandbox gets a pty as its console whose buffer is
+// filled up to the last byte. Init must go on switching runlevels as if
+// nothing happened, and what it said in the meantime reaches the logfile
+// once bootlogd reads again, as far as init's queue held it.
+#[test]
+fn init_carries_on_while_reads_are_paused() {
+    use std::io::Read;
+    use std::os::fd::AsRawFd;
+    use std::os::unix::fs::OpenOptionsExt;
+    use std::os::unix::process::CommandExt;
+    use std::process::Command;
+    use std::time::Instant;
+
+    const INIT: &str = env!("CARGO_BIN_EXE_rye-init");
+    const DEADLINE: Duration = Duration::from_secs(30);
+    const SWITCHES: usize = 100;
+
+    let root = scratch().join("init");
+    let _ = std::fs::remove_dir_all(&root);
+    for dir in ["etc/rye-init", "run", "var/run", "var/log"] {
+        std::fs::create_dir_all(root.join(dir)).unwrap();
+    }
+    std::fs::write(root.join("etc/inittab"), "id:2:initdefault:\n").unwrap();
+    // Only the queue may hold messages back
+    std::fs::write(root.join("etc/rye-init/init.conf"), "console-rate-limit off\n").unwrap();
+    let telinit = |level: &str| {
+        let status = Command::new(INIT).arg(level).env("RYE_INIT_ROOT", &root).status().unwrap();
+        assert!(status.success(), "rye-init {}: {}", level, status);
+    };
+    // The previous and the current runlevel, as the utmp in the root has them
+    let runlevel = || {
+        let out = Command::new(INIT).arg0("runlevel").env("RYE_INIT_ROOT", &root).output().unwrap();
+        String::from_utf8_lossy(&out.stdout).trim().to_string()
+    };
+    let wait_until = |what: &str, done: &dyn Fn() -> bool| {
+        let started = Instant::now();
+        while !done() {
+            assert!(started.elapsed() < DEADLINE, "init is stuck: {}", what);
+            std::thread::sleep(Duration::from_millis(5));
+        }
+    };
+
+    let pty = openpty(None, None).unwrap();
+    let mut raw = termios::tcgetattr(&pty.slave).unwrap();
+    termios::cfmakeraw(&mut raw);
+    termios::tcsetattr(&pty.slave, SetArg::TCSANOW, &raw).unwrap();
+    let tty = nix::unistd::ttyname(&pty.slave).unwrap();
+    let console = File::from(pty.slave);
+    let mut master = File::from(pty.master);
+    let mut init = Command::new(INIT)
+        .args(["--sandbox", "--root", root.to_str().unwrap()])
+        .stdin(console.try_clone().unwrap())
+        .stdout(console.try_clone().unwrap())
+        .stderr(console)
+        .spawn()
+        .unwrap();
+
+    // Up, then stop reading and fill what the pty buffers
+    let mut said = Vec::new();
+    let mut buf = [0u8; 1024];
+    while !String::from_utf8_lossy(&said).contains("Switching to runlevel: 2") {
+        let n = master.read(&mut buf).unwrap();
+        assert!(n > 0, "init went away: {:?}", String::from_utf8_lossy(&said));
+        said.extend_from_slice(&buf[..n]);
+    }
+    let mut filler = OpenOptions::new().write(true).custom_flags(libc::O_NONBLOCK | libc::O_NOCTTY)
+        .open(&tty).unwrap();
+    let line = [b"paused".as_slice(), &[b'.'; 57], b"\n"].concat();
+    // The kernel moves what was written on to the master side in the
+    // background, making room again for a while
+    loop {
+        let mut wrote = 0;
+        while let Ok(n @ 1..) = filler.write(&line) {
+            wrote += n;
+        }
+        if wrote == 0 {
+            break;
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    }
+    drop(filler);
+
+    for _ in 0..SWITCHES {
+        telinit("3");
+        wait_until("runlevel 3", &|| runlevel() == "2 3");
+        telinit("2");
+        wait_until("runlevel 2", &|| runlevel() == "3 2");
+    }
+
+    // Read again, wait for the pty to drain before init goes down
+    let pending = master.as_raw_fd();
+    let logfile = scratch().join("paused");
+    let _ = std::fs::remove_file(&logfile);
+    let path = logfile.clone();
+    let reader = std::thread::spawn(move || {
+        let null = OpenOptions::new().write(true).open("/dev/null").unwrap();
+        Bootlogd::new(master, vec![("/dev/null".to_string(), null)], path.to_str().unwrap(), options()).run()
+            .unwrap();
+    });
+    wait_until("bootlogd doesn't read", &|| {
+        let mut left: libc::c_int = 0;
+        unsafe { libc::ioctl(pending, libc::FIONREAD, &mut left) };
+        left == 0
+    });
+    telinit("0");
+    let started = Instant::now();
+    while init.try_wait().unwrap().is_none() {
+        assert!(started.elapsed() < DEADLINE, "init still running");
+        std::thread::sleep(Duration::from_millis(50));
+    }
+    reader.join().unwrap();
+
+    // The filler first, then what init had queued, a count of what it
+    // didn't have room for and init going down
+    let log = String::from_utf8_lossy(&session(&std::fs::read(&logfile).unwrap())).replace('\r', "");
+    let switched = log.matches("INIT: Switching to runlevel: 3").count();
+    assert!(log.starts_with("paused."), "{}", log);
+    assert!(switched > 0 && switched < SWITCHES, "{} switches to 3 logged:\n{}", switched, log);
+    let dropped = log.find(" console messages dropped\n").unwrap_or_else(|| panic!("nothing dropped:\n{}", log));
+    assert!(log[dropped..].contains("INIT: Switching to runlevel: 0\n"), "{}", log);
+    let _ = std::fs::remove_file(&logfile);
+    let _ = std::fs::remove_dir_all(&root);
+}
@@ -0,0 +1,128 @@
+// A runlevel change in the middle of an exit storm, run against an init in
+// the sandbox (rye-init --sandbox, see src/sandbox.rs) with a pty made here
+// as its console. The entries of runlevel 2 leave orphans behind as fast
+// as the shell can fork them, and the sandbox makes init their reaper, so
+// init has children to collect at any moment. Asking for runlevel 3 must
+// still get through in a fraction of the deadline, however many exits are
+// waiting.
+
+use std::fs::File;
+use std::io::Read;
+use std::path::PathBuf;
+use std::process::{Child, Command};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use nix::pty::openpty;
+
+const INIT: &str = env!("CARGO_BIN_EXE_rye-init");
+
+// Longer than init takes to boot, switch and go down when nothing is in
+// its way
+const DEADLINE: Duration = Duration::from_secs(30);
+
+// How long the storm rages before the runlevel change is asked for
+const STORM: Duration = Duration::from_secs(2);
+
+// Most a runlevel change may take to be acted on during the storm
+const SWITCH_LATENCY: Duration = Duration::from_secs(2);
+
+// Entries forking orphans in a tight loop
+const STORMERS: usize = 4;
+
+// Everything written to the console so far, without CRs
+#[derive(Clone, Default)]
+struct Transcript(Arc<Mutex<Vec<u8>>>);
+
+impl Transcript {
+    fn text(&self) -> String {
+        String::from_utf8_lossy(&self.0.lock().unwrap()).replace('\r', "")
+    }
+
+    // Wait for `what` to show up, return where it is
+    fn wait_for(&self, what: &str) -> usize {
+        let started = Instant::now();
+        loop {
+            if let Some(at) = self.text().find(what) {
+                return at;
+            }
+            assert!(started.elapsed() < DEADLINE, "no {:?} on the console:\n{}", what, self.text());
+            std::thread::sleep(Duration::from_millis(5));
+        }
+    }
+}
+
+// Root of the sandbox, with an inittab that boots to runlevel 2 and runs
+// the stormers there. Runlevel 3 has nothing.
+fn make_root() -> PathBuf {
+    let root = std::env::temp_dir().join(format!("rye-init-storm-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&root);
+    for dir in ["etc", "run", "var/run", "var/log"] {
+        std::fs::create_dir_all(root.join(dir)).unwrap();
+    }
+    let mut inittab = String::from("id:2:initdefault:\n");
+    for n in 1..=STORMERS {
+        inittab += &format!("s{0}:2:respawn:/bin/sh -c 'echo s{0}: up; while true; do (/bin/true &); done'\n", n);
+    }
+    std::fs::write(root.join("etc/inittab"), inittab).unwrap();
+    root
+}
+
+fn telinit(root: &PathBuf, level: &str) {
+    let status = Command::new(INIT).arg(level).env("RYE_INIT_ROOT", root).status().unwrap();
+    assert!(status.success(), "rye-init {}: {}", level, status);
+}
+
+fn wait_exit(init: &mut Child, transcript: &Transcript) {
+    let started = Instant::now();
+    while init.try_wait().unwrap().is_none() {
+        if started.elapsed() >= DEADLINE {
+            let _ = init.kill();
+            panic!("init still running:\n{}", transcript.text());
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    }
+}
+
+#[test]
+fn runlevel_change_during_exit_storm() {
+    let root = make_root();
+
+    let pty = openpty(None, None).unwrap();
+    let console = File::from(pty.slave);
+    let mut master = File::from(pty.master);
+    let transcript = Transcript::default();
+    let written = transcript.clone();
+    std::thread::spawn(move || {
+        let mut buf = [0u8; 1024];
+        while let Ok(n @ 1..) = master.read(&mut buf) {
+            written.0.lock().unwrap().extend_from_slice(&buf[..n]);
+        }
+    });
+
+    let mut init = Command::new(INIT)
+        .args(["--sandbox", "--root", root.to_str().unwrap()])
+        .stdin(console.try_clone().unwrap())
+        .stdout(console.try_clone().unwrap())
+        .stderr(console)
+        .spawn()
+        .unwrap();
+    for n in 1..=STORMERS {
+        transcript.wait_for(&format!("s{}: up", n));
+    }
+    std::thread::sleep(STORM);
+
+    let asked = Instant::now();
+    telinit(&root, "3");
+    transcript.wait_for("Switching to runlevel: 3");
+    let took = asked.elapsed();
+    assert!(took < SWITCH_LATENCY, "runlevel 3 took {:?}:\n{}", took, transcript.text());
+
+    // The stormers are gone with runlevel 2, the rest goes as usual
+    telinit(&root, "0");
+    wait_exit(&mut init, &transcript);
+    let text = transcript.text();
+    assert!(text.contains("sandbox: PowerOff instead of going down"), "{}", text);
+    assert!(!text.contains("respawning too fast"), "{}", text);
+    let _ = std::fs::remove_dir_all(&root);
+}
@@ -0,0 +1,173 @@
+// A whole shutdown, run against an init in the sandbox (rye-init
+// --sandbox, see src/sandbox.rs) that has a root of its own and a pty
+// made here as its console. The pty is also the terminal of a login in
+// that root's utmp, so what shutdown tells the users, what the entries
+// print when they are stopped and init's own messages all end up in one
+// transcript, in the order they happened. The reboot itself is recorded
+// instead of done and listed in init's last message.
+//
+// shutdown only brings the system down for root, the test is skipped for
+// anybody else.
+
+use std::fs::File;
+use std::io::Read;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use nix::pty::openpty;
+use nix::unistd::Uid;
+
+use rye_init::procscan;
+
+const INIT: &str = env!("CARGO_BIN_EXE_rye-init");
+
+// Longer than init takes to stop the entries and go down, sleep time
+// twice over
+const DEADLINE: Duration = Duration::from_secs(30);
+
+// An entry that reports its pid, ignores SIGTERM and says whether
+// shutdown had locked users out by the time it got it
+const ENTRY: &str = r#"echo "t1: up $$"
+trap 'echo "t1: TERM, nologin: $(cat "$ROOT/run/nologin" 2>/dev/null)"' TERM
+while :; do sleep 1 & wait $!; done
+"#;
+
+// Everything written to the console so far, without CRs
+#[derive(Clone, Default)]
+struct Transcript(Arc<Mutex<Vec<u8>>>);
+
+impl Transcript {
+    fn text(&self) -> String {
+        String::from_utf8_lossy(&self.0.lock().unwrap()).replace('\r', "")
+    }
+
+    // Wait for `what` to show up, return where it is
+    fn wait_for(&self, what: &str) -> usize {
+        let started = Instant::now();
+        loop {
+            if let Some(at) = self.text().find(what) {
+                return at;
+            }
+            assert!(started.elapsed() < DEADLINE, "no {:?} on the console:\n{}", what, self.text());
+            std::thread::sleep(Duration::from_millis(20));
+        }
+    }
+}
+
+// Root of the sandbox, with an inittab that boots to runlevel 2 and runs
+// ENTRY there
+fn make_root() -> PathBuf {
+    let root = std::env::temp_dir().join(format!("rye-init-shutdown-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&root);
+    for dir in ["etc", "run", "var/run", "var/log"] {
+        std::fs::create_dir_all(root.join(dir)).unwrap();
+    }
+    std::fs::write(root.join("t1.sh"), ENTRY.replace("$ROOT", root.to_str().unwrap())).unwrap();
+    std::fs::write(root.join("etc/inittab"),
+        format!("id:2:initdefault:\nt1:2:respawn:/bin/sh {}/t1.sh\n", root.display())).unwrap();
+    root
+}
+
+fn fill(field: &mut [libc::c_char], s: &str) {
+    for (to, from) in field.iter_mut().zip(s.bytes()) {
+        *to = from as libc::c_char;
+    }
+}
+
+// A user logged in on `tty` as far as the utmp in `root` knows, the only
+// one. The record is the C library's, as login would write it.
+fn log_in(root: &Path, tty: &str) {
+    let mut rec: libc::utmpx = unsafe { std::mem::zeroed() };
+    rec.ut_type = libc::USER_PROCESS;
+    rec.ut_pid = std::process::id() as i32;
+    fill(&mut rec.ut_line, tty.strip_prefix("/dev/").unwrap());
+    fill(&mut rec.ut_id, "test");
+    fill(&mut rec.ut_user, "tester");
+    rec.ut_tv.tv_sec = std::time::UNIX_EPOCH.elapsed().unwrap().as_secs() as _;
+    let bytes = unsafe {
+        std::slice::from_raw_parts(&rec as *const libc::utmpx as *const u8, std::mem::size_of::<libc::utmpx>())
+    };
+    std::fs::write(root.join("var/run/utmp"), bytes).unwrap();
+}
+
+fn wait_exit(init: &mut Child, transcript: &Transcript) {
+    let started = Instant::now();
+    while init.try_wait().unwrap().is_none() {
+        if started.elapsed() >= DEADLINE {
+            let _ = init.kill();
+            panic!("init still running:\n{}", transcript.text());
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    }
+}
+
+#[test]
+fn reboot_in_order() {
+    if !Uid::effective().is_root() {
+        eprintln!("shutdown needs root, skipped");
+        return;
+    }
+    let root = make_root();
+
+    let pty = openpty(None, None).unwrap();
+    let tty = nix::unistd::ttyname(&pty.slave).unwrap().display().to_string();
+    let console = File::from(pty.slave);
+    let mut master = File::from(pty.master);
+    let transcript = Transcript::default();
+    let written = transcript.clone();
+    std::thread::spawn(move || {
+        let mut buf = [0u8; 1024];
+        while let Ok(n @ 1..) = master.read(&mut buf) {
+            written.0.lock().unwrap().extend_from_slice(&buf[..n]);
+        }
+    });
+
+    let mut init = Command::new(INIT)
+        .args(["--sandbox", "--root", root.to_str().unwrap()])
+        .stdin(console.try_clone().unwrap())
+        .stdout(console.try_clone().unwrap())
+        .stderr(console.try_clone().unwrap())
+        .spawn()
+        .unwrap();
+    let up = transcript.wait_for("t1: up ");
+    let entry: i32 = transcript.text()[up + 7..].split_whitespace().next().unwrap().parse().unwrap();
+
+    log_in(&root, &tty);
+    let status = Command::new(INIT)
+        .arg0("shutdown")
+        .args(["-r", "+0", "back", "soon"])
+        .env("RYE_INIT_ROOT", &root)
+        .stdout(Stdio::null())
+        .status()
+        .unwrap();
+    assert!(status.success());
+    wait_exit(&mut init, &transcript);
+
+    // Users are warned, then locked out, then the entries are stopped
+    let text = transcript.text();
+    let wall = transcript.wait_for("The system is going down for reboot NOW!");
+    assert!(text[wall..].contains("back soon"), "{}", text);
+    let term = transcript.wait_for("t1: TERM, nologin: The system is going down for reboot NOW!");
+    assert!(wall < term, "{}", text);
+
+    // The entry ignored SIGTERM, so it took a SIGKILL to get rid of it
+    assert!(procscan::read_proc(entry).is_none_or(|p| p.state == 'Z'), "t1 (pid {}) still running", entry);
+    assert_eq!(text.matches("t1: up ").count(), 1, "{}", text);
+
+    // The machine is synced and rebooted last of all
+    let last = transcript.wait_for("sandbox: Restart instead of going down");
+    assert!(term < last);
+    let calls: Vec<&str> = text[last..].lines().next().unwrap().split("system calls: ").nth(1).unwrap().split(", ")
+        .collect();
+    let [.., sync, reboot] = calls.as_slice() else {
+        panic!("no system calls: {}", text);
+    };
+    assert!(sync.starts_with("sync"), "{:?}", calls);
+    assert_eq!(*reboot, "reboot Restart");
+
+    assert!(root.join("run/nologin").exists());
+    let _ = std::fs::remove_dir_all(&root);
+}